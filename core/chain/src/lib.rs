@@ -3,16 +3,35 @@ use std::sync::{Arc, RwLock};
 use primitives::hash::CryptoHash;
 use primitives::types::BlockId;
 use primitives::block_traits::{SignedBlock, SignedHeader};
-use storage::GenericStorage;
+use storage::{BlockChainStorage, GenericStorage};
 use std::marker::PhantomData;
 
 const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
 
+/// Result of inserting a block into the chain.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockInsertResult {
+    /// The block extended the best chain.
+    Extended,
+    /// The best chain switched to the branch of the block. `reverted` are the blocks that left
+    /// the best chain, from the highest one, and `applied` are the blocks that joined it, from
+    /// the lowest one.
+    Reorg { reverted: Vec<CryptoHash>, applied: Vec<CryptoHash> },
+    /// The block was stored on a branch that is not the best one.
+    Fork,
+    /// The block is already known.
+    Known,
+    /// The parent of the block is not known, so the block was not stored.
+    MissingParent,
+}
+
 /// General BlockChain container.
 pub struct BlockChain<H, B, S> {
     /// Storage backend.
     storage: Arc<RwLock<S>>,
-    // TODO: Add fork choice rule, tracking of finalized, pending, etc blocks.
+    /// Index of the last finalized block of the best chain. Branches that would revert it are
+    /// never chosen. Genesis is always final.
+    finalized_index: RwLock<u64>,
     phantom_header: PhantomData<H>,
     phantom_block: PhantomData<B>,
 }
@@ -24,8 +43,12 @@ where
     S: GenericStorage<H, B>,
 {
     pub fn new(genesis: B, storage: Arc<RwLock<S>>) -> Self {
-        let bc =
-            Self { storage, phantom_header: Default::default(), phantom_block: Default::default() };
+        let bc = Self {
+            storage,
+            finalized_index: RwLock::new(0),
+            phantom_header: Default::default(),
+            phantom_block: Default::default(),
+        };
         bc.storage
             .write()
             .expect(POISONED_LOCK_ERR)
@@ -72,26 +95,129 @@ where
         guard.blockchain_storage_mut().add_block(block).unwrap();
     }
 
-    /// Inserts a verified block.
-    pub fn insert_block(&self, block: B) {
+    /// Inserts a verified block. The best block is chosen by the heaviest chain rule: the branch
+    /// with the largest total weight of the blocks since the common ancestor wins, and on a tie
+    /// the current best chain is kept.
+    pub fn insert_block(&self, block: B) -> BlockInsertResult {
         let block_hash = block.block_hash();
         if self.is_known(&block_hash) {
             // TODO: known header but not known block.
-            return;
+            return BlockInsertResult::Known;
         }
 
-        if self
-            .storage
-            .write()
-            .expect(POISONED_LOCK_ERR)
-            .blockchain_storage_mut()
-            .block(&block.header().parent_hash())
-            .unwrap()
-            .is_none()
+        let parent_hash = block.header().parent_hash();
+        let best_hash = {
+            let mut guard = self.storage.write().expect(POISONED_LOCK_ERR);
+            let storage = guard.blockchain_storage_mut();
+            if storage.block(&parent_hash).unwrap().is_none() {
+                return BlockInsertResult::MissingParent;
+            }
+            *storage.best_block_hash().unwrap().unwrap()
+        };
+        if parent_hash == best_hash {
+            self.update_best_block(block);
+            return BlockInsertResult::Extended;
+        }
+
+        let mut guard = self.storage.write().expect(POISONED_LOCK_ERR);
+        let storage = guard.blockchain_storage_mut();
+        storage.set_header(&block_hash, block.header()).unwrap();
+        storage.set_block(&block_hash, block).unwrap();
+        let finalized_index = *self.finalized_index.read().expect(POISONED_LOCK_ERR);
+        let (reverted, applied) = Self::branches(storage, block_hash);
+        if Self::weight(storage, &applied) <= Self::weight(storage, &reverted)
+            || Self::ancestor_index(storage, &block_hash, &applied) < finalized_index
         {
-            return;
+            return BlockInsertResult::Fork;
+        }
+        Self::switch_best_block(storage, block_hash, reverted, applied)
+    }
+
+    /// Makes the given known block the best one, e.g. to follow the head chosen by another chain.
+    pub fn set_best_block(&self, hash: &CryptoHash) -> Result<BlockInsertResult, String> {
+        let mut guard = self.storage.write().expect(POISONED_LOCK_ERR);
+        let storage = guard.blockchain_storage_mut();
+        if storage.block(hash).unwrap().is_none() {
+            return Err(format!("Block {} is not known", hash));
+        }
+        if storage.best_block_hash().unwrap().unwrap() == hash {
+            return Ok(BlockInsertResult::Known);
+        }
+        let finalized_index = *self.finalized_index.read().expect(POISONED_LOCK_ERR);
+        let (reverted, applied) = Self::branches(storage, *hash);
+        if Self::ancestor_index(storage, hash, &applied) < finalized_index {
+            return Err(format!("Block {} is not a descendant of the finalized block", hash));
+        }
+        Ok(Self::switch_best_block(storage, *hash, reverted, applied))
+    }
+
+    /// Index of the last final block of the best chain.
+    pub fn finalized_index(&self) -> u64 {
+        *self.finalized_index.read().expect(POISONED_LOCK_ERR)
+    }
+
+    /// Marks the block with the given index on the best chain as final.
+    pub fn set_finalized_index(&self, index: u64) {
+        let mut finalized_index = self.finalized_index.write().expect(POISONED_LOCK_ERR);
+        *finalized_index = std::cmp::max(*finalized_index, index);
+    }
+
+    /// Returns the blocks of the best chain and of the branch ending with the given block that
+    /// are above their common ancestor. The best chain blocks go from the highest one, the other
+    /// branch from the lowest one.
+    fn branches(
+        storage: &mut BlockChainStorage<H, B>,
+        hash: CryptoHash,
+    ) -> (Vec<CryptoHash>, Vec<CryptoHash>) {
+        let mut applied = vec![];
+        let mut header = storage.header(&hash).unwrap().unwrap().clone();
+        while storage.hash_by_index(header.index()).unwrap() != Some(&header.block_hash()) {
+            applied.push(header.block_hash());
+            header = storage.header(&header.parent_hash()).unwrap().unwrap().clone();
+        }
+        applied.reverse();
+        let best_index = storage.best_block().unwrap().unwrap().index();
+        let reverted = (header.index() + 1..=best_index)
+            .rev()
+            .map(|index| *storage.hash_by_index(index).unwrap().unwrap())
+            .collect();
+        (reverted, applied)
+    }
+
+    /// Index of the common ancestor of the best chain and the branch ending with the given block.
+    fn ancestor_index(
+        storage: &mut BlockChainStorage<H, B>,
+        hash: &CryptoHash,
+        applied: &[CryptoHash],
+    ) -> u64 {
+        storage.header(hash).unwrap().unwrap().index() - applied.len() as u64
+    }
+
+    fn weight(storage: &mut BlockChainStorage<H, B>, hashes: &[CryptoHash]) -> u128 {
+        hashes.iter().map(|hash| storage.block(hash).unwrap().unwrap().weight()).sum()
+    }
+
+    fn switch_best_block(
+        storage: &mut BlockChainStorage<H, B>,
+        hash: CryptoHash,
+        reverted: Vec<CryptoHash>,
+        applied: Vec<CryptoHash>,
+    ) -> BlockInsertResult {
+        let indices = applied
+            .iter()
+            .map(|hash| (storage.header(hash).unwrap().unwrap().index(), *hash))
+            .collect();
+        // The previous best chain can be longer than the new one, its indices above the new head
+        // would point to blocks that are not on the best chain.
+        let head_index = storage.header(&hash).unwrap().unwrap().index();
+        let old_best_index = head_index - applied.len() as u64 + reverted.len() as u64;
+        let removed = (head_index + 1..=old_best_index).collect();
+        storage.set_best_chain(hash, indices, removed).unwrap();
+        if reverted.is_empty() {
+            BlockInsertResult::Extended
+        } else {
+            BlockInsertResult::Reorg { reverted, applied }
         }
-        self.update_best_block(block);
     }
 
     pub fn get_block(&self, id: &BlockId) -> Option<B> {
@@ -52,12 +52,11 @@ impl Pool {
         let originator = transaction.body.get_originator();
         let public_keys =
             self.state_viewer.get_public_keys_for_account(&mut state_update, &originator)?;
-        if !verify_transaction_signature(&transaction, &public_keys) {
-            return Err(format!(
-                "transaction not signed with a public key of originator {:?}",
-                originator
-            ));
-        }
+        let access_keys =
+            self.state_viewer.get_access_keys_for_account(&mut state_update, &originator)?;
+        let multisig_threshold =
+            self.state_viewer.get_multisig_threshold_for_account(&mut state_update, &originator)?;
+        verify_transaction_signature(&transaction, &public_keys, &access_keys, multisig_threshold)?;
         self.transactions.write().expect(POISONED_LOCK_ERR).insert(transaction);
         Ok(())
     }
@@ -91,6 +90,15 @@ impl Pool {
         ChainPayload { transactions, receipts }
     }
 
+    /// Returns transactions and receipts of the block that left the best chain back to the pool.
+    pub fn revert_block(&self, block: &SignedShardBlock) {
+        self.transactions
+            .write()
+            .expect(POISONED_LOCK_ERR)
+            .extend(block.body.transactions.iter().cloned());
+        self.receipts.write().expect(POISONED_LOCK_ERR).extend(block.body.receipts.iter().cloned());
+    }
+
     pub fn import_block(&self, block: &SignedShardBlock) {
         for transaction in block.body.transactions.iter() {
             self.transactions.write().expect(POISONED_LOCK_ERR).remove(transaction);
@@ -119,7 +127,14 @@ mod tests {
         let (chain_spec, _, secret_key) = generate_test_chain_spec();
         let shard_storage = create_beacon_shard_storages().1;
         let trie = Arc::new(Trie::new(shard_storage.clone()));
-        let runtime = Runtime {};
+        let runtime = Runtime {
+            fees: chain_spec.fees,
+            num_shards: chain_spec.num_shards,
+            max_gas_limit: chain_spec.max_gas_limit,
+            max_gas_price: chain_spec.max_gas_price,
+            storage_rent: chain_spec.storage_rent,
+            wasm_config: Default::default(),
+        };
         let state_update = TrieUpdate::new(trie.clone(), MerkleHash::default());
         let (genesis_root, db_changes) = runtime.apply_genesis_state(
             state_update,
@@ -128,7 +143,7 @@ mod tests {
             &chain_spec.initial_authorities,
         );
         trie.apply_changes(db_changes).expect("Failed to commit genesis state");
-        let genesis = SignedShardBlock::genesis(genesis_root);
+        let genesis = SignedShardBlock::genesis(0, genesis_root);
         let _ = Arc::new(chain::BlockChain::new(genesis, shard_storage.clone()));
         (shard_storage, trie, secret_key)
     }
@@ -159,5 +174,7 @@ mod tests {
         );
         pool.import_block(&block);
         assert_eq!(pool.transactions.read().expect(POISONED_LOCK_ERR).len(), 0);
+        pool.revert_block(&block);
+        assert_eq!(pool.transactions.read().expect(POISONED_LOCK_ERR).len(), 1);
     }
 }
@@ -10,8 +10,8 @@ pub struct BeaconBlockHeader {
     pub index: u64,
     /// Authority proposals.
     pub authority_proposal: Vec<AuthorityStake>,
-    /// Hash of the shard block.
-    pub shard_block_hash: CryptoHash,
+    /// Hashes of the shard blocks, indexed by the shard id.
+    pub shard_block_hashes: Vec<CryptoHash>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -53,9 +53,10 @@ impl SignedBeaconBlock {
         index: u64,
         parent_hash: CryptoHash,
         authority_proposal: Vec<AuthorityStake>,
-        shard_block_hash: CryptoHash,
+        shard_block_hashes: Vec<CryptoHash>,
     ) -> SignedBeaconBlock {
-        let header = BeaconBlockHeader { index, parent_hash, authority_proposal, shard_block_hash };
+        let header =
+            BeaconBlockHeader { index, parent_hash, authority_proposal, shard_block_hashes };
         let hash = hash_struct(&header);
         SignedBeaconBlock {
             body: BeaconBlock { header },
@@ -64,8 +65,8 @@ impl SignedBeaconBlock {
         }
     }
 
-    pub fn genesis(shard_block_hash: CryptoHash) -> SignedBeaconBlock {
-        SignedBeaconBlock::new(0, CryptoHash::default(), vec![], shard_block_hash)
+    pub fn genesis(shard_block_hashes: Vec<CryptoHash>) -> SignedBeaconBlock {
+        SignedBeaconBlock::new(0, CryptoHash::default(), vec![], shard_block_hashes)
     }
 }
 
@@ -99,9 +99,9 @@ impl SignedShardBlock {
         }
     }
 
-    pub fn genesis(merkle_root_state: MerkleHash) -> SignedShardBlock {
+    pub fn genesis(shard_id: ShardId, merkle_root_state: MerkleHash) -> SignedShardBlock {
         SignedShardBlock::new(
-            0,
+            shard_id,
             0,
             CryptoHash::default(),
             merkle_root_state,
@@ -98,6 +98,41 @@ pub mod bs58_format {
     }
 }
 
+pub mod bs58_vec_format {
+    use super::{bs58, CryptoHash};
+    use serde::de;
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(crypto_hashes: &[CryptoHash], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(crypto_hashes.len()))?;
+        for crypto_hash in crypto_hashes {
+            seq.serialize_element(&String::from(crypto_hash))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<CryptoHash>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let strings = Vec::<String>::deserialize(deserializer)?;
+        strings
+            .into_iter()
+            .map(|s| {
+                let mut array = [0; 32];
+                match bs58::decode(s).into(&mut array) {
+                    Ok(_) => Ok(CryptoHash::new(&array)),
+                    Err(e) => Err(de::Error::custom(e.to_string())),
+                }
+            })
+            .collect()
+    }
+}
+
 /// Calculates a hash of a bytes slice.
 ///
 /// # Examples
@@ -24,5 +24,7 @@ pub mod signer;
 pub mod test_utils;
 pub mod traits;
 pub mod transaction;
+pub mod trie_node;
+pub mod trie_proof;
 pub mod types;
 pub mod utils;
@@ -6,7 +6,7 @@ use near_protos::signed_transaction as transaction_proto;
 use super::hash::{CryptoHash, hash};
 use super::signature::{DEFAULT_SIGNATURE, PublicKey, Signature, verify};
 use super::types::{
-    AccountId, AccountingInfo, Balance, CallbackId, Mana,
+    AccountId, AccountingInfo, Balance, BlockIndex, CallbackId, Gas, Mana,
     ManaAccounting, StructSignature, ShardId,
 };
 use super::utils::account_to_shard_id;
@@ -23,6 +23,9 @@ pub enum TransactionBody {
     SwapKey(SwapKeyTransaction),
     AddKey(AddKeyTransaction),
     DeleteKey(DeleteKeyTransaction),
+    Unstake(UnstakeTransaction),
+    DeleteAccount(DeleteAccountTransaction),
+    SetMultisigPolicy(SetMultisigPolicyTransaction),
 }
 
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
@@ -65,11 +68,14 @@ pub struct DeployContractTransaction {
     pub nonce: u64,
     pub contract_id: AccountId,
     pub wasm_byte_array: Vec<u8>,
+    /// Method of the new code called within the same transaction after deploying it.
+    /// The deployment is reverted together with any state changes if the method fails.
+    pub migration_method_name: Option<Vec<u8>>,
 }
 
 impl fmt::Debug for DeployContractTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "DeployContractTransaction {{ nonce: {}, contract_id: {}, wasm_byte_array: ... }}", self.nonce, self.contract_id)
+        write!(f, "DeployContractTransaction {{ nonce: {}, contract_id: {}, wasm_byte_array: ..., migration_method_name: {:?} }}", self.nonce, self.contract_id, self.migration_method_name.clone().map(String::from_utf8))
     }
 }
 
@@ -79,6 +85,7 @@ impl From<transaction_proto::DeployContractTransaction> for DeployContractTransa
             nonce: t.nonce,
             contract_id: t.contract_id,
             wasm_byte_array: t.wasm_byte_array,
+            migration_method_name: if t.migration_method_name.is_empty() { None } else { Some(t.migration_method_name) },
         }
     }
 }
@@ -89,6 +96,7 @@ impl Into<transaction_proto::DeployContractTransaction> for DeployContractTransa
             nonce: self.nonce,
             contract_id: self.contract_id,
             wasm_byte_array: self.wasm_byte_array,
+            migration_method_name: self.migration_method_name.unwrap_or_default(),
             unknown_fields: Default::default(),
             cached_size: Default::default(),
         }
@@ -103,6 +111,11 @@ pub struct FunctionCallTransaction {
     pub method_name: Vec<u8>,
     pub args: Vec<u8>,
     pub amount: Balance,
+    /// Maximum gas the call may use, the chain-wide maximum if not given.
+    pub gas_limit: Option<Gas>,
+    /// Price of a unit of gas in fractions of a token, see `FeeConfig::gas_price_num`. The
+    /// minimum price of the chain if not given.
+    pub gas_price: Option<Balance>,
 }
 
 impl From<transaction_proto::FunctionCallTransaction> for FunctionCallTransaction {
@@ -114,6 +127,8 @@ impl From<transaction_proto::FunctionCallTransaction> for FunctionCallTransactio
             method_name: t.method_name,
             args: t.args,
             amount: t.amount,
+            gas_limit: if t.gas_limit == 0 { None } else { Some(t.gas_limit) },
+            gas_price: if t.gas_price == 0 { None } else { Some(t.gas_price) },
         }
     }
 }
@@ -127,6 +142,8 @@ impl Into<transaction_proto::FunctionCallTransaction> for FunctionCallTransactio
             method_name: self.method_name,
             args: self.args,
             amount: self.amount,
+            gas_limit: self.gas_limit.unwrap_or(0),
+            gas_price: self.gas_price.unwrap_or(0),
             unknown_fields: Default::default(),
             cached_size: Default::default(),
         }
@@ -135,7 +152,7 @@ impl Into<transaction_proto::FunctionCallTransaction> for FunctionCallTransactio
 
 impl fmt::Debug for FunctionCallTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "FunctionCallTransaction {{ nonce: {}, originator: {}, contract_id: {}, method_name: {:?}, args: ..., amount: {} }}", self.nonce, self.originator, self.contract_id, String::from_utf8(self.method_name.clone()), self.amount)
+        write!(f, "FunctionCallTransaction {{ nonce: {}, originator: {}, contract_id: {}, method_name: {:?}, args: ..., amount: {}, gas_limit: {:?}, gas_price: {:?} }}", self.nonce, self.originator, self.contract_id, String::from_utf8(self.method_name.clone()), self.amount, self.gas_limit, self.gas_price)
     }
 }
 
@@ -200,6 +217,35 @@ impl Into<transaction_proto::StakeTransaction> for StakeTransaction {
     }
 }
 
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct UnstakeTransaction {
+    pub nonce: u64,
+    pub originator: AccountId,
+    pub amount: Balance,
+}
+
+impl From<transaction_proto::UnstakeTransaction> for UnstakeTransaction {
+    fn from(t: transaction_proto::UnstakeTransaction) -> Self {
+        UnstakeTransaction {
+            nonce: t.nonce,
+            originator: t.originator,
+            amount: t.amount,
+        }
+    }
+}
+
+impl Into<transaction_proto::UnstakeTransaction> for UnstakeTransaction {
+    fn into(self) -> transaction_proto::UnstakeTransaction {
+        transaction_proto::UnstakeTransaction {
+            nonce: self.nonce,
+            originator: self.originator,
+            amount: self.amount,
+            unknown_fields: Default::default(),
+            cached_size: Default::default(),
+        }
+    }
+}
+
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct SwapKeyTransaction {
     pub nonce: u64,
@@ -233,11 +279,85 @@ impl Into<transaction_proto::SwapKeyTransaction> for SwapKeyTransaction {
     }
 }
 
+/// Permission of a key that may only call the methods of one contract. Keys without it have
+/// full access to the account.
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct AccessKey {
+    /// The only contract the key can call.
+    pub contract_id: AccountId,
+    /// Methods of the contract the key can call, any method if empty.
+    pub method_names: Vec<Vec<u8>>,
+    /// Amount of tokens the key can still spend on the attached amounts, the fees and the
+    /// prepaid gas of the calls, unlimited if `None`.
+    pub allowance: Option<Balance>,
+}
+
+impl AccessKey {
+    /// Checks that the transaction is a function call permitted by this key. The runtime checks
+    /// that the allowance also covers the fee and the prepaid gas.
+    pub fn check_transaction(&self, body: &TransactionBody) -> Result<(), String> {
+        let call = match body {
+            TransactionBody::FunctionCall(call) => call,
+            _ => return Err("Access key can only sign function calls".to_string()),
+        };
+        if call.contract_id != self.contract_id {
+            return Err(format!(
+                "Access key can only call contract {}, not {}",
+                self.contract_id, call.contract_id
+            ));
+        }
+        if !self.method_names.is_empty() && !self.method_names.contains(&call.method_name) {
+            return Err(format!(
+                "Access key is not allowed to call method {}",
+                String::from_utf8_lossy(&call.method_name)
+            ));
+        }
+        match self.allowance {
+            Some(allowance) if allowance < call.amount => Err(format!(
+                "Access key allowance {} is lower than the attached amount {}",
+                allowance, call.amount
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl From<transaction_proto::AccessKey> for AccessKey {
+    fn from(key: transaction_proto::AccessKey) -> Self {
+        AccessKey {
+            contract_id: key.contract_id,
+            method_names: key.method_names.into_vec(),
+            allowance: key.allowance.into_option().map(|allowance| allowance.amount),
+        }
+    }
+}
+
+impl Into<transaction_proto::AccessKey> for AccessKey {
+    fn into(self) -> transaction_proto::AccessKey {
+        transaction_proto::AccessKey {
+            contract_id: self.contract_id,
+            method_names: self.method_names.into(),
+            allowance: self
+                .allowance
+                .map(|amount| transaction_proto::AccessKeyAllowance {
+                    amount,
+                    unknown_fields: Default::default(),
+                    cached_size: Default::default(),
+                })
+                .into(),
+            unknown_fields: Default::default(),
+            cached_size: Default::default(),
+        }
+    }
+}
+
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct AddKeyTransaction {
     pub nonce: u64,
     pub originator: AccountId,
     pub new_key: Vec<u8>,
+    /// Permission of the new key, the key gets full access if `None`.
+    pub access_key: Option<AccessKey>,
 }
 
 impl From<transaction_proto::AddKeyTransaction> for AddKeyTransaction {
@@ -246,6 +366,7 @@ impl From<transaction_proto::AddKeyTransaction> for AddKeyTransaction {
             nonce: t.nonce,
             originator: t.originator,
             new_key: t.new_key,
+            access_key: t.access_key.into_option().map(AccessKey::from),
         }
     }
 }
@@ -256,6 +377,7 @@ impl Into<transaction_proto::AddKeyTransaction> for AddKeyTransaction {
             nonce: self.nonce,
             originator: self.originator,
             new_key: self.new_key,
+            access_key: self.access_key.map(Into::into).into(),
             unknown_fields: Default::default(),
             cached_size: Default::default(),
         }
@@ -291,6 +413,68 @@ impl Into<transaction_proto::DeleteKeyTransaction> for DeleteKeyTransaction {
     }
 }
 
+/// Deletes the originator account with all its data. The remaining balance is deposited to the
+/// beneficiary.
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct DeleteAccountTransaction {
+    pub nonce: u64,
+    pub originator: AccountId,
+    pub beneficiary_id: AccountId,
+}
+
+impl From<transaction_proto::DeleteAccountTransaction> for DeleteAccountTransaction {
+    fn from(t: transaction_proto::DeleteAccountTransaction) -> Self {
+        DeleteAccountTransaction {
+            nonce: t.nonce,
+            originator: t.originator,
+            beneficiary_id: t.beneficiary_id,
+        }
+    }
+}
+
+impl Into<transaction_proto::DeleteAccountTransaction> for DeleteAccountTransaction {
+    fn into(self) -> transaction_proto::DeleteAccountTransaction {
+        transaction_proto::DeleteAccountTransaction {
+            nonce: self.nonce,
+            originator: self.originator,
+            beneficiary_id: self.beneficiary_id,
+            unknown_fields: Default::default(),
+            cached_size: Default::default(),
+        }
+    }
+}
+
+/// Requires `threshold` distinct full access keys of the originator to sign each of its
+/// transactions. A threshold of 0 or 1 removes the policy.
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SetMultisigPolicyTransaction {
+    pub nonce: u64,
+    pub originator: AccountId,
+    pub threshold: u32,
+}
+
+impl From<transaction_proto::SetMultisigPolicyTransaction> for SetMultisigPolicyTransaction {
+    fn from(t: transaction_proto::SetMultisigPolicyTransaction) -> Self {
+        SetMultisigPolicyTransaction {
+            nonce: t.nonce,
+            originator: t.originator,
+            threshold: t.threshold,
+        }
+    }
+}
+
+impl Into<transaction_proto::SetMultisigPolicyTransaction> for SetMultisigPolicyTransaction {
+    fn into(self) -> transaction_proto::SetMultisigPolicyTransaction {
+        transaction_proto::SetMultisigPolicyTransaction {
+            nonce: self.nonce,
+            originator: self.originator,
+            threshold: self.threshold,
+            unknown_fields: Default::default(),
+            cached_size: Default::default(),
+        }
+    }
+}
+
 impl TransactionBody {
     pub fn get_nonce(&self) -> u64 {
         match self {
@@ -302,6 +486,9 @@ impl TransactionBody {
             TransactionBody::SwapKey(t) => t.nonce,
             TransactionBody::AddKey(t) => t.nonce,
             TransactionBody::DeleteKey(t) => t.nonce,
+            TransactionBody::Unstake(t) => t.nonce,
+            TransactionBody::DeleteAccount(t) => t.nonce,
+            TransactionBody::SetMultisigPolicy(t) => t.nonce,
         }
     }
 
@@ -315,6 +502,9 @@ impl TransactionBody {
             TransactionBody::SwapKey(t) => t.originator.clone(),
             TransactionBody::AddKey(t) => t.originator.clone(),
             TransactionBody::DeleteKey(t) => t.originator.clone(),
+            TransactionBody::Unstake(t) => t.originator.clone(),
+            TransactionBody::DeleteAccount(t) => t.originator.clone(),
+            TransactionBody::SetMultisigPolicy(t) => t.originator.clone(),
         }
     }
 
@@ -329,6 +519,9 @@ impl TransactionBody {
             TransactionBody::SwapKey(_) => None,
             TransactionBody::AddKey(_) => None,
             TransactionBody::DeleteKey(_) => None,
+            TransactionBody::Unstake(_) => None,
+            TransactionBody::DeleteAccount(_) => None,
+            TransactionBody::SetMultisigPolicy(_) => None,
         }
     }
 
@@ -344,6 +537,9 @@ impl TransactionBody {
             TransactionBody::SwapKey(_) => 1,
             TransactionBody::AddKey(_) => 1,
             TransactionBody::DeleteKey(_) => 1,
+            TransactionBody::Unstake(_) => 1,
+            TransactionBody::DeleteAccount(_) => 1,
+            TransactionBody::SetMultisigPolicy(_) => 1,
         }
     }
 
@@ -381,6 +577,18 @@ impl TransactionBody {
                 let proto: transaction_proto::DeleteKeyTransaction = t.into();
                 proto.write_to_bytes()
             }
+            TransactionBody::Unstake(t) => {
+                let proto: transaction_proto::UnstakeTransaction = t.into();
+                proto.write_to_bytes()
+            }
+            TransactionBody::DeleteAccount(t) => {
+                let proto: transaction_proto::DeleteAccountTransaction = t.into();
+                proto.write_to_bytes()
+            }
+            TransactionBody::SetMultisigPolicy(t) => {
+                let proto: transaction_proto::SetMultisigPolicyTransaction = t.into();
+                proto.write_to_bytes()
+            }
         };
         let bytes = bytes.unwrap();
         hash(&bytes)
@@ -391,6 +599,10 @@ impl TransactionBody {
 pub struct SignedTransaction {
     pub body: TransactionBody,
     pub signature: StructSignature,
+    /// Signatures of the other keys of the originator, required by multisig accounts.
+    pub additional_signatures: Vec<StructSignature>,
+    /// Hash of the body, which is what the keys sign.
+    body_hash: CryptoHash,
     hash: CryptoHash,
 }
 
@@ -399,16 +611,32 @@ impl SignedTransaction {
         signature: StructSignature,
         body: TransactionBody,
     ) -> Self {
-        let hash = body.get_hash();
+        let body_hash = body.get_hash();
+        let hash = Self::compute_hash(&body_hash, &signature);
         Self {
             signature,
+            additional_signatures: vec![],
             body,
+            body_hash,
             hash,
         }
     }
 
+    /// Keys of one account have independent nonces, so two keys can sign the same body. The
+    /// signature identifies the key, so it is hashed together with the body to tell such
+    /// transactions apart.
+    fn compute_hash(body_hash: &CryptoHash, signature: &StructSignature) -> CryptoHash {
+        let mut bytes = body_hash.as_ref().to_vec();
+        bytes.extend_from_slice(signature.as_ref());
+        hash(&bytes)
+    }
+
+    /// Hash that identifies the transaction, e.g. to look up its result.
     pub fn get_hash(&self) -> CryptoHash { self.hash }
 
+    /// Hash of the body, the signatures are made over it.
+    pub fn get_body_hash(&self) -> CryptoHash { self.body_hash }
+
     // this is for tests
     pub fn empty() -> SignedTransaction {
         let body = TransactionBody::SendMoney(SendMoneyTransaction {
@@ -417,7 +645,13 @@ impl SignedTransaction {
             receiver: AccountId::default(),
             amount: 0,
         });
-        SignedTransaction { signature: DEFAULT_SIGNATURE, body, hash: CryptoHash::default()}
+        SignedTransaction {
+            signature: DEFAULT_SIGNATURE,
+            additional_signatures: vec![],
+            body,
+            body_hash: CryptoHash::default(),
+            hash: CryptoHash::default(),
+        }
     }
 }
 
@@ -427,7 +661,9 @@ impl Hash for SignedTransaction {
 
 impl PartialEq for SignedTransaction {
     fn eq(&self, other: &SignedTransaction) -> bool {
-        self.hash == other.hash && self.signature == other.signature
+        self.hash == other.hash
+            && self.signature == other.signature
+            && self.additional_signatures == other.additional_signatures
     }
 }
 
@@ -467,14 +703,31 @@ impl From<transaction_proto::SignedTransaction> for SignedTransaction {
                 bytes = t.write_to_bytes();
                 TransactionBody::DeleteKey(DeleteKeyTransaction::from(t))
             }
+            Some(transaction_proto::SignedTransaction_oneof_body::unstake(t)) => {
+                bytes = t.write_to_bytes();
+                TransactionBody::Unstake(UnstakeTransaction::from(t))
+            }
+            Some(transaction_proto::SignedTransaction_oneof_body::delete_account(t)) => {
+                bytes = t.write_to_bytes();
+                TransactionBody::DeleteAccount(DeleteAccountTransaction::from(t))
+            }
+            Some(transaction_proto::SignedTransaction_oneof_body::set_multisig_policy(t)) => {
+                bytes = t.write_to_bytes();
+                TransactionBody::SetMultisigPolicy(SetMultisigPolicyTransaction::from(t))
+            }
             None => unreachable!()
         };
-        let bytes = bytes.unwrap();
-        let hash = hash(&bytes);
+        let body_hash = hash(&bytes.unwrap());
+        let signature = Signature::new(&t.signature);
         SignedTransaction {
             body,
-            signature: Signature::new(&t.signature),
-            hash,
+            hash: SignedTransaction::compute_hash(&body_hash, &signature),
+            body_hash,
+            signature,
+            additional_signatures: t.additional_signatures
+                .iter()
+                .map(|signature| Signature::new(signature))
+                .collect(),
         }
     }
 }
@@ -506,10 +759,24 @@ impl Into<transaction_proto::SignedTransaction> for SignedTransaction {
             TransactionBody::DeleteKey(t) => {
                 transaction_proto::SignedTransaction_oneof_body::delete_key(t.into())
             }
+            TransactionBody::Unstake(t) => {
+                transaction_proto::SignedTransaction_oneof_body::unstake(t.into())
+            }
+            TransactionBody::DeleteAccount(t) => {
+                transaction_proto::SignedTransaction_oneof_body::delete_account(t.into())
+            }
+            TransactionBody::SetMultisigPolicy(t) => {
+                transaction_proto::SignedTransaction_oneof_body::set_multisig_policy(t.into())
+            }
         };
         transaction_proto::SignedTransaction {
             body: Some(body),
             signature: self.signature.as_ref().to_vec(),
+            additional_signatures: self.additional_signatures
+                .iter()
+                .map(|signature| signature.as_ref().to_vec())
+                .collect::<Vec<_>>()
+                .into(),
             unknown_fields: Default::default(),
             cached_size: Default::default(),
         }
@@ -522,6 +789,7 @@ pub enum ReceiptBody {
     Callback(CallbackResult),
     Refund(u64),
     ManaAccounting(ManaAccounting),
+    ScheduledCall(ScheduledCall),
 }
 
 #[derive(Hash, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -532,6 +800,14 @@ pub struct AsyncCall {
     pub args: Vec<u8>,
     pub callback: Option<CallbackInfo>,
     pub accounting_info: AccountingInfo,
+    /// Gas fee paid in advance by the originator of the transaction, see `ManaAccounting`.
+    pub prepaid_gas_fee: Balance,
+    /// Maximum gas of the call, the chain-wide maximum if not given. Calls and callbacks created
+    /// by the contract inherit it.
+    pub gas_limit: Option<Gas>,
+    /// Price of a unit of gas of the call, see `FunctionCallTransaction::gas_price`. Calls and
+    /// callbacks created by the contract inherit it.
+    pub gas_price: Balance,
 }
 
 impl AsyncCall {
@@ -549,22 +825,42 @@ impl AsyncCall {
             args,
             callback: None,
             accounting_info,
+            prepaid_gas_fee: 0,
+            gas_limit: None,
+            gas_price: 0,
         }
     }
 }
 
 impl fmt::Debug for AsyncCall {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "AsyncCall {{ amount: {}, mana: {}, method_name: {:?}, args: ..., callback: {:?}, accounting_info: {:?} }}",
+        write!(f, "AsyncCall {{ amount: {}, mana: {}, method_name: {:?}, args: ..., callback: {:?}, accounting_info: {:?}, prepaid_gas_fee: {}, gas_limit: {:?}, gas_price: {} }}",
                self.amount,
                self.mana,
                String::from_utf8(self.method_name.clone()),
                self.callback,
                self.accounting_info,
+               self.prepaid_gas_fee,
+               self.gas_limit,
+               self.gas_price,
         )
     }
 }
 
+/// Async call that is executed when the block with the given index is processed. The receiver
+/// keeps it in the state until then.
+#[derive(Hash, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ScheduledCall {
+    pub block_index: BlockIndex,
+    pub call: AsyncCall,
+}
+
+impl ScheduledCall {
+    pub fn new(block_index: BlockIndex, call: AsyncCall) -> Self {
+        ScheduledCall { block_index, call }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Callback {
     pub method_name: Vec<u8>,
@@ -574,6 +870,12 @@ pub struct Callback {
     pub callback: Option<CallbackInfo>,
     pub result_counter: usize,
     pub accounting_info: AccountingInfo,
+    /// Part of the gas fee of the call that created the callback, paid in advance for its gas.
+    pub prepaid_gas_fee: Balance,
+    /// Maximum gas of the callback, the chain-wide maximum if not given.
+    pub gas_limit: Option<Gas>,
+    /// Price of a unit of gas of the callback, see `FunctionCallTransaction::gas_price`.
+    pub gas_price: Balance,
 }
 
 impl Callback {
@@ -586,18 +888,24 @@ impl Callback {
             callback: None,
             result_counter: 0,
             accounting_info,
+            prepaid_gas_fee: 0,
+            gas_limit: None,
+            gas_price: 0,
         }
     }
 }
 
 impl fmt::Debug for Callback {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Callback {{ method_name: {:?}, args: ..., results: ..., mana: {}, callback: {:?}, result_counter: {}, accounting_info: {:?} }}",
+        write!(f, "Callback {{ method_name: {:?}, args: ..., results: ..., mana: {}, callback: {:?}, result_counter: {}, accounting_info: {:?}, prepaid_gas_fee: {}, gas_limit: {:?}, gas_price: {} }}",
                String::from_utf8(self.method_name.clone()),
                self.mana,
                self.callback,
                self.result_counter,
                self.accounting_info,
+               self.prepaid_gas_fee,
+               self.gas_limit,
+               self.gas_price,
         )
     }
 }
@@ -657,8 +965,8 @@ impl ReceiptTransaction {
         }
     }
 
-    pub fn shard_id(&self) -> ShardId {
-        account_to_shard_id(&self.receiver)
+    pub fn shard_id(&self, num_shards: ShardId) -> ShardId {
+        account_to_shard_id(&self.receiver, num_shards)
     }
 }
 
@@ -690,7 +998,30 @@ pub struct TransactionResult {
     /// Logs from this transaction.
     pub logs: Vec<LogEntry>,
     /// Receipt ids generated by this transaction.
-    pub receipts: Vec<CryptoHash>
+    pub receipts: Vec<CryptoHash>,
+    /// Tokens charged as the fee: the transaction fee for transactions and the gas fee for
+    /// receipts.
+    pub fee: Balance,
+    /// Gas used by the contract executed by this receipt.
+    pub gas_used: Gas,
+    /// Events emitted by the contract while executing this receipt.
+    pub events: Vec<ContractEvent>,
+}
+
+/// Typed event emitted by a contract, that can be queried by contract and topic.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ContractEvent {
+    /// Account of the contract that emitted the event.
+    pub contract_id: AccountId,
+    pub topic: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Event emitted by the transaction or receipt with given hash.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TransactionEvent {
+    pub hash: CryptoHash,
+    pub event: ContractEvent,
 }
 
 /// Logs for transaction or receipt with given hash.
@@ -719,15 +1050,71 @@ pub struct TransactionAddress {
     pub index: usize
 }
 
+/// Key of the originator that signed a transaction.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TransactionSigner {
+    FullAccessKey(PublicKey),
+    AccessKey(PublicKey),
+}
+
+impl TransactionSigner {
+    pub fn public_key(&self) -> &PublicKey {
+        match self {
+            TransactionSigner::FullAccessKey(key) | TransactionSigner::AccessKey(key) => key,
+        }
+    }
+}
+
+/// Checks that the transaction is signed with one of the keys of the originator and that the key
+/// is allowed to sign it. Full access keys can sign any transaction, while access keys can only
+/// sign the function calls that their permission allows. If the originator has a multisig
+/// threshold above 1, that many distinct full access keys must sign the transaction, with the main
+/// signature and the additional signatures, and access keys cannot sign it at all.
 pub fn verify_transaction_signature(
     transaction: &SignedTransaction,
-    public_keys: &Vec<PublicKey>,
-) -> bool {
-    let hash = transaction.get_hash();
+    public_keys: &[PublicKey],
+    access_keys: &[(PublicKey, AccessKey)],
+    multisig_threshold: u32,
+) -> Result<TransactionSigner, String> {
+    let hash = transaction.get_body_hash();
     let hash = hash.as_ref();
-    public_keys.iter().any(|key| {
-        verify(&hash, &transaction.signature, &key)
-    })
+    if let Some(key) = public_keys.iter().find(|key| verify(&hash, &transaction.signature, &key)) {
+        if multisig_threshold > 1 {
+            let num_signers = public_keys
+                .iter()
+                .filter(|key| {
+                    verify(&hash, &transaction.signature, &key)
+                        || transaction
+                            .additional_signatures
+                            .iter()
+                            .any(|signature| verify(&hash, signature, &key))
+                })
+                .count();
+            if num_signers < multisig_threshold as usize {
+                return Err(format!(
+                    "transaction of multisig account {:?} is signed by {} of the required {} keys",
+                    transaction.body.get_originator(),
+                    num_signers,
+                    multisig_threshold
+                ));
+            }
+        }
+        return Ok(TransactionSigner::FullAccessKey(*key));
+    }
+    match access_keys.iter().find(|(key, _)| verify(&hash, &transaction.signature, &key)) {
+        Some(_) if multisig_threshold > 1 => Err(format!(
+            "access keys cannot sign transactions of multisig account {:?}",
+            transaction.body.get_originator()
+        )),
+        Some((key, access_key)) => {
+            access_key.check_transaction(&transaction.body)?;
+            Ok(TransactionSigner::AccessKey(*key))
+        }
+        None => Err(format!(
+            "transaction not signed with a public key of originator {:?}",
+            transaction.body.get_originator()
+        )),
+    }
 }
 
 #[cfg(test)]
@@ -741,14 +1128,131 @@ mod tests {
         let (public_key, private_key) = get_key_pair();
         let mut transaction = SignedTransaction::empty();
         transaction.signature = sign(
-            &transaction.hash.as_ref(),
+            &transaction.body_hash.as_ref(),
             &private_key,
         );
         let (wrong_public_key, _) = get_key_pair();
         let valid_keys = vec![public_key, wrong_public_key];
-        assert!(verify_transaction_signature(&transaction, &valid_keys));
+        assert_eq!(
+            verify_transaction_signature(&transaction, &valid_keys, &[], 0),
+            Ok(TransactionSigner::FullAccessKey(public_key))
+        );
 
         let invalid_keys = vec![wrong_public_key];
-        assert!(!verify_transaction_signature(&transaction, &invalid_keys));
+        assert!(verify_transaction_signature(&transaction, &invalid_keys, &[], 0).is_err());
+    }
+
+    #[test]
+    fn test_hash_depends_on_signer() {
+        let body = TransactionBody::SendMoney(SendMoneyTransaction {
+            nonce: 1,
+            originator: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount: 1,
+        });
+        let body_hash = body.get_hash();
+        let signed_with = |private_key| {
+            SignedTransaction::new(sign(body_hash.as_ref(), private_key), body.clone())
+        };
+        let transaction1 = signed_with(&get_key_pair().1);
+        let transaction2 = signed_with(&get_key_pair().1);
+        assert_eq!(transaction1.get_body_hash(), transaction2.get_body_hash());
+        assert_ne!(transaction1.get_hash(), transaction2.get_hash());
+
+        let proto: transaction_proto::SignedTransaction = transaction1.clone().into();
+        let decoded = SignedTransaction::from(proto);
+        assert_eq!(decoded.get_hash(), transaction1.get_hash());
+        assert_eq!(decoded.get_body_hash(), body_hash);
+    }
+
+    #[test]
+    fn test_hash_of_signed_transaction() {
+        let body = TransactionBody::SendMoney(SendMoneyTransaction {
+            nonce: 1,
+            originator: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount: 1,
+        });
+        let transaction = SignedTransaction::new(DEFAULT_SIGNATURE, body.clone());
+        let mut bytes = body.get_hash().as_ref().to_vec();
+        bytes.extend_from_slice(DEFAULT_SIGNATURE.as_ref());
+        assert_eq!(transaction.get_hash(), hash(&bytes));
+        // sha256(sha256(body) ++ signature), clients that look up transactions rely on it.
+        let expected = CryptoHash::from(vec![
+            212, 162, 163, 214, 54, 3, 172, 251, 209, 226, 55, 227, 34, 66, 253, 35, 142, 192, 246,
+            44, 115, 222, 45, 148, 216, 14, 242, 214, 254, 152, 171, 12,
+        ]);
+        assert_eq!(transaction.get_hash(), expected);
+    }
+
+    #[test]
+    fn test_verify_transaction_with_access_key() {
+        let (public_key, private_key) = get_key_pair();
+        let access_key = AccessKey {
+            contract_id: "bob".to_string(),
+            method_names: vec![b"run".to_vec()],
+            allowance: Some(10),
+        };
+        let access_keys = vec![(public_key, access_key)];
+        let call = |contract_id: &str, method_name: &[u8], amount| {
+            let body = TransactionBody::FunctionCall(FunctionCallTransaction {
+                nonce: 1,
+                originator: "alice".to_string(),
+                contract_id: contract_id.to_string(),
+                method_name: method_name.to_vec(),
+                args: vec![],
+                amount,
+                gas_limit: None,
+                gas_price: None,
+            });
+            let hash = body.get_hash();
+            SignedTransaction::new(sign(hash.as_ref(), &private_key), body)
+        };
+        let verify = |transaction| verify_transaction_signature(&transaction, &[], &access_keys, 0);
+        assert_eq!(verify(call("bob", b"run", 10)), Ok(TransactionSigner::AccessKey(public_key)));
+        assert!(verify(call("carol", b"run", 0)).is_err());
+        assert!(verify(call("bob", b"stop", 0)).is_err());
+        assert!(verify(call("bob", b"run", 11)).is_err());
+
+        let body = TransactionBody::SendMoney(SendMoneyTransaction {
+            nonce: 1,
+            originator: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount: 1,
+        });
+        let hash = body.get_hash();
+        assert!(verify(SignedTransaction::new(sign(hash.as_ref(), &private_key), body)).is_err());
+    }
+    #[test]
+    fn test_verify_multisig_transaction() {
+        let keys: Vec<_> = (0..3).map(|_| get_key_pair()).collect();
+        let public_keys: Vec<_> = keys.iter().map(|(public_key, _)| *public_key).collect();
+        let body = TransactionBody::SendMoney(SendMoneyTransaction {
+            nonce: 1,
+            originator: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount: 1,
+        });
+        let hash = body.get_hash();
+        let mut transaction = SignedTransaction::new(sign(hash.as_ref(), &keys[0].1), body);
+        let verify = |transaction: &SignedTransaction, threshold| {
+            verify_transaction_signature(transaction, &public_keys, &[], threshold)
+        };
+        assert!(verify(&transaction, 1).is_ok());
+        assert!(verify(&transaction, 2).is_err());
+
+        // Repeating a signature does not count as another signer.
+        transaction.additional_signatures.push(transaction.signature.clone());
+        assert!(verify(&transaction, 2).is_err());
+
+        transaction.additional_signatures.push(sign(hash.as_ref(), &keys[2].1));
+        assert_eq!(verify(&transaction, 2), Ok(TransactionSigner::FullAccessKey(public_keys[0])));
+        assert!(verify(&transaction, 3).is_err());
+
+        let (_, other_private_key) = get_key_pair();
+        transaction.additional_signatures.push(sign(hash.as_ref(), &other_private_key));
+        assert!(verify(&transaction, 3).is_err());
+        transaction.additional_signatures.push(sign(hash.as_ref(), &keys[1].1));
+        assert!(verify(&transaction, 3).is_ok());
     }
 }
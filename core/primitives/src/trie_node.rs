@@ -0,0 +1,155 @@
+//! Serialization of the trie nodes, shared by the trie in `storage` and by the verification of
+//! the merkle proofs in `trie_proof`, so that both always agree on the encoding.
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::hash::CryptoHash;
+
+const LEAF_NODE: u8 = 0;
+const BRANCH_NODE_NO_VALUE: u8 = 1;
+const BRANCH_NODE_WITH_VALUE: u8 = 2;
+const EXTENSION_NODE: u8 = 3;
+
+/// Trie node as it is stored, with the children referenced by hash.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum RawTrieNode {
+    /// Hex-prefix encoded key and value of the leaf node.
+    Leaf(Vec<u8>, Vec<u8>),
+    /// Branch of 16 possible children and value if key ends here.
+    Branch([Option<CryptoHash>; 16], Option<Vec<u8>>),
+    /// Hex-prefix encoded key and child of extension.
+    Extension(Vec<u8>, CryptoHash),
+}
+
+fn decode_children(cursor: &mut Cursor<&[u8]>) -> Result<[Option<CryptoHash>; 16], std::io::Error> {
+    let mut children: [Option<CryptoHash>; 16] = Default::default();
+    let bitmap = cursor.read_u16::<LittleEndian>()?;
+    let mut pos = 1;
+    for child in &mut children {
+        if bitmap & pos != 0 {
+            let mut arr = vec![0; 32];
+            cursor.read_exact(&mut arr)?;
+            *child = Some(CryptoHash::new(&arr));
+        }
+        pos <<= 1;
+    }
+    Ok(children)
+}
+
+fn read_bytes(cursor: &mut Cursor<&[u8]>) -> Result<Vec<u8>, std::io::Error> {
+    let length = cursor.read_u32::<LittleEndian>()?;
+    let mut bytes = vec![0; length as usize];
+    cursor.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl RawTrieNode {
+    /// Hashes of the nodes this node references.
+    pub fn children(&self) -> Vec<CryptoHash> {
+        match self {
+            RawTrieNode::Leaf(_, _) => vec![],
+            RawTrieNode::Branch(children, _) => children.iter().flatten().cloned().collect(),
+            RawTrieNode::Extension(_, child) => vec![*child],
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let mut cursor = Cursor::new(Vec::new());
+        match &self {
+            RawTrieNode::Leaf(key, value) => {
+                cursor.write_u8(LEAF_NODE)?;
+                cursor.write_u32::<LittleEndian>(key.len() as u32)?;
+                cursor.write_all(&key)?;
+                cursor.write_u32::<LittleEndian>(value.len() as u32)?;
+                cursor.write_all(&value)?;
+            }
+            RawTrieNode::Branch(children, value) => {
+                if let Some(bytes) = value {
+                    cursor.write_u8(BRANCH_NODE_WITH_VALUE)?;
+                    cursor.write_u32::<LittleEndian>(bytes.len() as u32)?;
+                    cursor.write_all(&bytes)?;
+                } else {
+                    cursor.write_u8(BRANCH_NODE_NO_VALUE)?;
+                }
+                let mut bitmap: u16 = 0;
+                let mut pos: u16 = 1;
+                for child in children.iter() {
+                    if child.is_some() {
+                        bitmap |= pos
+                    }
+                    pos <<= 1;
+                }
+                cursor.write_u16::<LittleEndian>(bitmap)?;
+                for child in children.iter() {
+                    if let Some(hash) = child {
+                        cursor.write_all(hash.as_ref())?;
+                    }
+                }
+            }
+            RawTrieNode::Extension(key, child) => {
+                cursor.write_u8(EXTENSION_NODE)?;
+                cursor.write_u32::<LittleEndian>(key.len() as u32)?;
+                cursor.write_all(&key)?;
+                cursor.write_all(child.as_ref())?;
+            }
+        }
+        Ok(cursor.into_inner())
+    }
+
+    /// Decodes a single node, the bytes must not contain anything after it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, std::io::Error> {
+        let mut cursor = Cursor::new(bytes);
+        let node = match cursor.read_u8()? {
+            LEAF_NODE => {
+                let key = read_bytes(&mut cursor)?;
+                let value = read_bytes(&mut cursor)?;
+                RawTrieNode::Leaf(key, value)
+            }
+            BRANCH_NODE_NO_VALUE => RawTrieNode::Branch(decode_children(&mut cursor)?, None),
+            BRANCH_NODE_WITH_VALUE => {
+                let value = read_bytes(&mut cursor)?;
+                RawTrieNode::Branch(decode_children(&mut cursor)?, Some(value))
+            }
+            EXTENSION_NODE => {
+                let key = read_bytes(&mut cursor)?;
+                let mut child = vec![0; 32];
+                cursor.read_exact(&mut child)?;
+                RawTrieNode::Extension(key, CryptoHash::new(&child))
+            }
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::Other, "Wrong type")),
+        };
+        if cursor.position() as usize != bytes.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Trailing bytes"));
+        }
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let node = RawTrieNode::Leaf(vec![1, 2, 3], vec![123, 245, 255]);
+        let buf = node.encode().expect("Failed to serialize");
+        let new_node = RawTrieNode::decode(&buf).expect("Failed to deserialize");
+        assert_eq!(node, new_node);
+
+        let mut children: [Option<CryptoHash>; 16] = Default::default();
+        children[3] = Some(CryptoHash::default());
+        let node = RawTrieNode::Branch(children, Some(vec![123, 245, 255]));
+        let buf = node.encode().expect("Failed to serialize");
+        let new_node = RawTrieNode::decode(&buf).expect("Failed to deserialize");
+        assert_eq!(node, new_node);
+
+        let node = RawTrieNode::Extension(vec![123, 245, 255], CryptoHash::default());
+        let mut buf = node.encode().expect("Failed to serialize");
+        let new_node = RawTrieNode::decode(&buf).expect("Failed to deserialize");
+        assert_eq!(node, new_node);
+        buf.push(0);
+        assert!(RawTrieNode::decode(&buf).is_err());
+    }
+}
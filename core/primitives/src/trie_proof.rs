@@ -0,0 +1,90 @@
+//! Verification of merkle proofs produced by `storage::Trie::get_with_proof`.
+//!
+//! A proof is the list of serialized trie nodes visited while looking up a key, starting from
+//! the root. Verification only needs the nodes themselves, so light clients can check a value
+//! against a `merkle_root_state` without access to the storage.
+use crate::hash::{hash, CryptoHash};
+use crate::trie_node::RawTrieNode;
+use crate::types::MerkleHash;
+
+/// Serialized trie nodes along the lookup path of a key, from the root down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TrieProof {
+    pub nodes: Vec<Vec<u8>>,
+}
+
+fn to_nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|b| vec![b >> 4, b & 15]).collect()
+}
+
+/// Decodes hex-prefix encoded key of a leaf or extension node into nibbles.
+fn decode_partial_key(encoded: &[u8]) -> Option<Vec<u8>> {
+    let first = *encoded.first()?;
+    let mut nibbles = to_nibbles(encoded);
+    let offset = if first & 16 == 16 { 1 } else { 2 };
+    Some(nibbles.split_off(offset))
+}
+
+/// Checks that `proof` shows `key` maps to `value` in the trie with the given `root`.
+/// If `value` is `None`, checks that the key is absent from the trie.
+pub fn verify_trie_proof(
+    root: &MerkleHash,
+    key: &[u8],
+    value: Option<&[u8]>,
+    proof: &TrieProof,
+) -> bool {
+    let mut expected_hash = *root;
+    let key = to_nibbles(key);
+    let mut key = &key[..];
+    let mut nodes = proof.nodes.iter();
+    loop {
+        // Empty root has no nodes, so there is nothing to visit.
+        if expected_hash == CryptoHash::default() {
+            return value.is_none() && nodes.next().is_none();
+        }
+        let node_bytes = match nodes.next() {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        if hash(node_bytes) != expected_hash {
+            return false;
+        }
+        let found = match RawTrieNode::decode(node_bytes) {
+            Ok(RawTrieNode::Leaf(existing_key, existing_value)) => {
+                match decode_partial_key(&existing_key) {
+                    Some(ref existing_key) if &existing_key[..] == key => Some(existing_value),
+                    Some(_) => None,
+                    None => return false,
+                }
+            }
+            Ok(RawTrieNode::Extension(existing_key, child)) => {
+                let existing_key = match decode_partial_key(&existing_key) {
+                    Some(existing_key) => existing_key,
+                    None => return false,
+                };
+                if key.starts_with(&existing_key) {
+                    expected_hash = child;
+                    key = &key[existing_key.len()..];
+                    continue;
+                }
+                None
+            }
+            Ok(RawTrieNode::Branch(children, existing_value)) => {
+                if key.is_empty() {
+                    existing_value
+                } else {
+                    match children[key[0] as usize] {
+                        Some(child) => {
+                            expected_hash = child;
+                            key = &key[1..];
+                            continue;
+                        }
+                        None => None,
+                    }
+                }
+            }
+            Err(_) => return false,
+        };
+        return nodes.next().is_none() && found.as_ref().map(|v| &v[..]) == value;
+    }
+}
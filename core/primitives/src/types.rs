@@ -116,6 +116,8 @@ pub struct ManaAccounting {
     pub accounting_info: AccountingInfo,
     pub mana_refund: Mana,
     pub gas_used: Gas,
+    /// Part of the prepaid gas fee that was not used and is returned to the originator.
+    pub gas_fee_refund: Balance,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Hash, Clone)]
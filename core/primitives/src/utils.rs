@@ -1,7 +1,8 @@
 use bs58;
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 
 use regex::Regex;
+use crate::hash::hash;
 use crate::types::{AccountId, ShardId};
 
 pub fn index_to_bytes(index: u64) -> Vec<u8> {
@@ -10,10 +11,9 @@ pub fn index_to_bytes(index: u64) -> Vec<u8> {
     bytes
 }
 
-#[allow(unused)]
-pub fn account_to_shard_id(account_id: &AccountId) -> ShardId {
-    // TODO: change to real sharding
-    0
+/// Shard of the account among `num_shards` shards, chosen by the hash of the account id.
+pub fn account_to_shard_id(account_id: &AccountId, num_shards: ShardId) -> ShardId {
+    LittleEndian::read_u32(&hash(account_id.as_bytes()).as_ref()[..4]) % num_shards
 }
 
 pub fn bs58_vec2str(buf: &[u8]) -> String {
@@ -24,3 +24,21 @@ pub fn is_valid_account_id(account_id: &AccountId) -> bool {
     let re = Regex::new(r"^[a-z0-9@._\-]{5,32}$").unwrap();
     re.is_match(account_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_account_to_shard_id() {
+        let account_id = "alice.near".to_string();
+        assert_eq!(account_to_shard_id(&account_id, 1), 0);
+        assert_eq!(account_to_shard_id(&account_id, 4), account_to_shard_id(&account_id, 4));
+        let shard_ids: HashSet<_> = (0..100)
+            .map(|i| account_to_shard_id(&format!("account{}.near", i), 4))
+            .collect();
+        assert_eq!(shard_ids.len(), 4);
+    }
+}
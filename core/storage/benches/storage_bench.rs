@@ -10,7 +10,7 @@ use std::sync::Arc;
 
 use std::path::Path;
 use std::sync::RwLock;
-use storage::create_storage;
+use storage::{create_storage, StorageBackend};
 use storage::BeaconChainStorage;
 use storage::ShardChainStorage;
 use primitives::beacon::SignedBeaconBlock;
@@ -27,7 +27,7 @@ fn get_storage(
     if base_path.exists() {
         std::fs::remove_dir_all(base_path.clone()).unwrap();
     }
-    let (beacon_chain, mut shard_chains) = create_storage(base_path.to_str().unwrap(), 1);
+    let (beacon_chain, mut shard_chains) = create_storage(base_path.to_str().unwrap(), 1, StorageBackend::RocksDb);
     let shard_chain = shard_chains.pop().unwrap();
     (beacon_chain, shard_chain)
 }
@@ -37,7 +37,7 @@ fn storage_save_block(bench: &mut Bencher) {
     let mut blocks = vec![];
     let mut prev_hash = CryptoHash::default();
     for i in 0..10 {
-        let block = SignedBeaconBlock::new(i, prev_hash, vec![], CryptoHash::default());
+        let block = SignedBeaconBlock::new(i, prev_hash, vec![], vec![]);
         prev_hash = block.hash;
         if i == 0 {
             beacon_chain.write().unwrap().blockchain_storage_mut().set_genesis(block.clone()).unwrap();
@@ -56,7 +56,7 @@ fn storage_save_get_block(bench: &mut Bencher) {
     let mut blocks_hashes = vec![];
     let mut prev_hash = CryptoHash::default();
     for i in 0..10 {
-        let block = SignedBeaconBlock::new(i, prev_hash, vec![], CryptoHash::default());
+        let block = SignedBeaconBlock::new(i, prev_hash, vec![], vec![]);
         prev_hash = block.hash;
         blocks_hashes.push(block.hash);
         if i == 0 {
@@ -6,7 +6,9 @@ use bencher::Bencher;
 
 extern crate storage;
 
-use storage::test_utils::create_trie;
+use std::sync::Arc;
+
+use storage::test_utils::{create_beacon_shard_storages, create_trie};
 use storage::Trie;
 
 use rand::random;
@@ -15,8 +17,7 @@ fn rand_bytes() -> Vec<u8> {
     (0..10).map(|_| random::<u8>()).collect()
 }
 
-fn trie_lookup(bench: &mut Bencher) {
-    let trie = create_trie();
+fn lookup_keys(bench: &mut Bencher, trie: Arc<Trie>) {
     let root = Trie::empty_root();
     let mut changes = vec![];
     for _ in 0..100 {
@@ -35,6 +36,16 @@ fn trie_lookup(bench: &mut Bencher) {
     });
 }
 
+fn trie_lookup(bench: &mut Bencher) {
+    lookup_keys(bench, create_trie());
+}
+
+/// Same lookups with a cache that is too small to hold the trie, so most nodes come from the db.
+fn trie_lookup_small_cache(bench: &mut Bencher) {
+    let storage = create_beacon_shard_storages().1;
+    lookup_keys(bench, Arc::new(Trie::with_cache_size(storage, 16)));
+}
+
 fn trie_update(bench: &mut Bencher) {
     let trie = create_trie();
     let root = Trie::empty_root();
@@ -49,5 +60,5 @@ fn trie_update(bench: &mut Bencher) {
     });
 }
 
-benchmark_group!(benches, trie_lookup, trie_update);
+benchmark_group!(benches, trie_lookup, trie_lookup_small_cache, trie_update);
 benchmark_main!(benches);
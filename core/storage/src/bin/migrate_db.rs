@@ -0,0 +1,30 @@
+//! Migrates the chain database to the schema version of this binary. The node must not be
+//! running while the database is migrated.
+use std::env;
+use std::process;
+
+use storage::storages::migrations::{migrate, SCHEMA_VERSION};
+use storage::{open_database, StorageBackend};
+
+fn main() {
+    let storage_path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: migrate_db <path to storage/db>");
+            process::exit(1);
+        }
+    };
+    let db = open_database(&storage_path, StorageBackend::RocksDb);
+    match migrate(db.as_ref()) {
+        Ok(version) if version == SCHEMA_VERSION => {
+            println!("The database is already at version {}", SCHEMA_VERSION)
+        }
+        Ok(version) => {
+            println!("Migrated the database from version {} to {}", version, SCHEMA_VERSION)
+        }
+        Err(err) => {
+            eprintln!("Failed to migrate the database: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 extern crate byteorder;
+extern crate cached;
 extern crate elastic_array;
 #[cfg(test)]
 extern crate hex_literal;
@@ -8,33 +9,233 @@ extern crate primitives;
 
 pub use kvdb::{DBTransaction, DBValue, KeyValueDB};
 use kvdb_rocksdb::{Database, DatabaseConfig};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 
 pub mod storages;
 pub mod test_utils;
 pub mod trie;
 
+use crate::storages::migrations::check_schema_version;
 use crate::storages::NUM_COLS;
 use std::sync::Arc;
 pub use trie::update::{TrieUpdate, TrieUpdateIterator};
+pub use trie::state_sync::TrieSync;
+pub use trie::cache::TrieCacheStats;
 pub use trie::{DBChanges, Trie};
 pub use storages::{BlockChainStorage, GenericStorage};
 pub use storages::beacon::BeaconChainStorage;
 pub use storages::shard::ShardChainStorage;
 use std::sync::RwLock;
 
-/// Initializes beacon and shard chain storages from the given path.
+/// Database that backs the beacon and shard chain storages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// RocksDB database at the storage path.
+    RocksDb,
+    /// Database that only lives in memory, nothing is written to disk.
+    Memory,
+    /// Copy of the RocksDB database at the storage path that is only read. The database at the
+    /// storage path is left untouched and every write fails, so the node can serve the queries
+    /// against a database copied from another node.
+    ReadOnly,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rocksdb" => Ok(StorageBackend::RocksDb),
+            "memory" => Ok(StorageBackend::Memory),
+            "read-only" => Ok(StorageBackend::ReadOnly),
+            _ => Err(format!("Unknown storage backend {}", s)),
+        }
+    }
+}
+
+fn open_rocksdb(storage_path: &str) -> Database {
+    let db_config = DatabaseConfig::with_columns(Some(NUM_COLS));
+    Database::open(&db_config, storage_path).expect("Failed to open the database")
+}
+
+/// Number of the database copies made by this process, makes the copy paths unique.
+static NUM_DATABASE_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary copy of a database directory that is removed on drop.
+struct DatabaseCopy(PathBuf);
+
+impl DatabaseCopy {
+    fn new(storage_path: &str) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "near-read-only-{}-{}",
+            std::process::id(),
+            NUM_DATABASE_COPIES.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir(&path)?;
+        // Removes the directory on drop if the copy fails.
+        let copy = DatabaseCopy(path);
+        for entry in std::fs::read_dir(storage_path)? {
+            let entry = entry?;
+            // The lock of the original database is held by the node that writes to it.
+            if entry.file_type()?.is_file() && entry.file_name() != "LOCK" {
+                std::fs::copy(entry.path(), copy.0.join(entry.file_name()))?;
+            }
+        }
+        Ok(copy)
+    }
+}
+
+impl Drop for DatabaseCopy {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            warn!(target: "storage", "Failed to remove the database copy at {:?}: {}", self.0, e);
+        }
+    }
+}
+
+/// Database that passes the reads to a copy of the database and fails on every write. The
+/// original database is never opened, so RocksDB doesn't lock it or write its logs and manifest.
+struct ReadOnlyDatabase {
+    db: Database,
+    // Dropped after the database is closed.
+    _copy: DatabaseCopy,
+}
+
+impl ReadOnlyDatabase {
+    fn open(storage_path: &str) -> io::Result<Self> {
+        let copy = DatabaseCopy::new(storage_path)?;
+        let db_config = DatabaseConfig::with_columns(Some(NUM_COLS));
+        let path = copy.0.to_str().expect("Temp dir path is not valid unicode");
+        let db = Database::open(&db_config, path)?;
+        Ok(ReadOnlyDatabase { db, _copy: copy })
+    }
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "The database is opened read-only")
+}
+
+impl KeyValueDB for ReadOnlyDatabase {
+    fn get(&self, col: Option<u32>, key: &[u8]) -> io::Result<Option<DBValue>> {
+        KeyValueDB::get(&self.db, col, key)
+    }
+
+    fn get_by_prefix(&self, col: Option<u32>, prefix: &[u8]) -> Option<Box<[u8]>> {
+        KeyValueDB::get_by_prefix(&self.db, col, prefix)
+    }
+
+    fn write_buffered(&self, transaction: DBTransaction) {
+        error!(
+            target: "storage",
+            "Dropped a transaction of {} operations: {}",
+            transaction.ops.len(),
+            read_only_error()
+        );
+    }
+
+    fn write(&self, _transaction: DBTransaction) -> io::Result<()> {
+        Err(read_only_error())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn iter<'a>(&'a self, col: Option<u32>) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        KeyValueDB::iter(&self.db, col)
+    }
+
+    fn iter_from_prefix<'a>(
+        &'a self,
+        col: Option<u32>,
+        prefix: &'a [u8],
+    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        KeyValueDB::iter_from_prefix(&self.db, col, prefix)
+    }
+
+    fn restore(&self, _new_db: &str) -> io::Result<()> {
+        Err(read_only_error())
+    }
+}
+
+/// Opens the database of the given backend.
+pub fn open_database(storage_path: &str, backend: StorageBackend) -> Arc<KeyValueDB> {
+    match backend {
+        StorageBackend::RocksDb => Arc::new(open_rocksdb(storage_path)),
+        StorageBackend::Memory => Arc::new(kvdb_memorydb::create(NUM_COLS)),
+        StorageBackend::ReadOnly => {
+            assert!(
+                Path::new(storage_path).exists(),
+                "No database at {} to open read-only",
+                storage_path
+            );
+            let db = ReadOnlyDatabase::open(storage_path)
+                .unwrap_or_else(|e| panic!("Failed to open the database read-only: {}", e));
+            Arc::new(db)
+        }
+    }
+}
+
+/// Creates beacon and shard chain storages on top of the given database.
+pub fn create_storage_from_db(
+    db: Arc<KeyValueDB>,
+    num_shards: u32,
+) -> (Arc<RwLock<BeaconChainStorage>>, Vec<Arc<RwLock<ShardChainStorage>>>) {
+    let beacon = Arc::new(RwLock::new(BeaconChainStorage::new(db.clone())));
+    let shards = (0..num_shards)
+        .map(|id| Arc::new(RwLock::new(ShardChainStorage::new(db.clone(), id))))
+        .collect();
+    (beacon, shards)
+}
+
+/// Initializes beacon and shard chain storages from the given path. Panics if the database was
+/// written with another schema version.
 pub fn create_storage(
     storage_path: &str,
     num_shards: u32,
+    backend: StorageBackend,
 ) -> (Arc<RwLock<BeaconChainStorage>>, Vec<Arc<RwLock<ShardChainStorage>>>) {
-    let db_config = DatabaseConfig::with_columns(Some(NUM_COLS));
-    let db =
-        Arc::new(Database::open(&db_config, storage_path).expect("Failed to open the database"));
-    let beacon = Arc::new(RwLock::new(BeaconChainStorage::new(db.clone())));
-    let mut shards = vec![];
-    for id in 0..num_shards {
-        shards.push(Arc::new(RwLock::new(ShardChainStorage::new(db.clone(), id))));
+    let db = open_database(storage_path, backend);
+    if let Err(err) = check_schema_version(db.as_ref()) {
+        panic!("Cannot open the database at {}: {}", storage_path, err);
+    }
+    create_storage_from_db(db, num_shards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::hash::hash;
+
+    #[test]
+    fn test_read_only_backend() {
+        let path = std::env::temp_dir()
+            .join(format!("storage_test_read_only_backend_{}", std::process::id()));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        let dir = path.clone();
+        let path = path.to_str().unwrap();
+        let mut changes = DBChanges::default();
+        changes.insert(hash(b"node").as_ref().to_vec(), Some(b"value".to_vec()));
+        {
+            let (_, shards) = create_storage(path, 1, StorageBackend::RocksDb);
+            shards[0].read().unwrap().apply_state_updates(&changes).unwrap();
+        }
+
+        let (_, shards) = create_storage(path, 1, StorageBackend::ReadOnly);
+        let shard = shards[0].read().unwrap();
+        assert_eq!(shard.get_state(&hash(b"node")).unwrap(), Some(b"value".to_vec()));
+        changes.insert(hash(b"node").as_ref().to_vec(), None);
+        assert!(shard.apply_state_updates(&changes).is_err());
+        assert_eq!(shard.get_state(&hash(b"node")).unwrap(), Some(b"value".to_vec()));
+        let db = open_database(path, StorageBackend::ReadOnly);
+        db.write_buffered(DBTransaction::new());
+        assert!(db.flush().is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
     }
-    (beacon, shards)
 }
@@ -0,0 +1,179 @@
+//! Versioning of the database schema. The version is stored in the database, and a node refuses
+//! to open a database of another version, since the stored structs are encoded without any
+//! version marker. Older databases are rewritten offline with `migrate`, unless they are too old
+//! to be rewritten and the node has to resync from scratch.
+use std::io;
+
+use super::NUM_COLS;
+use crate::{DBTransaction, KeyValueDB};
+use primitives::traits::{Decode, Encode};
+
+/// Version of the schema this binary reads and writes. It must be bumped together with adding
+/// a migration to `MIGRATIONS` whenever the columns or the encoding of the stored structs change.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Oldest version that can be migrated. Version 0 databases can not be rewritten: the encoding
+/// of the transactions, transaction results, accounts, calls, callbacks and beacon block headers
+/// changed, two columns were added, and the reference counts of the trie nodes did not count the
+/// references from the other states.
+const MIN_MIGRATABLE_VERSION: u32 = 1;
+
+/// Key of the schema version in the default column.
+const SCHEMA_VERSION_KEY: &[u8] = b"SCHEMA_VERSION";
+
+/// Adds the changes that rewrite the database to the next version to the transaction.
+type Migration = fn(&KeyValueDB, &mut DBTransaction) -> io::Result<()>;
+
+/// `MIGRATIONS[n]` migrates the database from version `MIN_MIGRATABLE_VERSION + n` to the next
+/// one.
+const MIGRATIONS: [Migration; (SCHEMA_VERSION - MIN_MIGRATABLE_VERSION) as usize] = [];
+
+/// Schema version stored in the database, if any.
+pub fn stored_schema_version(db: &KeyValueDB) -> io::Result<Option<u32>> {
+    match db.get(None, SCHEMA_VERSION_KEY)? {
+        Some(bytes) => Ok(Some(Decode::decode(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+fn is_empty(db: &KeyValueDB) -> bool {
+    (0..NUM_COLS).all(|col| db.iter(Some(col)).next().is_none())
+}
+
+/// Version of the database. Databases that were written before the version was stored have
+/// version 0.
+fn schema_version(db: &KeyValueDB) -> io::Result<u32> {
+    Ok(stored_schema_version(db)?.unwrap_or(0))
+}
+
+fn put_schema_version(db_transaction: &mut DBTransaction, version: u32) -> io::Result<()> {
+    db_transaction.put(None, SCHEMA_VERSION_KEY, &version.encode()?);
+    Ok(())
+}
+
+fn resync_required(version: u32) -> String {
+    format!(
+        "Database schema version {} is too old to be migrated to {}, resync required: remove the \
+         database and restart the node",
+        version, SCHEMA_VERSION
+    )
+}
+
+/// Checks that the database can be used by this binary. A new database gets the current
+/// version.
+pub fn check_schema_version(db: &KeyValueDB) -> Result<(), String> {
+    let stored = stored_schema_version(db).map_err(|e| format!("{}", e))?;
+    let version = match stored {
+        None if is_empty(db) => {
+            let mut db_transaction = db.transaction();
+            put_schema_version(&mut db_transaction, SCHEMA_VERSION).map_err(|e| format!("{}", e))?;
+            return db.write(db_transaction).map_err(|e| format!("{}", e));
+        }
+        stored => stored.unwrap_or(0),
+    };
+    if version == SCHEMA_VERSION {
+        Ok(())
+    } else if version < MIN_MIGRATABLE_VERSION {
+        Err(resync_required(version))
+    } else if version < SCHEMA_VERSION {
+        Err(format!(
+            "Database schema version {} is older than {}, run migrate_db on the database",
+            version, SCHEMA_VERSION
+        ))
+    } else {
+        Err(format!(
+            "Database schema version {} was written by a newer version of the node, expected {}",
+            version, SCHEMA_VERSION
+        ))
+    }
+}
+
+/// Rewrites the database to the current version, one version at a time. Each step is written in
+/// one transaction together with its version, so an interrupted migration can be resumed.
+/// Returns the version the database had before the migration. Databases older than
+/// `MIN_MIGRATABLE_VERSION` are left untouched.
+pub fn migrate(db: &KeyValueDB) -> Result<u32, String> {
+    let initial_version = schema_version(db).map_err(|e| format!("{}", e))?;
+    if initial_version > SCHEMA_VERSION {
+        return Err(format!(
+            "Database schema version {} is newer than {}",
+            initial_version, SCHEMA_VERSION
+        ));
+    }
+    if initial_version < MIN_MIGRATABLE_VERSION {
+        return Err(resync_required(initial_version));
+    }
+    for version in initial_version..SCHEMA_VERSION {
+        info!(
+            target: "storage",
+            "Migrating the database from version {} to {}",
+            version,
+            version + 1
+        );
+        let mut db_transaction = db.transaction();
+        MIGRATIONS[(version - MIN_MIGRATABLE_VERSION) as usize](db, &mut db_transaction)
+            .and_then(|_| put_schema_version(&mut db_transaction, version + 1))
+            .and_then(|_| db.write(db_transaction))
+            .map_err(|e| format!("Failed to migrate from version {}: {}", version, e))?;
+    }
+    Ok(initial_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storages::{BlockChainStorage, ChainId};
+    use primitives::chain::{SignedShardBlock, SignedShardBlockHeader};
+    use primitives::hash::CryptoHash;
+
+    #[test]
+    fn test_new_database_gets_current_version() {
+        let db = kvdb_memorydb::create(NUM_COLS);
+        assert_eq!(stored_schema_version(&db).unwrap(), None);
+        assert!(check_schema_version(&db).is_ok());
+        assert_eq!(stored_schema_version(&db).unwrap(), Some(SCHEMA_VERSION));
+        assert!(check_schema_version(&db).is_ok());
+    }
+
+    #[test]
+    fn test_refuse_other_versions() {
+        let db = kvdb_memorydb::create(NUM_COLS);
+        let mut db_transaction = db.transaction();
+        put_schema_version(&mut db_transaction, SCHEMA_VERSION + 1).unwrap();
+        db.write(db_transaction).unwrap();
+        assert!(check_schema_version(&db).is_err());
+        assert!(migrate(&db).is_err());
+    }
+
+    #[test]
+    fn test_refuse_to_migrate_version_0() {
+        let db = Arc::new(kvdb_memorydb::create(NUM_COLS));
+        let mut chain: BlockChainStorage<SignedShardBlockHeader, SignedShardBlock> =
+            BlockChainStorage::new(db.clone(), ChainId::ShardChain(0));
+        chain.set_hash_by_index(0, CryptoHash::default()).unwrap();
+        chain.set_hash_by_index(5, CryptoHash::default()).unwrap();
+        let contents = |db: &KeyValueDB| -> Vec<Vec<(Box<[u8]>, Box<[u8]>)>> {
+            (0..NUM_COLS).map(|col| db.iter(Some(col)).collect()).collect()
+        };
+        let before = contents(db.as_ref());
+
+        // Written before the version was stored.
+        let err = check_schema_version(db.as_ref()).unwrap_err();
+        assert!(err.contains("resync required"), "{}", err);
+        let err = migrate(db.as_ref()).unwrap_err();
+        assert!(err.contains("resync required"), "{}", err);
+        assert_eq!(stored_schema_version(db.as_ref()).unwrap(), None);
+        assert_eq!(contents(db.as_ref()), before);
+    }
+
+    #[test]
+    fn test_migrate_current_version() {
+        let db = kvdb_memorydb::create(NUM_COLS);
+        assert!(check_schema_version(&db).is_ok());
+        // Nothing to do for the current version.
+        assert_eq!(migrate(&db), Ok(SCHEMA_VERSION));
+        assert!(check_schema_version(&db).is_ok());
+    }
+}
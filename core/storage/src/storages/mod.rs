@@ -11,6 +11,7 @@ use std::io;
 use std::sync::Arc;
 
 pub mod beacon;
+pub mod migrations;
 pub mod shard;
 
 type StorageResult<T> = io::Result<Option<T>>;
@@ -56,9 +57,13 @@ const COL_BLOCK_INDICES: u32 = 3;
 const COL_STATE: u32 = 4;
 const COL_TRANSACTION_RESULTS: u32 = 5;
 const COL_TRANSACTION_ADDRESSES: u32 = 6;
+/// Column that stores the mapping: block hash -> contract events emitted in the block.
+const COL_CONTRACT_EVENTS: u32 = 7;
+/// Column that stores the index of the first block whose state is not pruned yet.
+const COL_STATE_PRUNING: u32 = 8;
 
 /// Number of columns per chain.
-pub const NUM_COLS: u32 = 7;
+pub const NUM_COLS: u32 = 9;
 
 /// Error that occurs when we try operating with genesis-specific columns, without setting the
 /// genesis in advance.
@@ -224,6 +229,40 @@ where
             hash,
         )
     }
+
+    /// Switches the best chain to another branch: sets the best block and rewrites the indices
+    /// of the best chain in one transaction, so that they can not get out of sync. `removed` are
+    /// the indices of the previous best chain above the new best block.
+    pub fn set_best_chain(
+        &mut self,
+        best_block_hash: CryptoHash,
+        hashes: Vec<(u64, CryptoHash)>,
+        removed: Vec<u64>,
+    ) -> io::Result<()> {
+        let mut db_transaction = self.storage.transaction();
+        let best_block_key =
+            self.enc_hash(self.genesis_hash.as_ref().expect(MISSING_GENESIS_ERR)).to_vec();
+        db_transaction.put(Some(COL_BEST_BLOCK), &best_block_key, &best_block_hash.encode()?);
+        let mut indices = vec![];
+        for (index, hash) in hashes {
+            let key = self.enc_index(index).to_vec();
+            db_transaction.put(Some(COL_BLOCK_INDICES), &key, &hash.encode()?);
+            indices.push((key, hash));
+        }
+        let removed: Vec<_> =
+            removed.into_iter().map(|index| self.enc_index(index).to_vec()).collect();
+        for key in removed.iter() {
+            db_transaction.delete(Some(COL_BLOCK_INDICES), key);
+        }
+        self.storage.write(db_transaction)?;
+        // If it has reached here then it is safe to update the caches.
+        self.best_block_hash.insert(best_block_key, best_block_hash);
+        for key in removed.iter() {
+            self.block_indices.remove(key);
+        }
+        self.block_indices.extend(indices);
+        Ok(())
+    }
 }
 
 /// Provides a view on the bytes that constitute the u64 index.
@@ -267,6 +306,17 @@ fn extend_with_cache<T: Clone + Encode>(
     col: u32,
     cache: &mut HashMap<Vec<u8>, T>,
     values: HashMap<Vec<u8>, T>,
+) -> io::Result<()> {
+    update_with_cache(storage, col, cache, values, vec![])
+}
+
+/// Writes the values and deletes the removed keys in one transaction.
+fn update_with_cache<T: Clone + Encode>(
+    storage: &KeyValueDB,
+    col: u32,
+    cache: &mut HashMap<Vec<u8>, T>,
+    values: HashMap<Vec<u8>, T>,
+    removed: Vec<Vec<u8>>,
 ) -> io::Result<()> {
     let mut db_transaction = storage.transaction();
     let mut cache_to_extend = vec![];
@@ -275,8 +325,14 @@ fn extend_with_cache<T: Clone + Encode>(
         cache_to_extend.push((key.clone(), value));
         db_transaction.put(Some(col), &key, &data);
     }
+    for key in removed.iter() {
+        db_transaction.delete(Some(col), key);
+    }
     storage.write(db_transaction)?;
-    // If it has reached here then it is safe to put in cache.
+    // If it has reached here then it is safe to update the cache.
+    for key in removed.iter() {
+        cache.remove(key);
+    }
     cache.extend(cache_to_extend);
     Ok(())
 }
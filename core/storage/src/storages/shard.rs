@@ -1,21 +1,34 @@
-use super::{extend_with_cache, read_with_cache, StorageResult};
+use super::{extend_with_cache, read_with_cache, write_with_cache, StorageResult};
 use super::{BlockChainStorage, GenericStorage};
 use super::{ChainId, KeyValueDB};
-use super::{COL_STATE, COL_TRANSACTION_ADDRESSES, COL_TRANSACTION_RESULTS};
+use crate::DBTransaction;
+use super::{COL_CONTRACT_EVENTS, COL_STATE, COL_STATE_PRUNING};
+use super::{COL_TRANSACTION_ADDRESSES, COL_TRANSACTION_RESULTS};
 use primitives::chain::SignedShardBlock;
 use primitives::chain::SignedShardBlockHeader;
 use primitives::hash::CryptoHash;
+use primitives::traits::{Decode, Encode};
 use primitives::transaction::TransactionAddress;
+use primitives::transaction::TransactionEvent;
 use primitives::transaction::TransactionResult;
+use primitives::types::BlockIndex;
+use primitives::utils::index_to_bytes;
 use std::collections::HashMap;
 use std::io;
 use std::sync::Arc;
 
+/// Key of the pruned state index in `COL_STATE_PRUNING`.
+const PRUNED_STATE_INDEX_KEY: &[u8] = b"PRUNED_STATE_INDEX";
+
+/// Prefix of the keys of the state roots of the blocks of one index in `COL_STATE_PRUNING`.
+const STATE_ROOTS_PREFIX: &[u8] = b"STATE_ROOTS";
+
 /// Shard chain
 pub struct ShardChainStorage {
     generic_storage: BlockChainStorage<SignedShardBlockHeader, SignedShardBlock>,
     transaction_results: HashMap<Vec<u8>, TransactionResult>,
     transaction_addresses: HashMap<Vec<u8>, TransactionAddress>,
+    contract_events: HashMap<Vec<u8>, Vec<TransactionEvent>>,
 }
 
 impl GenericStorage<SignedShardBlockHeader, SignedShardBlock> for ShardChainStorage {
@@ -33,32 +46,31 @@ impl ShardChainStorage {
             generic_storage: BlockChainStorage::new(storage, ChainId::ShardChain(shard_id)),
             transaction_results: Default::default(),
             transaction_addresses: Default::default(),
+            contract_events: Default::default(),
         }
     }
 
-    /// Records the transaction addresses and the transaction results of the processed block.
+    /// Records the transaction addresses, the transaction results and the contract events of the
+    /// processed block. The results of the scheduled receipts executed by the block come first,
+    /// these receipts are not part of the block, so they do not get an address.
     pub fn extend_transaction_results_addresses(
         &mut self,
         block: &SignedShardBlock,
+        scheduled_receipts: &[CryptoHash],
         tx_results: Vec<TransactionResult>,
     ) -> io::Result<()> {
-        let keys: Vec<_> = block
-            .body
-            .receipts
+        let hashes: Vec<_> = scheduled_receipts
             .iter()
-            .flat_map(|b| b.receipts.iter().map(|r| self.generic_storage.enc_hash(&r.nonce)))
-            .chain(
-                block
-                    .body
-                    .transactions
-                    .iter()
-                    .map(|t| self.generic_storage.enc_hash(&t.get_hash())),
-            )
-            .map(|k| k.to_vec())
+            .cloned()
+            .chain(block.body.receipts.iter().flat_map(|b| b.receipts.iter().map(|r| r.nonce)))
+            .chain(block.body.transactions.iter().map(|t| t.get_hash()))
             .collect();
+        let keys: Vec<_> =
+            hashes.iter().map(|h| self.generic_storage.enc_hash(h).to_vec()).collect();
 
         let updates: HashMap<Vec<u8>, TransactionAddress> = keys
             .iter()
+            .skip(scheduled_receipts.len())
             .cloned()
             .enumerate()
             .map(|(i, key)| (key, TransactionAddress { block_hash: block.hash, index: i }))
@@ -70,6 +82,26 @@ impl ShardChainStorage {
             updates,
         )?;
 
+        let events: Vec<TransactionEvent> = hashes
+            .iter()
+            .zip(tx_results.iter())
+            .flat_map(|(hash, result)| {
+                result.events.iter().map(move |event| TransactionEvent {
+                    hash: *hash,
+                    event: event.clone(),
+                })
+            })
+            .collect();
+        if !events.is_empty() {
+            write_with_cache(
+                self.generic_storage.storage.as_ref(),
+                COL_CONTRACT_EVENTS,
+                &mut self.contract_events,
+                &self.generic_storage.enc_hash(&block.hash),
+                events,
+            )?;
+        }
+
         let updates: HashMap<Vec<u8>, TransactionResult> =
             keys.into_iter().zip(tx_results.into_iter()).collect();
         extend_with_cache(
@@ -80,6 +112,21 @@ impl ShardChainStorage {
         )
     }
 
+    #[inline]
+    /// Get contract events emitted in the block with the given hash. Blocks without events have
+    /// no entry.
+    pub fn contract_events(
+        &mut self,
+        block_hash: &CryptoHash,
+    ) -> StorageResult<&Vec<TransactionEvent>> {
+        read_with_cache(
+            self.generic_storage.storage.as_ref(),
+            COL_CONTRACT_EVENTS,
+            &mut self.contract_events,
+            &self.generic_storage.enc_hash(block_hash),
+        )
+    }
+
     #[inline]
     /// Get transaction address of the computed transaction from its hash.
     pub fn transaction_address(&mut self, hash: &CryptoHash) -> StorageResult<&TransactionAddress> {
@@ -111,11 +158,10 @@ impl ShardChainStorage {
             .map(|a| a.map(|b| b.to_vec()))
     }
 
-    /// Saves state updates in the db.
-    pub fn apply_state_updates(
+    fn state_updates_transaction(
         &self,
         changes: &HashMap<Vec<u8>, Option<Vec<u8>>>,
-    ) -> std::io::Result<()> {
+    ) -> DBTransaction {
         let mut db_transaction = self.generic_storage.storage.transaction();
         let col = Some(COL_STATE);
         for (key, value) in changes {
@@ -124,6 +170,79 @@ impl ShardChainStorage {
                 None => db_transaction.delete(col, &self.generic_storage.enc_slice(&key)),
             }
         }
+        db_transaction
+    }
+
+    /// Saves state updates in the db.
+    pub fn apply_state_updates(
+        &self,
+        changes: &HashMap<Vec<u8>, Option<Vec<u8>>>,
+    ) -> std::io::Result<()> {
+        self.generic_storage.storage.write(self.state_updates_transaction(changes))
+    }
+
+    /// Saves the state updates of a block together with its state root, so that the state can be
+    /// pruned even if the block does not make it to the best chain.
+    pub fn apply_block_state_updates(
+        &self,
+        changes: &HashMap<Vec<u8>, Option<Vec<u8>>>,
+        index: BlockIndex,
+        state_root: &CryptoHash,
+    ) -> io::Result<()> {
+        let mut state_roots = self.state_roots(index)?;
+        state_roots.push(*state_root);
+        let mut db_transaction = self.state_updates_transaction(changes);
+        db_transaction.put(
+            Some(COL_STATE_PRUNING),
+            &self.state_roots_key(index),
+            &state_roots.encode()?,
+        );
+        self.generic_storage.storage.write(db_transaction)
+    }
+
+    fn state_roots_key(&self, index: BlockIndex) -> Vec<u8> {
+        let mut key = STATE_ROOTS_PREFIX.to_vec();
+        key.extend_from_slice(&index_to_bytes(index));
+        self.generic_storage.enc_slice(&key)
+    }
+
+    /// State roots of the stored states of the blocks with the given index, one per stored block,
+    /// including the blocks that are not on the best chain.
+    pub fn state_roots(&self, index: BlockIndex) -> io::Result<Vec<CryptoHash>> {
+        let key = self.state_roots_key(index);
+        let value = self.generic_storage.storage.get(Some(COL_STATE_PRUNING), &key)?;
+        match value {
+            Some(bytes) => Decode::decode(&bytes),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Index of the first block whose state has not been pruned yet.
+    pub fn pruned_state_index(&self) -> io::Result<BlockIndex> {
+        let value = self
+            .generic_storage
+            .storage
+            .get(Some(COL_STATE_PRUNING), &self.generic_storage.enc_slice(PRUNED_STATE_INDEX_KEY))?;
+        match value {
+            Some(bytes) => Decode::decode(&bytes),
+            None => Ok(0),
+        }
+    }
+
+    /// Saves the state updates that release the states of the blocks with the given index, and
+    /// marks the index as pruned in the same transaction.
+    pub fn apply_pruned_state_updates(
+        &self,
+        changes: &HashMap<Vec<u8>, Option<Vec<u8>>>,
+        index: BlockIndex,
+    ) -> io::Result<()> {
+        let mut db_transaction = self.state_updates_transaction(changes);
+        db_transaction.delete(Some(COL_STATE_PRUNING), &self.state_roots_key(index));
+        db_transaction.put(
+            Some(COL_STATE_PRUNING),
+            &self.generic_storage.enc_slice(PRUNED_STATE_INDEX_KEY),
+            &(index + 1).encode()?,
+        );
         self.generic_storage.storage.write(db_transaction)
     }
 }
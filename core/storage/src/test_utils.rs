@@ -3,7 +3,7 @@ use std::sync::Arc;
 use crate::storages::beacon::BeaconChainStorage;
 use crate::storages::shard::ShardChainStorage;
 use crate::storages::NUM_COLS;
-use crate::Trie;
+use crate::{create_storage_from_db, Trie};
 use std::sync::RwLock;
 
 /// Creates one beacon storage and one shard storage using in-memory database.
@@ -14,6 +14,13 @@ pub fn create_beacon_shard_storages() -> (Arc<RwLock<BeaconChainStorage>>, Arc<R
     (Arc::new(RwLock::new(beacon)), Arc::new(RwLock::new(shard)))
 }
 
+/// Creates one beacon storage and `num_shards` shard storages using in-memory database.
+pub fn create_memory_storage(
+    num_shards: u32,
+) -> (Arc<RwLock<BeaconChainStorage>>, Vec<Arc<RwLock<ShardChainStorage>>>) {
+    create_storage_from_db(Arc::new(kvdb_memorydb::create(NUM_COLS)), num_shards)
+}
+
 /// Creates a Trie using a single shard storage that uses in-memory database.
 pub fn create_trie() -> Arc<Trie> {
     let shard_storage = create_beacon_shard_storages().1;
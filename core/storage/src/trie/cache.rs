@@ -0,0 +1,94 @@
+//! Bounded cache of decoded trie nodes. Nodes are addressed by the hash of their content, so a
+//! cached node never goes stale; it only has to be dropped once the node is removed from the
+//! storage.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use cached::{Cached, SizedCache};
+
+use primitives::hash::CryptoHash;
+
+use super::{RawTrieNode, POISONED_LOCK_ERR};
+
+/// Default number of decoded nodes to hold.
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// Counters of the trie node cache since it was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrieCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl TrieCacheStats {
+    /// Share of the node lookups that did not hit the storage.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+pub(crate) struct TrieCache {
+    nodes: Mutex<SizedCache<CryptoHash, RawTrieNode>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl TrieCache {
+    pub fn new(size: usize) -> Self {
+        TrieCache {
+            nodes: Mutex::new(SizedCache::with_size(size)),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, hash: &CryptoHash) -> Option<RawTrieNode> {
+        let result = self.nodes.lock().expect(POISONED_LOCK_ERR).cache_get(hash).cloned();
+        match result {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    pub fn insert(&self, hash: CryptoHash, node: RawTrieNode) {
+        self.nodes.lock().expect(POISONED_LOCK_ERR).cache_set(hash, node);
+    }
+
+    pub fn remove(&self, hash: &CryptoHash) {
+        self.nodes.lock().expect(POISONED_LOCK_ERR).cache_remove(hash);
+    }
+
+    pub fn stats(&self) -> TrieCacheStats {
+        TrieCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::hash::hash;
+
+    #[test]
+    fn test_trie_cache() {
+        let cache = TrieCache::new(1);
+        let leaf = |value: &[u8]| RawTrieNode::Leaf(vec![1], value.to_vec());
+        assert_eq!(cache.get(&hash(b"a")), None);
+        cache.insert(hash(b"a"), leaf(b"a"));
+        assert_eq!(cache.get(&hash(b"a")), Some(leaf(b"a")));
+        // The least recently used node is evicted.
+        cache.insert(hash(b"b"), leaf(b"b"));
+        assert_eq!(cache.get(&hash(b"a")), None);
+        cache.remove(&hash(b"b"));
+        assert_eq!(cache.get(&hash(b"b")), None);
+        assert_eq!(cache.stats(), TrieCacheStats { hits: 1, misses: 3 });
+        assert_eq!(cache.stats().hit_rate(), 0.25);
+    }
+}
@@ -1,14 +1,20 @@
+use self::cache::{TrieCache, TrieCacheStats, DEFAULT_CACHE_SIZE};
 use self::nibble_slice::NibbleSlice;
 use crate::storages::shard::ShardChainStorage;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 pub use kvdb::DBValue;
 use primitives::hash::{hash, CryptoHash};
+use primitives::trie_node::RawTrieNode;
+use primitives::trie_proof::TrieProof;
+use primitives::types::BlockIndex;
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, Write};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{RwLock, RwLockReadGuard};
 
+pub mod cache;
 mod nibble_slice;
+pub mod state_sync;
 pub mod update;
 
 const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
@@ -48,120 +54,12 @@ impl TrieNode {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
-#[allow(clippy::large_enum_variant)]
-enum RawTrieNode {
-    Leaf(Vec<u8>, Vec<u8>),
-    Branch([Option<CryptoHash>; 16], Option<Vec<u8>>),
-    Extension(Vec<u8>, CryptoHash),
-}
-
-const LEAF_NODE: u8 = 0;
-const BRANCH_NODE_NO_VALUE: u8 = 1;
-const BRANCH_NODE_WITH_VALUE: u8 = 2;
-const EXTENSION_NODE: u8 = 3;
-
 #[derive(Debug, Eq, PartialEq)]
 struct RcTrieNode {
     data: RawTrieNode,
     rc: u32,
 }
 
-fn decode_children(cursor: &mut Cursor<&[u8]>) -> Result<[Option<CryptoHash>; 16], std::io::Error> {
-    let mut children: [Option<CryptoHash>; 16] = Default::default();
-    let bitmap = cursor.read_u16::<LittleEndian>()?;
-    let mut pos = 1;
-    for child in &mut children {
-        if bitmap & pos != 0 {
-            let mut arr = vec![0; 32];
-            cursor.read_exact(&mut arr)?;
-            *child = Some(CryptoHash::new(&arr));
-        }
-        pos <<= 1;
-    }
-    Ok(children)
-}
-
-impl RawTrieNode {
-    fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
-        let mut cursor = Cursor::new(Vec::new());
-        match &self {
-            RawTrieNode::Leaf(key, value) => {
-                cursor.write_u8(LEAF_NODE)?;
-                cursor.write_u32::<LittleEndian>(key.len() as u32)?;
-                cursor.write_all(&key)?;
-                cursor.write_u32::<LittleEndian>(value.len() as u32)?;
-                cursor.write_all(&value)?;
-            }
-            RawTrieNode::Branch(children, value) => {
-                if let Some(bytes) = value {
-                    cursor.write_u8(BRANCH_NODE_WITH_VALUE)?;
-                    cursor.write_u32::<LittleEndian>(bytes.len() as u32)?;
-                    cursor.write_all(&bytes)?;
-                } else {
-                    cursor.write_u8(BRANCH_NODE_NO_VALUE)?;
-                }
-                let mut bitmap: u16 = 0;
-                let mut pos: u16 = 1;
-                for child in children.iter() {
-                    if child.is_some() {
-                        bitmap |= pos
-                    }
-                    pos <<= 1;
-                }
-                cursor.write_u16::<LittleEndian>(bitmap)?;
-                for child in children.iter() {
-                    if let Some(hash) = child {
-                        cursor.write_all(hash.as_ref())?;
-                    }
-                }
-            }
-            RawTrieNode::Extension(key, child) => {
-                cursor.write_u8(EXTENSION_NODE)?;
-                cursor.write_u32::<LittleEndian>(key.len() as u32)?;
-                cursor.write_all(&key)?;
-                cursor.write_all(child.as_ref())?;
-            }
-        }
-        Ok(cursor.into_inner())
-    }
-
-    fn decode(bytes: &[u8]) -> Result<Self, std::io::Error> {
-        let mut cursor = Cursor::new(bytes);
-        match cursor.read_u8()? {
-            LEAF_NODE => {
-                let key_length = cursor.read_u32::<LittleEndian>()?;
-                let mut key = vec![0; key_length as usize];
-                cursor.read_exact(&mut key)?;
-                let value_length = cursor.read_u32::<LittleEndian>()?;
-                let mut value = vec![0; value_length as usize];
-                cursor.read_exact(&mut value)?;
-                Ok(RawTrieNode::Leaf(key, value))
-            }
-            BRANCH_NODE_NO_VALUE => {
-                let children = decode_children(&mut cursor)?;
-                Ok(RawTrieNode::Branch(children, None))
-            }
-            BRANCH_NODE_WITH_VALUE => {
-                let value_length = cursor.read_u32::<LittleEndian>()?;
-                let mut value = vec![0; value_length as usize];
-                cursor.read_exact(&mut value)?;
-                let children = decode_children(&mut cursor)?;
-                Ok(RawTrieNode::Branch(children, Some(value)))
-            }
-            EXTENSION_NODE => {
-                let key_length = cursor.read_u32::<LittleEndian>()?;
-                let mut key = vec![0; key_length as usize];
-                cursor.read_exact(&mut key)?;
-                let mut child = vec![0; 32];
-                cursor.read_exact(&mut child)?;
-                Ok(RawTrieNode::Extension(key, CryptoHash::new(&child)))
-            }
-            _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "Wrong type")),
-        }
-    }
-}
-
 impl RcTrieNode {
     fn encode(data: &Vec<u8>, rc: u32) -> Result<Vec<u8>, std::io::Error> {
         let mut cursor = Cursor::new(Vec::new());
@@ -172,22 +70,52 @@ impl RcTrieNode {
 
     fn decode(bytes: &Vec<u8>) -> Result<(RawTrieNode, u32), std::io::Error> {
         let node = RawTrieNode::decode(&bytes[..bytes.len() - 4])?;
-        let mut cursor = Cursor::new(&bytes[bytes.len() - 4..]);
-        let rc = cursor.read_u32::<LittleEndian>()?;
+        let rc = RcTrieNode::decode_rc(bytes)?;
         Ok((node, rc))
     }
+
+    fn decode_rc(bytes: &[u8]) -> Result<u32, std::io::Error> {
+        let mut cursor = Cursor::new(&bytes[bytes.len() - 4..]);
+        cursor.read_u32::<LittleEndian>()
+    }
 }
 
 pub struct Trie {
     storage: Arc<RwLock<ShardChainStorage>>,
     null_node: CryptoHash,
+    /// Decoded nodes, shared by all the readers of the trie.
+    cache: TrieCache,
+    /// Held for writing while states are pruned, see `pause_pruning`.
+    pruning: RwLock<()>,
 }
 
 pub type DBChanges = HashMap<Vec<u8>, Option<Vec<u8>>>;
 
 impl Trie {
     pub fn new(storage: Arc<RwLock<ShardChainStorage>>) -> Self {
-        Trie { storage, null_node: Trie::empty_root() }
+        Trie::with_cache_size(storage, DEFAULT_CACHE_SIZE)
+    }
+
+    /// Creates the trie that caches up to `cache_size` decoded nodes.
+    pub fn with_cache_size(storage: Arc<RwLock<ShardChainStorage>>, cache_size: usize) -> Self {
+        Trie {
+            storage,
+            null_node: Trie::empty_root(),
+            cache: TrieCache::new(cache_size),
+            pruning: RwLock::new(()),
+        }
+    }
+
+    /// Keeps the states from being pruned while the guard is alive. The changes computed by
+    /// `update` only reference the nodes that were stored at the time, so the guard has to be held
+    /// from `update` until the changes are applied, otherwise pruning could remove these nodes in
+    /// between.
+    pub fn pause_pruning(&self) -> RwLockReadGuard<()> {
+        self.pruning.read().expect(POISONED_LOCK_ERR)
+    }
+
+    pub fn cache_stats(&self) -> TrieCacheStats {
+        self.cache.stats()
     }
 
     pub fn empty_root() -> CryptoHash {
@@ -198,9 +126,20 @@ impl Trie {
         if *hash == self.null_node {
             return Ok(TrieNode::Empty);
         }
+        self.retrieve_raw_node(hash).map(TrieNode::new)
+    }
+
+    /// Reads the node from the cache, falling back to the storage.
+    fn retrieve_raw_node(&self, hash: &CryptoHash) -> Result<RawTrieNode, String> {
+        if let Some(node) = self.cache.get(hash) {
+            return Ok(node);
+        }
         if let Ok(Some(bytes)) = self.storage.read().expect(POISONED_LOCK_ERR).get_state(hash) {
             match RcTrieNode::decode(&bytes) {
-                Ok((value, _)) => Ok(TrieNode::new(value)),
+                Ok((node, _)) => {
+                    self.cache.insert(*hash, node.clone());
+                    Ok(node)
+                }
                 Err(_) => Err(format!("Failed to decode node {}", hash)),
             }
         } else {
@@ -208,19 +147,24 @@ impl Trie {
         }
     }
 
-    fn lookup(&self, root: &CryptoHash, mut key: NibbleSlice) -> Result<Option<Vec<u8>>, String> {
+    /// Looks up the key, recording serialized nodes on the path into `proof` if it is given.
+    fn lookup(
+        &self,
+        root: &CryptoHash,
+        mut key: NibbleSlice,
+        mut proof: Option<&mut Vec<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>, String> {
         let mut hash = *root;
 
         loop {
             if hash == self.null_node {
                 return Ok(None);
             }
-            let node = match self.storage.read().expect(POISONED_LOCK_ERR).get_state(&hash) {
-                Ok(Some(bytes)) => RcTrieNode::decode(&bytes)
-                    .map(|trie_node| trie_node.0)
-                    .map_err(|_| "Failed to decode node".to_string())?,
-                _ => return Err(format!("Node {} not found in storage", hash)),
-            };
+            let node = self.retrieve_raw_node(&hash)?;
+            if let Some(ref mut proof) = proof {
+                // The encoding is canonical, so it matches the data covered by the node hash.
+                proof.push(node.encode().map_err(|_| "Failed to encode node".to_string())?);
+            }
 
             match node {
                 RawTrieNode::Leaf(existing_key, value) => {
@@ -258,7 +202,7 @@ impl Trie {
 
     pub fn get(&self, root: &CryptoHash, key: &[u8]) -> Option<Vec<u8>> {
         let key = NibbleSlice::new(key);
-        match self.lookup(root, key) {
+        match self.lookup(root, key, None) {
             Ok(value) => value,
             Err(err) => {
                 println!("Failed to lookup key={:?} for root={:?}: {}", key, root, err);
@@ -267,6 +211,33 @@ impl Trie {
         }
     }
 
+    /// Returns the value together with the nodes on its lookup path. The proof also works for
+    /// missing keys, showing that the key is not in the trie under the given root.
+    /// Use `primitives::trie_proof::verify_trie_proof` to check it.
+    pub fn get_with_proof(
+        &self,
+        root: &CryptoHash,
+        key: &[u8],
+    ) -> Result<(Option<Vec<u8>>, TrieProof), String> {
+        let mut nodes = vec![];
+        let value = self.lookup(root, NibbleSlice::new(key), Some(&mut nodes))?;
+        Ok((value, TrieProof { nodes }))
+    }
+
+    /// Returns serialized nodes with the given hashes, skipping the ones that are not found.
+    /// Used to serve the state to the nodes that sync it with `TrieSync`.
+    pub fn get_state_nodes(&self, hashes: &[CryptoHash]) -> Vec<Vec<u8>> {
+        let storage = self.storage.read().expect(POISONED_LOCK_ERR);
+        hashes
+            .iter()
+            .filter_map(|hash| match storage.get_state(hash) {
+                // Strip the reference count, it is local to our storage.
+                Ok(Some(bytes)) => Some(bytes[..bytes.len() - 4].to_vec()),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn insert(
         &self,
         node: TrieNode,
@@ -388,26 +359,18 @@ impl Trie {
     fn delete(
         &self,
         node: TrieNode,
-        hash: Option<CryptoHash>,
         partial: NibbleSlice,
-        death_row: &mut HashMap<CryptoHash, u32>,
     ) -> Result<Option<TrieNode>, String> {
         match node {
             TrieNode::Empty => Ok(Some(node)),
             TrieNode::Leaf(key, value) => {
                 if NibbleSlice::from_encoded(&key).0 == partial {
-                    if let Some(hash) = hash {
-                        *death_row.entry(hash).or_insert(0) += 1
-                    }
                     Ok(None)
                 } else {
                     Ok(Some(TrieNode::Leaf(key, value)))
                 }
             }
             TrieNode::Branch(mut children, value) => {
-                if let Some(hash) = hash {
-                    *death_row.entry(hash).or_insert(0) += 1;
-                }
                 if partial.is_empty() {
                     if children.iter().filter(|&x| x.is_some()).count() == 0 {
                         Ok(None)
@@ -419,16 +382,9 @@ impl Trie {
                     if let Some(node_or_hash) = children[idx].take() {
                         let new_node = match node_or_hash {
                             NodeHandle::Hash(hash) => {
-                                self.delete(
-                                    self.retrieve_node(&hash)?,
-                                    Some(hash),
-                                    partial.mid(1),
-                                    death_row,
-                                )?
-                            },
-                            NodeHandle::InMemory(node) => {
-                                self.delete(*node, None, partial.mid(1), death_row)?
+                                self.delete(self.retrieve_node(&hash)?, partial.mid(1))?
                             }
+                            NodeHandle::InMemory(node) => self.delete(*node, partial.mid(1))?,
                         };
                         children[idx] = match new_node {
                             Some(node) => Some(NodeHandle::InMemory(Box::new(node))),
@@ -451,23 +407,17 @@ impl Trie {
                 }
             }
             TrieNode::Extension(key, child) => {
-                if let Some(hash) = hash {
-                    *death_row.entry(hash).or_insert(0) += 1
-                }
                 let (common_prefix, existing_len) = {
                     let existing_key = NibbleSlice::from_encoded(&key).0;
                     (existing_key.common_prefix(&partial), existing_key.len())
                 };
                 if common_prefix == existing_len {
                     let result = match child {
-                        NodeHandle::Hash(hash) => self.delete(
-                            self.retrieve_node(&hash)?,
-                            Some(hash),
-                            partial.mid(existing_len),
-                            death_row,
-                        )?,
+                        NodeHandle::Hash(hash) => {
+                            self.delete(self.retrieve_node(&hash)?, partial.mid(existing_len))?
+                        }
                         NodeHandle::InMemory(node) => {
-                            self.delete(*node, None, partial.mid(existing_len), death_row)?
+                            self.delete(*node, partial.mid(existing_len))?
                         }
                     };
                     // TODO: fix tree if the child is not a branch.
@@ -487,7 +437,7 @@ impl Trie {
     fn flatten_nodes(
         &self,
         node: TrieNode,
-        nodes: &mut HashMap<CryptoHash, (Vec<u8>, u32)>,
+        nodes: &mut HashMap<CryptoHash, Vec<u8>>,
     ) -> CryptoHash {
         let rc_node = match node {
             TrieNode::Empty => return self.null_node,
@@ -515,50 +465,85 @@ impl Trie {
         };
         let data = rc_node.encode().expect("Failed to serialize");
         let key = hash(&data);
-        let entry = nodes.entry(key).or_insert((data, 0));
-        entry.1 += 1;
+        nodes.insert(key, data);
         key
     }
 
+    /// Records a new reference to the node. The reference count of a node is the number of
+    /// stored nodes referencing it plus the number of state roots pointing at it, so the
+    /// children of a node are only referenced when the node itself is not stored yet.
+    fn add_reference(
+        &self,
+        node_hash: CryptoHash,
+        new_nodes: &HashMap<CryptoHash, Vec<u8>>,
+        references: &mut HashMap<CryptoHash, (Vec<u8>, u32)>,
+    ) -> Result<(), String> {
+        if node_hash == self.null_node {
+            return Ok(());
+        }
+        if let Some(entry) = references.get_mut(&node_hash) {
+            entry.1 += 1;
+            return Ok(());
+        }
+        let stored = self
+            .storage
+            .read()
+            .expect(POISONED_LOCK_ERR)
+            .get_state(&node_hash)
+            .map_err(|e| format!("Failed to read node {}: {}", node_hash, e))?;
+        if let Some(bytes) = stored {
+            references.insert(node_hash, (bytes[..bytes.len() - 4].to_vec(), 1));
+            return Ok(());
+        }
+        let data = new_nodes
+            .get(&node_hash)
+            .ok_or_else(|| format!("Node {} not found in storage", node_hash))?
+            .clone();
+        let node =
+            RawTrieNode::decode(&data).map_err(|_| format!("Failed to decode node {}", node_hash))?;
+        references.insert(node_hash, (data, 1));
+        for child in node.children() {
+            self.add_reference(child, new_nodes, references)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the changes to the trie under `root`. The returned changes store the new nodes
+    /// and hold the number of references added to every node, see `apply_changes`. The nodes
+    /// of the old root are kept until it is released with `release_root`. Pruning must be paused
+    /// until the changes are applied, see `pause_pruning`.
     pub fn update<I>(&self, root: &CryptoHash, changes: I) -> (DBChanges, CryptoHash)
     where
         I: Iterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
     {
-        let mut death_row: HashMap<CryptoHash, u32> = HashMap::default();
-        let mut last_root = Some(*root);
         let mut root_node = self.retrieve_node(root).expect("Root not found");
         for (key, value) in changes {
             let key = NibbleSlice::new(&key);
             match value {
                 Some(arr) => {
                     root_node = self.insert(root_node, key, arr).expect("Failed to insert");
-                    last_root = None;
                 }
                 None => {
-                    root_node = match self
-                        .delete(root_node, last_root, key, &mut death_row)
-                        .expect("Failed to remove element")
+                    root_node = match self.delete(root_node, key).expect("Failed to remove element")
                     {
                         Some(value) => value,
                         None => TrieNode::Empty,
                     };
-                    last_root = None;
                 }
             }
         }
-        let mut db_changes = HashMap::default();
 
         let mut nodes = HashMap::default();
-        // TODO: The reference counting doesn't account for the number of existing nodes in
-        // storage that were not touched by this update.
         let new_root = self.flatten_nodes(root_node, &mut nodes);
-        for (key, (value, rc)) in nodes.drain() {
-            let bytes = RcTrieNode::encode(&value, rc).expect("Failed to serialize");
-            db_changes.insert(key.as_ref().to_vec(), Some(bytes));
-        }
-        for (hash, _) in death_row {
-            db_changes.insert(hash.as_ref().to_vec(), None);
-        }
+        let mut references = HashMap::default();
+        self.add_reference(new_root, &nodes, &mut references).expect("Failed to reference nodes");
+        let db_changes = references
+            .into_iter()
+            .map(|(key, (value, rc))| {
+                let bytes = RcTrieNode::encode(&value, rc).expect("Failed to serialize");
+                (key.as_ref().to_vec(), Some(bytes))
+            })
+            .collect();
         (db_changes, new_root)
     }
 
@@ -566,9 +551,139 @@ impl Trie {
         TrieIterator::new(self, root)
     }
 
-    #[inline]
+    /// Stores the changes. The reference counts in the changes are added to the counts of the
+    /// nodes that are already stored, so changes computed on top of the same state can be
+    /// applied in any order. Databases of schema version 0 counted the references differently
+    /// and are refused by `check_schema_version`, so all the stored counts follow these rules.
     pub fn apply_changes(&self, changes: DBChanges) -> std::io::Result<()> {
-        self.storage.read().expect(POISONED_LOCK_ERR).apply_state_updates(&changes)
+        let storage = self.storage.write().expect(POISONED_LOCK_ERR);
+        let merged = self.merge_changes(&storage, changes)?;
+        storage.apply_state_updates(&merged)
+    }
+
+    /// Stores the changes of the state of a block like `apply_changes`, and records its root,
+    /// so that `prune_states` releases it whether the block ends up on the best chain or not.
+    pub fn apply_block_changes(
+        &self,
+        changes: DBChanges,
+        index: BlockIndex,
+        root: &CryptoHash,
+    ) -> std::io::Result<()> {
+        let storage = self.storage.write().expect(POISONED_LOCK_ERR);
+        let merged = self.merge_changes(&storage, changes)?;
+        storage.apply_block_state_updates(&merged, index, root)
+    }
+
+    fn merge_changes(
+        &self,
+        storage: &ShardChainStorage,
+        changes: DBChanges,
+    ) -> std::io::Result<DBChanges> {
+        let mut merged = DBChanges::default();
+        for (key, value) in changes {
+            let value = match value {
+                Some(bytes) => match storage.get_state(&CryptoHash::new(&key))? {
+                    Some(stored) => {
+                        let rc = RcTrieNode::decode_rc(&stored)? + RcTrieNode::decode_rc(&bytes)?;
+                        Some(RcTrieNode::encode(&stored[..stored.len() - 4].to_vec(), rc)?)
+                    }
+                    None => Some(bytes),
+                },
+                None => {
+                    self.cache.remove(&CryptoHash::new(&key));
+                    None
+                }
+            };
+            merged.insert(key, value);
+        }
+        Ok(merged)
+    }
+
+    /// Releases the reference of a state root that is no longer needed. Nodes that are left
+    /// without references are removed, releasing their children in turn. Returns the number of
+    /// removed nodes.
+    pub fn release_root(&self, root: &CryptoHash) -> Result<usize, String> {
+        let _pruning = self.pruning.write().expect(POISONED_LOCK_ERR);
+        let storage = self.storage.write().expect(POISONED_LOCK_ERR);
+        let (changes, removed) = self.release_roots(&storage, &[*root])?;
+        storage
+            .apply_state_updates(&changes)
+            .map_err(|e| format!("Failed to release root {}: {}", root, e))?;
+        Ok(removed)
+    }
+
+    /// Releases the states of the blocks with the given index, recorded by `apply_block_changes`,
+    /// and marks the index as pruned in the same transaction. `fallback_root` is released for the
+    /// index if there are no recorded states, e.g. for the blocks stored before the states were
+    /// recorded. Returns the number of removed nodes.
+    pub fn prune_states(
+        &self,
+        index: BlockIndex,
+        fallback_root: Option<CryptoHash>,
+    ) -> Result<usize, String> {
+        let _pruning = self.pruning.write().expect(POISONED_LOCK_ERR);
+        let storage = self.storage.write().expect(POISONED_LOCK_ERR);
+        let mut roots = storage
+            .state_roots(index)
+            .map_err(|e| format!("Failed to read the states of block {}: {}", index, e))?;
+        if roots.is_empty() {
+            roots.extend(fallback_root);
+        }
+        let (changes, removed) = self.release_roots(&storage, &roots)?;
+        storage
+            .apply_pruned_state_updates(&changes, index)
+            .map_err(|e| format!("Failed to prune the states of block {}: {}", index, e))?;
+        Ok(removed)
+    }
+
+    /// Changes that release the references of the roots, and the number of removed nodes.
+    fn release_roots(
+        &self,
+        storage: &ShardChainStorage,
+        roots: &[CryptoHash],
+    ) -> Result<(DBChanges, usize), String> {
+        let mut released: HashMap<CryptoHash, Option<(Vec<u8>, u32)>> = HashMap::default();
+        let mut stack = roots.to_vec();
+        let mut removed = 0;
+        while let Some(node_hash) = stack.pop() {
+            if node_hash == self.null_node {
+                continue;
+            }
+            let (data, rc) = match released.get(&node_hash) {
+                Some(Some(entry)) => entry.clone(),
+                Some(None) => continue,
+                None => match storage.get_state(&node_hash) {
+                    Ok(Some(bytes)) => {
+                        let rc = RcTrieNode::decode_rc(&bytes)
+                            .map_err(|_| format!("Failed to decode node {}", node_hash))?;
+                        (bytes[..bytes.len() - 4].to_vec(), rc)
+                    }
+                    // The state was never stored, e.g. it is older than the synced state.
+                    Ok(None) => continue,
+                    Err(e) => return Err(format!("Failed to read node {}: {}", node_hash, e)),
+                },
+            };
+            if rc > 1 {
+                released.insert(node_hash, Some((data, rc - 1)));
+                continue;
+            }
+            let node = RawTrieNode::decode(&data)
+                .map_err(|_| format!("Failed to decode node {}", node_hash))?;
+            stack.extend(node.children());
+            self.cache.remove(&node_hash);
+            released.insert(node_hash, None);
+            removed += 1;
+        }
+        let changes = released
+            .into_iter()
+            .map(|(node_hash, value)| {
+                let value = value.map(|(data, rc)| {
+                    RcTrieNode::encode(&data, rc).expect("Failed to serialize")
+                });
+                (node_hash.as_ref().to_vec(), value)
+            })
+            .collect();
+        Ok((changes, removed))
     }
 }
 
@@ -798,6 +913,7 @@ impl<'a> Iterator for TrieIterator<'a> {
 mod tests {
     use super::*;
     use crate::test_utils::create_trie;
+    use primitives::trie_proof::verify_trie_proof;
 
     type TrieChanges = Vec<(Vec<u8>, Option<Vec<u8>>)>;
 
@@ -823,26 +939,6 @@ mod tests {
         root
     }
 
-    #[test]
-    fn test_encode_decode() {
-        let node = RawTrieNode::Leaf(vec![1, 2, 3], vec![123, 245, 255]);
-        let buf = node.encode().expect("Failed to serialize");
-        let new_node = RawTrieNode::decode(&buf).expect("Failed to deserialize");
-        assert_eq!(node, new_node);
-
-        let mut children: [Option<CryptoHash>; 16] = Default::default();
-        children[3] = Some(CryptoHash::default());
-        let node = RawTrieNode::Branch(children, Some(vec![123, 245, 255]));
-        let buf = node.encode().expect("Failed to serialize");
-        let new_node = RawTrieNode::decode(&buf).expect("Failed to deserialize");
-        assert_eq!(node, new_node);
-
-        let node = RawTrieNode::Extension(vec![123, 245, 255], CryptoHash::default());
-        let buf = node.encode().expect("Failed to serialize");
-        let new_node = RawTrieNode::decode(&buf).expect("Failed to deserialize");
-        assert_eq!(node, new_node);
-    }
-
     #[test]
     fn test_basic_trie() {
         let trie = create_trie();
@@ -862,6 +958,38 @@ mod tests {
 //        assert_eq!(storage.iter(Some(0)).fold(0, |acc, _| acc + 1), 0);
     }
 
+    #[test]
+    fn test_trie_get_with_proof() {
+        let trie = create_trie();
+        let changes = vec![
+            (b"doge".to_vec(), Some(b"coin".to_vec())),
+            (b"docu".to_vec(), Some(b"value".to_vec())),
+            (b"do".to_vec(), Some(b"verb".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+            (b"dog".to_vec(), Some(b"puppy".to_vec())),
+            (b"h".to_vec(), Some(b"value".to_vec())),
+        ];
+        let root = test_populate_trie(&trie, &Trie::empty_root(), changes.clone());
+        for (key, value) in changes {
+            let (found, proof) = trie.get_with_proof(&root, &key).unwrap();
+            assert_eq!(found, value);
+            let value = value.unwrap();
+            assert!(verify_trie_proof(&root, &key, Some(&value), &proof));
+            assert!(!verify_trie_proof(&root, &key, Some(b"other"), &proof));
+            assert!(!verify_trie_proof(&root, &key, None, &proof));
+            assert!(!verify_trie_proof(&Trie::empty_root(), &key, Some(&value), &proof));
+        }
+        for key in vec![b"dogs".to_vec(), b"d".to_vec(), b"cat".to_vec(), b"hors".to_vec()] {
+            let (found, proof) = trie.get_with_proof(&root, &key).unwrap();
+            assert_eq!(found, None);
+            assert!(verify_trie_proof(&root, &key, None, &proof));
+            assert!(!verify_trie_proof(&root, &key, Some(b"puppy"), &proof));
+        }
+        let (found, proof) = trie.get_with_proof(&Trie::empty_root(), b"dog").unwrap();
+        assert_eq!(found, None);
+        assert!(verify_trie_proof(&Trie::empty_root(), b"dog", None, &proof));
+    }
+
     #[test]
     fn test_trie_iter() {
         let trie = create_trie();
@@ -908,6 +1036,53 @@ mod tests {
         test_populate_trie(&trie, &Trie::empty_root(), changes);
     }
 
+    #[test]
+    fn test_trie_cache_stats() {
+        let trie = create_trie();
+        let changes = vec![
+            (b"doge".to_vec(), Some(b"coin".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+        ];
+        let root = test_populate_trie(&trie, &Trie::empty_root(), changes);
+        let misses = trie.cache_stats().misses;
+        let hits = trie.cache_stats().hits;
+        assert_eq!(trie.get(&root, b"doge"), Some(b"coin".to_vec()));
+        // All the nodes on the path were read by the checks in `test_populate_trie`.
+        assert_eq!(trie.cache_stats().misses, misses);
+        assert!(trie.cache_stats().hits > hits);
+    }
+
+    #[test]
+    fn test_release_root() {
+        let trie = create_trie();
+        let changes = vec![
+            (b"doge".to_vec(), Some(b"coin".to_vec())),
+            (b"docu".to_vec(), Some(b"value".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+        ];
+        let old_root = test_populate_trie(&trie, &Trie::empty_root(), changes.clone());
+        let new_root = test_populate_trie(
+            &trie,
+            &old_root,
+            vec![(b"doge".to_vec(), Some(b"dogecoin".to_vec()))],
+        );
+
+        assert_eq!(trie.get(&old_root, b"doge"), Some(b"coin".to_vec()));
+
+        // Only the nodes on the path to the changed leaf are not shared with the new root.
+        assert!(trie.release_root(&old_root).unwrap() > 0);
+        assert!(trie.get_state_nodes(&[old_root]).is_empty());
+        // Removed nodes are dropped from the cache as well.
+        assert_eq!(trie.get(&old_root, b"doge"), None);
+        assert_eq!(trie.get(&new_root, b"doge"), Some(b"dogecoin".to_vec()));
+        assert_eq!(trie.get(&new_root, b"horse"), Some(b"stallion".to_vec()));
+
+        // Releasing the last root removes all the nodes.
+        assert!(trie.release_root(&new_root).unwrap() > 0);
+        assert_eq!(trie.storage.read().unwrap().get_state(&new_root).unwrap(), None);
+        assert_eq!(trie.release_root(&new_root), Ok(0));
+    }
+
     #[test]
     fn test_trie_iter_seek_stop_at_extension() {
         let trie = create_trie();
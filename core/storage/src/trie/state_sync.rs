@@ -0,0 +1,245 @@
+//! Downloads the trie under the given root from other nodes in chunks. Trie nodes are
+//! content-addressed, so every received node is checked against its hash, and only the nodes
+//! that are referenced by already verified nodes are accepted.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use primitives::hash::{hash, CryptoHash};
+
+use super::{DBChanges, RawTrieNode, RcTrieNode, Trie, POISONED_LOCK_ERR};
+
+pub struct TrieSync {
+    trie: Arc<Trie>,
+    root: CryptoHash,
+    /// Nodes referenced by the verified nodes that were not requested yet.
+    missing: VecDeque<CryptoHash>,
+    /// Nodes that were requested, but not received yet.
+    requested: HashSet<CryptoHash>,
+    /// Number of references to the nodes that are either missing or requested.
+    pending_rc: HashMap<CryptoHash, u32>,
+}
+
+impl TrieSync {
+    /// Starts the download of the trie under `root`. If the root is already in the storage,
+    /// there is nothing to download.
+    pub fn new(trie: Arc<Trie>, root: CryptoHash) -> Self {
+        let mut result = TrieSync {
+            trie,
+            root,
+            missing: VecDeque::new(),
+            requested: HashSet::new(),
+            pending_rc: HashMap::new(),
+        };
+        let has_root = root == result.trie.null_node
+            || result.stored_node(&root).ok().and_then(|n| n).is_some();
+        if !has_root {
+            // Same as after `Trie::update`, the root is referenced once.
+            result.pending_rc.insert(root, 1);
+            result.missing.push_back(root);
+        }
+        result
+    }
+
+    pub fn root(&self) -> CryptoHash {
+        self.root
+    }
+
+    /// Whether all the nodes under the root were received.
+    pub fn is_done(&self) -> bool {
+        self.missing.is_empty() && self.requested.is_empty()
+    }
+
+    /// Returns up to `max` hashes of the nodes that should be requested next.
+    pub fn next_request(&mut self, max: usize) -> Vec<CryptoHash> {
+        let count = std::cmp::min(max, self.missing.len());
+        let hashes: Vec<_> = self.missing.drain(..count).collect();
+        self.requested.extend(hashes.iter().cloned());
+        hashes
+    }
+
+    /// Puts the requested nodes back to be requested again, e.g. from a different peer.
+    pub fn retry(&mut self) {
+        self.missing.extend(self.requested.drain());
+    }
+
+    /// Verifies the received nodes and returns the changes that store them with their reference
+    /// counts. The changes should be applied before the next chunk is processed. Requested nodes
+    /// that were not received are requested again, and if any of the nodes is invalid the whole
+    /// chunk is rejected.
+    pub fn on_nodes(&mut self, nodes: Vec<Vec<u8>>) -> Result<DBChanges, String> {
+        let mut verified = vec![];
+        let mut received = HashSet::new();
+        for data in nodes {
+            let node_hash = hash(&data);
+            if !self.requested.contains(&node_hash) || !received.insert(node_hash) {
+                self.retry();
+                return Err(format!("Received node {} that was not requested", node_hash));
+            }
+            match RawTrieNode::decode(&data) {
+                Ok(node) => verified.push((node_hash, data, node)),
+                Err(_) => {
+                    self.retry();
+                    return Err(format!("Failed to decode node {}", node_hash));
+                }
+            }
+        }
+
+        let mut changes = DBChanges::default();
+        for (node_hash, data, node) in verified {
+            self.requested.remove(&node_hash);
+            let rc = self.pending_rc.remove(&node_hash).expect("Requested nodes are referenced");
+            let bytes = RcTrieNode::encode(&data, rc).expect("Failed to serialize");
+            changes.insert(node_hash.as_ref().to_vec(), Some(bytes));
+            match node {
+                RawTrieNode::Leaf(_, _) => {}
+                RawTrieNode::Branch(children, _) => {
+                    for child in children.iter().flatten() {
+                        self.add_reference(*child, &mut changes)?;
+                    }
+                }
+                RawTrieNode::Extension(_, child) => self.add_reference(child, &mut changes)?,
+            }
+        }
+        self.retry();
+        Ok(changes)
+    }
+
+    /// Records a reference to the child of a verified node. The changes hold the number of
+    /// added references, see `Trie::apply_changes`, so children that we already have only get
+    /// their reference count increased, the others are scheduled for download.
+    fn add_reference(&mut self, child: CryptoHash, changes: &mut DBChanges) -> Result<(), String> {
+        if let Some(rc) = self.pending_rc.get_mut(&child) {
+            *rc += 1;
+            return Ok(());
+        }
+        let reference = match changes.get(child.as_ref()) {
+            Some(Some(bytes)) => {
+                let rc = RcTrieNode::decode_rc(bytes)
+                    .map_err(|_| format!("Failed to decode node {}", child))?;
+                Some((bytes[..bytes.len() - 4].to_vec(), rc + 1))
+            }
+            _ => self.stored_node(&child)?.map(|data| (data, 1)),
+        };
+        match reference {
+            Some((data, rc)) => {
+                let bytes = RcTrieNode::encode(&data, rc).expect("Failed to serialize");
+                changes.insert(child.as_ref().to_vec(), Some(bytes));
+            }
+            None => {
+                self.pending_rc.insert(child, 1);
+                self.missing.push_back(child);
+            }
+        }
+        Ok(())
+    }
+
+    /// Data of the node if it is already in the storage.
+    fn stored_node(&self, node_hash: &CryptoHash) -> Result<Option<Vec<u8>>, String> {
+        match self.trie.storage.read().expect(POISONED_LOCK_ERR).get_state(node_hash) {
+            Ok(Some(bytes)) => Ok(Some(bytes[..bytes.len() - 4].to_vec())),
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("Failed to read node {}: {}", node_hash, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::create_trie;
+
+    fn populate(trie: &Trie, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> CryptoHash {
+        let (db_changes, root) = trie.update(&Trie::empty_root(), changes.into_iter());
+        trie.apply_changes(db_changes).unwrap();
+        root
+    }
+
+    fn test_changes() -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        vec![
+            (b"doge".to_vec(), Some(b"coin".to_vec())),
+            (b"docu".to_vec(), Some(b"value".to_vec())),
+            (b"do".to_vec(), Some(b"verb".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+            (b"dog".to_vec(), Some(b"puppy".to_vec())),
+            (b"h".to_vec(), Some(b"value".to_vec())),
+        ]
+    }
+
+    #[test]
+    fn test_trie_sync() {
+        let source = create_trie();
+        let root = populate(&source, test_changes());
+        let target = create_trie();
+        let mut sync = TrieSync::new(target.clone(), root);
+        let mut synced = vec![];
+        while !sync.is_done() {
+            let hashes = sync.next_request(2);
+            assert!(!hashes.is_empty());
+            let changes = sync.on_nodes(source.get_state_nodes(&hashes)).unwrap();
+            target.apply_changes(changes).unwrap();
+            synced.extend(hashes);
+        }
+        for (key, value) in test_changes() {
+            assert_eq!(target.get(&root, &key), value);
+        }
+        // Nodes are stored with the same reference counts as in the source.
+        for node_hash in synced {
+            let storage = target.storage.read().unwrap();
+            let expected = source.storage.read().unwrap().get_state(&node_hash).unwrap();
+            assert_eq!(storage.get_state(&node_hash).unwrap(), expected);
+        }
+        // Nothing to download once the state is present.
+        assert!(TrieSync::new(target, root).is_done());
+    }
+
+    #[test]
+    fn test_trie_sync_rejects_unknown_nodes() {
+        let source = create_trie();
+        let root = populate(&source, test_changes());
+        let other = create_trie();
+        let other_root = populate(&other, vec![(b"cat".to_vec(), Some(b"meow".to_vec()))]);
+        let target = create_trie();
+        let mut sync = TrieSync::new(target.clone(), root);
+        let hashes = sync.next_request(10);
+        assert_eq!(hashes, vec![root]);
+        assert!(sync.on_nodes(other.get_state_nodes(&[other_root])).is_err());
+        // The root is requested again after the invalid response.
+        assert_eq!(sync.next_request(10), vec![root]);
+        // Missing nodes are requested again.
+        assert!(sync.on_nodes(vec![]).unwrap().is_empty());
+        assert_eq!(sync.next_request(10), vec![root]);
+        let changes = sync.on_nodes(source.get_state_nodes(&[root])).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(!sync.is_done());
+    }
+
+    #[test]
+    fn test_trie_sync_reuses_present_nodes() {
+        let target = create_trie();
+        populate(&target, test_changes());
+        // All the keys of the old state start with nibble 6, so its subtree is shared.
+        let mut changes = test_changes();
+        changes.push((b"zebra".to_vec(), Some(b"stripes".to_vec())));
+        let source = create_trie();
+        let root = populate(&source, changes.clone());
+        let mut sync = TrieSync::new(target.clone(), root);
+        let mut synced = vec![];
+        while !sync.is_done() {
+            let hashes = sync.next_request(10);
+            let changes = sync.on_nodes(source.get_state_nodes(&hashes)).unwrap();
+            target.apply_changes(changes).unwrap();
+            synced.extend(hashes);
+        }
+        // Only the new root and the new leaf are downloaded.
+        assert_eq!(synced.len(), 2);
+        for (key, value) in changes {
+            assert_eq!(target.get(&root, &key), value);
+        }
+        let shared = match RawTrieNode::decode(&source.get_state_nodes(&[root])[0]).unwrap() {
+            RawTrieNode::Branch(children, _) => children[6].unwrap(),
+            _ => panic!("Expected the root to be a branch"),
+        };
+        let bytes = target.storage.read().unwrap().get_state(&shared).unwrap().unwrap();
+        assert_eq!(RcTrieNode::decode(&bytes).unwrap().1, 2);
+    }
+}
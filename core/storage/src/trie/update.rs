@@ -1,4 +1,5 @@
 use kvdb::DBValue;
+use primitives::trie_proof::TrieProof;
 use primitives::types::MerkleHash;
 use std::collections::BTreeMap;
 use std::iter::Peekable;
@@ -27,6 +28,14 @@ impl TrieUpdate {
             self.trie.get(&self.root, key).map(|x| DBValue::from_slice(&x))
         }
     }
+    /// Reads the value with its merkle proof against `get_root()`. Fails if the key has
+    /// uncommitted changes, since those are not reflected in the trie yet.
+    pub fn get_with_proof(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, TrieProof), String> {
+        if self.prospective.contains_key(key) || self.committed.contains_key(key) {
+            return Err("Cannot create proof for a key with uncommitted changes".to_string());
+        }
+        self.trie.get_with_proof(&self.root, key)
+    }
     pub fn set(&mut self, key: &[u8], value: &DBValue) {
         self.prospective.insert(key.to_vec(), Some(value.to_vec()));
     }
@@ -0,0 +1,18 @@
+//! Exposes the wasmer revision from Cargo.toml as `WASMER_VERSION`, so that the key of the
+//! on-disk cache of compiled modules always follows the dependency.
+use std::fs;
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    let manifest = fs::read_to_string("Cargo.toml").expect("Failed to read Cargo.toml");
+    let line = manifest
+        .lines()
+        .find(|line| line.trim_start().starts_with("wasmer-runtime"))
+        .expect("wasmer-runtime dependency is missing from Cargo.toml");
+    let rev = line
+        .split("rev")
+        .nth(1)
+        .and_then(|rest| rest.split('"').nth(1))
+        .expect("wasmer-runtime dependency must be pinned to a git rev");
+    println!("cargo:rustc-env=WASMER_VERSION={}", rev);
+}
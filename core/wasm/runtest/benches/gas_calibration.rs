@@ -0,0 +1,84 @@
+//! Calibration of the gas cost table against the test contracts.
+//!
+//! Every benchmark reports the gas used by one call as its throughput, so `cargo bench` prints
+//! the gas burnt per second for each method. With a well calibrated `Config` the throughput is
+//! roughly the same for all methods; a method that burns gas much faster or slower than the
+//! others points at the instruction classes or host functions that are mispriced.
+//!
+//! To recalibrate:
+//!
+//! 1. Run `cargo bench` in `core/wasm/runtest` on an otherwise idle machine and take the median
+//!    gas per second over all methods as the target throughput.
+//! 2. For every method that deviates from the target by more than ~20%, multiply the costs of the
+//!    instruction classes (`OpCosts`) or host functions (`HostCosts`) that dominate it by
+//!    `target / throughput`: `arithmetic` is dominated by `add`, `mul`, `local` and
+//!    `control_flow`, `storage` by the storage host costs and `studio_total_supply` by `load`,
+//!    `store` and `global`. `hashing` and `random` spend most of their time in host functions
+//!    that have no entry in `HostCosts` yet; a low throughput there means one should be added.
+//! 3. Round the new costs to integers, keeping `regular_op_cost` at 1 as the unit, and repeat
+//!    until all methods are within the bound.
+#[macro_use]
+extern crate bencher;
+extern crate primitives;
+extern crate runtest;
+extern crate wasm;
+
+use std::fs;
+
+use bencher::Bencher;
+
+use primitives::types::Gas;
+use runtest::MyExt;
+use wasm::executor::{self, ExecutionOutcome};
+use wasm::types::{Config, RuntimeContext};
+
+const GAS_LIMIT: Gas = 10 * 1024 * 1024;
+
+fn execute(code: &[u8], method_name: &[u8], input_data: &[u8], config: &Config) -> ExecutionOutcome {
+    let context = RuntimeContext::new(
+        0,
+        0,
+        &"alice.near".to_string(),
+        &"bob".to_string(),
+        0,
+        GAS_LIMIT,
+        123,
+        b"yolo".to_vec(),
+    );
+    let mut ext = MyExt::default();
+    executor::execute(code, method_name, input_data, &[], &mut ext, config, &context)
+        .expect("execution failed")
+}
+
+fn bench_method(bench: &mut Bencher, filename: &str, method_name: &[u8], input_data: &[u8]) {
+    let code = fs::read(filename).expect("Unable to read file");
+    let config = Config::default();
+    // The first call compiles the contract, so it is not measured.
+    let outcome = execute(&code, method_name, input_data, &config);
+    assert!(outcome.return_data.is_ok(), "method failed");
+    bench.bytes = outcome.gas_used;
+    bench.iter(|| execute(&code, method_name, input_data, &config));
+}
+
+fn storage(bench: &mut Bencher) {
+    bench_method(bench, "res/wasm_with_mem.wasm", b"run_test", b"");
+}
+
+fn arithmetic(bench: &mut Bencher) {
+    bench_method(bench, "res/wasm_with_mem.wasm", b"sum_with_input", &[10, 0, 0, 0, 30, 0, 0, 0]);
+}
+
+fn hashing(bench: &mut Bencher) {
+    bench_method(bench, "res/wasm_with_mem.wasm", b"hash_given_input", &[7u8; 1024]);
+}
+
+fn random(bench: &mut Bencher) {
+    bench_method(bench, "res/wasm_with_mem.wasm", b"get_random_buf", &[0, 4, 0, 0]);
+}
+
+fn studio_total_supply(bench: &mut Bencher) {
+    bench_method(bench, "res/studio.wasm", b"totalSupply", b"{}");
+}
+
+benchmark_group!(benches, storage, arithmetic, hashing, random, studio_total_supply);
+benchmark_main!(benches);
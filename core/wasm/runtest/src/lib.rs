@@ -7,10 +7,11 @@ use wasm::ext::{External, Result as ExtResult, Error as ExtError};
 extern crate byteorder;
 
 extern crate primitives;
-use primitives::types::{AccountId, PromiseId, ReceiptId, Mana, Balance};
+use primitives::types::{AccountId, PromiseId, ReceiptId, Mana, Balance, BlockIndex};
 
+/// Mock of the external environment for running the test contracts.
 #[derive(Default)]
-struct MyExt {
+pub struct MyExt {
     storage: BTreeMap<Vec<u8>, Vec<u8>>,
     num_receipts: u32,
 }
@@ -80,6 +81,18 @@ impl External for MyExt {
         Ok(PromiseId::Receipt(generate_promise_id(self.num_receipts - 1)))
     }
 
+    fn promise_schedule(
+        &mut self,
+        _account_id: AccountId,
+        _method_name: Vec<u8>,
+        _arguments: Vec<u8>,
+        _mana: Mana,
+        _amount: Balance,
+        _block_index: BlockIndex,
+    ) -> ExtResult<PromiseId> {
+        Err(ExtError::NotImplemented)
+    }
+
     fn promise_then(
         &mut self,
         promise_id: PromiseId,
@@ -109,6 +122,7 @@ mod tests {
     use wasm::executor::{self, ExecutionOutcome};
     use wasm::types::{Error, Config, RuntimeContext, ReturnData};
     use primitives::hash::hash;
+    use primitives::types::Gas;
     
     use super::*;
 
@@ -162,6 +176,8 @@ mod tests {
         tmp
     }
 
+    const GAS_LIMIT: Gas = 10 * 1024 * 1024;
+
     fn runtime_context(
         balance: Balance,
         amount: Balance,
@@ -173,6 +189,7 @@ mod tests {
             &"alice.near".to_string(),
             &"bob".to_string(),
             mana,
+            GAS_LIMIT,
             123,
             b"yolo".to_vec(),
         )
@@ -355,7 +372,10 @@ mod tests {
         ).map(|outcome| outcome.return_data)
         .expect("ok");
 
-        let approximate_expected_gas = Config::default().gas_limit;
+        // The only metered block executed before `gas_left` consists of the two calls of
+        // `near_func_get_gas_left`.
+        let approximate_expected_gas =
+            GAS_LIMIT - 2 * u64::from(Config::default().op_costs.control_flow);
 
         match return_data {
             Ok(ReturnData::Value(output_data)) => {
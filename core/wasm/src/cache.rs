@@ -1,26 +1,205 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
 use cached::SizedCache;
 use wasmer_runtime;
 
 use primitives::traits::Encode;
-use primitives::hash::hash;
+use primitives::hash::{hash, CryptoHash};
 use crate::prepare;
 use crate::types::{Config, Error};
 
 /// Cache size in number of cached modules to hold.
 const CACHE_SIZE: usize = 1024;
 
+/// Revision of wasmer used to compile the modules, taken from Cargo.toml by the build script.
+/// Compiled modules are not portable between wasmer versions, so it is part of the on-disk cache
+/// key.
+const WASMER_VERSION: &str = env!("WASMER_VERSION");
+
+/// Extension of the file that stores the hash of the compiled module next to it.
+const CHECKSUM_EXTENSION: &str = "checksum";
+
+lazy_static! {
+    /// Directory where compiled modules are persisted. The on-disk cache is disabled until set.
+    static ref DISK_CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+static DISK_HITS: AtomicUsize = AtomicUsize::new(0);
+static MISSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables the on-disk cache of compiled modules in the given directory, so that contracts
+/// do not need to be recompiled after a restart.
+pub fn set_disk_cache_dir(dir: PathBuf) -> io::Result<()> {
+    fs::create_dir_all(&dir)?;
+    info!(target: "wasm", "Using {:?} for the compiled contracts cache", dir);
+    *DISK_CACHE_DIR.write().expect("cache dir lock is poisoned") = Some(dir);
+    Ok(())
+}
+
+/// Counters of the compiled module cache since the start of the process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub memory_hits: usize,
+    pub disk_hits: usize,
+    pub misses: usize,
+}
+
+impl CacheStats {
+    /// Share of the lookups that did not require compiling the contract.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.memory_hits + self.disk_hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        (self.memory_hits + self.disk_hits) as f64 / total as f64
+    }
+}
+
+pub fn cache_stats() -> CacheStats {
+    let lookups = LOOKUPS.load(Ordering::Relaxed);
+    let disk_hits = DISK_HITS.load(Ordering::Relaxed);
+    let misses = MISSES.load(Ordering::Relaxed);
+    CacheStats {
+        memory_hits: lookups.saturating_sub(disk_hits + misses),
+        disk_hits,
+        misses,
+    }
+}
+
+pub fn compile_cached_module(code: &[u8], config: &Config) -> Result<wasmer_runtime::Cache, Error> {
+    LOOKUPS.fetch_add(1, Ordering::Relaxed);
+    compile_module(code, config)
+}
+
+fn config_hash(config: &Config) -> CryptoHash {
+    hash(&config.encode().expect("encoding of config shouldn't fail"))
+}
+
 cached_key! {
     MODULES: SizedCache<String, Result<wasmer_runtime::Cache, Error>> = SizedCache::with_size(CACHE_SIZE);
     Key = {
         format!("{}:{}",
             hash(code),
-            hash(&config.encode().expect("encoding of config shouldn't fail")),
+            config_hash(config),
         )
     };
 
-    fn compile_cached_module(code: &[u8], config: &Config) -> Result<wasmer_runtime::Cache, Error> = {
-        let prepared_code = prepare::prepare_contract(code, config).map_err(Error::Prepare)?;
+    fn compile_module(code: &[u8], config: &Config) -> Result<wasmer_runtime::Cache, Error> = {
+        load_or_compile_module(code, config)
+    }
+}
+
+/// Loads the compiled module from the on-disk cache if it is enabled, otherwise compiles it
+/// and persists the result.
+fn load_or_compile_module(code: &[u8], config: &Config) -> Result<wasmer_runtime::Cache, Error> {
+    let disk_cache_dir = DISK_CACHE_DIR.read().expect("cache dir lock is poisoned").clone();
+    let module_path = disk_cache_dir
+        .map(|dir| dir.join(disk_cache_file_name(&hash(code), &config_hash(config))));
+    if let Some(ref path) = module_path {
+        match load_from_disk(path) {
+            Ok(Some(cache)) => {
+                DISK_HITS.fetch_add(1, Ordering::Relaxed);
+                return Ok(cache);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warn!(target: "wasm", "Dropping compiled module {:?}: {}", path, err);
+                remove_from_disk(path);
+            }
+        }
+    }
+
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    debug!(target: "wasm", "Compiling contract {}, cache hit rate {:.2}", hash(code), cache_stats().hit_rate());
+    let prepared_code = prepare::prepare_contract(code, config).map_err(Error::Prepare)?;
+    let cache = wasmer_runtime::compile_cache(&prepared_code).map_err(|e| Error::Wasmer(e.into()))?;
+
+    if let Some(ref path) = module_path {
+        if let Err(err) = store_to_disk(path, &cache) {
+            warn!(target: "wasm", "Failed to persist compiled module {:?}: {}", path, err);
+            remove_from_disk(path);
+        }
+    }
+    Ok(cache)
+}
+
+fn disk_cache_file_name(code_hash: &CryptoHash, config_hash: &CryptoHash) -> String {
+    format!("{}-{}-{}", code_hash, config_hash, WASMER_VERSION)
+}
+
+fn checksum_path(module_path: &Path) -> PathBuf {
+    module_path.with_extension(CHECKSUM_EXTENSION)
+}
+
+/// Checks that the module file matches the checksum written next to it, so that a truncated or
+/// partially written file is never passed to `into_module`. This does not protect against
+/// deliberate modification, anyone who can write the module can also rewrite its checksum.
+fn verify_checksum(module_path: &Path) -> Result<bool, String> {
+    let checksum_path = checksum_path(module_path);
+    if !module_path.exists() || !checksum_path.exists() {
+        return Ok(false);
+    }
+    let module = fs::read(module_path).map_err(|e| format!("{}", e))?;
+    let checksum = fs::read(&checksum_path).map_err(|e| format!("{}", e))?;
+    if hash(&module).as_ref() != checksum.as_slice() {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(true)
+}
+
+fn load_from_disk(module_path: &Path) -> Result<Option<wasmer_runtime::Cache>, String> {
+    if !verify_checksum(module_path)? {
+        return Ok(None);
+    }
+    wasmer_runtime::Cache::open(module_path)
+        .map(Some)
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn store_to_disk(module_path: &Path, cache: &wasmer_runtime::Cache) -> Result<(), String> {
+    cache.store(module_path).map_err(|e| format!("{:?}", e))?;
+    let module = fs::read(module_path).map_err(|e| format!("{}", e))?;
+    fs::write(checksum_path(module_path), hash(&module).as_ref()).map_err(|e| format!("{}", e))
+}
+
+fn remove_from_disk(module_path: &Path) {
+    let _ = fs::remove_file(module_path);
+    let _ = fs::remove_file(checksum_path(module_path));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_verify_checksum() {
+        let dir = env::temp_dir().join("wasm_cache_test_verify_checksum");
+        fs::create_dir_all(&dir).unwrap();
+        let module_path = dir.join(disk_cache_file_name(&hash(b"code"), &hash(b"config")));
+        remove_from_disk(&module_path);
+        assert_eq!(verify_checksum(&module_path), Ok(false));
+
+        fs::write(&module_path, b"compiled module").unwrap();
+        fs::write(checksum_path(&module_path), hash(b"compiled module").as_ref()).unwrap();
+        assert_eq!(verify_checksum(&module_path), Ok(true));
+
+        fs::write(&module_path, b"compiled").unwrap();
+        assert!(verify_checksum(&module_path).is_err());
+        remove_from_disk(&module_path);
+    }
 
-        wasmer_runtime::compile_cache(&prepared_code).map_err(|e| Error::Wasmer(e.into()))
+    #[test]
+    fn test_hit_rate() {
+        let stats = CacheStats { memory_hits: 2, disk_hits: 1, misses: 1 };
+        assert_eq!(stats.hit_rate(), 0.75);
+        let empty = CacheStats { memory_hits: 0, disk_hits: 0, misses: 0 };
+        assert_eq!(empty.hit_rate(), 0.0);
     }
-}
\ No newline at end of file
+}
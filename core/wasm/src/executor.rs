@@ -4,6 +4,7 @@ use std::ffi::c_void;
 
 use crate::runtime::{self, Runtime};
 use crate::types::{RuntimeContext, Config, ReturnData, Error};
+use primitives::transaction::ContractEvent;
 use primitives::types::{Balance, Mana, Gas};
 use crate::cache;
 
@@ -25,6 +26,7 @@ pub struct ExecutionOutcome {
     pub balance: Balance,
     pub random_seed: Vec<u8>,
     pub logs: Vec<String>,
+    pub events: Vec<ContractEvent>,
 }
 
 pub fn execute<'a>(
@@ -44,7 +46,10 @@ pub fn execute<'a>(
 
     // into_module method is unsafe because the runtime cannot confirm
     // that this cache was not tampered with or corrupted.
-    // In our case the cache is cloned from memory, so it's safe to use.
+    // In our case the cache is either cloned from memory or read from disk after
+    // verifying its checksum, which catches truncated or partially written files.
+    // The checksum does not protect against deliberate changes, so the cache directory
+    // must only be writable by the node.
     let module = unsafe { wasm_cache.into_module() }
         .map_err(|e| Error::Cache(format!("Cache error: {:?}", e)))?;
 
@@ -58,8 +63,9 @@ pub fn execute<'a>(
         ext,
         input_data,
         result_data,
+        config,
         context,
-        config.gas_limit,
+        context.gas_limit,
         memory.clone(),
     );
 
@@ -83,6 +89,7 @@ pub fn execute<'a>(
             balance: runtime.balance,
             random_seed: runtime.random_seed,
             logs: runtime.logs,
+            events: runtime.events,
         }),
         Err(e) => Ok(ExecutionOutcome {
             gas_used: runtime.gas_counter,
@@ -92,6 +99,7 @@ pub fn execute<'a>(
             balance: context.initial_balance,
             random_seed: runtime.random_seed,
             logs: runtime.logs,
+            events: vec![],
         })
     }
 }
@@ -1,4 +1,4 @@
-use primitives::types::{AccountId, PromiseId, Balance, Mana};
+use primitives::types::{AccountId, PromiseId, Balance, BlockIndex, Mana};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Error {
@@ -8,6 +8,8 @@ pub enum Error {
     PromiseAlreadyHasCallback,
     TrieIteratorError,
     TrieIteratorMissing,
+    /// Removed storage was not counted in the storage usage of the account.
+    StorageUsageUnderflow,
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -38,6 +40,16 @@ pub trait External {
         amount: Balance,
     ) -> Result<PromiseId>;
 
+    fn promise_schedule(
+        &mut self,
+        account_id: AccountId,
+        method_name: Vec<u8>,
+        arguments: Vec<u8>,
+        mana: Mana,
+        amount: Balance,
+        block_index: BlockIndex,
+    ) -> Result<PromiseId>;
+
     fn promise_then(
         &mut self,
         promise_id: PromiseId,
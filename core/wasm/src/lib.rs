@@ -3,6 +3,8 @@ extern crate pwasm_utils;
 extern crate wasmi;
 #[macro_use]
 extern crate cached;
+#[macro_use]
+extern crate lazy_static;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -19,7 +21,7 @@ extern crate primitives;
 #[macro_use]
 extern crate log;
 
-mod cache;
+pub mod cache;
 pub mod executor;
 pub mod ext;
 pub mod prepare;
@@ -1,9 +1,12 @@
 //! Module that takes care of loading, checking and preprocessing of a
 //! wasm module before execution.
 
+use std::collections::HashMap;
+
 use parity_wasm::elements::{self, External, MemoryType, Type, MemorySection};
 use parity_wasm::builder;
 use pwasm_utils::{self, rules};
+use pwasm_utils::rules::{InstructionType, Metering};
 use crate::types::{Config, PrepareError as Error};
 
 struct ContractModule<'a> {
@@ -70,10 +73,42 @@ impl<'a> ContractModule<'a> {
         Ok(())
     }
 
-    fn inject_gas_metering(&mut self) -> Result<(), Error> {
-        // TODO(#194): Re-enable .with_forbidden_floats() once AssemblyScript is fixed.
-        let gas_rules = rules::Set::new(self.config.regular_op_cost, Default::default())
+    fn gas_rules(&self) -> rules::Set {
+        let costs = &self.config.op_costs;
+        let mut entries = HashMap::new();
+        entries.insert(InstructionType::Bit, Metering::Fixed(costs.bit));
+        entries.insert(InstructionType::Add, Metering::Fixed(costs.add));
+        entries.insert(InstructionType::Mul, Metering::Fixed(costs.mul));
+        entries.insert(InstructionType::Div, Metering::Fixed(costs.div));
+        entries.insert(InstructionType::Load, Metering::Fixed(costs.load));
+        entries.insert(InstructionType::Store, Metering::Fixed(costs.store));
+        entries.insert(InstructionType::Const, Metering::Fixed(costs.constant));
+        entries.insert(InstructionType::Local, Metering::Fixed(costs.local));
+        entries.insert(InstructionType::Global, Metering::Fixed(costs.global));
+        entries.insert(InstructionType::ControlFlow, Metering::Fixed(costs.control_flow));
+        entries.insert(InstructionType::IntegerComparsion, Metering::Fixed(costs.comparison));
+        entries.insert(InstructionType::Conversion, Metering::Fixed(costs.conversion));
+        for float_type in &[
+            InstructionType::FloatConst,
+            InstructionType::FloatComparsion,
+            InstructionType::Float,
+            InstructionType::FloatConversion,
+            InstructionType::Reinterpretation,
+        ] {
+            entries.insert(*float_type, Metering::Fixed(costs.float));
+        }
+
+        let gas_rules = rules::Set::new(self.config.regular_op_cost, entries)
             .with_grow_cost(self.config.grow_mem_cost);
+        if self.config.allow_floats {
+            gas_rules
+        } else {
+            gas_rules.with_forbidden_floats()
+        }
+    }
+
+    fn inject_gas_metering(&mut self) -> Result<(), Error> {
+        let gas_rules = self.gas_rules();
 
         let module = self
             .module
@@ -241,6 +276,18 @@ mod tests {
         assert_matches!(r, Ok(_));
     }
 
+    #[test]
+    fn floats() {
+        let wasm = wabt::Wat2Wasm::new()
+            .convert(r#"(module (func (result f32) (f32.const 1.5)))"#)
+            .unwrap();
+        let config = Config::default();
+        assert_matches!(prepare_contract(wasm.as_ref(), &config), Ok(_));
+
+        let config = Config { allow_floats: false, ..Default::default() };
+        assert_matches!(prepare_contract(wasm.as_ref(), &config), Err(Error::GasInstrumentation));
+    }
+
     #[test]
     fn imports() {
         // nothing can be imported from non-"env" module for now.
@@ -1,9 +1,10 @@
 use crate::ext::External;
 
-use crate::types::{RuntimeError as Error, ReturnData, RuntimeContext};
+use crate::types::{RuntimeError as Error, ReturnData, RuntimeContext, Config};
 
 use primitives::types::{AccountId, PromiseId, ReceiptId, Balance, Mana, Gas};
 use primitives::hash::hash;
+use primitives::transaction::ContractEvent;
 use primitives::utils::is_valid_account_id;
 use std::collections::HashSet;
 use wasmer_runtime::{
@@ -24,6 +25,7 @@ pub struct Runtime<'a> {
     input_data: &'a [u8],
     result_data: &'a [Option<Vec<u8>>],
     pub mana_counter: Mana,
+    config: &'a Config,
     context: &'a RuntimeContext,
     pub balance: Balance,
     pub gas_counter: Gas,
@@ -33,6 +35,7 @@ pub struct Runtime<'a> {
     pub random_seed: Vec<u8>,
     random_buffer_offset: usize,
     pub logs: Vec<String>,
+    pub events: Vec<ContractEvent>,
     memory: Memory,
 }
 
@@ -41,6 +44,7 @@ impl<'a> Runtime<'a> {
         ext: &'a mut External,
         input_data: &'a [u8],
         result_data: &'a [Option<Vec<u8>>],
+        config: &'a Config,
         context: &'a RuntimeContext,
         gas_limit: Gas,
         memory: Memory,
@@ -50,6 +54,7 @@ impl<'a> Runtime<'a> {
             input_data,
             result_data,
             mana_counter: 0,
+            config,
             context,
             balance: context.initial_balance + context.received_amount,
             gas_counter: 0,
@@ -59,6 +64,7 @@ impl<'a> Runtime<'a> {
             random_seed: hash(&context.random_seed).into(),
             random_buffer_offset: 0,
             logs: Vec::new(),
+            events: Vec::new(),
             memory,
         }
     }
@@ -155,6 +161,14 @@ impl<'a> Runtime<'a> {
         }
     }
 
+    fn charge_gas_or_fail(&mut self, gas_amount: u32) -> Result<()> {
+        if self.charge_gas(Gas::from(gas_amount)) {
+            Ok(())
+        } else {
+            Err(Error::GasLimit)
+        }
+    }
+
     fn charge_mana(&mut self, mana: Mana) -> bool {
         let prev = self.mana_counter;
         match prev.checked_add(mana) {
@@ -178,6 +192,7 @@ impl<'a> Runtime<'a> {
 
     /// Returns length of the value from the storage
     fn storage_read_len(&mut self, key_ptr: u32) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.storage_read)?;
         let key = self.read_buffer(key_ptr as usize)?;
         let val = self
             .ext
@@ -193,6 +208,7 @@ impl<'a> Runtime<'a> {
 
     /// Reads from the storage to wasm memory
     fn storage_read_into(&mut self, key_ptr: u32, val_ptr: u32) -> Result<()> {
+        self.charge_gas_or_fail(self.config.host_costs.storage_read)?;
         let key = self.read_buffer(key_ptr as usize)?;
         let val = self
             .ext
@@ -207,9 +223,9 @@ impl<'a> Runtime<'a> {
 
     /// Writes to storage from wasm memory
     fn storage_write(&mut self, key_ptr: u32, val_ptr: u32) -> Result<()> {
+        self.charge_gas_or_fail(self.config.host_costs.storage_write)?;
         let key = self.read_buffer(key_ptr as usize)?;
         let val = self.read_buffer(val_ptr as usize)?;
-        // TODO: Charge gas for storage
 
         self.ext
             .storage_set(&key, &val)
@@ -220,6 +236,7 @@ impl<'a> Runtime<'a> {
 
     /// Remove key from storage
     fn storage_remove(&mut self, key_ptr: u32) -> Result<()> {
+        self.charge_gas_or_fail(self.config.host_costs.storage_remove)?;
         let key = self.read_buffer(key_ptr as usize)?;
         self.ext.storage_remove(&key).map_err(|_| Error::StorageRemoveError)?;
         debug!(target: "wasm", "storage_remove('{}')", format_buf(&key));
@@ -228,6 +245,7 @@ impl<'a> Runtime<'a> {
 
     /// Gets iterator for keys with given prefix
     fn storage_iter(&mut self, prefix_ptr: u32) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.storage_iter)?;
         let prefix = self.read_buffer(prefix_ptr as usize)?;
         let storage_id = self
             .ext
@@ -239,6 +257,7 @@ impl<'a> Runtime<'a> {
 
     /// Advances iterator. Returns true if iteration isn't finished yet.
     fn storage_iter_next(&mut self, storage_id: u32) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.storage_iter)?;
         let key = self
             .ext
             .storage_iter_next(storage_id)
@@ -272,14 +291,11 @@ impl<'a> Runtime<'a> {
     }
 
     fn gas(&mut self, gas_amount: u32) -> Result<()> {
-        if self.charge_gas(Gas::from(gas_amount)) {
-            Ok(())
-        } else {
-            Err(Error::GasLimit)
-        }
+        self.charge_gas_or_fail(gas_amount)
     }
 
     fn promise_create(&mut self, account_id_ptr: u32, method_name_ptr: u32, arguments_ptr: u32, mana: u32, amount: u64) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.promise_create)?;
         let account_id = self.read_and_parse_account_id(account_id_ptr)?;
         let method_name = self.read_buffer(method_name_ptr as usize)?;
 
@@ -310,7 +326,43 @@ impl<'a> Runtime<'a> {
         Ok(promise_index as u32)
     }
 
+    fn promise_schedule(&mut self, account_id_ptr: u32, method_name_ptr: u32, arguments_ptr: u32, mana: u32, amount: u64, block_index: u64) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.promise_create)?;
+        let account_id = self.read_and_parse_account_id(account_id_ptr)?;
+        let method_name = self.read_buffer(method_name_ptr as usize)?;
+
+        match method_name.get(0) {
+            Some(b'_') => return Err(Error::PrivateMethod),
+            None if amount == 0 => return Err(Error::EmptyMethodNameWithZeroAmount),
+            _ => (),
+        };
+        if block_index <= self.context.block_index {
+            return Err(Error::ScheduledInThePast);
+        }
+
+        let arguments = self.read_buffer(arguments_ptr as usize)?;
+
+        // Charging the reserved mana + 1 at scheduling time, same as for `promise_create`.
+        self.charge_mana_or_fail(mana)?;
+        self.charge_mana_or_fail(1)?;
+
+        if amount > self.balance {
+            return Err(Error::BalanceExceeded);
+        }
+        self.balance -= amount;
+
+        let promise_id = self.ext
+            .promise_schedule(account_id, method_name, arguments, mana, amount, block_index)
+            .map_err(|_| Error::PromiseError)?;
+
+        let promise_index = self.promise_ids.len();
+        self.promise_ids.push(promise_id);
+
+        Ok(promise_index as u32)
+    }
+
     fn promise_then(&mut self, promise_index: u32, method_name_ptr: u32, arguments_ptr: u32, mana: u32) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.promise_then)?;
         let promise_id = self.promise_index_to_id(promise_index)?;
         let method_name = self.read_buffer(method_name_ptr as usize)?;
         if method_name.is_empty() {
@@ -339,6 +391,7 @@ impl<'a> Runtime<'a> {
     }
 
     fn promise_and(&mut self, promise_index1: u32, promise_index2: u32) -> Result<u32> {
+        self.charge_gas_or_fail(self.config.host_costs.promise_and)?;
         let promise_ids = [
             self.promise_index_to_id(promise_index1)?,
             self.promise_index_to_id(promise_index2)?,
@@ -484,6 +537,19 @@ impl<'a> Runtime<'a> {
         self.debug(msg_ptr)
     }
 
+    fn emit_event(&mut self, topic_ptr: u32, data_ptr: u32) -> Result<()> {
+        let topic = self.read_buffer(topic_ptr as usize)?;
+        let data = self.read_buffer(data_ptr as usize)?;
+        debug!(target: "wasm", "emit_event('{}', '{}')", format_buf(&topic), format_buf(&data));
+        self.events.push(ContractEvent {
+            contract_id: self.context.account_id.clone(),
+            topic,
+            data,
+        });
+
+        Ok(())
+    }
+
     /// Returns length of the buffer for the type/key pair
     fn read_len(&mut self, buffer_type_index: BufferTypeIndex, _key_ptr: u32) -> Result<u32> {
         let len = match buffer_type_index {
@@ -616,6 +682,9 @@ pub mod imports {
         // Promises, callbacks and async calls
         // Creates a new promise that makes an async call to some other contract.
         "promise_create" => promise_create<[account_id_ptr: u32, method_name_ptr: u32, arguments_ptr: u32, mana: u32, amount: u64] -> [u32]>,
+        // Creates a new promise that makes an async call to some other contract when the block
+        // with the given index is processed.
+        "promise_schedule" => promise_schedule<[account_id_ptr: u32, method_name_ptr: u32, arguments_ptr: u32, mana: u32, amount: u64, block_index: u64] -> [u32]>,
         // Attaches a callback to a given promise. This promise can be either an
         // async call or multiple joined promises.
         // NOTE: The given promise can't be a callback.
@@ -661,6 +730,8 @@ pub mod imports {
         "random32" => random32<[] -> [u32]>,
         "debug" => debug<[msg_ptr: u32] -> []>,
         "log" => log<[msg_ptr: u32] -> []>,
+        // Emits a typed event with the given topic and data buffers.
+        "emit_event" => emit_event<[topic_ptr: u32, data_ptr: u32] -> []>,
 
         // Function for the injected gas counter. Automatically called by the gas meter.
         "gas" => gas<[gas_amount: u32] -> []>,
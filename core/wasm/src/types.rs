@@ -1,4 +1,4 @@
-use primitives::types::{PromiseId, AccountId, Balance, Mana, BlockIndex};
+use primitives::types::{PromiseId, AccountId, Balance, Mana, BlockIndex, Gas};
 use wasmer_runtime::error as WasmerError;
 
 #[derive(Debug, Clone)]
@@ -105,6 +105,8 @@ pub enum RuntimeError {
     /// Creating a promise with an empty method name and 0 amount.
     /// It's considered useless waste of mana
     EmptyMethodNameWithZeroAmount,
+    /// Scheduling a promise at a block that is not after the current one.
+    ScheduledInThePast,
     /// Panic with message
     Panic(String),
 }
@@ -143,6 +145,7 @@ impl ::std::fmt::Display for RuntimeError {
             RuntimeError::PrivateMethod => write!(f, "Creating a promise with a private method"),
             RuntimeError::EmptyMethodName => write!(f, "Creating a callback with an empty method name"),
             RuntimeError::EmptyMethodNameWithZeroAmount => write!(f, "Creating a promise with an empty method name and 0 amount"),
+            RuntimeError::ScheduledInThePast => write!(f, "Scheduling a promise at a block that is not after the current one"),
             RuntimeError::Panic(ref msg) => write!(f, "Panic: {}", msg),
         }
     }
@@ -195,7 +198,7 @@ pub enum ReturnData {
 }
 
 // TODO: Extract it to the root of the crate
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, Serialize)]
 pub struct Config {
     /// Gas cost of a growing memory by single page.
     pub grow_mem_cost: u32,
@@ -203,6 +206,16 @@ pub struct Config {
     /// Gas cost of a regular operation.
     pub regular_op_cost: u32,
 
+    /// Gas costs of the instruction classes that are not priced as regular operations.
+    pub op_costs: OpCosts,
+
+    /// Whether floating point instructions are allowed in the contracts. Floats are not
+    /// deterministic across platforms, so the contracts using them are rejected when disabled.
+    pub allow_floats: bool,
+
+    /// Base gas costs of the host functions.
+    pub host_costs: HostCosts,
+
     /// Gas cost per one byte returned.
     pub return_data_per_byte_cost: u32,
 
@@ -218,9 +231,6 @@ pub struct Config {
     /// What is the maximal memory pages amount is allowed to have for
     /// a contract.
     pub max_memory_pages: u32,
-
-    /// Gas limit of the one contract call
-    pub gas_limit: u64,
 }
 
 impl Default for Config {
@@ -228,11 +238,99 @@ impl Default for Config {
         Config {
             grow_mem_cost: 1,
             regular_op_cost: 1,
+            op_costs: OpCosts::default(),
+            // TODO(#194): Forbid floats once AssemblyScript is fixed.
+            allow_floats: true,
+            host_costs: HostCosts::default(),
             return_data_per_byte_cost: 1,
             max_stack_height: 64 * 1024,
             initial_memory_pages: 17,
             max_memory_pages: 32,
-            gas_limit: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// Gas costs of the wasm instruction classes. Calls are priced together with the other
+/// control flow instructions. Use `cargo bench -p runtest` to calibrate the weights.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct OpCosts {
+    /// Bitwise operations and shifts.
+    pub bit: u32,
+    /// Integer addition and subtraction.
+    pub add: u32,
+    /// Integer multiplication.
+    pub mul: u32,
+    /// Integer division and remainder.
+    pub div: u32,
+    /// Memory loads.
+    pub load: u32,
+    /// Memory stores.
+    pub store: u32,
+    /// Integer constants.
+    pub constant: u32,
+    /// Access to the locals.
+    pub local: u32,
+    /// Access to the globals.
+    pub global: u32,
+    /// Branches, calls and returns.
+    pub control_flow: u32,
+    /// Integer comparisons.
+    pub comparison: u32,
+    /// Integer conversions.
+    pub conversion: u32,
+    /// Floating point instructions, if they are allowed.
+    pub float: u32,
+}
+
+impl Default for OpCosts {
+    fn default() -> OpCosts {
+        OpCosts {
+            bit: 1,
+            add: 1,
+            mul: 2,
+            div: 4,
+            load: 2,
+            store: 2,
+            constant: 1,
+            local: 1,
+            global: 1,
+            control_flow: 2,
+            comparison: 1,
+            conversion: 1,
+            float: 4,
+        }
+    }
+}
+
+/// Base gas costs of the host functions, charged on top of the instructions of the contract.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct HostCosts {
+    /// Reading a value or its length from the storage.
+    pub storage_read: u32,
+    /// Writing a value to the storage.
+    pub storage_write: u32,
+    /// Removing a value from the storage.
+    pub storage_remove: u32,
+    /// Creating or advancing a storage iterator.
+    pub storage_iter: u32,
+    /// Creating or scheduling a promise.
+    pub promise_create: u32,
+    /// Attaching a callback to a promise.
+    pub promise_then: u32,
+    /// Joining two promises.
+    pub promise_and: u32,
+}
+
+impl Default for HostCosts {
+    fn default() -> HostCosts {
+        HostCosts {
+            storage_read: 100,
+            storage_write: 200,
+            storage_remove: 100,
+            storage_iter: 100,
+            promise_create: 500,
+            promise_then: 200,
+            promise_and: 100,
         }
     }
 }
@@ -251,6 +349,8 @@ pub struct RuntimeContext {
     pub account_id: AccountId,
     /// Available mana for the execution by this contract.
     pub mana: Mana,
+    /// Maximum gas that can be used by this contract call.
+    pub gas_limit: Gas,
     /// Currently produced block index
     pub block_index: BlockIndex,
     /// Initial seed for randomness
@@ -264,6 +364,7 @@ impl RuntimeContext {
         sender_id: &AccountId,
         account_id: &AccountId,
         mana: Mana,
+        gas_limit: Gas,
         block_index: BlockIndex,
         random_seed: Vec<u8>,
     ) -> RuntimeContext {
@@ -273,6 +374,7 @@ impl RuntimeContext {
             originator_id: sender_id.clone(),
             account_id: account_id.clone(),
             mana,
+            gas_limit,
             block_index,
             random_seed,
         }
@@ -1,6 +1,9 @@
 const bs58 = require('bs58');
 
-const { CreateAccountTransaction, SignedTransaction } = require('./protos');
+const {
+    CreateAccountTransaction, DeleteAccountTransaction, SignedTransaction, StakeTransaction,
+    UnstakeTransaction,
+} = require('./protos');
 const KeyPair = require('./signing/key_pair');
 
 /**
@@ -64,6 +67,97 @@ class Account {
         return { key: keyWithRandomSeed, ...createAccountResult }; 
     }
 
+    /**
+     * Stakes tokens of the originator account. The account has to be an authority.
+     * @param {number} amount amount of tokens to add to the stake
+     * @param {string} originator account that stakes its tokens
+     */
+    async stake (amount, originator) {
+        const nonce = await this.nearClient.getNonce(originator);
+        const stake = StakeTransaction.create({
+            originator,
+            amount,
+        });
+        // Integers with value of 0 must be omitted
+        // https://github.com/dcodeIO/protobuf.js/issues/1138
+        if (nonce !== 0) {
+            stake.nonce = nonce;
+        }
+
+        const buffer = StakeTransaction.encode(stake).finish();
+        const signature = await this.nearClient.signer.signTransactionBody(
+            buffer,
+            originator,
+        );
+
+        const signedTransaction = SignedTransaction.create({
+            stake,
+            signature,
+        });
+        return await this.nearClient.submitTransaction(signedTransaction);
+    }
+
+    /**
+     * Lowers the stake of the originator account. The tokens are returned to the account only
+     * after the authority leaves the set with its previous stake.
+     * @param {number} amount amount of tokens to remove from the stake
+     * @param {string} originator account that unstakes its tokens
+     */
+    async unstake (amount, originator) {
+        const nonce = await this.nearClient.getNonce(originator);
+        const unstake = UnstakeTransaction.create({
+            originator,
+            amount,
+        });
+        // Integers with value of 0 must be omitted
+        // https://github.com/dcodeIO/protobuf.js/issues/1138
+        if (nonce !== 0) {
+            unstake.nonce = nonce;
+        }
+
+        const buffer = UnstakeTransaction.encode(unstake).finish();
+        const signature = await this.nearClient.signer.signTransactionBody(
+            buffer,
+            originator,
+        );
+
+        const signedTransaction = SignedTransaction.create({
+            unstake,
+            signature,
+        });
+        return await this.nearClient.submitTransaction(signedTransaction);
+    }
+
+    /**
+     * Deletes the originator account. The account must not have any stake left.
+     * @param {string} originator account to delete
+     * @param {string} beneficiaryId account that receives the remaining balance
+     */
+    async deleteAccount (originator, beneficiaryId) {
+        const nonce = await this.nearClient.getNonce(originator);
+        const deleteAccount = DeleteAccountTransaction.create({
+            originator,
+            beneficiaryId,
+        });
+        // Integers with value of 0 must be omitted
+        // https://github.com/dcodeIO/protobuf.js/issues/1138
+        if (nonce !== 0) {
+            deleteAccount.nonce = nonce;
+        }
+
+        const buffer = DeleteAccountTransaction.encode(deleteAccount).finish();
+        const signature = await this.nearClient.signer.signTransactionBody(
+            buffer,
+            originator,
+        );
+
+        const signedTransaction = SignedTransaction.create({
+            deleteAccount,
+            signature,
+        });
+        return await this.nearClient.submitTransaction(signedTransaction);
+    }
+
     /**
      * 
      * @param {string} accountId id of the account to look up 
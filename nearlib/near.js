@@ -63,8 +63,10 @@ class Near {
      * @param {string} contractAccountId account id of the contract
      * @param {string} methodName method to call
      * @param {object} args arguments to pass to the method
+     * @param {number} gasLimit optional maximum gas of the call, the chain-wide maximum by default
+     * @param {number} gasPrice optional price of a unit of gas, the minimum gas price by default
      */
-    async scheduleFunctionCall(amount, originator, contractId, methodName, args) {
+    async scheduleFunctionCall(amount, originator, contractId, methodName, args, gasLimit, gasPrice) {
         if (!args) {
             args = {};
         }
@@ -85,6 +87,12 @@ class Near {
         if (amount !== 0) {
             functionCall.amount = amount;
         }
+        if (gasLimit) {
+            functionCall.gasLimit = gasLimit;
+        }
+        if (gasPrice) {
+            functionCall.gasPrice = gasPrice;
+        }
 
         const buffer = FunctionCallTransaction.encode(functionCall).finish();
         const signature = await this.nearClient.signer.signTransactionBody(
@@ -103,14 +111,19 @@ class Near {
      * Deploys a smart contract to the block chain
      * @param {string} contractAccountId account id of the contract
      * @param {Uint8Array} wasmArray wasm binary
+     * @param {string} migrationMethodName optional method of the new code to call right after deploying it.
+     * The deployment is reverted if the method fails.
      */
-    async deployContract(contractId, wasmByteArray) {
+    async deployContract(contractId, wasmByteArray, migrationMethodName) {
         const nonce = await this.nearClient.getNonce(contractId);
 
         const deployContract = DeployContractTransaction.create({
             contractId,
             wasmByteArray,
         });
+        if (migrationMethodName) {
+            deployContract.migrationMethodName = new Uint8Array(Buffer.from(migrationMethodName));
+        }
         // Integers with value of 0 must be omitted
         // https://github.com/dcodeIO/protobuf.js/issues/1138
         if (nonce !== 0) {
@@ -44,8 +44,11 @@ fn find_threshold(stakes: &[u64], num_seats: u64) -> Result<u64, String> {
 /// Keeps track and selects authorities for given blockchain.
 /// To participate in epoch E an authority must submit a proposal in epoch E-2.
 /// Those authorities that submitted proposals in epoch E-2 and those that participated in epoch E-2
-/// are used in authority selection for epoch E. For each authority the stake used in selection is
-/// computed as: <amount staked in E-2> - <amount not used in E-2> + <proposed amount in E-2>.
+/// are used in authority selection for epoch E. A proposal carries the total stake of the
+/// authority, so for each authority the stake used in selection is the last amount it proposed in
+/// E-2 or, if it did not propose, computed as: <amount staked in E-2> - <amount not used in E-2>.
+/// An authority that lowered its stake in E-2 keeps the old stake locked while it is used in E-1,
+/// and the remainder is withdrawn at the first block of E, unless it proposed again in E-1.
 pub struct Authority {
     /// Authority configuration.
     authority_config: AuthorityConfig,
@@ -61,6 +64,8 @@ pub struct Authority {
     thresholds: HashMap<Epoch, u64>,
     /// Authorities that were accepted for the given slots.
     accepted_authorities: HashMap<Slot, Vec<AuthorityStake>>,
+    /// Stakes to release at the first slot of the given epoch.
+    withdrawals: HashMap<Epoch, Vec<AuthorityStake>>,
 }
 
 impl Authority {
@@ -89,6 +94,7 @@ impl Authority {
             processed_blocks: HashMap::new(),
             thresholds: HashMap::new(),
             accepted_authorities: HashMap::new(),
+            withdrawals: HashMap::new(),
         };
         // Initial authorities operate for the first two epochs.
         let (accepted_authorities, threshold) = result.compute_threshold_accepted(
@@ -118,6 +124,51 @@ impl Authority {
         result
     }
 
+    /// Forgets the blocks above the given index and everything computed from them, e.g. when the
+    /// best chain switches to another branch with the common ancestor at this index. The blocks
+    /// of the new branch are processed afterwards with `process_block_header`.
+    pub fn revert_to(&mut self, index: Slot) {
+        let first_epoch = self.slot_to_epoch(index + 1);
+        self.proposals.retain(|slot, _| *slot <= index);
+        self.participation.retain(|slot, _| *slot <= index);
+        for slots in self.processed_blocks.values_mut() {
+            slots.retain(|slot| *slot <= index);
+        }
+        self.processed_blocks.retain(|_, slots| !slots.is_empty());
+        // The first epoch is no longer complete, so the authorities selected when it was completed
+        // and everything after them are unknown.
+        let first_slot = (first_epoch + 2) * self.authority_config.epoch_length + 1;
+        self.thresholds.retain(|epoch, _| *epoch < first_epoch + 2);
+        self.accepted_authorities.retain(|slot, _| *slot < first_slot);
+        self.withdrawals.retain(|epoch, _| *epoch < first_epoch + 1);
+    }
+
+    /// Keeps only the last proposal of each authority, preserving the order of the first ones.
+    fn last_proposals(proposals: Vec<AuthorityStake>) -> Vec<AuthorityStake> {
+        let mut result: Vec<AuthorityStake> = vec![];
+        let mut indices = HashMap::new();
+        for p in proposals {
+            match indices.entry(p.account_id.clone()) {
+                Entry::Occupied(e) => {
+                    result[*e.get()] = p;
+                }
+                Entry::Vacant(e) => {
+                    e.insert(result.len());
+                    result.push(p);
+                }
+            }
+        }
+        result
+    }
+
+    /// Last proposal of each authority made in the given epoch.
+    fn last_proposals_in(&self, epoch: Epoch) -> Vec<AuthorityStake> {
+        let proposals = self
+            .epoch_to_slots(epoch)
+            .flat_map(|s| self.proposals.get(&s).cloned().unwrap_or_default());
+        Self::last_proposals(proposals.collect())
+    }
+
     /// Computes accepted authorities and the threshold from the given proposals.
     /// Args:
     ///     seed: for shuffling;
@@ -129,18 +180,16 @@ impl Authority {
         proposals: Vec<AuthorityStake>,
         mut rollovers: Vec<AuthorityStake>,
     ) -> (Vec<Vec<AuthorityStake>>, u64) {
-        // Combine proposals with rollovers.
-        let mut ordered_proposals = proposals;
+        // Combine proposals with rollovers. The last proposal of an authority replaces its
+        // rollover and its earlier proposals.
+        let mut ordered_proposals = Self::last_proposals(proposals);
         let mut indices = HashMap::new();
         for (i, p) in ordered_proposals.iter().enumerate() {
             indices.insert(p.account_id.clone(), i);
         }
         for r in rollovers.drain(..) {
             match indices.entry(r.account_id.clone()) {
-                Entry::Occupied(e) => {
-                    let i = *e.get();
-                    ordered_proposals[i].amount += r.amount;
-                }
+                Entry::Occupied(_) => {}
                 Entry::Vacant(e) => {
                     e.insert(ordered_proposals.len());
                     ordered_proposals.push(r);
@@ -223,6 +272,16 @@ impl Authority {
                     }
                 }
             }
+            // Authorities that lowered their stake in epoch-3 participated in epoch-2 with the
+            // old stake, which is withdrawn by now.
+            let lowered: HashMap<_, _> = if epoch >= 3 {
+                self.last_proposals_in(epoch - 3)
+                    .into_iter()
+                    .map(|p| (p.account_id, p.amount))
+                    .collect()
+            } else {
+                HashMap::new()
+            };
             // Apply penalties.
             let rollovers: Vec<_> = ordered_rollovers
                 .drain(..)
@@ -234,6 +293,12 @@ impl Authority {
                     }
                     true
                 })
+                .map(|mut r| {
+                    if let Some(amount) = lowered.get(&r.account_id) {
+                        r.amount = r.amount.min(*amount);
+                    }
+                    r
+                })
                 .collect();
 
             // Second, use the proposals and the rollovers.
@@ -252,6 +317,26 @@ impl Authority {
             self.accepted_authorities
                 .extend(slots.iter().cloned().zip(accepted_authorities.drain(..)));
         }
+        if epoch >= 3 {
+            self.compute_withdrawals(epoch - 1);
+        }
+    }
+
+    /// Computes stakes released at the beginning of the given epoch. Proposals from epoch-2 were
+    /// used to select authorities for the epoch, so the stake they replaced was last used in
+    /// epoch-1. Authorities that proposed again in epoch-1 are skipped, their latest proposal
+    /// defines the stake released later.
+    fn compute_withdrawals(&mut self, epoch: Epoch) {
+        let later: HashSet<_> =
+            self.last_proposals_in(epoch - 1).into_iter().map(|p| p.account_id).collect();
+        let withdrawals: Vec<_> = self
+            .last_proposals_in(epoch - 2)
+            .into_iter()
+            .filter(|p| !later.contains(&p.account_id))
+            .collect();
+        if !withdrawals.is_empty() {
+            self.withdrawals.insert(epoch, withdrawals);
+        }
     }
 
     /// Record proposals and participation from the given block.
@@ -278,6 +363,15 @@ impl Authority {
         }
     }
 
+    /// Returns the stakes that should be withdrawn when applying the given block. Each entry
+    /// holds the amount that remains staked by the authority, everything above it is released.
+    pub fn get_withdrawals(&self, slot: Slot) -> Vec<AuthorityStake> {
+        if slot == 0 || (slot - 1) % self.authority_config.epoch_length != 0 {
+            return vec![];
+        }
+        self.withdrawals.get(&self.slot_to_epoch(slot)).cloned().unwrap_or_default()
+    }
+
     /// Returns authorities for given block number.
     pub fn get_authorities(&self, slot: Slot) -> Result<Vec<AuthorityStake>, String> {
         if slot == 0 {
@@ -310,6 +404,7 @@ mod test {
     use super::*;
     use primitives::block_traits::SignedHeader;
     use configs::ChainSpec;
+    use configs::chain_spec::{DEFAULT_MAX_GAS_LIMIT, DEFAULT_MAX_GAS_PRICE, StorageRentConfig};
     use crate::beacon_chain::BeaconClient;
 
     fn get_test_chainspec(
@@ -333,17 +428,23 @@ mod test {
             beacon_chain_epoch_length,
             beacon_chain_num_seats_per_slot,
             boot_nodes: Default::default(),
+            fees: Default::default(),
+            num_shards: 1,
+            max_gas_limit: DEFAULT_MAX_GAS_LIMIT,
+            max_gas_price: DEFAULT_MAX_GAS_PRICE,
+            storage_rent: StorageRentConfig::default(),
+            genesis_state: None,
         }
     }
 
     fn test_blockchain(num_blocks: u64, chain_spec: &ChainSpec) -> BeaconClient {
         let storage = create_beacon_shard_storages().0;
         let mut last_block =
-            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], CryptoHash::default());
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
         let bc = BeaconClient::new(last_block.clone(), chain_spec, storage);
         for i in 1..num_blocks {
             let block =
-                SignedBeaconBlock::new(i, last_block.block_hash(), vec![], CryptoHash::default());
+                SignedBeaconBlock::new(i, last_block.block_hash(), vec![], vec![]);
             bc.chain.insert_block(block.clone());
             last_block = block;
         }
@@ -365,7 +466,7 @@ mod test {
             .map(|x| x.account_id == initial_authorities[0].account_id)
             .count();
         for i in 1..11 {
-            let block = SignedBeaconBlock::new(i, prev_hash, vec![], CryptoHash::default());
+            let block = SignedBeaconBlock::new(i, prev_hash, vec![], vec![]);
             let mut header = block.header();
             header.signature.authority_mask = (0..num_seats).map(|_| true).collect();
             authority.process_block_header(&header);
@@ -398,11 +499,11 @@ mod test {
             vec![initial_authorities[1].clone(), initial_authorities[2].clone()]
         );
         assert!(authority.get_authorities(5).is_err());
-        let block1 = SignedBeaconBlock::new(1, bc.chain.genesis_hash(), vec![], CryptoHash::default());
+        let block1 = SignedBeaconBlock::new(1, bc.chain.genesis_hash(), vec![], vec![]);
         let mut header1 = block1.header();
         // Authority #1 didn't show up.
         header1.signature.authority_mask = vec![true, false];
-        let block2 = SignedBeaconBlock::new(2, header1.block_hash(), vec![], CryptoHash::default());
+        let block2 = SignedBeaconBlock::new(2, header1.block_hash(), vec![], vec![]);
         let mut header2 = block2.header();
         header2.signature.authority_mask = vec![true, true];
         authority.process_block_header(&header1);
@@ -417,6 +518,76 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_withdrawals() {
+        let chain_spec = get_test_chainspec(4, 2, 2);
+        let bc = test_blockchain(0, &chain_spec);
+        let config = bc.authority.read().unwrap().authority_config.clone();
+        let initial_authorities = config.initial_proposals.to_vec();
+        let mut authority = bc.authority.write().unwrap();
+        // Authority #0 leaves the set in epoch 0.
+        let unstake = AuthorityStake { amount: 0, ..initial_authorities[0].clone() };
+        let mut prev_hash = bc.chain.genesis_hash();
+        for i in 1..7 {
+            let proposals = if i == 1 { vec![unstake.clone()] } else { vec![] };
+            let block = SignedBeaconBlock::new(i, prev_hash, proposals, vec![]);
+            let mut header = block.header();
+            header.signature.authority_mask = vec![true, true];
+            authority.process_block_header(&header);
+            prev_hash = header.block_hash();
+        }
+        for slot in 5..9 {
+            assert!(authority
+                .get_authorities(slot)
+                .unwrap()
+                .iter()
+                .all(|a| a.account_id != unstake.account_id));
+        }
+        // The stake is released at the beginning of epoch 2, once epoch 1 no longer uses it.
+        assert_eq!(authority.get_withdrawals(3), vec![]);
+        assert_eq!(authority.get_withdrawals(5), vec![unstake]);
+        assert_eq!(authority.get_withdrawals(5)[0].amount, 0);
+        assert_eq!(authority.get_withdrawals(6), vec![]);
+    }
+
+    #[test]
+    fn test_revert_to() {
+        let chain_spec = get_test_chainspec(4, 2, 2);
+        let bc = test_blockchain(0, &chain_spec);
+        let config = bc.authority.read().unwrap().authority_config.clone();
+        let initial_authorities = config.initial_proposals.to_vec();
+        let unstake = AuthorityStake { amount: 0, ..initial_authorities[0].clone() };
+        let headers = |unstake_slot: Slot| {
+            let mut prev_hash = bc.chain.genesis_hash();
+            let mut headers = vec![];
+            for i in 1..7 {
+                let proposals = if i == unstake_slot { vec![unstake.clone()] } else { vec![] };
+                let block = SignedBeaconBlock::new(i, prev_hash, proposals, vec![]);
+                let mut header = block.header();
+                header.signature.authority_mask = vec![true, true];
+                prev_hash = header.block_hash();
+                headers.push(header);
+            }
+            headers
+        };
+        let mut authority = Authority::new(config.clone(), &bc.chain);
+        let mut expected = Authority::new(config, &bc.chain);
+        for header in headers(1) {
+            authority.process_block_header(&header);
+        }
+        // The branch without the unstake wins from the genesis on.
+        authority.revert_to(0);
+        for header in headers(0) {
+            authority.process_block_header(&header);
+            expected.process_block_header(&header);
+        }
+        for slot in 1..9 {
+            assert_eq!(authority.get_authorities(slot), expected.get_authorities(slot));
+            assert_eq!(authority.get_withdrawals(slot), expected.get_withdrawals(slot));
+        }
+        assert_eq!(authority.get_withdrawals(5), vec![]);
+    }
+
     #[test]
     fn test_find_threshold() {
         assert_eq!(find_threshold(&[1000000, 1000000, 10], 10).unwrap(), 200000);
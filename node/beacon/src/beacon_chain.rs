@@ -26,14 +26,13 @@ impl BeaconClient {
 
 #[cfg(test)]
 mod tests {
-//    use std::sync::Arc;
+    use std::collections::HashMap;
 
-    use chain::BlockChain;
-//    use primitives::aggregate_signature::BlsSignature;
-//    use primitives::hash::hash;
+    use chain::{BlockChain, BlockInsertResult};
+    use primitives::aggregate_signature::BlsSignature;
+    use primitives::hash::hash;
     use primitives::signer::InMemorySigner;
     use primitives::types::BlockId;
-//    use std::collections::HashMap;
 
     use super::*;
     use primitives::block_traits::SignedBlock;
@@ -45,7 +44,7 @@ mod tests {
     fn test_genesis() {
         let storage = create_beacon_shard_storages().0;
         let genesis =
-            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], CryptoHash::default());
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
         let bc = BlockChain::new(genesis.clone(), storage);
         assert_eq!(bc.get_block(&BlockId::Hash(genesis.block_hash())).unwrap(), genesis);
         assert_eq!(bc.get_block(&BlockId::Number(0)).unwrap(), genesis);
@@ -55,10 +54,10 @@ mod tests {
     fn test_restart_chain() {
         let storage = create_beacon_shard_storages().0;
         let genesis =
-            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], CryptoHash::default());
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
         let bc = BlockChain::new(genesis.clone(), storage.clone());
         let mut block1 =
-            SignedBeaconBlock::new(1, genesis.block_hash(), vec![], CryptoHash::default());
+            SignedBeaconBlock::new(1, genesis.block_hash(), vec![], vec![]);
         let signer = InMemorySigner::default();
         let sig = block1.sign(&signer);
         block1.add_signature(&sig, 0);
@@ -87,87 +86,188 @@ mod tests {
 //        assert_eq!(bc1.best_block().block_hash(), genesis1.block_hash());
 //        assert_eq!(bc2.best_block().block_hash(), genesis2.block_hash());
 //    }
-//
-//    fn test_fork_choice_rule_helper(graph: Vec<(u32, u32, usize)>, expect: u32) {
-//        let storage = Arc::new(create_memory_db());
-//
-//        let genesis =
-//            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], CryptoHash::default());
-//        let bc = BlockChain::new(genesis.clone(), storage);
-//        let mut blocks: HashMap<u32, SignedBeaconBlock> = HashMap::new();
-//        blocks.insert(0, genesis.clone());
-//
-//        for (self_id, parent_id, sign_count) in graph.iter() {
-//            let mut block;
-//            {
-//                let parent = blocks.get(parent_id).unwrap();
-//                block = SignedBeaconBlock::new(
-//                    parent.body.header.index + 1,
-//                    parent.block_hash(),
-//                    vec![],
-//                    hash(&[*self_id as u8]),
-//                );
-//            }
-//            for i in 0..*sign_count {
-//                // Having proper signing here is far too slow, and unnecessary for this test
-//                let sig = BlsSignature::empty();
-//                block.add_signature(&sig, i);
-//            }
-//            blocks.insert(*self_id, block.clone());
-//            assert_eq!(bc.insert_block(block.clone()), false);
-//        }
-//        let best_hash = bc.best_block().block_hash();
-//        assert_eq!(best_hash, blocks.get(&expect).unwrap().block_hash());
-//    }
-//
-//    #[test]
-//    fn test_fork_choice_rule() {
-//        // First 3 examples from https://ethresear.ch/t/immediate-message-driven-ghost-as-ffg-fork-choice-rule/2561
-//
-//        //    15 - 16 - 65
-//        //  /
-//        // -
-//        //  \
-//        //    55 - 56
-//        //
-//        // We prefer the bottom fork, even though the top is longer.
-//        test_fork_choice_rule_helper(
-//            vec![(1, 0, 15), (2, 1, 16), (3, 2, 65), (4, 0, 55), (5, 4, 56)],
-//            5,
-//        );
-//        test_fork_choice_rule_helper(
-//            vec![(4, 0, 55), (5, 4, 56), (1, 0, 15), (2, 1, 16), (3, 2, 65)],
-//            5,
-//        );
-//
-//        //    15 - 51
-//        //  /
-//        // -
-//        //  \
-//        //    65 - 20
-//        test_fork_choice_rule_helper(vec![(1, 0, 15), (2, 0, 65), (3, 1, 51), (4, 2, 20)], 4);
-//
-//        //    40 - 51
-//        //  /
-//        // -
-//        //  \
-//        //    60 - 5
-//        test_fork_choice_rule_helper(vec![(1, 0, 40), (2, 0, 60), (3, 1, 51), (4, 2, 5)], 3);
-//
-//        //    65 - 20
-//        //  /
-//        // -
-//        //  \      35
-//        //   \   /
-//        //     30
-//        //       \
-//        //         40
-//        //
-//        // If we were using GHOST, we would prefer the bottom fork, because at each step the total
-//        // subtree weight of the lower fork is higher.  As is, we prefer the top fork.
-//        test_fork_choice_rule_helper(
-//            vec![(1, 0, 65), (2, 1, 20), (3, 0, 30), (4, 3, 35), (5, 3, 40)],
-//            2,
-//        );
-//    }
+
+    fn test_fork_choice_rule_helper(graph: Vec<(u32, u32, usize)>, expect: u32) {
+        let storage = create_beacon_shard_storages().0;
+        let genesis =
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
+        let bc = BlockChain::new(genesis.clone(), storage);
+        let mut blocks: HashMap<u32, SignedBeaconBlock> = HashMap::new();
+        blocks.insert(0, genesis.clone());
+
+        for (self_id, parent_id, sign_count) in graph.iter() {
+            let mut block;
+            {
+                let parent = blocks.get(parent_id).unwrap();
+                block = SignedBeaconBlock::new(
+                    parent.body.header.index + 1,
+                    parent.block_hash(),
+                    vec![],
+                    vec![hash(&[*self_id as u8])],
+                );
+            }
+            for i in 0..*sign_count {
+                // Having proper signing here is far too slow, and unnecessary for this test
+                let sig = BlsSignature::empty();
+                block.add_signature(&sig, i);
+            }
+            blocks.insert(*self_id, block.clone());
+            bc.insert_block(block.clone());
+        }
+        let best_block = bc.best_block();
+        assert_eq!(best_block.block_hash(), blocks.get(&expect).unwrap().block_hash());
+        // Indices point to the blocks of the best chain.
+        let mut block = best_block;
+        while block.index() > 0 {
+            assert_eq!(bc.get_block(&BlockId::Number(block.index())).unwrap(), block);
+            block = bc.get_block(&BlockId::Hash(block.body.header.parent_hash)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fork_choice_rule() {
+        // First 3 examples from https://ethresear.ch/t/immediate-message-driven-ghost-as-ffg-fork-choice-rule/2561
+
+        //    15 - 16 - 65
+        //  /
+        // -
+        //  \
+        //    55 - 56
+        //
+        // We prefer the bottom fork, even though the top is longer.
+        test_fork_choice_rule_helper(
+            vec![(1, 0, 15), (2, 1, 16), (3, 2, 65), (4, 0, 55), (5, 4, 56)],
+            5,
+        );
+        test_fork_choice_rule_helper(
+            vec![(4, 0, 55), (5, 4, 56), (1, 0, 15), (2, 1, 16), (3, 2, 65)],
+            5,
+        );
+
+        //    15 - 51
+        //  /
+        // -
+        //  \
+        //    65 - 20
+        test_fork_choice_rule_helper(vec![(1, 0, 15), (2, 0, 65), (3, 1, 51), (4, 2, 20)], 4);
+
+        //    40 - 51
+        //  /
+        // -
+        //  \
+        //    60 - 5
+        test_fork_choice_rule_helper(vec![(1, 0, 40), (2, 0, 60), (3, 1, 51), (4, 2, 5)], 3);
+
+        //    65 - 20
+        //  /
+        // -
+        //  \      35
+        //   \   /
+        //     30
+        //       \
+        //         40
+        //
+        // If we were using GHOST, we would prefer the bottom fork, because at each step the total
+        // subtree weight of the lower fork is higher.  As is, we prefer the top fork.
+        test_fork_choice_rule_helper(
+            vec![(1, 0, 65), (2, 1, 20), (3, 0, 30), (4, 3, 35), (5, 3, 40)],
+            2,
+        );
+    }
+
+    fn signed_block(parent: &SignedBeaconBlock, sign_count: usize, seed: u8) -> SignedBeaconBlock {
+        let mut block = SignedBeaconBlock::new(
+            parent.body.header.index + 1,
+            parent.block_hash(),
+            vec![],
+            vec![hash(&[seed])],
+        );
+        for i in 0..sign_count {
+            block.add_signature(&BlsSignature::empty(), i);
+        }
+        block
+    }
+
+    #[test]
+    fn test_reorg() {
+        let storage = create_beacon_shard_storages().0;
+        let genesis =
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
+        let bc = BlockChain::new(genesis.clone(), storage);
+        let a1 = signed_block(&genesis, 1, 1);
+        let a2 = signed_block(&a1, 1, 2);
+        let b1 = signed_block(&genesis, 1, 3);
+        let b2 = signed_block(&b1, 2, 4);
+        assert_eq!(bc.insert_block(a1.clone()), BlockInsertResult::Extended);
+        assert_eq!(bc.insert_block(a2.clone()), BlockInsertResult::Extended);
+        assert_eq!(bc.insert_block(a2.clone()), BlockInsertResult::Known);
+        assert_eq!(bc.insert_block(b1.clone()), BlockInsertResult::Fork);
+        assert_eq!(
+            bc.insert_block(b2.clone()),
+            BlockInsertResult::Reorg {
+                reverted: vec![a2.block_hash(), a1.block_hash()],
+                applied: vec![b1.block_hash(), b2.block_hash()],
+            }
+        );
+        assert_eq!(bc.best_hash(), b2.block_hash());
+        assert_eq!(bc.get_block(&BlockId::Number(1)).unwrap(), b1);
+        // Blocks of the old branch are still available by hash.
+        assert_eq!(bc.get_block(&BlockId::Hash(a1.block_hash())).unwrap(), a1);
+        let orphan = signed_block(&signed_block(&b2, 1, 5), 1, 6);
+        assert_eq!(bc.insert_block(orphan), BlockInsertResult::MissingParent);
+        // Switch back explicitly.
+        assert_eq!(
+            bc.set_best_block(&a2.block_hash()),
+            Ok(BlockInsertResult::Reorg {
+                reverted: vec![b2.block_hash(), b1.block_hash()],
+                applied: vec![a1.block_hash(), a2.block_hash()],
+            })
+        );
+        assert_eq!(bc.get_block(&BlockId::Number(2)).unwrap(), a2);
+    }
+
+    #[test]
+    fn test_reorg_to_shorter_branch() {
+        let storage = create_beacon_shard_storages().0;
+        let genesis =
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
+        let bc = BlockChain::new(genesis.clone(), storage);
+        let a1 = signed_block(&genesis, 1, 1);
+        let a2 = signed_block(&a1, 1, 2);
+        let a3 = signed_block(&a2, 1, 3);
+        let b1 = signed_block(&genesis, 4, 4);
+        bc.insert_block(a1);
+        bc.insert_block(a2);
+        bc.insert_block(a3);
+        match bc.insert_block(b1.clone()) {
+            BlockInsertResult::Reorg { .. } => {}
+            result => panic!("Expected a reorg, got {:?}", result),
+        }
+        assert_eq!(bc.get_block(&BlockId::Number(1)).unwrap(), b1);
+        assert!(bc.get_block(&BlockId::Number(2)).is_none());
+        assert!(bc.get_block(&BlockId::Number(3)).is_none());
+        let b2 = signed_block(&b1, 1, 5);
+        assert_eq!(bc.insert_block(b2.clone()), BlockInsertResult::Extended);
+        assert_eq!(bc.get_block(&BlockId::Number(2)).unwrap(), b2);
+        assert!(bc.get_block(&BlockId::Number(3)).is_none());
+        assert_eq!(bc.get_blocks_by_index(1, 2).unwrap(), vec![b1, b2]);
+    }
+
+    #[test]
+    fn test_no_reorg_past_finalized() {
+        let storage = create_beacon_shard_storages().0;
+        let genesis =
+            SignedBeaconBlock::new(0, CryptoHash::default(), vec![], vec![]);
+        let bc = BlockChain::new(genesis.clone(), storage);
+        let a1 = signed_block(&genesis, 1, 1);
+        let b1 = signed_block(&genesis, 1, 2);
+        let b2 = signed_block(&b1, 5, 3);
+        bc.insert_block(a1.clone());
+        bc.set_finalized_index(1);
+        assert_eq!(bc.insert_block(b1.clone()), BlockInsertResult::Fork);
+        // Heavier, but would revert the finalized block.
+        assert_eq!(bc.insert_block(b2.clone()), BlockInsertResult::Fork);
+        assert_eq!(bc.best_hash(), a1.block_hash());
+        assert!(bc.set_best_block(&b2.block_hash()).is_err());
+    }
 }
@@ -1,6 +1,7 @@
 use bencher::{benchmark_group, benchmark_main, Bencher};
 
 use client::{BlockProductionResult, ChainConsensusBlockBody, Client};
+use configs::chain_spec::{DEFAULT_MAX_GAS_LIMIT, DEFAULT_MAX_GAS_PRICE, StorageRentConfig};
 use configs::{ChainSpec, ClientConfig};
 use primitives::aggregate_signature::{BlsPublicKey, BlsSecretKey};
 use primitives::block_traits::SignedBlock;
@@ -56,6 +57,12 @@ fn get_chain_spec() -> (ChainSpec, SK, SK) {
         beacon_chain_epoch_length: 1,
         beacon_chain_num_seats_per_slot: 1,
         boot_nodes: vec![],
+        fees: Default::default(),
+        num_shards: 1,
+        max_gas_limit: DEFAULT_MAX_GAS_LIMIT,
+        max_gas_price: DEFAULT_MAX_GAS_PRICE,
+        storage_rent: StorageRentConfig::default(),
+        genesis_state: None,
     };
     (spec, alice_sk, bob_sk)
 }
@@ -101,7 +108,7 @@ fn transaction_and_receipts_to_consensus(
 fn produce_blocks(batches: &mut Vec<Vec<SignedTransaction>>, client: &mut Client) {
     let mut prev_receipt_blocks = vec![];
     let mut transactions;
-    let mut next_block_idx = client.shard_client.chain.best_index() + 1;
+    let mut next_block_idx = client.beacon_chain.chain.best_index() + 1;
     loop {
         if batches.is_empty() {
             if prev_receipt_blocks.is_empty() {
@@ -119,14 +126,17 @@ fn produce_blocks(batches: &mut Vec<Vec<SignedTransaction>>, client: &mut Client
             prev_receipt_blocks,
             next_block_idx,
         );
-        if let BlockProductionResult::Success(_beacon_block, shard_block) =
+        if let BlockProductionResult::Success(_beacon_block, shard_blocks) =
             client.try_produce_block(consensus)
         {
-            prev_receipt_blocks = client
-                .shard_client
-                .get_receipt_block(shard_block.index(), shard_block.shard_id())
-                .map(|b| vec![b])
-                .unwrap_or(vec![]);
+            prev_receipt_blocks = vec![];
+            for shard_block in shard_blocks {
+                let shard_client = client.shard_client(shard_block.shard_id());
+                for shard_id in 0..client.num_shards() {
+                    prev_receipt_blocks
+                        .extend(shard_client.get_receipt_block(shard_block.index(), shard_id));
+                }
+            }
         } else {
             panic!("Block production should always succeed");
         }
@@ -167,6 +177,7 @@ fn deploy_test_contract(
         nonce: next_nonce,
         contract_id: CONTRACT_ID.to_string(),
         wasm_byte_array: wasm_binary.to_vec(),
+        migration_method_name: None,
     };
     let t_deploy = TransactionBody::DeployContract(t_deploy);
     let t_deploy = sign_transaction(t_deploy, &contract_sk);
@@ -189,6 +200,8 @@ fn call_contract(
         method_name: method_name.as_bytes().to_vec(),
         args: args.as_bytes().to_vec(),
         amount: 0,
+        gas_limit: None,
+        gas_price: None,
     };
     let t = TransactionBody::FunctionCall(t);
     let t = sign_transaction(t, &deployer_sk);
@@ -200,10 +213,10 @@ fn call_contract(
 fn verify_transaction_statuses(hashes: &Vec<CryptoHash>, client: &mut Client) {
     for h in hashes {
         assert_eq!(
-            client.shard_client.get_transaction_final_result(h).status,
+            client.get_transaction_final_result(h).status,
             FinalTransactionStatus::Completed,
             "Transaction was not completed {:?}",
-            client.shard_client.get_transaction_final_result(h)
+            client.get_transaction_final_result(h)
         );
     }
 }
@@ -243,7 +256,7 @@ fn money_transaction_blocks(bench: &mut Bencher) {
             }
             let t = TransactionBody::SendMoney(t);
             let t = sign_transaction(t, &sk);
-            hashes.push(t.body.get_hash());
+            hashes.push(t.get_hash());
             batch.push(t);
         }
         batches.push(batch);
@@ -269,7 +282,7 @@ fn heavy_storage_blocks(bench: &mut Bencher) {
     // First run the client until the contract account is created.
     let (t_create, t_deploy, _next_nonce) = deploy_test_contract(&secret_key_alice, next_nonce);
     next_nonce = _next_nonce;
-    hashes.extend(vec![t_create.body.get_hash(), t_deploy.body.get_hash()]);
+    hashes.extend(vec![t_create.get_hash(), t_deploy.get_hash()]);
     produce_blocks(&mut vec![vec![t_create]], &mut client);
     // First run the client until the contract is deployed.
     produce_blocks(&mut vec![vec![t_deploy]], &mut client);
@@ -279,7 +292,7 @@ fn heavy_storage_blocks(bench: &mut Bencher) {
         for _transaction_idx in 0..transactions_per_block {
             let (t, _next_nonce) = call_contract(&secret_key_alice, next_nonce, method_name, args);
             next_nonce = _next_nonce;
-            hashes.push(t.body.get_hash());
+            hashes.push(t.get_hash());
             batch.push(t);
         }
         batches.push(batch);
@@ -307,7 +320,7 @@ fn set_get_values_blocks(bench: &mut Bencher) {
     // First run the client until the contract account is created.
     let (t_create, t_deploy, _next_nonce) = deploy_test_contract(&secret_key_alice, next_nonce);
     next_nonce = _next_nonce;
-    hashes.extend(vec![t_create.body.get_hash(), t_deploy.body.get_hash()]);
+    hashes.extend(vec![t_create.get_hash(), t_deploy.get_hash()]);
     produce_blocks(&mut vec![vec![t_create]], &mut client);
     // First run the client until the contract is deployed.
     produce_blocks(&mut vec![vec![t_deploy]], &mut client);
@@ -325,7 +338,7 @@ fn set_get_values_blocks(bench: &mut Bencher) {
                 format!("{{\"key\":\"{}\", \"value\":\"{}\"}}", key, value).as_str(),
             );
             next_nonce = _next_nonce;
-            hashes.push(t.body.get_hash());
+            hashes.push(t.get_hash());
             batch.push(t);
         }
         batches.push(batch);
@@ -337,10 +350,10 @@ fn set_get_values_blocks(bench: &mut Bencher) {
         // As a part of the benchmark get values from the storage and verify that they are correct.
 
         for (k, v) in expected_storage.iter() {
-            let state_update = client.shard_client.get_state_update();
-            let best_index = client.shard_client.chain.best_index();
-            let res = client
-                .shard_client
+            let shard_client = client.shard_client_for_account(&CONTRACT_ID.to_string());
+            let state_update = shard_client.get_state_update();
+            let best_index = shard_client.chain.best_index();
+            let res = shard_client
                 .trie_viewer
                 .call_function(
                     state_update,
@@ -18,16 +18,23 @@ use std::{cmp, env, fs};
 use env_logger::Builder;
 
 use beacon::beacon_chain::BeaconClient;
+use chain::BlockInsertResult;
 use configs::ClientConfig;
 use primitives::beacon::{SignedBeaconBlock, SignedBeaconBlockHeader};
 use primitives::block_traits::SignedBlock;
-use primitives::chain::{ChainPayload, SignedShardBlock};
-use primitives::hash::CryptoHash;
+use primitives::chain::{ChainPayload, ReceiptBlock, SignedShardBlock};
+use primitives::hash::{hash_struct, CryptoHash};
 use primitives::signer::InMemorySigner;
-use primitives::types::{AccountId, AuthorityStake, ConsensusBlockBody, UID};
-use shard::ShardClient;
+use primitives::transaction::{
+    FinalTransactionResult, SignedTransaction, TransactionResult, TransactionStatus,
+};
+use primitives::types::{
+    AccountId, AuthorityStake, BlockId, ConsensusBlockBody, GroupSignature, ShardId, UID,
+};
+use primitives::utils::account_to_shard_id;
+use shard::{ShardClient, SignedTransactionInfo};
 use std::sync::RwLock;
-use storage::create_storage;
+use storage::{create_storage, StorageBackend};
 
 const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
 
@@ -37,7 +44,7 @@ type BlockIdx = u64;
 #[allow(clippy::large_enum_variant)]  // This enum is no different from `Option`.
 pub enum BlockProductionResult {
     /// The blocks were successfully produced.
-    Success(SignedBeaconBlock, SignedShardBlock),
+    Success(SignedBeaconBlock, Vec<SignedShardBlock>),
     /// The consensus was achieved after the block with the given index was already imported.
     /// The beacon and the shard chains are currently at index `current_index`.
     LateConsensus { current_index: BlockIdx },
@@ -55,13 +62,16 @@ pub enum BlockImportingResult {
     MissingParent { parent_hash: CryptoHash, missing_indices: Vec<BlockIdx> },
     /// The block was not imported, because it is already in the blockchain.
     AlreadyImported,
+    /// The block was not imported, because it does not have a shard block for every shard.
+    InvalidBlock,
 }
 
 pub struct Client {
     pub account_id: AccountId,
     pub signer: InMemorySigner,
 
-    pub shard_client: ShardClient,
+    /// Clients of the shards, indexed by the shard id. The node tracks all the shards.
+    pub shard_clients: Vec<ShardClient>,
     pub beacon_chain: BeaconClient,
 
     // TODO: The following logic might need to be hidden somewhere.
@@ -106,6 +116,7 @@ pub const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
 
 const STORAGE_PATH: &str = "storage/db";
 const KEY_STORE_PATH: &str = "storage/keystore";
+const WASM_CACHE_PATH: &str = "storage/wasm_cache";
 
 fn get_storage_path(base_path: &Path) -> String {
     let mut storage_path = base_path.to_owned();
@@ -119,19 +130,34 @@ fn get_storage_path(base_path: &Path) -> String {
 
 pub type ChainConsensusBlockBody = ConsensusBlockBody<ChainPayload>;
 
+/// Whether the authorities in the mask of the signature hold more than 2/3 of the stake of the
+/// authority set. A block signed by fewer authorities may come from a malicious minority.
+fn signed_by_supermajority(signature: &GroupSignature, authorities: &[AuthorityStake]) -> bool {
+    let total_stake: u128 = authorities.iter().map(|a| u128::from(a.amount)).sum();
+    let signed_stake: u128 = authorities
+        .iter()
+        .zip(signature.authority_mask.iter())
+        .filter(|(_, signed)| **signed)
+        .map(|(a, _)| u128::from(a.amount))
+        .sum();
+    signed_stake * 3 > total_stake * 2
+}
+
 impl Client {
     pub fn new(config: &ClientConfig) -> Self {
         let storage_path = get_storage_path(&config.base_path);
-        // For now, use only one shard.
-        let num_shards = 1;
-        let (beacon_storage, mut shard_storages) =
-            create_storage(storage_path.as_str(), num_shards);
-        let shard_storage = shard_storages.pop().unwrap();
-
         let chain_spec = &config.chain_spec;
-        let shard_client = ShardClient::new(chain_spec, shard_storage);
-        info!(target: "client", "Genesis root: {:?}", shard_client.genesis_hash());
-        let genesis = SignedBeaconBlock::genesis(shard_client.genesis_hash());
+        let (beacon_storage, shard_storages) =
+            create_storage(storage_path.as_str(), chain_spec.num_shards, config.storage_backend);
+
+        let shard_clients: Vec<_> = shard_storages
+            .into_iter()
+            .enumerate()
+            .map(|(shard_id, storage)| ShardClient::new(chain_spec, shard_id as ShardId, storage))
+            .collect();
+        let genesis_hashes: Vec<_> = shard_clients.iter().map(|c| c.genesis_hash()).collect();
+        info!(target: "client", "Genesis roots: {:?}", genesis_hashes);
+        let genesis = SignedBeaconBlock::genesis(genesis_hashes);
         let beacon_chain = BeaconClient::new(genesis, &chain_spec, beacon_storage);
 
         let mut key_file_path = config.base_path.to_path_buf();
@@ -144,16 +170,84 @@ impl Client {
 
         configure_logging(config.log_level);
 
+        // The other backends must not write to the disk, the contracts are only cached in memory.
+        if config.storage_backend == StorageBackend::RocksDb {
+            let mut wasm_cache_path = config.base_path.to_path_buf();
+            wasm_cache_path.push(WASM_CACHE_PATH);
+            if let Err(e) = wasm::cache::set_disk_cache_dir(wasm_cache_path) {
+                warn!(target: "client", "Failed to enable the compiled contracts cache: {}", e);
+            }
+        }
+
         Self {
             account_id: config.account_id.clone(),
             signer,
-            shard_client,
+            shard_clients,
             beacon_chain,
             pending_beacon_blocks: RwLock::new(HashMap::new()),
             pending_shard_blocks: RwLock::new(HashMap::new()),
         }
     }
 
+    pub fn num_shards(&self) -> ShardId {
+        self.shard_clients.len() as ShardId
+    }
+
+    pub fn shard_client(&self, shard_id: ShardId) -> &ShardClient {
+        &self.shard_clients[shard_id as usize]
+    }
+
+    /// Client of the shard that the given account belongs to.
+    pub fn shard_client_for_account(&self, account_id: &AccountId) -> &ShardClient {
+        self.shard_client(account_to_shard_id(account_id, self.num_shards()))
+    }
+
+    /// Removes the states of the blocks that are more than `retention` blocks behind the best
+    /// block in every shard. Returns the number of removed trie nodes.
+    pub fn prune_state(&self, retention: BlockIdx) -> usize {
+        self.shard_clients.iter().map(|c| c.prune_state(retention)).sum()
+    }
+
+    /// Result of the transaction or the receipt, looked up in all the shards.
+    pub fn get_transaction_result(&self, hash: &CryptoHash) -> TransactionResult {
+        self.shard_clients
+            .iter()
+            .map(|c| c.get_transaction_result(hash))
+            .find(|r| r.status != TransactionStatus::Unknown)
+            .unwrap_or_default()
+    }
+
+    pub fn get_transaction_info(&self, hash: &CryptoHash) -> Option<SignedTransactionInfo> {
+        self.shard_clients.iter().find_map(|c| c.get_transaction_info(hash))
+    }
+
+    /// Final result of the transaction, following its receipts across the shards.
+    pub fn get_transaction_final_result(&self, hash: &CryptoHash) -> FinalTransactionResult {
+        shard::get_transaction_final_result(hash, |hash| self.get_transaction_result(hash))
+    }
+
+    /// Splits the payload between the shards: transactions go to the shard of the originator,
+    /// receipt blocks go to the shard of their receivers.
+    fn split_payload(
+        &self,
+        transactions: Vec<SignedTransaction>,
+        receipts: Vec<ReceiptBlock>,
+    ) -> Vec<(Vec<SignedTransaction>, Vec<ReceiptBlock>)> {
+        let num_shards = self.num_shards();
+        let mut payloads: Vec<_> = (0..num_shards).map(|_| (vec![], vec![])).collect();
+        for transaction in transactions {
+            let shard_id = account_to_shard_id(&transaction.body.get_originator(), num_shards);
+            payloads[shard_id as usize].0.push(transaction);
+        }
+        for receipt_block in receipts {
+            match receipt_block.receipts.first().map(|r| r.shard_id(num_shards)) {
+                Some(shard_id) => payloads[shard_id as usize].1.push(receipt_block),
+                None => warn!(target: "client", "Dropping empty receipt block"),
+            }
+        }
+        payloads
+    }
+
     /// Get indices of the blocks that we are missing.
     fn get_missing_indices(&self) -> Vec<BlockIdx> {
         // Use `pending_beacon_blocks` because currently beacon blocks and shard blocks are tied
@@ -192,28 +286,51 @@ impl Client {
         }
 
         let last_block = self.beacon_chain.chain.best_block();
-        let authorities = self
-            .beacon_chain
-            .authority
-            .read()
-            .expect(POISONED_LOCK_ERR)
-            .get_authorities(last_block.body.header.index + 1)
-            .expect("Authorities should be present for given block to produce it");
-        let (mut shard_block, (transaction, authority_proposals, tx_results, new_receipts)) = self
-            .shard_client
-            .prepare_new_block(last_block.body.header.shard_block_hash, receipts, transactions);
+        let (authorities, withdrawals) = {
+            let authority = self.beacon_chain.authority.read().expect(POISONED_LOCK_ERR);
+            let index = last_block.body.header.index + 1;
+            (
+                authority
+                    .get_authorities(index)
+                    .expect("Authorities should be present for given block to produce it"),
+                authority.get_withdrawals(index),
+            )
+        };
+        // The states are pruned only after the new shard blocks are stored.
+        let _pruning: Vec<_> = self.shard_clients.iter().map(|c| c.trie.pause_pruning()).collect();
+        let mut shard_blocks = vec![];
+        let mut shard_extras = vec![];
+        let mut authority_proposals = vec![];
+        let payloads = self.split_payload(transactions, receipts);
+        for (shard_client, (transactions, receipts)) in self.shard_clients.iter().zip(payloads) {
+            let (shard_block, (db_changes, proposals, scheduled, tx_results, new_receipts)) =
+                shard_client.prepare_new_block(
+                    last_block.body.header.shard_block_hashes[shard_client.shard_id as usize],
+                    receipts,
+                    transactions,
+                    withdrawals.clone(),
+                );
+            authority_proposals.extend(proposals);
+            shard_blocks.push(shard_block);
+            shard_extras.push((db_changes, scheduled, tx_results, new_receipts));
+        }
         let mut block = SignedBeaconBlock::new(
             last_block.body.header.index + 1,
             last_block.block_hash(),
             authority_proposals,
-            shard_block.block_hash(),
+            shard_blocks.iter().map(|b| b.block_hash()).collect(),
         );
-        let shard_block_signature = shard_block.sign(&self.signer);
         let block_signature = block.sign(&self.signer);
+        let shard_block_signatures: Vec<_> =
+            shard_blocks.iter().map(|b| b.sign(&self.signer)).collect();
         for (i, authority) in authorities.iter().enumerate() {
             if authority.account_id == self.signer.account_id {
-                shard_block.add_signature(&shard_block_signature, i);
                 block.add_signature(&block_signature, i);
+                for (shard_block, signature) in
+                    shard_blocks.iter_mut().zip(shard_block_signatures.iter())
+                {
+                    shard_block.add_signature(signature, i);
+                }
             }
         }
 
@@ -223,18 +340,35 @@ impl Client {
              This should never happen, because block production is atomic."
         );
 
-        self.shard_client.insert_block(&shard_block.clone(), transaction, tx_results, new_receipts);
+        for ((shard_client, shard_block), (db_changes, scheduled, tx_results, new_receipts)) in
+            self.shard_clients.iter().zip(shard_blocks.iter()).zip(shard_extras)
+        {
+            shard_client.insert_block(
+                shard_block,
+                db_changes,
+                &scheduled,
+                tx_results,
+                new_receipts,
+            );
+        }
         self.beacon_chain.chain.insert_block(block.clone());
+        // The block was agreed on by the consensus, so it cannot be reverted.
+        self.beacon_chain.chain.set_finalized_index(block.index());
+        for (shard_client, shard_block) in self.shard_clients.iter().zip(shard_blocks.iter()) {
+            shard_client.chain.set_finalized_index(shard_block.index());
+        }
         info!(target: "client",
-                  "Producing block index: {:?}, beacon = {:?}, shard = {:?}",
-                  block.body.header.index, block.hash, shard_block.hash);
+                  "Producing block index: {:?}, beacon = {:?}, shards = {:?}",
+                  block.body.header.index, block.hash, block.body.header.shard_block_hashes);
         io::stdout().flush().expect("Could not flush stdout");
         // Just produced blocks should be the best in the blockchain.
-        assert_eq!(self.shard_client.chain.best_block().hash, shard_block.hash);
+        for (shard_client, shard_block) in self.shard_clients.iter().zip(shard_blocks.iter()) {
+            assert_eq!(shard_client.chain.best_block().hash, shard_block.hash);
+        }
         assert_eq!(self.beacon_chain.chain.best_block().hash, block.hash);
         // Update the authority.
         self.update_authority(&block.header());
-        BlockProductionResult::Success(block, shard_block)
+        BlockProductionResult::Success(block, shard_blocks)
     }
 
     fn blocks_to_process(
@@ -242,14 +376,15 @@ impl Client {
     ) -> (Vec<SignedBeaconBlock>, HashMap<CryptoHash, SignedBeaconBlock>) {
         let mut part_add = vec![];
         let mut part_pending = HashMap::default();
+        let pending_shard_blocks = self.pending_shard_blocks.read().expect(POISONED_LOCK_ERR);
         for (hash, other) in self.pending_beacon_blocks.write().expect(POISONED_LOCK_ERR).drain() {
             if self.beacon_chain.chain.is_known(&other.body.header.parent_hash)
-                && (self.shard_client.chain.is_known(&other.body.header.shard_block_hash)
-                    || self
-                        .pending_shard_blocks
-                        .read()
-                        .expect(POISONED_LOCK_ERR)
-                        .contains_key(&other.body.header.shard_block_hash))
+                && self.shard_clients.iter().zip(other.body.header.shard_block_hashes.iter()).all(
+                    |(shard_client, shard_block_hash)| {
+                        shard_client.chain.is_known(shard_block_hash)
+                            || pending_shard_blocks.contains_key(shard_block_hash)
+                    },
+                )
             {
                 part_add.push(other);
             } else {
@@ -265,15 +400,19 @@ impl Client {
     pub fn try_import_blocks(
         &self,
         beacon_block: SignedBeaconBlock,
-        shard_block: SignedShardBlock,
+        shard_blocks: Vec<SignedShardBlock>,
     ) -> BlockImportingResult {
         // Check if this block was either already added, or it is already pending, or it has
         // invalid signature.
         let hash = beacon_block.block_hash();
-        info!(target: "client", "Importing block index: {:?}, beacon = {:?}, shard = {:?}", beacon_block.body.header.index, beacon_block.hash, shard_block.hash);
+        info!(target: "client", "Importing block index: {:?}, beacon = {:?}, shards = {:?}", beacon_block.body.header.index, beacon_block.hash, beacon_block.body.header.shard_block_hashes);
         if self.beacon_chain.chain.is_known(&hash) {
             return BlockImportingResult::AlreadyImported;
         }
+        if beacon_block.body.header.shard_block_hashes.len() != self.shard_clients.len() {
+            error!(target: "client", "Beacon block {:?} has a wrong number of shard blocks", hash);
+            return BlockImportingResult::InvalidBlock;
+        }
 
         if self.pending_beacon_blocks.read().expect(POISONED_LOCK_ERR).contains_key(&hash) {
             return BlockImportingResult::MissingParent {
@@ -285,7 +424,7 @@ impl Client {
         self.pending_shard_blocks
             .write()
             .expect(POISONED_LOCK_ERR)
-            .insert(shard_block.hash, shard_block);
+            .extend(shard_blocks.into_iter().map(|b| (b.hash, b)));
         self.pending_beacon_blocks.write().expect(POISONED_LOCK_ERR).insert(hash, beacon_block);
         let best_block_hash = self.beacon_chain.chain.best_hash();
 
@@ -308,18 +447,31 @@ impl Client {
                 continue;
             }
 
-            let next_shard_block = self
-                .pending_shard_blocks
-                .write()
+            let withdrawals = self
+                .beacon_chain
+                .authority
+                .read()
                 .expect(POISONED_LOCK_ERR)
-                .remove(&next_beacon_block.body.header.shard_block_hash)
-                .expect("Expected to have shard block present when processing beacon block");
-
-            if self.shard_client.apply_block(next_shard_block) {
-                self.beacon_chain.chain.insert_block(next_beacon_block.clone());
+                .get_withdrawals(next_beacon_block.body.header.index);
+            let mut applied = true;
+            let shard_block_hashes = next_beacon_block.body.header.shard_block_hashes.iter();
+            for (shard_client, shard_block_hash) in self.shard_clients.iter().zip(shard_block_hashes) {
+                // Skip the shard blocks that were already applied.
+                if shard_client.chain.is_known(shard_block_hash) {
+                    continue;
+                }
+                let next_shard_block = self
+                    .pending_shard_blocks
+                    .write()
+                    .expect(POISONED_LOCK_ERR)
+                    .remove(shard_block_hash)
+                    .expect("Expected to have shard block present when processing beacon block");
+                applied &= shard_client.apply_block(next_shard_block, withdrawals.clone());
+            }
+            if applied {
+                let result = self.beacon_chain.chain.insert_block(next_beacon_block.clone());
+                self.process_insert_result(&next_beacon_block, result);
             }
-            // Update the authority.
-            self.update_authority(&next_beacon_block.header());
         }
         let new_best_block = self.beacon_chain.chain.best_block();
 
@@ -333,6 +485,143 @@ impl Client {
         }
     }
 
+    /// Imports blocks that extend the best chain without applying their transactions. Used by
+    /// the state sync, the state of the last imported block is downloaded afterwards. Stops at
+    /// the first block that does not extend the chain or is not signed by its authorities, and
+    /// returns the number of imported blocks.
+    pub fn import_blocks_without_state(
+        &self,
+        blocks: Vec<(SignedBeaconBlock, Vec<SignedShardBlock>)>,
+    ) -> usize {
+        let mut imported = 0;
+        for (beacon_block, shard_blocks) in blocks {
+            let extends_chain = beacon_block.body.header.parent_hash
+                == self.beacon_chain.chain.best_hash()
+                && shard_blocks.len() == self.shard_clients.len()
+                && beacon_block.body.header.shard_block_hashes.len() == self.shard_clients.len()
+                && self
+                    .shard_clients
+                    .iter()
+                    .zip(shard_blocks.iter())
+                    .zip(beacon_block.body.header.shard_block_hashes.iter())
+                    .all(|((shard_client, shard_block), hash)| {
+                        shard_block.hash == *hash
+                            && shard_block.body.header.parent_hash == shard_client.chain.best_hash()
+                    });
+            if !extends_chain || !self.verify_block_signatures(&beacon_block, &shard_blocks) {
+                break;
+            }
+            for (shard_client, shard_block) in self.shard_clients.iter().zip(shard_blocks) {
+                shard_client.chain.insert_block(shard_block);
+            }
+            self.beacon_chain.chain.insert_block(beacon_block.clone());
+            // Authorities only depend on the beacon headers, so they are still tracked.
+            self.update_authority(&beacon_block.header());
+            imported += 1;
+        }
+        imported
+    }
+
+    /// Checks that the hashes of the blocks match their headers and that the blocks are signed by
+    /// authorities holding more than 2/3 of the stake of their index. The state roots of the
+    /// blocks imported without applying them are only trusted because of these signatures.
+    fn verify_block_signatures(
+        &self,
+        beacon_block: &SignedBeaconBlock,
+        shard_blocks: &[SignedShardBlock],
+    ) -> bool {
+        let authorities = match self
+            .beacon_chain
+            .authority
+            .read()
+            .expect(POISONED_LOCK_ERR)
+            .get_authorities(beacon_block.index())
+        {
+            Ok(authorities) => authorities,
+            Err(err) => {
+                warn!(target: "client", "Cannot verify block {}: {}", beacon_block.hash, err);
+                return false;
+            }
+        };
+        let keys: Vec<_> = authorities.iter().map(|a| a.public_key.clone()).collect();
+        let valid = beacon_block.hash == hash_struct(&beacon_block.body.header)
+            && signed_by_supermajority(&beacon_block.signature, &authorities)
+            && beacon_block.signature.verify(&keys, beacon_block.hash.as_ref())
+            && shard_blocks.iter().all(|shard_block| {
+                shard_block.hash == hash_struct(&shard_block.body.header)
+                    && signed_by_supermajority(&shard_block.signature, &authorities)
+                    && shard_block.signature.verify(&keys, shard_block.hash.as_ref())
+            });
+        if !valid {
+            warn!(target: "client", "Block {} has an invalid signature", beacon_block.hash);
+        }
+        valid
+    }
+
+    /// Returns beacon blocks with their shard blocks for indices `from..=to`, stopping at the
+    /// first missing block.
+    pub fn get_blocks_range(
+        &self,
+        from: BlockIdx,
+        to: BlockIdx,
+    ) -> Vec<(SignedBeaconBlock, Vec<SignedShardBlock>)> {
+        if to < from {
+            return vec![];
+        }
+        let beacon_blocks =
+            self.beacon_chain.chain.get_blocks_by_index(from, to - from).unwrap_or_default();
+        let mut result = vec![];
+        for (index, beacon_block) in (from..=to).zip(beacon_blocks) {
+            if beacon_block.index() != index {
+                break;
+            }
+            let shard_blocks: Option<Vec<_>> = self
+                .shard_clients
+                .iter()
+                .zip(beacon_block.body.header.shard_block_hashes.iter())
+                .map(|(shard_client, hash)| shard_client.chain.get_block(&BlockId::Hash(*hash)))
+                .collect();
+            match shard_blocks {
+                Some(shard_blocks) => result.push((beacon_block, shard_blocks)),
+                None => break,
+            }
+        }
+        result
+    }
+
+    /// Updates the authorities and the shard chains after the beacon block was inserted. When the
+    /// beacon chain switches to another branch, the authorities are rolled back to the common
+    /// ancestor and follow the new branch, and the shard chains follow the shard blocks of the new
+    /// best beacon block.
+    fn process_insert_result(&self, beacon_block: &SignedBeaconBlock, result: BlockInsertResult) {
+        match result {
+            BlockInsertResult::Extended => self.update_authority(&beacon_block.header()),
+            BlockInsertResult::Reorg { reverted, applied } => {
+                info!(target: "client",
+                      "Switching to the branch of block {:?}, reverting {} blocks",
+                      beacon_block.hash, reverted.len());
+                let ancestor_index = self.beacon_chain.chain.best_index() - applied.len() as u64;
+                let mut authority = self.beacon_chain.authority.write().expect(POISONED_LOCK_ERR);
+                authority.revert_to(ancestor_index);
+                for hash in applied {
+                    let header = self
+                        .beacon_chain
+                        .chain
+                        .get_header(&BlockId::Hash(hash))
+                        .expect("Blocks of the best chain must be known");
+                    authority.process_block_header(&header);
+                }
+            }
+            _ => {}
+        }
+        let best_block = self.beacon_chain.chain.best_block();
+        for (shard_client, hash) in
+            self.shard_clients.iter().zip(best_block.body.header.shard_block_hashes.iter())
+        {
+            shard_client.set_head(hash);
+        }
+    }
+
     // Authority-related code. Consider hiding it inside the shard chain.
     fn update_authority(&self, beacon_header: &SignedBeaconBlockHeader) {
         self.beacon_chain
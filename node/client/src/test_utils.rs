@@ -5,22 +5,28 @@ use beacon::beacon_chain::BeaconClient;
 use configs::ChainSpec;
 use primitives::beacon::SignedBeaconBlock;
 use primitives::signer::InMemorySigner;
+use primitives::types::ShardId;
 use shard::ShardClient;
 use std::sync::RwLock;
-use storage::test_utils::create_beacon_shard_storages;
+use storage::test_utils::create_memory_storage;
 
 /// Implements dummy client for testing. The differences with the real client:
 /// * It does not do the correct signing;
 /// * It has in-memory storage.
 pub fn get_client_from_cfg(chain_spec: &ChainSpec, signer: InMemorySigner) -> Client {
-    let (beacon_storage, shard_storage) = create_beacon_shard_storages();
-    let shard_client = ShardClient::new(chain_spec, shard_storage);
-    let genesis = SignedBeaconBlock::genesis(shard_client.genesis_hash());
+    let (beacon_storage, shard_storages) = create_memory_storage(chain_spec.num_shards);
+    let shard_clients: Vec<_> = shard_storages
+        .into_iter()
+        .enumerate()
+        .map(|(shard_id, storage)| ShardClient::new(chain_spec, shard_id as ShardId, storage))
+        .collect();
+    let genesis =
+        SignedBeaconBlock::genesis(shard_clients.iter().map(|c| c.genesis_hash()).collect());
     let beacon_chain = BeaconClient::new(genesis, chain_spec, beacon_storage);
     Client {
         account_id: signer.account_id.clone(),
         signer,
-        shard_client,
+        shard_clients,
         beacon_chain,
         pending_beacon_blocks: RwLock::new(HashMap::new()),
         pending_shard_blocks: RwLock::new(HashMap::new()),
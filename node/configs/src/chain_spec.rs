@@ -2,9 +2,10 @@ use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 
+use serde::de::{Deserialize, Deserializer, Error};
 use serde_json;
 
-use primitives::types::{AccountId, Balance, ReadablePublicKey, ReadableBlsPublicKey};
+use primitives::types::{AccountId, Balance, Gas, ReadablePublicKey, ReadableBlsPublicKey, ShardId};
 use primitives::network::PeerInfo;
 
 /// Specification of the blockchain in general.
@@ -22,8 +23,33 @@ pub struct ChainSpec {
     pub beacon_chain_num_seats_per_slot: u64,
 
     pub boot_nodes: Vec<PeerInfo>,
+
+    /// Fees in tokens charged for the transactions.
+    pub fees: FeeConfig,
+
+    /// Number of shards, accounts are assigned to the shards by `account_to_shard_id`.
+    pub num_shards: ShardId,
+
+    /// Maximum gas of a contract call, function call transactions can set a lower limit.
+    pub max_gas_limit: Gas,
+
+    /// Maximum gas price numerator a function call transaction can offer.
+    pub max_gas_price: Balance,
+
+    /// Balance the accounts lock for the bytes they take in the state.
+    pub storage_rent: StorageRentConfig,
+
+    /// State dump written by `state_dump export` to start the chain from instead of `accounts`,
+    /// e.g. the state of an existing chain. Only supported for a single shard.
+    pub genesis_state: Option<PathBuf>,
 }
 
+/// Maximum gas of a contract call if the chain spec does not set it.
+pub const DEFAULT_MAX_GAS_LIMIT: Gas = 10 * 1024 * 1024;
+
+/// Maximum gas price numerator if the chain spec does not set it.
+pub const DEFAULT_MAX_GAS_PRICE: Balance = 1_000_000_000;
+
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "ChainSpec")]
 struct ChainSpecRef {
@@ -33,6 +59,133 @@ struct ChainSpecRef {
     beacon_chain_epoch_length: u64,
     beacon_chain_num_seats_per_slot: u64,
     boot_nodes: Vec<PeerInfo>,
+    #[serde(default, deserialize_with = "deserialize_fees")]
+    fees: FeeConfig,
+    #[serde(default = "default_num_shards", deserialize_with = "deserialize_num_shards")]
+    num_shards: ShardId,
+    #[serde(default = "default_max_gas_limit")]
+    max_gas_limit: Gas,
+    #[serde(default = "default_max_gas_price")]
+    max_gas_price: Balance,
+    #[serde(default, deserialize_with = "deserialize_storage_rent")]
+    storage_rent: StorageRentConfig,
+    #[serde(default)]
+    genesis_state: Option<PathBuf>,
+}
+
+fn default_num_shards() -> ShardId {
+    1
+}
+
+/// Accounts are assigned to the shards modulo the number of shards, so there must be at least one.
+fn deserialize_num_shards<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ShardId, D::Error> {
+    let num_shards = ShardId::deserialize(deserializer)?;
+    if num_shards == 0 {
+        return Err(D::Error::custom("num_shards must be at least 1"));
+    }
+    Ok(num_shards)
+}
+
+/// The gas fees are divided by the gas price denumerator, so it must not be zero.
+fn deserialize_fees<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FeeConfig, D::Error> {
+    let fees = FeeConfig::deserialize(deserializer)?;
+    if fees.gas_price_denum == 0 {
+        return Err(D::Error::custom("gas_price_denum must be at least 1"));
+    }
+    Ok(fees)
+}
+
+/// The locked balance is divided by the denumerator, so it must not be zero.
+fn deserialize_storage_rent<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<StorageRentConfig, D::Error> {
+    let storage_rent = StorageRentConfig::deserialize(deserializer)?;
+    if storage_rent.balance_per_byte_denum == 0 {
+        return Err(D::Error::custom("balance_per_byte_denum must be at least 1"));
+    }
+    Ok(storage_rent)
+}
+
+fn default_max_gas_limit() -> Gas {
+    DEFAULT_MAX_GAS_LIMIT
+}
+
+fn default_max_gas_price() -> Balance {
+    DEFAULT_MAX_GAS_PRICE
+}
+
+/// Fee schedule of the transactions. The originator pays the fee of the transaction type plus the
+/// fee per byte of the code or of the function call, and prepays the gas of the function call.
+/// The gas that was not used by the contract is refunded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct FeeConfig {
+    pub create_account: Balance,
+    pub deploy_contract: Balance,
+    pub function_call: Balance,
+    pub send_money: Balance,
+    pub stake: Balance,
+    pub swap_key: Balance,
+    pub add_key: Balance,
+    pub delete_key: Balance,
+    pub unstake: Balance,
+    pub delete_account: Balance,
+    pub set_multisig_policy: Balance,
+    /// Fee per byte of the deployed code or of the method name and the arguments of the call.
+    pub per_byte: Balance,
+    /// Minimum price of a unit of wasm gas (numerator), function calls can offer a higher price.
+    pub gas_price_num: Balance,
+    /// Common denumerator for the gas price.
+    pub gas_price_denum: Balance,
+}
+
+impl Default for FeeConfig {
+    /// Transactions are free by default.
+    fn default() -> FeeConfig {
+        FeeConfig {
+            create_account: 0,
+            deploy_contract: 0,
+            function_call: 0,
+            send_money: 0,
+            stake: 0,
+            swap_key: 0,
+            add_key: 0,
+            delete_key: 0,
+            unstake: 0,
+            delete_account: 0,
+            set_multisig_policy: 0,
+            per_byte: 0,
+            gas_price_num: 0,
+            gas_price_denum: 1,
+        }
+    }
+}
+
+/// Balance an account has to keep to pay for the bytes it takes in the state.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct StorageRentConfig {
+    /// Balance locked per byte of storage (numerator).
+    pub balance_per_byte_num: Balance,
+    /// Common denumerator for the balance per byte.
+    pub balance_per_byte_denum: Balance,
+}
+
+impl Default for StorageRentConfig {
+    fn default() -> StorageRentConfig {
+        StorageRentConfig {
+            // Default is 1 coin per 100KB of storage.
+            balance_per_byte_num: 1,
+            balance_per_byte_denum: 100_000,
+        }
+    }
+}
+
+impl StorageRentConfig {
+    /// Balance that has to stay on the account to cover the given storage usage.
+    pub fn locked_balance(&self, storage_usage: u64) -> Balance {
+        storage_usage.saturating_mul(self.balance_per_byte_num) / self.balance_per_byte_denum
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -83,4 +236,89 @@ fn test_deserialize() {
         spec.initial_authorities[0],
         ("alice.near".to_string(), ReadableBlsPublicKey("7AnjkhbpbtqbZHwg4gTZJd4ZGc84EN3FUj5diEbipGinQfYA2MDfaoe5uo1qRhCnkD".to_string()), 50)
     );
+    assert_eq!(spec.fees, FeeConfig::default());
+    assert_eq!(spec.num_shards, 1);
+    assert_eq!(spec.max_gas_limit, DEFAULT_MAX_GAS_LIMIT);
+    assert_eq!(spec.max_gas_price, DEFAULT_MAX_GAS_PRICE);
+    assert_eq!(spec.storage_rent, StorageRentConfig::default());
+    assert_eq!(spec.genesis_state, None);
+}
+
+#[test]
+fn test_deserialize_fees() {
+    let data = json!({
+        "accounts": [],
+        "initial_authorities": [],
+        "genesis_wasm": [],
+        "beacon_chain_epoch_length": 10,
+        "beacon_chain_num_seats_per_slot": 100,
+        "boot_nodes": [],
+        "fees": {"send_money": 1, "per_byte": 2, "gas_price_num": 1, "gas_price_denum": 1000},
+    });
+    let spec = deserialize_chain_spec(&data.to_string());
+    assert_eq!(spec.fees.send_money, 1);
+    assert_eq!(spec.fees.per_byte, 2);
+    assert_eq!(spec.fees.gas_price_denum, 1000);
+    assert_eq!(spec.fees.function_call, 0);
+}
+
+#[test]
+#[should_panic(expected = "num_shards must be at least 1")]
+fn test_deserialize_zero_shards() {
+    let data = json!({
+        "accounts": [],
+        "initial_authorities": [],
+        "genesis_wasm": [],
+        "beacon_chain_epoch_length": 10,
+        "beacon_chain_num_seats_per_slot": 100,
+        "boot_nodes": [],
+        "num_shards": 0,
+    });
+    deserialize_chain_spec(&data.to_string());
+}
+
+#[test]
+#[should_panic(expected = "gas_price_denum must be at least 1")]
+fn test_deserialize_zero_gas_price_denum() {
+    let data = json!({
+        "accounts": [],
+        "initial_authorities": [],
+        "genesis_wasm": [],
+        "beacon_chain_epoch_length": 10,
+        "beacon_chain_num_seats_per_slot": 100,
+        "boot_nodes": [],
+        "fees": {"gas_price_num": 1, "gas_price_denum": 0},
+    });
+    deserialize_chain_spec(&data.to_string());
+}
+
+#[test]
+fn test_deserialize_storage_rent() {
+    let data = json!({
+        "accounts": [],
+        "initial_authorities": [],
+        "genesis_wasm": [],
+        "beacon_chain_epoch_length": 10,
+        "beacon_chain_num_seats_per_slot": 100,
+        "boot_nodes": [],
+        "storage_rent": {"balance_per_byte_num": 3},
+    });
+    let spec = deserialize_chain_spec(&data.to_string());
+    assert_eq!(spec.storage_rent.balance_per_byte_num, 3);
+    assert_eq!(spec.storage_rent.balance_per_byte_denum, 100_000);
+}
+
+#[test]
+#[should_panic(expected = "balance_per_byte_denum must be at least 1")]
+fn test_deserialize_zero_balance_per_byte_denum() {
+    let data = json!({
+        "accounts": [],
+        "initial_authorities": [],
+        "genesis_wasm": [],
+        "beacon_chain_epoch_length": 10,
+        "beacon_chain_num_seats_per_slot": 100,
+        "boot_nodes": [],
+        "storage_rent": {"balance_per_byte_denum": 0},
+    });
+    deserialize_chain_spec(&data.to_string());
 }
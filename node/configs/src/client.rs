@@ -5,10 +5,13 @@ use clap::{Arg, ArgMatches};
 
 use crate::chain_spec::read_or_default_chain_spec;
 use crate::chain_spec::ChainSpec;
-use primitives::types::AccountId;
+use primitives::types::{AccountId, BlockIndex};
+use storage::StorageBackend;
 
 const DEFAULT_BASE_PATH: &str = ".";
 const DEFAULT_LOG_LEVEL: &str = "Info";
+const DEFAULT_STATE_RETENTION: &str = "100";
+const DEFAULT_STORAGE_BACKEND: &str = "rocksdb";
 
 pub struct ClientConfig {
     pub base_path: PathBuf,
@@ -16,6 +19,12 @@ pub struct ClientConfig {
     pub public_key: Option<String>,
     pub chain_spec: ChainSpec,
     pub log_level: log::LevelFilter,
+    /// Keeps the state of all the blocks, disabling the state pruning.
+    pub archive: bool,
+    /// Number of the latest blocks whose state is kept, the state of the last final block is
+    /// always kept as well.
+    pub state_retention: BlockIndex,
+    pub storage_backend: StorageBackend,
 }
 
 impl Default for ClientConfig {
@@ -26,6 +35,9 @@ impl Default for ClientConfig {
             public_key: None,
             chain_spec: read_or_default_chain_spec(&None),
             log_level: log::LevelFilter::Info,
+            archive: false,
+            state_retention: DEFAULT_STATE_RETENTION.parse().unwrap(),
+            storage_backend: StorageBackend::RocksDb,
         }
     }
 }
@@ -67,6 +79,22 @@ pub fn get_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
             .possible_values(&["Debug", "Info", "Warn"])
             .default_value(DEFAULT_LOG_LEVEL)
             .takes_value(true),
+        Arg::with_name("archive")
+            .long("archive")
+            .help("Keep the state of all the blocks, disables state pruning."),
+        Arg::with_name("state_retention")
+            .long("state-retention")
+            .value_name("BLOCKS")
+            .help("Number of the latest blocks whose state is kept.")
+            .default_value(DEFAULT_STATE_RETENTION)
+            .takes_value(true),
+        Arg::with_name("storage_backend")
+            .long("storage-backend")
+            .value_name("BACKEND")
+            .help("Database to use. read-only only serves RPC queries from a copy of the database.")
+            .possible_values(&["rocksdb", "memory", "read-only"])
+            .default_value(DEFAULT_STORAGE_BACKEND)
+            .takes_value(true),
     ]
 }
 
@@ -75,8 +103,22 @@ pub fn from_matches(matches: &ArgMatches) -> ClientConfig {
     let account_id = matches.value_of("account_id").map(String::from).unwrap();
     let public_key = matches.value_of("public_key").map(String::from);
     let log_level = matches.value_of("log_level").map(log::LevelFilter::from_str).unwrap().unwrap();
+    let archive = matches.is_present("archive");
+    let state_retention =
+        matches.value_of("state_retention").map(|x| x.parse::<BlockIndex>().unwrap()).unwrap();
+    let storage_backend =
+        matches.value_of("storage_backend").map(StorageBackend::from_str).unwrap().unwrap();
 
     let chain_spec_path = matches.value_of("chain_spec_file").map(PathBuf::from);
     let chain_spec = read_or_default_chain_spec(&chain_spec_path);
-    ClientConfig { base_path, account_id, public_key, chain_spec, log_level }
+    ClientConfig {
+        base_path,
+        account_id,
+        public_key,
+        chain_spec,
+        log_level,
+        archive,
+        state_retention,
+        storage_backend,
+    }
 }
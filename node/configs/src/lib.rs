@@ -17,6 +17,7 @@ pub use crate::{
     authority::AuthorityConfig, chain_spec::ChainSpec, client::ClientConfig, devnet::DevNetConfig,
     network::NetworkConfig, rpc::RPCConfig,
 };
+pub use storage::StorageBackend;
 
 pub fn get_testnet_configs() -> (ClientConfig, NetworkConfig, RPCConfig) {
     let matches = App::new("Near TestNet")
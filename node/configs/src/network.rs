@@ -22,6 +22,8 @@ pub struct NetworkConfig {
     pub reconnect_delay: Duration,
     pub gossip_interval: Duration,
     pub gossip_sample_size: usize,
+    /// Download the state at a recent block from the peers instead of applying all the blocks.
+    pub state_sync: bool,
 }
 
 pub fn get_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
@@ -71,6 +73,12 @@ pub fn get_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
             .help("Delay in ms between gossiping peers info with known peers.")
             .default_value(DEFAULT_GOSSIP_SAMPLE_SIZE)
             .takes_value(true),
+        Arg::with_name("state_sync")
+            .long("state-sync")
+            .help(
+                "Download the state at a recent block from the peers instead of \
+                 applying all the blocks since genesis.",
+            ),
     ]
 }
 
@@ -130,5 +138,6 @@ pub fn from_matches(client_config: &ClientConfig, matches: &ArgMatches) -> Netwo
         reconnect_delay: Duration::from_millis(reconnect_delay_ms),
         gossip_interval: Duration::from_millis(gossip_interval_ms),
         gossip_sample_size,
+        state_sync: matches.is_present("state_sync"),
     }
 }
@@ -11,10 +11,10 @@ use primitives::chain::SignedShardBlock;
 
 pub fn spawn_block_importer(
     client: Arc<Client>,
-    incoming_block_tx: Receiver<(SignedBeaconBlock, SignedShardBlock)>,
+    incoming_block_tx: Receiver<(SignedBeaconBlock, Vec<SignedShardBlock>)>,
 ) {
-    let task = incoming_block_tx.for_each(move |(beacon_block, shard_block)| {
-        client.try_import_blocks(beacon_block, shard_block);
+    let task = incoming_block_tx.for_each(move |(beacon_block, shard_blocks)| {
+        client.try_import_blocks(beacon_block, shard_blocks);
         future::ok(())
     });
     tokio::spawn(task);
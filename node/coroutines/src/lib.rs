@@ -11,4 +11,5 @@ extern crate txflow;
 
 pub mod producer;
 pub mod importer;
+pub mod state_pruner;
 mod control_builder;
@@ -19,7 +19,7 @@ use client::BlockProductionResult;
 pub fn spawn_block_producer(
     client: Arc<Client>,
     receiver: Receiver<ChainConsensusBlockBody>,
-    block_announce_tx: Sender<(SignedBeaconBlock, SignedShardBlock)>,
+    block_announce_tx: Sender<(SignedBeaconBlock, Vec<SignedShardBlock>)>,
     new_receipts_tx: Sender<ReceiptBlock>,
     control_tx: Sender<Control<BeaconWitnessSelector>>,
 ) {
@@ -32,12 +32,12 @@ pub fn spawn_block_producer(
 
     let task = receiver
         .for_each(move |body| {
-            if let BlockProductionResult::Success(new_beacon_block, new_shard_block) = client.try_produce_block(body) {
+            if let BlockProductionResult::Success(new_beacon_block, new_shard_blocks) = client.try_produce_block(body) {
                 // Send beacon block to network
                 tokio::spawn({
                     block_announce_tx
                         .clone()
-                        .send((new_beacon_block.clone(), new_shard_block.clone()))
+                        .send((new_beacon_block.clone(), new_shard_blocks.clone()))
                         .map(|_| ())
                         // TODO: In DevNet this will silently fail, because there is no network and so
                         // the announcements cannot be make. In TestNet the failure should not be silent.
@@ -55,22 +55,34 @@ pub fn spawn_block_producer(
                     .map(|_| ())
                     .map_err(|e| error!("Error sending control to TxFlow: {}", e));
                 if needs_receipt_rerouting {
-                    let receipt_block = client.shard_client.get_receipt_block(
-                        new_shard_block.index(),
-                        new_shard_block.shard_id()
-                    );
-                    if let Some(receipt_block) = receipt_block {
-                        if !receipt_block.receipts.is_empty() {
-                            let receipts_task = new_receipts_tx
-                            .clone()
-                            .send(receipt_block)
-                            .map(|_| ())
-                            .map_err(|e| error!("Error sending receipts: {}", e));
-                            // First tells TxFlow to reset.
-                            // Then, redirect the receipts from the previous block for processing in the next one.
-                            tokio::spawn(txflow_task.and_then(|_| receipts_task));
+                    // Receipts produced by every shard block for every shard, including its own.
+                    let mut receipt_blocks = vec![];
+                    for new_shard_block in new_shard_blocks.iter() {
+                        let shard_client = client.shard_client(new_shard_block.shard_id());
+                        for shard_id in 0..client.num_shards() {
+                            let receipt_block = shard_client.get_receipt_block(
+                                new_shard_block.index(),
+                                shard_id
+                            );
+                            if let Some(receipt_block) = receipt_block {
+                                if !receipt_block.receipts.is_empty() {
+                                    receipt_blocks.push(receipt_block);
+                                }
+                            }
                         }
                     }
+                    if !receipt_blocks.is_empty() {
+                        let receipts_task = future::join_all(
+                            receipt_blocks
+                                .into_iter()
+                                .map(|receipt_block| new_receipts_tx.clone().send(receipt_block))
+                        )
+                        .map(|_| ())
+                        .map_err(|e| error!("Error sending receipts: {}", e));
+                        // First tells TxFlow to reset.
+                        // Then, redirect the receipts from the previous block for processing in the next one.
+                        tokio::spawn(txflow_task.and_then(|_| receipts_task));
+                    }
                 } else {
                     // Tells TxFlow to stop.
                     tokio::spawn(txflow_task);
@@ -0,0 +1,25 @@
+//! StatePruner periodically removes the state of the blocks that fell out of the retention window.
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future, Stream};
+use tokio::timer::Interval;
+
+use client::Client;
+use primitives::types::BlockIndex;
+
+/// How often the state of the old blocks is pruned.
+pub const PRUNING_PERIOD: Duration = Duration::from_secs(10);
+
+pub fn spawn_state_pruner(client: Arc<Client>, retention: BlockIndex, period: Duration) {
+    let task = Interval::new_interval(period)
+        .for_each(move |_| {
+            let removed = client.prune_state(retention);
+            if removed > 0 {
+                info!(target: "client", "Pruned {} state nodes", removed);
+            }
+            future::ok(())
+        })
+        .map_err(|e| warn!(target: "client", "Error pruning the state {}", e));
+    tokio::spawn(task);
+}
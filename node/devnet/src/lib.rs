@@ -5,7 +5,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use futures::sync::mpsc::{channel, Sender};
 use futures::future;
 
-use configs::{get_devnet_configs, ClientConfig, DevNetConfig, RPCConfig};
+use configs::{get_devnet_configs, ClientConfig, DevNetConfig, RPCConfig, StorageBackend};
 use client::Client;
 use consensus::adapters::transaction_to_payload;
 use consensus::passthrough::spawn_consensus;
@@ -24,11 +24,23 @@ pub fn start_from_configs(client_cfg: ClientConfig, devnet_cfg: DevNetConfig, rp
         let (transactions_tx, transactions_rx) = channel(1024);
         let (receipts_tx, receipts_rx) = channel(1024);
         spawn_rpc_server_task(transactions_tx.clone(), &rpc_cfg, client.clone());
+        // A read-only database can't store blocks, the node only answers the queries.
+        if client_cfg.storage_backend == StorageBackend::ReadOnly {
+            return Ok(());
+        }
 
         // Create a task that receives new blocks from importer/producer
         // and send the authority information to consensus
         let (consensus_control_tx, consensus_control_rx) = channel(1024);
 
+        if !client_cfg.archive {
+            coroutines::state_pruner::spawn_state_pruner(
+                client.clone(),
+                client_cfg.state_retention,
+                coroutines::state_pruner::PRUNING_PERIOD,
+            );
+        }
+
         // Create a task that consumes the consensuses
         // and produces the beacon chain blocks.
         let (beacon_block_consensus_body_tx, beacon_block_consensus_body_rx) = channel(1024);
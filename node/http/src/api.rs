@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use futures::sync::mpsc::Sender;
@@ -7,16 +8,21 @@ use primitives::types::BlockId;
 use primitives::utils::bs58_vec2str;
 
 use crate::types::{
-    CallViewFunctionRequest, CallViewFunctionResponse, GetBlockByHashRequest,
-    GetBlocksByIndexRequest, GetTransactionRequest, SignedBeaconBlockResponse,
+    CallViewFunctionRequest, CallViewFunctionResponse, ContractEventResponse,
+    ContractEventsResponse, GetBlockByHashRequest, GetBlocksByIndexRequest,
+    GetContractEventsRequest, GetTransactionRequest, SignedBeaconBlockResponse,
     SignedBeaconBlocksResponse, SignedShardBlockResponse, SignedShardBlocksResponse,
     SubmitTransactionRequest, SubmitTransactionResponse, TransactionInfoResponse,
-    TransactionResultResponse, ViewAccountRequest, ViewAccountResponse, ViewStateRequest,
-    ViewStateResponse,
+    TransactionResultResponse, ViewAccountRequest, ViewAccountResponse,
+    ViewAccountWithProofResponse, ViewKeyNonceRequest, ViewKeyNonceResponse, ViewStateRequest,
+    ViewStateResponse, ViewStateWithProofResponse,
 };
 use primitives::transaction::SignedTransaction;
 use primitives::transaction::verify_transaction_signature;
 
+/// Maximum number of blocks that can be scanned for contract events in one request.
+const MAX_EVENTS_BLOCK_RANGE: u64 = 1000;
+
 pub struct HttpApi {
     client: Arc<Client>,
     submit_txn_sender: Sender<SignedTransaction>,
@@ -37,8 +43,9 @@ pub enum RPCError {
 impl HttpApi {
     pub fn view_account(&self, r: &ViewAccountRequest) -> Result<ViewAccountResponse, String> {
         debug!(target: "near-rpc", "View account {:?}", r.account_id);
-        let mut state_update = self.client.shard_client.get_state_update();
-        match self.client.shard_client.trie_viewer.view_account(
+        let shard_client = self.client.shard_client_for_account(&r.account_id);
+        let mut state_update = shard_client.get_state_update();
+        match shard_client.trie_viewer.view_account(
             &mut state_update,
             &r.account_id
         ) {
@@ -53,6 +60,43 @@ impl HttpApi {
         }
     }
 
+    pub fn view_account_with_proof(
+        &self,
+        r: &ViewAccountRequest,
+    ) -> Result<ViewAccountWithProofResponse, String> {
+        debug!(target: "near-rpc", "View account with proof {:?}", r.account_id);
+        let shard_client = self.client.shard_client_for_account(&r.account_id);
+        let state_update = shard_client.get_state_update();
+        let result = shard_client.trie_viewer
+            .view_account_with_proof(&state_update, &r.account_id)?;
+        Ok(ViewAccountWithProofResponse {
+            account: result.account.map(|r| ViewAccountResponse {
+                account_id: r.account,
+                amount: r.amount,
+                stake: r.stake,
+                code_hash: r.code_hash,
+                nonce: r.nonce,
+            }),
+            merkle_root_state: result.root,
+            key: result.key,
+            value: result.value,
+            proof: result.proof,
+        })
+    }
+
+    pub fn view_key_nonce(&self, r: &ViewKeyNonceRequest) -> Result<ViewKeyNonceResponse, String> {
+        debug!(target: "near-rpc", "View nonce of key {} of {:?}", r.public_key, r.account_id);
+        let shard_client = self.client.shard_client_for_account(&r.account_id);
+        let state_update = shard_client.get_state_update();
+        let nonce = shard_client.trie_viewer
+            .get_nonce_for_key(&state_update, &r.account_id, &r.public_key)?;
+        Ok(ViewKeyNonceResponse {
+            account_id: r.account_id.clone(),
+            public_key: r.public_key,
+            nonce,
+        })
+    }
+
     pub fn call_view_function(
         &self,
         r: &CallViewFunctionRequest,
@@ -63,9 +107,10 @@ impl HttpApi {
             r.contract_account_id,
             r.method_name,
         );
-        let state_update = self.client.shard_client.get_state_update();
-        let best_index = self.client.shard_client.chain.best_index();
-        match self.client.shard_client.trie_viewer.call_function(
+        let shard_client = self.client.shard_client_for_account(&r.contract_account_id);
+        let state_update = shard_client.get_state_update();
+        let best_index = shard_client.chain.best_index();
+        match shard_client.trie_viewer.call_function(
             state_update,
             best_index, 
             &r.contract_account_id,
@@ -84,15 +129,19 @@ impl HttpApi {
         let transaction: SignedTransaction = r.transaction.clone().into();
         debug!(target: "near-rpc", "Received transaction {:?}", transaction);
         let originator = transaction.body.get_originator();
-        let mut state_update = self.client.shard_client.get_state_update();
-        let public_keys = self.client.shard_client.trie_viewer
+        let shard_client = self.client.shard_client_for_account(&originator);
+        let mut state_update = shard_client.get_state_update();
+        let public_keys = shard_client.trie_viewer
             .get_public_keys_for_account(&mut state_update, &originator)
             .map_err(RPCError::BadRequest)?;
-        if !verify_transaction_signature(&transaction, &public_keys) {
-            let msg =
-                format!("transaction not signed with a public key of originator {:?}", originator,);
-            return Err(RPCError::BadRequest(msg));
-        }
+        let access_keys = shard_client.trie_viewer
+            .get_access_keys_for_account(&mut state_update, &originator)
+            .map_err(RPCError::BadRequest)?;
+        let multisig_threshold = shard_client.trie_viewer
+            .get_multisig_threshold_for_account(&mut state_update, &originator)
+            .map_err(RPCError::BadRequest)?;
+        verify_transaction_signature(&transaction, &public_keys, &access_keys, multisig_threshold)
+            .map_err(RPCError::BadRequest)?;
 
         self.submit_txn_sender
             .clone()
@@ -103,8 +152,9 @@ impl HttpApi {
 
     pub fn view_state(&self, r: &ViewStateRequest) -> Result<ViewStateResponse, String> {
         debug!(target: "near-rpc", "View state {:?}", r.contract_account_id);
-        let state_update = self.client.shard_client.get_state_update();
-        let result = self.client.shard_client.trie_viewer
+        let shard_client = self.client.shard_client_for_account(&r.contract_account_id);
+        let state_update = shard_client.get_state_update();
+        let result = shard_client.trie_viewer
             .view_state(&state_update, &r.contract_account_id)?;
         let response = ViewStateResponse {
             contract_account_id: r.contract_account_id.clone(),
@@ -113,6 +163,64 @@ impl HttpApi {
         Ok(response)
     }
 
+    pub fn get_contract_events(
+        &self,
+        r: &GetContractEventsRequest,
+    ) -> Result<ContractEventsResponse, String> {
+        debug!(target: "near-rpc", "Get contract events {:?}", r.contract_account_id);
+        if r.from_index > r.to_index {
+            return Err(format!(
+                "from_index {} is larger than to_index {}",
+                r.from_index,
+                r.to_index,
+            ));
+        }
+        if r.to_index - r.from_index >= MAX_EVENTS_BLOCK_RANGE {
+            return Err(format!("cannot query more than {} blocks", MAX_EVENTS_BLOCK_RANGE));
+        }
+        let shard_client = self.client.shard_client_for_account(&r.contract_account_id);
+        let events = shard_client
+            .get_contract_events(
+                &r.contract_account_id,
+                r.topic.as_ref().map(|t| t.as_slice()),
+                r.from_index,
+                r.to_index,
+            )?
+            .into_iter()
+            .map(|info| ContractEventResponse {
+                block_index: info.block_index,
+                hash: info.hash,
+                topic: info.event.topic,
+                data: info.event.data,
+            })
+            .collect();
+        Ok(ContractEventsResponse { contract_account_id: r.contract_account_id.clone(), events })
+    }
+
+    pub fn view_state_with_proof(
+        &self,
+        r: &ViewStateRequest,
+    ) -> Result<ViewStateWithProofResponse, String> {
+        debug!(target: "near-rpc", "View state with proof {:?}", r.contract_account_id);
+        let shard_client = self.client.shard_client_for_account(&r.contract_account_id);
+        let state_update = shard_client.get_state_update();
+        let result = shard_client.trie_viewer
+            .view_state_with_proof(&state_update, &r.contract_account_id)?;
+        let mut values = HashMap::default();
+        let mut proofs = HashMap::default();
+        for (key, (value, proof)) in result.values {
+            let key = bs58_vec2str(&key);
+            values.insert(key.clone(), value);
+            proofs.insert(key, proof);
+        }
+        Ok(ViewStateWithProofResponse {
+            contract_account_id: r.contract_account_id.clone(),
+            merkle_root_state: result.root,
+            values,
+            proofs,
+        })
+    }
+
     pub fn view_latest_beacon_block(&self) -> Result<SignedBeaconBlockResponse, ()> {
         Ok(self.client.beacon_chain.chain.best_block().into())
     }
@@ -127,27 +235,31 @@ impl HttpApi {
         }
     }
 
+    /// Latest block of the first shard.
     pub fn view_latest_shard_block(&self) -> Result<SignedShardBlockResponse, ()> {
-        Ok(self.client.shard_client.chain.best_block().into())
+        Ok(self.client.shard_client(0).chain.best_block().into())
     }
 
     pub fn get_shard_block_by_hash(
         &self,
         r: &GetBlockByHashRequest,
     ) -> Result<SignedShardBlockResponse, &str> {
-        match self.client.shard_client.chain.get_block(&BlockId::Hash(r.hash)) {
+        let block_id = BlockId::Hash(r.hash);
+        match self.client.shard_clients.iter().find_map(|c| c.chain.get_block(&block_id)) {
             Some(block) => Ok(block.into()),
             None => Err("block not found"),
         }
     }
 
+    /// Blocks of the first shard.
     pub fn get_shard_blocks_by_index(
         &self,
         r: &GetBlocksByIndexRequest,
     ) -> Result<SignedShardBlocksResponse, String> {
-        let start = r.start.unwrap_or_else(|| self.client.shard_client.chain.best_index());
+        let shard_client = self.client.shard_client(0);
+        let start = r.start.unwrap_or_else(|| shard_client.chain.best_index());
         let limit = r.limit.unwrap_or(25);
-        self.client.shard_client.chain.get_blocks_by_index(start, limit).map(|blocks| {
+        shard_client.chain.get_blocks_by_index(start, limit).map(|blocks| {
             SignedShardBlocksResponse {
                 blocks: blocks.into_iter().map(|x| x.into()).collect(),
             }
@@ -171,7 +283,7 @@ impl HttpApi {
         &self,
         r: &GetTransactionRequest,
     ) -> Result<TransactionInfoResponse, RPCError> {
-        match self.client.shard_client.get_transaction_info(&r.hash) {
+        match self.client.get_transaction_info(&r.hash) {
             Some(info) => Ok(TransactionInfoResponse {
                 transaction: info.transaction.into(),
                 block_index: info.block_index,
@@ -186,7 +298,7 @@ impl HttpApi {
         &self,
         r: &GetTransactionRequest,
     ) -> Result<TransactionResultResponse, ()> {
-        let result = self.client.shard_client.get_transaction_final_result(&r.hash);
+        let result = self.client.get_transaction_final_result(&r.hash);
         Ok(TransactionResultResponse { result })
     }
 }
@@ -121,6 +121,33 @@ fn serve(http_api: Arc<HttpApi>, req: Request<Body>) -> BoxFut {
                 }
             }))
         }
+        (&Method::POST, "/view_key_nonce") => {
+            Box::new(req.into_body().concat2().map(move |chunk| {
+                match serde_json::from_slice(&chunk) {
+                    Ok(data) => {
+                        match http_api.view_key_nonce(&data) {
+                            Ok(response) => {
+                                build_response()
+                                    .body(Body::from(serde_json::to_string(&response).unwrap()))
+                                    .unwrap()
+                            }
+                            Err(e) => {
+                                build_response()
+                                    .status(StatusCode::BAD_REQUEST)
+                                    .body(Body::from(e))
+                                    .unwrap()
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        build_response()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from(e.to_string()))
+                            .unwrap()
+                    }
+                }
+            }))
+        }
         (&Method::POST, "/view_state") => {
             Box::new(req.into_body().concat2().map(move |chunk| {
                 match serde_json::from_slice(&chunk) {
@@ -148,6 +175,87 @@ fn serve(http_api: Arc<HttpApi>, req: Request<Body>) -> BoxFut {
                 }
             }))
         }
+        (&Method::POST, "/view_account_with_proof") => {
+            Box::new(req.into_body().concat2().map(move |chunk| {
+                match serde_json::from_slice(&chunk) {
+                    Ok(data) => {
+                        match http_api.view_account_with_proof(&data) {
+                            Ok(response) => {
+                                build_response()
+                                    .body(Body::from(serde_json::to_string(&response).unwrap()))
+                                    .unwrap()
+                            }
+                            Err(e) => {
+                                build_response()
+                                    .status(StatusCode::BAD_REQUEST)
+                                    .body(Body::from(e.to_string()))
+                                    .unwrap()
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        build_response()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from(e.to_string()))
+                            .unwrap()
+                    }
+                }
+            }))
+        }
+        (&Method::POST, "/view_state_with_proof") => {
+            Box::new(req.into_body().concat2().map(move |chunk| {
+                match serde_json::from_slice(&chunk) {
+                    Ok(data) => {
+                        match http_api.view_state_with_proof(&data) {
+                            Ok(response) => {
+                                build_response()
+                                    .body(Body::from(serde_json::to_string(&response).unwrap()))
+                                    .unwrap()
+                            }
+                            Err(e) => {
+                                build_response()
+                                    .status(StatusCode::BAD_REQUEST)
+                                    .body(Body::from(e))
+                                    .unwrap()
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        build_response()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from(e.to_string()))
+                            .unwrap()
+                    }
+                }
+            }))
+        }
+        (&Method::POST, "/get_contract_events") => {
+            Box::new(req.into_body().concat2().map(move |chunk| {
+                match serde_json::from_slice(&chunk) {
+                    Ok(data) => {
+                        match http_api.get_contract_events(&data) {
+                            Ok(response) => {
+                                build_response()
+                                    .body(Body::from(serde_json::to_string(&response).unwrap()))
+                                    .unwrap()
+                            }
+                            Err(e) => {
+                                build_response()
+                                    .status(StatusCode::BAD_REQUEST)
+                                    .body(Body::from(e))
+                                    .unwrap()
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        build_response()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from(e.to_string()))
+                            .unwrap()
+                    }
+                }
+            }))
+        }
         (&Method::POST, "/view_latest_beacon_block") => {
             Box::new(future::ok(
                 match http_api.view_latest_beacon_block() {
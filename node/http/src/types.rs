@@ -3,15 +3,16 @@ use std::collections::HashMap;
 use primitives::beacon::{BeaconBlock, BeaconBlockHeader, SignedBeaconBlock};
 use near_protos::serde::b64_format as protos_b64_format;
 use primitives::aggregate_signature::BlsPublicKey;
-use primitives::hash::{bs58_format, CryptoHash};
-use primitives::signature::{bs58_serializer};
+use primitives::hash::{bs58_format, bs58_vec_format, CryptoHash};
+use primitives::signature::{bs58_pub_key_format, bs58_serializer, PublicKey};
 use primitives::types::{
-    AccountId, AuthorityStake, Balance, GroupSignature, MerkleHash, ShardId
+    AccountId, AuthorityStake, Balance, BlockIndex, GroupSignature, MerkleHash, ShardId
 };
 use primitives::chain::{ShardBlock, ShardBlockHeader, SignedShardBlock, ReceiptBlock};
 use primitives::transaction::{
     FinalTransactionResult, SignedTransaction, TransactionResult,
 };
+use primitives::trie_proof::TrieProof;
 
 #[derive(Serialize, Deserialize)]
 pub struct ViewAccountRequest {
@@ -28,6 +29,32 @@ pub struct ViewAccountResponse {
     pub code_hash: CryptoHash,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ViewKeyNonceRequest {
+    pub account_id: AccountId,
+    #[serde(with = "bs58_pub_key_format")]
+    pub public_key: PublicKey,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ViewKeyNonceResponse {
+    pub account_id: AccountId,
+    #[serde(with = "bs58_pub_key_format")]
+    pub public_key: PublicKey,
+    pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ViewAccountWithProofResponse {
+    /// `None` if the account does not exist, the proof then shows its absence.
+    pub account: Option<ViewAccountResponse>,
+    #[serde(with = "bs58_format")]
+    pub merkle_root_state: MerkleHash,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    pub proof: TrieProof,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CallViewFunctionRequest {
     pub contract_account_id: AccountId,
@@ -51,6 +78,15 @@ pub struct ViewStateResponse {
     pub values: HashMap<String, Vec<u8>>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ViewStateWithProofResponse {
+    pub contract_account_id: AccountId,
+    #[serde(with = "bs58_format")]
+    pub merkle_root_state: MerkleHash,
+    pub values: HashMap<String, Vec<u8>>,
+    pub proofs: HashMap<String, TrieProof>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct AuthorityProposalResponse {
     pub account_id: AccountId,
@@ -75,8 +111,8 @@ pub struct BeaconBlockHeaderResponse {
     pub parent_hash: CryptoHash,
     pub index: u64,
     pub authority_proposal: Vec<AuthorityProposalResponse>,
-    #[serde(with = "bs58_format")]
-    pub shard_block_hash: CryptoHash,
+    #[serde(with = "bs58_vec_format")]
+    pub shard_block_hashes: Vec<CryptoHash>,
 }
 
 impl From<BeaconBlockHeader> for BeaconBlockHeaderResponse {
@@ -88,7 +124,7 @@ impl From<BeaconBlockHeader> for BeaconBlockHeaderResponse {
             parent_hash: header.parent_hash,
             index: header.index,
             authority_proposal,
-            shard_block_hash: header.shard_block_hash,
+            shard_block_hashes: header.shard_block_hashes,
         }
     }
 }
@@ -250,3 +286,28 @@ pub struct SubmitTransactionRequest {
     #[serde(with = "protos_b64_format")]
     pub transaction: near_protos::signed_transaction::SignedTransaction,
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct GetContractEventsRequest {
+    pub contract_account_id: AccountId,
+    /// Only events with this topic are returned, if given.
+    pub topic: Option<Vec<u8>>,
+    pub from_index: BlockIndex,
+    pub to_index: BlockIndex,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContractEventResponse {
+    pub block_index: BlockIndex,
+    /// Hash of the transaction or receipt that emitted the event.
+    #[serde(with = "bs58_format")]
+    pub hash: CryptoHash,
+    pub topic: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContractEventsResponse {
+    pub contract_account_id: AccountId,
+    pub events: Vec<ContractEventResponse>,
+}
@@ -4,6 +4,8 @@ mod message;
 mod peer;
 mod peer_manager;
 mod protocol;
+mod state_sync;
+mod sync;
 #[cfg(test)]
 mod testing_utils;
 
@@ -1,7 +1,7 @@
 use primitives::beacon::SignedBeaconBlock;
 use primitives::chain::{ChainPayload, ReceiptBlock, SignedShardBlock};
 use primitives::hash::CryptoHash;
-use primitives::types::{AccountId, BlockId, Gossip};
+use primitives::types::{AccountId, BlockId, Gossip, ShardId};
 use primitives::transaction::SignedTransaction;
 use serde_derive::{Deserialize, Serialize};
 
@@ -14,8 +14,12 @@ pub enum Message {
     Transaction(Box<SignedTransaction>),
     Receipt(Box<ReceiptBlock>),
     Status(Status),
-    BlockAnnounce(Box<(SignedBeaconBlock, SignedShardBlock)>),
+    BlockAnnounce(Box<(SignedBeaconBlock, Vec<SignedShardBlock>)>),
     Gossip(Box<Gossip<ChainPayload>>),
+    BlockRequest(BlockRequest),
+    BlockResponse(Box<BlockResponse>),
+    StateRequest(StateRequest),
+    StateResponse(Box<StateResponse>),
 }
 
 /// status sent on connection
@@ -50,8 +54,26 @@ pub struct BlockResponse {
     // request id that the response is responding to
     pub id: RequestId,
     // block data
-    pub blocks: Vec<(SignedBeaconBlock, SignedShardBlock)>,
+    pub blocks: Vec<(SignedBeaconBlock, Vec<SignedShardBlock>)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct StateRequest {
+    /// request id
+    pub id: RequestId,
+    /// shard of the requested trie nodes
+    pub shard_id: ShardId,
+    /// hashes of the requested trie nodes
+    pub hashes: Vec<CryptoHash>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateResponse {
+    // request id that the response is responding to
+    pub id: RequestId,
+    // serialized trie nodes that the peer has, without reference counts
+    pub nodes: Vec<Vec<u8>>,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub struct BlockAnnounce(pub SignedBeaconBlock, pub SignedShardBlock);
+pub struct BlockAnnounce(pub SignedBeaconBlock, pub Vec<SignedShardBlock>);
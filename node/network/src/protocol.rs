@@ -1,24 +1,34 @@
-use crate::message::Message;
+use crate::message::{BlockRequest, BlockResponse, Message, StateRequest, StateResponse, Status};
 use crate::peer::PeerMessage;
 use crate::peer_manager::PeerManager;
+use crate::state_sync::{StateSync, MAX_NODES_PER_REQUEST};
+use crate::sync::{BlockSync, MAX_BLOCKS_PER_REQUEST, STATUS_INTERVAL};
 use client::Client;
 use configs::NetworkConfig;
 use futures::future;
 use futures::sink::Sink;
-use futures::stream::Stream;
+use futures::stream::{iter_ok, Stream};
 use futures::sync::mpsc::channel;
 use futures::sync::mpsc::Receiver;
 use futures::sync::mpsc::Sender;
 use futures::Future;
-use log::{warn, error};
+use log::{error, info, warn};
 use primitives::network::PeerInfo;
 use primitives::serialize::{Decode, Encode};
-use primitives::types::AccountId;
+use primitives::types::{AccountId, BlockId, PeerId};
 use primitives::types::Gossip;
-use std::sync::Arc;
+use std::cmp;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use primitives::block_traits::{SignedBlock, SignedHeader};
 use primitives::chain::ChainPayload;
 use primitives::beacon::SignedBeaconBlock;
 use primitives::chain::SignedShardBlock;
+use tokio::timer::Interval;
+
+/// Version of the protocol reported in the status.
+const PROTOCOL_VERSION: u32 = 1;
+const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
 
 /// Spawn network tasks that process incoming and outgoing messages of various kind.
 /// Args:
@@ -28,7 +38,8 @@ use primitives::chain::SignedShardBlock;
 ///   exporting, importing blocks;
 /// * `inc_gossip_tx`: Channel where protocol places incoming TxFlow gossip;
 /// * `out_gossip_rx`: Channel where from protocol reads gossip that should be sent to other peers;
-/// * `inc_block_tx`: Channel where protocol places incoming blocks;
+/// * `inc_block_tx`: Channel where protocol places incoming blocks, both announced and the ones
+///   requested from the peers that are ahead of us;
 /// * `out_blocks_rx`: Channel where from protocol reads blocks that should be sent for
 ///   announcements.
 pub fn spawn_network(
@@ -37,11 +48,17 @@ pub fn spawn_network(
     client: Arc<Client>,
     inc_gossip_tx: Sender<Gossip<ChainPayload>>,
     out_gossip_rx: Receiver<Gossip<ChainPayload>>,
-    inc_block_tx: Sender<(SignedBeaconBlock, SignedShardBlock)>,
-    out_block_rx: Receiver<(SignedBeaconBlock, SignedShardBlock)>,
+    inc_block_tx: Sender<(SignedBeaconBlock, Vec<SignedShardBlock>)>,
+    out_block_rx: Receiver<(SignedBeaconBlock, Vec<SignedShardBlock>)>,
 ) {
     let (inc_msg_tx, inc_msg_rx) = channel(1024);
-    let (_, out_msg_rx) = channel(1024);
+    let (out_msg_tx, out_msg_rx) = channel(1024);
+    let block_sync =
+        Arc::new(RwLock::new(BlockSync::new(client.beacon_chain.chain.genesis_hash())));
+    let state_sync = Arc::new(RwLock::new(StateSync::new(
+        client.shard_clients.iter().map(|c| c.trie.clone()).collect(),
+        network_cfg.state_sync,
+    )));
 
     let peer_manager = Arc::new(PeerManager::new(
         network_cfg.reconnect_delay,
@@ -50,7 +67,7 @@ pub fn spawn_network(
         PeerInfo {
             id: network_cfg.peer_id,
             addr: network_cfg.listen_addr,
-            account_id,
+            account_id: account_id.clone(),
         },
         &network_cfg.boot_nodes,
         inc_msg_tx,
@@ -59,27 +76,91 @@ pub fn spawn_network(
 
     // Spawn a task that decodes incoming messages and places them in the corresponding channels.
     let client1 = client.clone();
-    let task = inc_msg_rx.for_each(move |(_, data)| {
+    let block_sync1 = block_sync.clone();
+    let state_sync1 = state_sync.clone();
+    let out_msg_tx1 = out_msg_tx.clone();
+    let task = inc_msg_rx.for_each(move |(peer_id, data)| {
         match Decode::decode(&data) {
             Ok(m) => match m {
                 Message::Gossip(gossip) => {
                     forward_msg(inc_gossip_tx.clone(), *gossip)
                 },
                 Message::BlockAnnounce(block) => {
-                    let unboxed = *block;
-                    forward_msg(inc_block_tx.clone(), (unboxed.0, unboxed.1));
+                    // The announced blocks cannot be applied until the state is synced.
+                    if !state_sync1.read().expect(POISONED_LOCK_ERR).is_syncing() {
+                        let unboxed = *block;
+                        forward_msg(inc_block_tx.clone(), (unboxed.0, unboxed.1));
+                    }
+                }
+                Message::Status(status) => {
+                    block_sync1.write().expect(POISONED_LOCK_ERR).on_status(peer_id, status);
+                    request_state(&client1, &block_sync1, &state_sync1, &out_msg_tx1);
+                    request_blocks(&client1, &block_sync1, &out_msg_tx1);
+                }
+                Message::BlockRequest(request) => {
+                    let response = Box::new(get_block_response(&client1, request));
+                    let data = Encode::encode(&Message::BlockResponse(response)).unwrap();
+                    forward_msg(out_msg_tx1.clone(), (peer_id, data));
+                }
+                Message::BlockResponse(response) => {
+                    let response = *response;
+                    let expected = block_sync1
+                        .write()
+                        .expect(POISONED_LOCK_ERR)
+                        .on_response(&peer_id, &response);
+                    let syncing_state = state_sync1.read().expect(POISONED_LOCK_ERR).is_syncing();
+                    if expected && syncing_state {
+                        client1.import_blocks_without_state(response.blocks);
+                        request_state(&client1, &block_sync1, &state_sync1, &out_msg_tx1);
+                        request_blocks(&client1, &block_sync1, &out_msg_tx1);
+                    } else if expected {
+                        // Send the blocks in one go to keep them ordered for the importer.
+                        let task = inc_block_tx
+                            .clone()
+                            .send_all(iter_ok(response.blocks))
+                            .map(|_| ())
+                            .map_err(|e| warn!(target: "network", "Error forwarding blocks {}", e));
+                        tokio::spawn(task);
+                    }
+                }
+                Message::StateRequest(request) => {
+                    let response = Box::new(get_state_response(&client1, request));
+                    let data = Encode::encode(&Message::StateResponse(response)).unwrap();
+                    forward_msg(out_msg_tx1.clone(), (peer_id, data));
+                }
+                Message::StateResponse(response) => {
+                    let result = state_sync1
+                        .write()
+                        .expect(POISONED_LOCK_ERR)
+                        .on_response(&peer_id, *response);
+                    match result {
+                        Ok(Some(index)) => finish_state_sync(&block_sync1, index),
+                        Ok(None) => {}
+                        Err(e) => warn!(target: "network", "Invalid state from {}: {}", peer_id, e),
+                    }
+                    request_state(&client1, &block_sync1, &state_sync1, &out_msg_tx1);
+                    request_blocks(&client1, &block_sync1, &out_msg_tx1);
                 }
                 Message::Transaction(tx) => {
-                    if let Err(e) = client1.shard_client.pool.add_transaction(*tx) {
+                    let shard_client = client1.shard_client_for_account(&tx.body.get_originator());
+                    if let Err(e) = shard_client.pool.add_transaction(*tx) {
                         error!(target: "network", "{}", e);
                     }
                 }
                 Message::Receipt(receipt) => {
-                    if let Err(e) = client1.shard_client.pool.add_receipt(*receipt) {
-                        error!(target: "network", "{}", e);
+                    let shard_client = receipt
+                        .receipts
+                        .first()
+                        .map(|r| client1.shard_client_for_account(&r.receiver));
+                    match shard_client {
+                        Some(shard_client) => {
+                            if let Err(e) = shard_client.pool.add_receipt(*receipt) {
+                                error!(target: "network", "{}", e);
+                            }
+                        }
+                        None => warn!(target: "network", "Received empty receipt block"),
                     }
                 }
-                _ => (),
             },
             Err(e) => warn!(target: "network", "{}", e),
         };
@@ -104,6 +185,24 @@ pub fn spawn_network(
     });
     tokio::spawn(task);
 
+    // Spawn a task that periodically sends our status to the peers and requests missing blocks
+    // and state from the peers that are ahead of us. It also retries the requests that timed out.
+    let client2 = client.clone();
+    let peer_manager2 = peer_manager.clone();
+    let task = Interval::new_interval(STATUS_INTERVAL)
+        .for_each(move |_| {
+            let status = get_status(&client2, account_id.clone());
+            let data = Encode::encode(&Message::Status(status)).unwrap();
+            for ch in peer_manager2.get_ready_channels() {
+                forward_msg(ch, PeerMessage::Message(data.to_vec()));
+            }
+            request_state(&client2, &block_sync, &state_sync, &out_msg_tx);
+            request_blocks(&client2, &block_sync, &out_msg_tx);
+            future::ok(())
+        })
+        .map_err(|e| warn!(target: "network", "Error sending status {}", e));
+    tokio::spawn(task);
+
     // Spawn a task that encodes and sends outgoing block announcements.
     let task = out_block_rx.for_each(move |b| {
         let data = Encode::encode(&Message::BlockAnnounce(Box::new((
@@ -119,6 +218,105 @@ pub fn spawn_network(
     tokio::spawn(task);
 }
 
+fn get_status(client: &Client, account_id: Option<AccountId>) -> Status {
+    let best_block = client.beacon_chain.chain.best_block();
+    Status {
+        version: PROTOCOL_VERSION,
+        best_index: best_block.index(),
+        best_hash: best_block.block_hash(),
+        genesis_hash: client.beacon_chain.chain.genesis_hash(),
+        account_id,
+    }
+}
+
+/// Sends the request for the next range of blocks, if we are behind some peer.
+fn request_blocks(
+    client: &Client,
+    block_sync: &RwLock<BlockSync>,
+    out_msg_tx: &Sender<(PeerId, Vec<u8>)>,
+) {
+    let best_index = client.beacon_chain.chain.best_index();
+    let request =
+        block_sync.write().expect(POISONED_LOCK_ERR).next_request(best_index, Instant::now());
+    if let Some((peer_id, request)) = request {
+        let data = Encode::encode(&Message::BlockRequest(request)).unwrap();
+        forward_msg(out_msg_tx.clone(), (peer_id, data));
+    }
+}
+
+/// Drives the state sync: chooses the block to sync the state at, starts the state download once
+/// the blocks up to it are imported and requests the next chunk of the trie nodes.
+fn request_state(
+    client: &Client,
+    block_sync: &RwLock<BlockSync>,
+    state_sync: &RwLock<StateSync>,
+    out_msg_tx: &Sender<(PeerId, Vec<u8>)>,
+) {
+    let mut state_sync = state_sync.write().expect(POISONED_LOCK_ERR);
+    if !state_sync.is_syncing() {
+        return;
+    }
+    let best_block = client.beacon_chain.chain.best_block();
+    if state_sync.target().is_none() {
+        match block_sync.read().expect(POISONED_LOCK_ERR).best_peer_index() {
+            Some(target) => state_sync.set_target(best_block.index(), target),
+            None => return,
+        }
+        block_sync.write().expect(POISONED_LOCK_ERR).set_target(state_sync.target());
+    }
+    let state_roots =
+        client.shard_clients.iter().map(|c| c.chain.best_block().merkle_root_state()).collect();
+    let synced = state_sync.on_blocks_imported(best_block.index(), state_roots);
+    if let Some(index) = synced {
+        finish_state_sync(block_sync, index);
+    }
+    let target = match state_sync.target() {
+        Some(target) => target,
+        None => return,
+    };
+    let peers = block_sync.read().expect(POISONED_LOCK_ERR).peers_with_block(target);
+    if let Some((peer_id, request)) = state_sync.next_request(&peers, Instant::now()) {
+        let data = Encode::encode(&Message::StateRequest(request)).unwrap();
+        forward_msg(out_msg_tx.clone(), (peer_id, data));
+    }
+}
+
+/// Resumes the normal block sync after the state was downloaded.
+fn finish_state_sync(block_sync: &RwLock<BlockSync>, index: u64) {
+    info!(target: "network", "State synced at block {}", index);
+    block_sync.write().expect(POISONED_LOCK_ERR).set_target(None);
+}
+
+/// Serves the blocks requested by the peer, at most `MAX_BLOCKS_PER_REQUEST` at once.
+fn get_block_response(client: &Client, request: BlockRequest) -> BlockResponse {
+    let to_index = |id: &BlockId| match id {
+        BlockId::Number(index) => Some(*index),
+        BlockId::Hash(_) => client.beacon_chain.chain.get_header(id).map(|h| h.index()),
+    };
+    let max = cmp::min(request.max.unwrap_or(MAX_BLOCKS_PER_REQUEST), MAX_BLOCKS_PER_REQUEST);
+    let blocks = match to_index(&request.from) {
+        Some(from) if max > 0 => {
+            let mut to = from + max - 1;
+            if let Some(requested_to) = request.to.as_ref().and_then(to_index) {
+                to = cmp::min(to, requested_to);
+            }
+            client.get_blocks_range(from, to)
+        }
+        _ => vec![],
+    };
+    BlockResponse { id: request.id, blocks }
+}
+
+/// Serves the trie nodes requested by the peer, at most `MAX_NODES_PER_REQUEST` at once.
+fn get_state_response(client: &Client, request: StateRequest) -> StateResponse {
+    let count = cmp::min(request.hashes.len(), MAX_NODES_PER_REQUEST);
+    let nodes = match client.shard_clients.get(request.shard_id as usize) {
+        Some(shard_client) => shard_client.trie.get_state_nodes(&request.hashes[..count]),
+        None => vec![],
+    };
+    StateResponse { id: request.id, nodes }
+}
+
 fn forward_msg<T>(ch: Sender<T>, el: T)
 where
     T: Send + 'static,
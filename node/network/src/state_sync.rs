@@ -0,0 +1,272 @@
+//! State sync lets a new node skip applying the whole history. The blocks up to the target block,
+//! chosen from the statuses of the peers, are imported without applying them. Then the tries of
+//! all the shards under the state roots of the target block are downloaded from the peers in
+//! chunks, one shard after another, after which the blocks are imported normally.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use primitives::types::{BlockIndex, MerkleHash, PeerId, ShardId};
+use storage::{Trie, TrieSync};
+
+use crate::message::{RequestId, StateRequest, StateResponse};
+
+/// How long do we wait for the peer to respond to the state request.
+pub const STATE_REQUEST_TIMEOUT: Duration = Duration::from_millis(5000);
+/// Max number of trie nodes we request or serve at once.
+pub const MAX_NODES_PER_REQUEST: usize = 1000;
+
+enum Phase {
+    /// Waiting for the peers to report their best blocks to choose the target.
+    WaitingForPeers,
+    /// Importing the blocks up to the target without applying them.
+    Blocks(BlockIndex),
+    /// Downloading the state of the block with the given index, with a trie sync per shard.
+    State(BlockIndex, Vec<TrieSync>),
+    Done,
+}
+
+struct PendingRequest {
+    id: RequestId,
+    peer_id: PeerId,
+    shard_id: ShardId,
+    requested_at: Instant,
+}
+
+pub struct StateSync {
+    /// Tries of the shards, indexed by the shard id.
+    tries: Vec<Arc<Trie>>,
+    phase: Phase,
+    /// Request that is currently in flight, we only request one chunk at a time.
+    pending: Option<PendingRequest>,
+    next_request_id: RequestId,
+}
+
+impl StateSync {
+    pub fn new(tries: Vec<Arc<Trie>>, enabled: bool) -> Self {
+        let phase = if enabled { Phase::WaitingForPeers } else { Phase::Done };
+        StateSync { tries, phase, pending: None, next_request_id: 0 }
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        match self.phase {
+            Phase::Done => false,
+            _ => true,
+        }
+    }
+
+    /// Index of the block the state is synced at, once it is chosen.
+    pub fn target(&self) -> Option<BlockIndex> {
+        match self.phase {
+            Phase::Blocks(target) | Phase::State(target, _) => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Chooses the block to sync the state at. The sync is skipped if we are not behind.
+    pub fn set_target(&mut self, best_index: BlockIndex, target: BlockIndex) {
+        if let Phase::WaitingForPeers = self.phase {
+            self.phase = if target > best_index { Phase::Blocks(target) } else { Phase::Done };
+        }
+    }
+
+    /// Starts downloading the state once the blocks up to the target are imported, `state_roots`
+    /// are the roots of the shards at the best block. Returns the index of the synced block if
+    /// the state is already present.
+    pub fn on_blocks_imported(
+        &mut self,
+        best_index: BlockIndex,
+        state_roots: Vec<MerkleHash>,
+    ) -> Option<BlockIndex> {
+        match self.phase {
+            Phase::Blocks(target) if best_index >= target => {
+                let trie_syncs = self
+                    .tries
+                    .iter()
+                    .zip(state_roots)
+                    .map(|(trie, state_root)| TrieSync::new(trie.clone(), state_root))
+                    .collect();
+                self.phase = Phase::State(best_index, trie_syncs);
+                self.finish_if_done()
+            }
+            _ => None,
+        }
+    }
+
+    /// If no request is in flight, returns the request for the next chunk of the trie nodes
+    /// together with the peer it should be sent to. Peers are taken in turns, so that a peer
+    /// that does not respond is not asked again right away.
+    pub fn next_request(
+        &mut self,
+        peers: &[PeerId],
+        now: Instant,
+    ) -> Option<(PeerId, StateRequest)> {
+        let trie_syncs = match &mut self.phase {
+            Phase::State(_, trie_syncs) => trie_syncs,
+            _ => return None,
+        };
+        if let Some(pending) = &self.pending {
+            if now.duration_since(pending.requested_at) < STATE_REQUEST_TIMEOUT {
+                return None;
+            }
+            trie_syncs[pending.shard_id as usize].retry();
+            self.pending = None;
+        }
+        if peers.is_empty() {
+            return None;
+        }
+        // Shards are downloaded one after another.
+        let (shard_id, trie_sync) = trie_syncs.iter_mut().enumerate().find(|(_, t)| !t.is_done())?;
+        let shard_id = shard_id as ShardId;
+        let hashes = trie_sync.next_request(MAX_NODES_PER_REQUEST);
+        if hashes.is_empty() {
+            return None;
+        }
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        let peer_id = peers[id as usize % peers.len()];
+        self.pending = Some(PendingRequest { id, peer_id, shard_id, requested_at: now });
+        Some((peer_id, StateRequest { id, shard_id, hashes }))
+    }
+
+    /// Verifies and stores the received trie nodes. Returns the index of the synced block once
+    /// the whole state is downloaded. Responses to requests that timed out are dropped.
+    pub fn on_response(
+        &mut self,
+        peer_id: &PeerId,
+        response: StateResponse,
+    ) -> Result<Option<BlockIndex>, String> {
+        let shard_id = match &self.pending {
+            Some(pending) if pending.id == response.id && &pending.peer_id == peer_id => {
+                pending.shard_id as usize
+            }
+            _ => return Ok(None),
+        };
+        self.pending = None;
+        if let Phase::State(_, trie_syncs) = &mut self.phase {
+            let changes = trie_syncs[shard_id].on_nodes(response.nodes)?;
+            self.tries[shard_id]
+                .apply_changes(changes)
+                .map_err(|e| format!("Failed to store the state: {}", e))?;
+        }
+        Ok(self.finish_if_done())
+    }
+
+    fn finish_if_done(&mut self) -> Option<BlockIndex> {
+        let index = match &self.phase {
+            Phase::State(index, trie_syncs) if trie_syncs.iter().all(|t| t.is_done()) => *index,
+            _ => return None,
+        };
+        self.phase = Phase::Done;
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::hash::hash;
+    use storage::test_utils::create_trie;
+
+    #[test]
+    fn test_state_sync() {
+        let source = create_trie();
+        let changes = vec![
+            (b"alice".to_vec(), Some(b"100".to_vec())),
+            (b"bob".to_vec(), Some(b"200".to_vec())),
+            (b"carol".to_vec(), Some(b"300".to_vec())),
+        ];
+        let (db_changes, root) = source.update(&Trie::empty_root(), changes.clone().into_iter());
+        source.apply_changes(db_changes).unwrap();
+
+        let target = create_trie();
+        let mut sync = StateSync::new(vec![target.clone()], true);
+        let (peer1, peer2) = (hash(&[1]), hash(&[2]));
+        sync.set_target(0, 10);
+        assert_eq!(sync.target(), Some(10));
+        assert_eq!(sync.on_blocks_imported(5, vec![root]), None);
+        let now = Instant::now();
+        assert!(sync.next_request(&[peer1, peer2], now).is_none());
+        assert_eq!(sync.on_blocks_imported(10, vec![root]), None);
+
+        let mut synced = None;
+        while synced.is_none() {
+            let (peer_id, request) = sync.next_request(&[peer1, peer2], now).unwrap();
+            // Only one request is in flight.
+            assert!(sync.next_request(&[peer1, peer2], now).is_none());
+            let nodes = source.get_state_nodes(&request.hashes);
+            synced = sync.on_response(&peer_id, StateResponse { id: request.id, nodes }).unwrap();
+        }
+        assert_eq!(synced, Some(10));
+        assert!(!sync.is_syncing());
+        for (key, value) in changes {
+            assert_eq!(target.get(&root, &key), value);
+        }
+    }
+
+    #[test]
+    fn test_state_sync_not_behind() {
+        let mut sync = StateSync::new(vec![create_trie()], true);
+        assert!(sync.is_syncing());
+        sync.set_target(10, 10);
+        assert!(!sync.is_syncing());
+        assert!(!StateSync::new(vec![create_trie()], false).is_syncing());
+    }
+
+    #[test]
+    fn test_state_sync_timeout() {
+        let source = create_trie();
+        let changes = vec![(b"alice".to_vec(), Some(b"100".to_vec()))];
+        let (db_changes, root) = source.update(&Trie::empty_root(), changes.into_iter());
+        source.apply_changes(db_changes).unwrap();
+
+        let mut sync = StateSync::new(vec![create_trie()], true);
+        let (peer1, peer2) = (hash(&[1]), hash(&[2]));
+        sync.set_target(0, 1);
+        sync.on_blocks_imported(1, vec![root]);
+        let now = Instant::now();
+        let (first_peer, first) = sync.next_request(&[peer1, peer2], now).unwrap();
+        let later = now + STATE_REQUEST_TIMEOUT;
+        let (second_peer, second) = sync.next_request(&[peer1, peer2], later).unwrap();
+        assert_ne!(first_peer, second_peer);
+        assert_eq!(first.hashes, second.hashes);
+        // Late response to the timed out request is ignored.
+        let nodes = source.get_state_nodes(&first.hashes);
+        assert_eq!(sync.on_response(&first_peer, StateResponse { id: first.id, nodes }), Ok(None));
+        assert!(sync.is_syncing());
+        let nodes = source.get_state_nodes(&second.hashes);
+        let response = StateResponse { id: second.id, nodes };
+        assert_eq!(sync.on_response(&second_peer, response), Ok(Some(1)));
+    }
+
+    #[test]
+    fn test_state_sync_shards() {
+        let sources = vec![create_trie(), create_trie()];
+        let mut roots = vec![];
+        for (i, source) in sources.iter().enumerate() {
+            let changes = vec![(vec![i as u8], Some(vec![i as u8; 10]))];
+            let (db_changes, root) = source.update(&Trie::empty_root(), changes.into_iter());
+            source.apply_changes(db_changes).unwrap();
+            roots.push(root);
+        }
+
+        let targets = vec![create_trie(), create_trie()];
+        let mut sync = StateSync::new(targets.clone(), true);
+        let peer = hash(&[1]);
+        sync.set_target(0, 1);
+        assert_eq!(sync.on_blocks_imported(1, roots.clone()), None);
+        let now = Instant::now();
+        let mut synced = None;
+        let mut requested_shards = vec![];
+        while synced.is_none() {
+            let (peer_id, request) = sync.next_request(&[peer], now).unwrap();
+            requested_shards.push(request.shard_id);
+            let nodes = sources[request.shard_id as usize].get_state_nodes(&request.hashes);
+            synced = sync.on_response(&peer_id, StateResponse { id: request.id, nodes }).unwrap();
+        }
+        assert_eq!(synced, Some(1));
+        assert_eq!(requested_shards, vec![0, 1]);
+        for (i, (target, root)) in targets.iter().zip(roots.iter()).enumerate() {
+            assert_eq!(target.get(root, &[i as u8]), Some(vec![i as u8; 10]));
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Tracks the best blocks reported by the peers and decides which block ranges should be
+//! requested from them, so that a node that fell behind can catch up.
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use primitives::hash::CryptoHash;
+use primitives::types::{BlockId, PeerId};
+
+use crate::message::{BlockRequest, BlockResponse, RequestId, Status};
+
+/// How often do we send our status to the peers.
+pub const STATUS_INTERVAL: Duration = Duration::from_millis(1000);
+/// How long do we wait for the peer to respond to the block request.
+pub const BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_millis(5000);
+/// Max number of blocks we request or serve at once.
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+struct PendingRequest {
+    id: RequestId,
+    peer_id: PeerId,
+    requested_at: Instant,
+}
+
+pub struct BlockSync {
+    /// Genesis hash of our chain. Peers from other chains are ignored.
+    genesis_hash: CryptoHash,
+    /// Last status received from each peer.
+    peer_statuses: HashMap<PeerId, Status>,
+    /// Request that is currently in flight, we only request one range at a time.
+    pending: Option<PendingRequest>,
+    /// Peers that did not respond in time. They are only retried when no other peer can help.
+    timed_out: HashSet<PeerId>,
+    next_request_id: RequestId,
+    /// Blocks after this index are not requested. Set while the state is synced at this block.
+    target: Option<u64>,
+}
+
+impl BlockSync {
+    pub fn new(genesis_hash: CryptoHash) -> Self {
+        BlockSync {
+            genesis_hash,
+            peer_statuses: HashMap::new(),
+            pending: None,
+            timed_out: HashSet::new(),
+            next_request_id: 0,
+            target: None,
+        }
+    }
+
+    pub fn set_target(&mut self, target: Option<u64>) {
+        self.target = target;
+    }
+
+    /// Highest best block index reported by the peers.
+    pub fn best_peer_index(&self) -> Option<u64> {
+        self.peer_statuses.values().map(|status| status.best_index).max()
+    }
+
+    /// Peers that reported having the block with the given index.
+    pub fn peers_with_block(&self, index: u64) -> Vec<PeerId> {
+        let mut peers: Vec<_> = self
+            .peer_statuses
+            .iter()
+            .filter(|(_, status)| status.best_index >= index)
+            .map(|(id, _)| *id)
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    /// Records the status reported by the peer.
+    pub fn on_status(&mut self, peer_id: PeerId, status: Status) {
+        if status.genesis_hash == self.genesis_hash {
+            self.peer_statuses.insert(peer_id, status);
+        }
+    }
+
+    /// Returns whether the response is the one we are waiting for. Responses to requests that
+    /// already timed out are dropped.
+    pub fn on_response(&mut self, peer_id: &PeerId, response: &BlockResponse) -> bool {
+        match &self.pending {
+            Some(pending) if pending.id == response.id && &pending.peer_id == peer_id => {
+                self.pending = None;
+                self.timed_out.remove(peer_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// If we are behind the peers and there is no request in flight, returns the request for
+    /// the next range of blocks together with the peer it should be sent to.
+    pub fn next_request(
+        &mut self,
+        best_index: u64,
+        now: Instant,
+    ) -> Option<(PeerId, BlockRequest)> {
+        if let Some(pending) = &self.pending {
+            if now.duration_since(pending.requested_at) < BLOCK_REQUEST_TIMEOUT {
+                return None;
+            }
+            self.timed_out.insert(pending.peer_id);
+            self.pending = None;
+        }
+        if self.target.map_or(false, |target| best_index >= target) {
+            return None;
+        }
+        let (peer_id, peer_best_index) = match self.best_peer(best_index) {
+            Some(peer) => peer,
+            None => {
+                // Everyone who is ahead has timed out, give them another chance.
+                self.timed_out.clear();
+                self.best_peer(best_index)?
+            }
+        };
+        let from = best_index + 1;
+        let mut to = cmp::min(peer_best_index, from + MAX_BLOCKS_PER_REQUEST - 1);
+        if let Some(target) = self.target {
+            to = cmp::min(to, target);
+        }
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        self.pending = Some(PendingRequest { id, peer_id, requested_at: now });
+        Some((
+            peer_id,
+            BlockRequest {
+                id,
+                from: BlockId::Number(from),
+                to: Some(BlockId::Number(to)),
+                max: Some(MAX_BLOCKS_PER_REQUEST),
+            },
+        ))
+    }
+
+    /// Peer with the highest best block that is ahead of us and did not time out.
+    fn best_peer(&self, best_index: u64) -> Option<(PeerId, u64)> {
+        self.peer_statuses
+            .iter()
+            .filter(|(id, status)| {
+                status.best_index > best_index && !self.timed_out.contains(*id)
+            })
+            .map(|(id, status)| (*id, status.best_index))
+            .max_by_key(|(id, index)| (*index, *id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::hash::hash;
+
+    fn status(best_index: u64, genesis_hash: CryptoHash) -> Status {
+        Status {
+            version: 1,
+            best_index,
+            best_hash: CryptoHash::default(),
+            genesis_hash,
+            account_id: None,
+        }
+    }
+
+    #[test]
+    fn test_request_from_best_peer() {
+        let genesis_hash = hash(b"genesis");
+        let mut sync = BlockSync::new(genesis_hash);
+        let (peer1, peer2, peer3) = (hash(&[1]), hash(&[2]), hash(&[3]));
+        sync.on_status(peer1, status(10, genesis_hash));
+        sync.on_status(peer2, status(500, genesis_hash));
+        // Peer on a different chain is ignored.
+        sync.on_status(peer3, status(1000, CryptoHash::default()));
+        let now = Instant::now();
+        let (peer_id, request) = sync.next_request(5, now).unwrap();
+        assert_eq!(peer_id, peer2);
+        assert_eq!(request.from, BlockId::Number(6));
+        assert_eq!(request.to, Some(BlockId::Number(5 + MAX_BLOCKS_PER_REQUEST)));
+        // Only one request is in flight.
+        assert!(sync.next_request(5, now).is_none());
+        let response = BlockResponse { id: request.id, blocks: vec![] };
+        assert!(!sync.on_response(&peer1, &response));
+        assert!(sync.on_response(&peer2, &response));
+        assert!(sync.next_request(500, now).is_none());
+    }
+
+    #[test]
+    fn test_retry_after_timeout() {
+        let genesis_hash = hash(b"genesis");
+        let mut sync = BlockSync::new(genesis_hash);
+        let (peer1, peer2) = (hash(&[1]), hash(&[2]));
+        sync.on_status(peer1, status(10, genesis_hash));
+        sync.on_status(peer2, status(20, genesis_hash));
+        let now = Instant::now();
+        let (peer_id, first) = sync.next_request(0, now).unwrap();
+        assert_eq!(peer_id, peer2);
+        let later = now + BLOCK_REQUEST_TIMEOUT;
+        let (peer_id, second) = sync.next_request(0, later).unwrap();
+        assert_eq!(peer_id, peer1);
+        assert_eq!(second.to, Some(BlockId::Number(10)));
+        // Late response to the timed out request is ignored.
+        assert!(!sync.on_response(&peer2, &BlockResponse { id: first.id, blocks: vec![] }));
+        // Once every peer timed out, we start over.
+        let (peer_id, _) = sync.next_request(0, later + BLOCK_REQUEST_TIMEOUT).unwrap();
+        assert_eq!(peer_id, peer2);
+    }
+
+    #[test]
+    fn test_stop_at_target() {
+        let genesis_hash = hash(b"genesis");
+        let mut sync = BlockSync::new(genesis_hash);
+        let (peer1, peer2) = (hash(&[1]), hash(&[2]));
+        sync.on_status(peer1, status(10, genesis_hash));
+        sync.on_status(peer2, status(20, genesis_hash));
+        assert_eq!(sync.best_peer_index(), Some(20));
+        assert_eq!(sync.peers_with_block(15), vec![peer2]);
+        sync.set_target(Some(15));
+        let now = Instant::now();
+        let (_, request) = sync.next_request(0, now).unwrap();
+        assert_eq!(request.to, Some(BlockId::Number(15)));
+        sync.on_response(&peer2, &BlockResponse { id: request.id, blocks: vec![] });
+        assert!(sync.next_request(15, now).is_none());
+        sync.set_target(None);
+        assert!(sync.next_request(15, now).is_some());
+    }
+}
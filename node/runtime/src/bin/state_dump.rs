@@ -0,0 +1,56 @@
+//! Exports the state of a shard from the chain database into a dump file, and imports a dump into
+//! the database. The node must not be running while its database is used.
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+use node_runtime::state_dump::{export_state, import_state, read_state_dump, write_state_dump};
+use primitives::types::{MerkleHash, ShardId};
+use storage::{create_storage, StorageBackend, Trie};
+
+const USAGE: &str = "Usage:
+    state_dump export <path to storage/db> <shard id> <state root> <dump file>
+    state_dump import <path to storage/db> <shard id> <dump file>";
+
+fn open_trie(storage_path: &str, shard_id: &str) -> Arc<Trie> {
+    let shard_id: ShardId = shard_id.parse().unwrap_or_else(|_| exit_with_usage());
+    let (_, mut shards) = create_storage(storage_path, shard_id + 1, StorageBackend::RocksDb);
+    Arc::new(Trie::new(shards.pop().expect("Shard storage was created")))
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    match args {
+        [command, storage_path, shard_id, root, dump_path] if command == "export" => {
+            let trie = open_trie(storage_path, shard_id);
+            let root: MerkleHash = root.clone().into();
+            let dump = export_state(&trie, &root)?;
+            write_state_dump(Path::new(dump_path), &dump)?;
+            println!("Exported {} records of state {}", dump.records.len(), root);
+            Ok(())
+        }
+        [command, storage_path, shard_id, dump_path] if command == "import" => {
+            let trie = open_trie(storage_path, shard_id);
+            let dump = read_state_dump(Path::new(dump_path))?;
+            let (root, db_changes) = import_state(&trie, &dump)?;
+            trie.apply_changes(db_changes)
+                .map_err(|e| format!("Failed to store the state: {}", e))?;
+            println!("Imported {} records of state {}", dump.records.len(), root);
+            Ok(())
+        }
+        _ => exit_with_usage(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -1,18 +1,22 @@
 use std::collections::HashMap;
 use std::iter::Peekable;
 
+use configs::chain_spec::FeeConfig;
 use kvdb::DBValue;
 
 use primitives::hash::CryptoHash;
 use primitives::types::{
-    AccountId, AccountingInfo, Balance, CallbackId,
-    Mana, PromiseId, ReceiptId,
+    AccountId, AccountingInfo, Balance, BlockIndex, CallbackId,
+    Gas, Mana, PromiseId, ReceiptId,
+};
+use primitives::transaction::{
+    AsyncCall, ReceiptTransaction, Callback, CallbackInfo, ReceiptBody, ScheduledCall,
 };
-use primitives::transaction::{AsyncCall, ReceiptTransaction, Callback, CallbackInfo, ReceiptBody};
 use storage::{TrieUpdate, TrieUpdateIterator};
 use wasm::ext::{External, Result as ExtResult, Error as ExtError};
 
 use super::{account_id_to_bytes, create_nonce_with_nonce, COL_ACCOUNT, callback_id_to_bytes, set};
+use crate::fees::gas_for_fee;
 
 pub struct RuntimeExt<'a> {
     trie_update: &'a mut TrieUpdate,
@@ -25,6 +29,12 @@ pub struct RuntimeExt<'a> {
     transaction_hash: &'a CryptoHash,
     iters: HashMap<u32, Peekable<TrieUpdateIterator<'a>>>,
     last_iter_id: u32,
+    /// Storage usage of the account, updated as the contract writes to its storage.
+    storage_usage: u64,
+    /// Gas limit of the executed call, inherited by the calls and the callbacks it creates.
+    gas_limit: Option<Gas>,
+    /// Gas price of the executed call, inherited by the calls and the callbacks it creates.
+    gas_price: Balance,
 }
 
 impl<'a> RuntimeExt<'a> {
@@ -32,7 +42,10 @@ impl<'a> RuntimeExt<'a> {
         trie_update: &'a mut TrieUpdate,
         account_id: &AccountId,
         accounting_info: &AccountingInfo,
-        transaction_hash: &'a CryptoHash
+        transaction_hash: &'a CryptoHash,
+        storage_usage: u64,
+        gas_limit: Option<Gas>,
+        gas_price: Balance,
     ) -> Self {
         let mut prefix = account_id_to_bytes(COL_ACCOUNT, account_id);
         prefix.append(&mut b",".to_vec());
@@ -47,25 +60,79 @@ impl<'a> RuntimeExt<'a> {
             transaction_hash,
             iters: HashMap::new(),
             last_iter_id: 0,
+            storage_usage,
+            gas_limit,
+            gas_price,
         }
     }
 
+    pub fn storage_usage(&self) -> u64 {
+        self.storage_usage
+    }
+
     pub fn create_storage_key(&self, key: &[u8]) -> Vec<u8> {
         let mut storage_key = self.storage_prefix.clone();
         storage_key.extend_from_slice(key);
         storage_key
     }
 
+    /// Subtracts the size of the entry that is overwritten or removed from the storage usage.
+    fn release_storage(&mut self, storage_key: &[u8]) -> ExtResult<()> {
+        if let Some(old_value) = self.trie_update.get(storage_key) {
+            self.storage_usage = self
+                .storage_usage
+                .checked_sub((storage_key.len() + old_value.len()) as u64)
+                .ok_or(ExtError::StorageUsageUnderflow)?;
+        }
+        Ok(())
+    }
+
     pub fn create_nonce(&mut self) -> CryptoHash {
         let nonce = create_nonce_with_nonce(self.transaction_hash, self.nonce);
         self.nonce += 1;
         nonce
     }
 
+    fn add_receipt(&mut self, receiver_id: AccountId, body: ReceiptBody) -> PromiseId {
+        let nonce = self.create_nonce();
+        let receipt = ReceiptTransaction::new(self.account_id.clone(), receiver_id, nonce, body);
+        self.receipts.insert(nonce.as_ref().to_vec(), receipt);
+        PromiseId::Receipt(nonce.as_ref().to_vec())
+    }
+
     pub fn get_receipts(&mut self) -> Vec<ReceiptTransaction> {
         self.receipts.drain().map(|(_, v)| v).collect()
     }
 
+    /// Passes the unused gas fee of the executed call in equal shares to the calls and the
+    /// callbacks it created, and limits their gas to what their share pays for. Returns the part
+    /// of the fee that is not passed on and is refunded to the originator.
+    pub fn share_gas_fee(&mut self, fees: &FeeConfig, gas_fee: Balance) -> Balance {
+        let num_children = (self.receipts.len() + self.callbacks.len()) as Balance;
+        if num_children == 0 {
+            return gas_fee;
+        }
+        let share = gas_fee / num_children;
+        let share_gas = gas_for_fee(fees, self.gas_price, share);
+        let limit =
+            |gas_limit: Option<Gas>| Some(gas_limit.map_or(share_gas, |g| g.min(share_gas)));
+        for receipt in self.receipts.values_mut() {
+            match receipt.body {
+                ReceiptBody::NewCall(ref mut call)
+                | ReceiptBody::ScheduledCall(ScheduledCall { ref mut call, .. }) => {
+                    call.prepaid_gas_fee = share;
+                    call.gas_limit = limit(call.gas_limit);
+                }
+                _ => unreachable!("contracts only create calls"),
+            }
+        }
+        for callback in self.callbacks.values_mut() {
+            callback.prepaid_gas_fee = share;
+            callback.gas_limit = limit(callback.gas_limit);
+        }
+        gas_fee - share * num_children
+    }
+
     /// write callbacks to stateUpdate
     pub fn flush_callbacks(&mut self) {
         for (id, callback) in self.callbacks.drain() {
@@ -81,6 +148,8 @@ impl<'a> RuntimeExt<'a> {
 impl<'a> External for RuntimeExt<'a> {
     fn storage_set(&mut self, key: &[u8], value: &[u8]) -> ExtResult<()> {
         let storage_key = self.create_storage_key(key);
+        self.release_storage(&storage_key)?;
+        self.storage_usage += (storage_key.len() + value.len()) as u64;
         self.trie_update.set(&storage_key, &DBValue::from_slice(value));
         Ok(())
     }
@@ -93,6 +162,7 @@ impl<'a> External for RuntimeExt<'a> {
 
     fn storage_remove(&mut self, key: &[u8]) -> ExtResult<()> {
         let storage_key = self.create_storage_key(key);
+        self.release_storage(&storage_key)?;
         self.trie_update.remove(&storage_key);
         Ok(())
     }
@@ -153,22 +223,28 @@ impl<'a> External for RuntimeExt<'a> {
         mana: Mana,
         amount: Balance,
     ) -> ExtResult<PromiseId> {
-        let nonce = self.create_nonce();
-        let receipt = ReceiptTransaction::new(
-            self.account_id.clone(),
-            account_id,
-            nonce,
-            ReceiptBody::NewCall(AsyncCall::new(
-                method_name,
-                arguments,
-                amount,
-                mana,
-                self.accounting_info.clone(),
-            )),
-        );
-        let promise_id = PromiseId::Receipt(nonce.as_ref().to_vec());
-        self.receipts.insert(nonce.as_ref().to_vec(), receipt);
-        Ok(promise_id)
+        let accounting_info = self.accounting_info.clone();
+        let mut call = AsyncCall::new(method_name, arguments, amount, mana, accounting_info);
+        call.gas_limit = self.gas_limit;
+        call.gas_price = self.gas_price;
+        Ok(self.add_receipt(account_id, ReceiptBody::NewCall(call)))
+    }
+
+    fn promise_schedule(
+        &mut self,
+        account_id: AccountId,
+        method_name: Vec<u8>,
+        arguments: Vec<u8>,
+        mana: Mana,
+        amount: Balance,
+        block_index: BlockIndex,
+    ) -> ExtResult<PromiseId> {
+        let accounting_info = self.accounting_info.clone();
+        let mut call = AsyncCall::new(method_name, arguments, amount, mana, accounting_info);
+        call.gas_limit = self.gas_limit;
+        call.gas_price = self.gas_price;
+        let body = ReceiptBody::ScheduledCall(ScheduledCall::new(block_index, call));
+        Ok(self.add_receipt(account_id, body))
     }
 
     fn promise_then(
@@ -190,6 +266,8 @@ impl<'a> External for RuntimeExt<'a> {
             mana,
             self.accounting_info.clone(),
         );
+        callback.gas_limit = self.gas_limit;
+        callback.gas_price = self.gas_price;
         callback.results.resize(receipt_ids.len(), None);
         for (index, receipt_id) in receipt_ids.iter().enumerate() {
             let receipt = match self.receipts.get_mut(receipt_id) {
@@ -197,7 +275,8 @@ impl<'a> External for RuntimeExt<'a> {
                 _ => return Err(ExtError::PromiseIdNotFound)
             };
             match receipt.body {
-                ReceiptBody::NewCall(ref mut async_call) => {
+                ReceiptBody::NewCall(ref mut async_call)
+                | ReceiptBody::ScheduledCall(ScheduledCall { call: ref mut async_call, .. }) => {
                     let callback_info = CallbackInfo::new(callback_id.as_ref().to_vec(), index, self.account_id.clone());
                     match async_call.callback {
                         Some(_) => return Err(ExtError::PromiseAlreadyHasCallback),
@@ -215,3 +294,77 @@ impl<'a> External for RuntimeExt<'a> {
         Ok(PromiseId::Callback(callback_id.as_ref().to_vec()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use primitives::types::MerkleHash;
+    use storage::test_utils::create_trie;
+
+    use crate::test_utils::bob_account;
+
+    use super::*;
+
+    #[test]
+    fn test_storage_usage_underflow() {
+        let trie = create_trie();
+        let mut state_update = TrieUpdate::new(trie, MerkleHash::default());
+        let account_id = bob_account();
+        let hash = CryptoHash::default();
+        let mut ext = RuntimeExt::new(
+            &mut state_update,
+            &account_id,
+            &AccountingInfo::default(),
+            &hash,
+            0,
+            None,
+            0,
+        );
+        ext.storage_set(b"key", b"value").unwrap();
+        let usage = ext.storage_usage();
+        ext.storage_remove(b"key").unwrap();
+        assert_eq!(ext.storage_usage(), 0);
+
+        // The entry is not accounted for, e.g. written before the storage usage was tracked.
+        ext.storage_set(b"key", b"value").unwrap();
+        ext.storage_usage = usage - 1;
+        assert_eq!(ext.storage_remove(b"key"), Err(ExtError::StorageUsageUnderflow));
+        assert_eq!(ext.storage_set(b"key", b"new"), Err(ExtError::StorageUsageUnderflow));
+    }
+
+    #[test]
+    fn test_share_gas_fee() {
+        let trie = create_trie();
+        let mut state_update = TrieUpdate::new(trie, MerkleHash::default());
+        let account_id = bob_account();
+        let hash = CryptoHash::default();
+        let mut ext = RuntimeExt::new(
+            &mut state_update,
+            &account_id,
+            &AccountingInfo::default(),
+            &hash,
+            0,
+            Some(1_000_000),
+            2,
+        );
+        let fees = FeeConfig { gas_price_num: 1, gas_price_denum: 1000, ..Default::default() };
+        assert_eq!(ext.share_gas_fee(&fees, 100), 100);
+
+        let promise = ext.promise_create(account_id.clone(), b"a".to_vec(), vec![], 0, 0).unwrap();
+        ext.promise_create(account_id.clone(), b"b".to_vec(), vec![], 0, 0).unwrap();
+        ext.promise_then(promise, b"c".to_vec(), vec![], 0).unwrap();
+        assert_eq!(ext.share_gas_fee(&fees, 100), 1);
+        for receipt in ext.receipts.values() {
+            match &receipt.body {
+                ReceiptBody::NewCall(call) => {
+                    assert_eq!(call.prepaid_gas_fee, 33);
+                    assert_eq!(call.gas_limit, Some(16_500));
+                    assert_eq!(call.gas_price, 2);
+                }
+                _ => panic!("Expected a new call, got {:?}", receipt.body),
+            }
+        }
+        let callback = ext.callbacks.values().next().unwrap();
+        assert_eq!((callback.prepaid_gas_fee, callback.gas_limit), (33, Some(16_500)));
+        assert_eq!(callback.gas_price, 2);
+    }
+}
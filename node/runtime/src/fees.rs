@@ -0,0 +1,93 @@
+//! Fees in tokens that are charged to the originator of the transaction on top of the mana. The
+//! gas of the function call is prepaid with the gas limit of the call at the gas price it offers,
+//! and the gas that was not used by the contract is returned with the mana accounting receipt.
+use configs::chain_spec::FeeConfig;
+use primitives::transaction::TransactionBody;
+use primitives::types::{Balance, Gas};
+
+/// Fee of the transaction, without the prepaid gas. Fails if the fee overflows.
+pub fn transaction_fee(config: &FeeConfig, body: &TransactionBody) -> Result<Balance, String> {
+    let (base_fee, num_bytes) = match body {
+        TransactionBody::CreateAccount(_) => (config.create_account, 0),
+        TransactionBody::DeployContract(t) => (config.deploy_contract, t.wasm_byte_array.len()),
+        TransactionBody::FunctionCall(t) => {
+            (config.function_call, t.method_name.len() + t.args.len())
+        }
+        TransactionBody::SendMoney(_) => (config.send_money, 0),
+        TransactionBody::Stake(_) => (config.stake, 0),
+        TransactionBody::SwapKey(_) => (config.swap_key, 0),
+        TransactionBody::AddKey(_) => (config.add_key, 0),
+        TransactionBody::DeleteKey(_) => (config.delete_key, 0),
+        TransactionBody::Unstake(_) => (config.unstake, 0),
+        TransactionBody::DeleteAccount(_) => (config.delete_account, 0),
+        TransactionBody::SetMultisigPolicy(_) => (config.set_multisig_policy, 0),
+    };
+    config
+        .per_byte
+        .checked_mul(num_bytes as Balance)
+        .and_then(|bytes_fee| bytes_fee.checked_add(base_fee))
+        .ok_or_else(|| format!("Fee of the transaction with {} bytes overflows", num_bytes))
+}
+
+/// Price of the given amount of wasm gas at the given gas price numerator, rounded up. `None` if
+/// the price overflows.
+pub fn gas_fee(config: &FeeConfig, gas_price: Balance, gas: Gas) -> Option<Balance> {
+    let price = gas.checked_mul(gas_price)?;
+    Some(price / config.gas_price_denum + if price % config.gas_price_denum > 0 { 1 } else { 0 })
+}
+
+/// Amount of wasm gas that the given fee pays for at the given gas price numerator, rounded down.
+pub fn gas_for_fee(config: &FeeConfig, gas_price: Balance, fee: Balance) -> Gas {
+    if gas_price == 0 {
+        return Gas::max_value();
+    }
+    fee.saturating_mul(config.gas_price_denum) / gas_price
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::transaction::{DeployContractTransaction, SendMoneyTransaction};
+
+    #[test]
+    fn test_transaction_fee() {
+        let config = FeeConfig { send_money: 2, deploy_contract: 5, per_byte: 1, ..Default::default() };
+        let send_money = TransactionBody::SendMoney(SendMoneyTransaction {
+            nonce: 1,
+            originator: "alice.near".to_string(),
+            receiver: "bob.near".to_string(),
+            amount: 10,
+        });
+        assert_eq!(transaction_fee(&config, &send_money), Ok(2));
+        let deploy = TransactionBody::DeployContract(DeployContractTransaction {
+            nonce: 1,
+            contract_id: "alice.near".to_string(),
+            wasm_byte_array: vec![0; 10],
+            migration_method_name: None,
+        });
+        assert_eq!(transaction_fee(&config, &deploy), Ok(15));
+        assert_eq!(transaction_fee(&FeeConfig::default(), &deploy), Ok(0));
+        let config = FeeConfig { per_byte: Balance::max_value(), ..Default::default() };
+        assert!(transaction_fee(&config, &deploy).is_err());
+    }
+
+    #[test]
+    fn test_gas_fee() {
+        let config = FeeConfig { gas_price_num: 1, gas_price_denum: 1000, ..Default::default() };
+        assert_eq!(gas_fee(&config, 1, 0), Some(0));
+        assert_eq!(gas_fee(&config, 1, 1000), Some(1));
+        assert_eq!(gas_fee(&config, 1, 1001), Some(2));
+        assert_eq!(gas_fee(&config, 3, 1000), Some(3));
+        assert_eq!(gas_fee(&FeeConfig::default(), 0, 1_000_000), Some(0));
+        assert_eq!(gas_fee(&config, Balance::max_value(), 2), None);
+    }
+
+    #[test]
+    fn test_gas_for_fee() {
+        let config = FeeConfig { gas_price_num: 1, gas_price_denum: 1000, ..Default::default() };
+        assert_eq!(gas_for_fee(&config, 3, 0), 0);
+        assert_eq!(gas_for_fee(&config, 3, 1), 333);
+        assert!(gas_fee(&config, 3, gas_for_fee(&config, 3, 10)).unwrap() <= 10);
+        assert_eq!(gas_for_fee(&FeeConfig::default(), 0, 0), Gas::max_value());
+    }
+}
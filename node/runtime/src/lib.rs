@@ -7,6 +7,7 @@ extern crate primitives;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate storage;
 extern crate wasm;
 
@@ -17,28 +18,31 @@ use serde::{de::DeserializeOwned, Serialize};
 use primitives::aggregate_signature::BlsPublicKey;
 use primitives::hash::{CryptoHash, hash};
 use primitives::signature::{bs58_serializer, PublicKey};
-use primitives::traits::{Decode, Encode};
+use primitives::traits::{Base58Encoded, Decode, Encode};
 use primitives::types::{
     AccountId, AccountingInfo, AuthorityStake,
-    Balance, BlockIndex, Mana,
+    Balance, BlockIndex, Gas, Mana,
     ManaAccounting, MerkleHash, PromiseId, ReadablePublicKey, ReadableBlsPublicKey, ShardId,
 };
 use primitives::utils::{
     account_to_shard_id, index_to_bytes, is_valid_account_id
 };
 use primitives::transaction::{
-    AsyncCall, Callback, CallbackInfo, CallbackResult,
+    AsyncCall, Callback, CallbackInfo, CallbackResult, ContractEvent,
     FunctionCallTransaction, LogEntry, ReceiptBody,
-    ReceiptTransaction, SignedTransaction,
-    TransactionBody, TransactionResult, TransactionStatus,
-    verify_transaction_signature
+    ReceiptTransaction, ScheduledCall, SignedTransaction,
+    TransactionBody, TransactionResult, TransactionSigner, TransactionStatus,
+    AccessKey, verify_transaction_signature
 };
+use configs::chain_spec::{FeeConfig, StorageRentConfig};
 use wasm::executor;
-use wasm::types::{ReturnData, RuntimeContext};
+use wasm::types::{Config, ReturnData, RuntimeContext};
 use primitives::chain::ReceiptBlock;
 
 use crate::ext::RuntimeExt;
 use crate::tx_stakes::{get_tx_stake_key, TxStakeConfig, TxTotalStake};
+use crate::storage_rent::{check_storage_rent, entries_storage_usage};
+use crate::fees::{gas_fee, transaction_fee};
 use crate::system::{
     SYSTEM_METHOD_CREATE_ACCOUNT, system_account,
     system_create_account
@@ -47,7 +51,10 @@ use storage::TrieUpdate;
 
 pub mod test_utils;
 pub mod state_viewer;
+pub mod state_dump;
 mod tx_stakes;
+mod storage_rent;
+mod fees;
 mod ext;
 mod system;
 
@@ -56,24 +63,41 @@ const COL_CALLBACK: &[u8] = &[1];
 const COL_CODE: &[u8] = &[2];
 const COL_TX_STAKE: &[u8] = &[3];
 const COL_TX_STAKE_SEPARATOR: &[u8] = &[4];
+const COL_ACCESS_KEY: &[u8] = &[5];
+const COL_KEY_NONCE: &[u8] = &[6];
+const COL_SCHEDULED_RECEIPT: &[u8] = &[7];
+const COL_PROPOSED_STAKE: &[u8] = &[8];
 
 /// Per account information stored in the state.
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct Account {
     pub public_keys: Vec<PublicKey>,
     // TODO: Multiple bls keys associated with the same account
     #[serde(with = "bs58_serializer")]
     pub bls_public_key: BlsPublicKey,
-    pub nonce: u64,
     // amount + staked is the total value of the account
     pub amount: u64,
     pub staked: u64,
     pub code_hash: CryptoHash,
+    /// Bytes taken by the code and the contract storage of the account. The other entries of the
+    /// account are counted by `storage_rent::entries_storage_usage`.
+    pub storage_usage: u64,
+    /// Number of distinct public keys that must sign each transaction of the account, any single
+    /// key is enough if it is 0 or 1. See `verify_transaction_signature`.
+    pub multisig_threshold: u32,
 }
 
 impl Account {
     pub fn new(public_keys: Vec<PublicKey>, amount: Balance, code_hash: CryptoHash) -> Self {
-        Account { public_keys, bls_public_key: BlsPublicKey::empty(), nonce: 0, amount, staked: 0, code_hash }
+        Account {
+            public_keys,
+            bls_public_key: BlsPublicKey::empty(),
+            amount,
+            staked: 0,
+            code_hash,
+            storage_usage: 0,
+            multisig_threshold: 0,
+        }
     }
 }
 
@@ -83,12 +107,79 @@ fn account_id_to_bytes(col: &[u8], account_key: &AccountId) -> Vec<u8> {
     key
 }
 
+/// Prefix of the per key entries of the given account in the column, the entries are stored
+/// under `<col>,<account_id>,<public_key>`.
+fn public_key_prefix(col: &[u8], account_id: &AccountId) -> Vec<u8> {
+    let mut key = account_id_to_bytes(col, account_id);
+    key.push(b',');
+    key
+}
+
+fn public_key_to_bytes(col: &[u8], account_id: &AccountId, public_key: &PublicKey) -> Vec<u8> {
+    let mut key = public_key_prefix(col, account_id);
+    key.extend_from_slice(&public_key.0[..]);
+    key
+}
+
+/// Keys in the state that start with the prefix, including the uncommitted changes.
+fn get_keys_with_prefix(state_update: &TrieUpdate, prefix: &[u8]) -> Vec<Vec<u8>> {
+    match state_update.iter(prefix) {
+        Ok(iter) => iter.collect(),
+        Err(e) => {
+            debug!(target: "runtime", "Error while iterating by prefix: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// Nonce of the last transaction signed with the given key of the account. Keys of one account
+/// have independent nonces, so that they do not race each other.
+fn get_key_nonce(
+    state_update: &TrieUpdate,
+    account_id: &AccountId,
+    public_key: &PublicKey,
+) -> u64 {
+    get(state_update, &public_key_to_bytes(COL_KEY_NONCE, account_id, public_key)).unwrap_or(0)
+}
+
+/// The largest nonce used by any key of the account.
+fn get_max_key_nonce(state_update: &TrieUpdate, account_id: &AccountId) -> u64 {
+    get_keys_with_prefix(state_update, &public_key_prefix(COL_KEY_NONCE, account_id))
+        .iter()
+        .filter_map(|key| get(state_update, key))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Function-call-only keys of the account with their permissions.
+fn get_access_keys(
+    state_update: &TrieUpdate,
+    account_id: &AccountId,
+) -> Vec<(PublicKey, AccessKey)> {
+    let prefix = public_key_prefix(COL_ACCESS_KEY, account_id);
+    get_keys_with_prefix(state_update, &prefix)
+        .into_iter()
+        .filter_map(|key| {
+            let public_key = PublicKey::new(&key[prefix.len()..]).ok()?;
+            let access_key = get(state_update, &key)?;
+            Some((public_key, access_key))
+        })
+        .collect()
+}
+
 fn callback_id_to_bytes(id: &[u8]) -> Vec<u8> {
     let mut key = COL_CALLBACK.to_vec();
     key.extend_from_slice(id);
     key
 }
 
+/// Key prefix of the receipts scheduled at the given block.
+fn scheduled_receipts_prefix(block_index: BlockIndex) -> Vec<u8> {
+    let mut key = COL_SCHEDULED_RECEIPT.to_vec();
+    key.append(&mut index_to_bytes(block_index));
+    key
+}
+
 fn create_nonce_with_nonce(base: &CryptoHash, salt: u64) -> CryptoHash {
     let mut nonce: Vec<u8> = base.as_ref().to_owned();
     nonce.append(&mut index_to_bytes(salt));
@@ -101,6 +192,8 @@ pub struct ApplyState {
     pub shard_id: ShardId,
     pub block_index: u64,
     pub parent_block_hash: CryptoHash,
+    /// Stakes that are no longer locked by the authority set and should be released.
+    pub withdrawals: Vec<AuthorityStake>,
 }
 
 #[derive(Clone, Debug)]
@@ -110,10 +203,14 @@ pub struct ApplyResult {
     pub db_changes: storage::DBChanges,
     pub authority_proposals: Vec<AuthorityStake>,
     pub new_receipts: HashMap<ShardId, Vec<ReceiptTransaction>>,
+    /// Hashes of the executed scheduled receipts, which are not part of the block. Their results
+    /// come first in `tx_result`, followed by the results of the receipts and the transactions of
+    /// the block.
+    pub scheduled_receipts: Vec<CryptoHash>,
     pub tx_result: Vec<TransactionResult>,
 }
 
-fn get<T: DeserializeOwned>(state_update: &mut TrieUpdate, key: &[u8]) -> Option<T> {
+fn get<T: DeserializeOwned>(state_update: &TrieUpdate, key: &[u8]) -> Option<T> {
     state_update.get(key).and_then(|data| Decode::decode(&data).ok())
 }
 
@@ -124,10 +221,58 @@ fn set<T: Serialize>(state_update: &mut TrieUpdate, key: &[u8], value: &T) {
         .unwrap_or_else(|| { debug!("set value failed"); })
 }
 
-#[derive(Clone, Copy, Default)]
-pub struct Runtime {}
+#[derive(Clone, Copy)]
+pub struct Runtime {
+    pub fees: FeeConfig,
+    pub num_shards: ShardId,
+    /// Chain-wide maximum gas of a contract call.
+    pub max_gas_limit: Gas,
+    /// Chain-wide maximum gas price numerator a function call can offer.
+    pub max_gas_price: Balance,
+    pub storage_rent: StorageRentConfig,
+    /// Gas costs and limits of the contracts, used for every execution of the contract code.
+    pub wasm_config: Config,
+}
 
 impl Runtime {
+    /// Gas limit of a call that asks for the given limit, capped by the chain-wide maximum.
+    fn gas_limit(self, gas_limit: Option<Gas>) -> Gas {
+        gas_limit.map_or(self.max_gas_limit, |gas_limit| gas_limit.min(self.max_gas_limit))
+    }
+
+    /// Gas limit, gas price and the prepaid gas fee of the function call. The gas price must be
+    /// between the minimum gas price and the chain-wide maximum.
+    fn gas_prepayment(
+        self,
+        transaction: &FunctionCallTransaction,
+    ) -> Result<(Gas, Balance, Balance), String> {
+        let gas_limit = self.gas_limit(transaction.gas_limit);
+        let gas_price = transaction.gas_price.unwrap_or(self.fees.gas_price_num);
+        if gas_price < self.fees.gas_price_num {
+            return Err(format!(
+                "Account {} offers the gas price {}, lower than the minimum gas price {}",
+                transaction.originator, gas_price, self.fees.gas_price_num,
+            ));
+        }
+        if gas_price > self.max_gas_price {
+            return Err(format!(
+                "Account {} offers the gas price {}, higher than the maximum gas price {}",
+                transaction.originator, gas_price, self.max_gas_price,
+            ));
+        }
+        // Calls without a method name only transfer tokens and do not execute wasm.
+        let gas_prepayment = if transaction.method_name.is_empty() {
+            0
+        } else {
+            gas_fee(&self.fees, gas_price, gas_limit).ok_or_else(|| {
+                format!(
+                    "Account {} tries to prepay {} gas at the gas price {}, which overflows",
+                    transaction.originator, gas_limit, gas_price,
+                )
+            })?
+        };
+        Ok((gas_limit, gas_price, gas_prepayment))
+    }
 
     fn try_charge_mana(
         self,
@@ -188,28 +333,41 @@ impl Runtime {
             )),
             _ => (),
         };
-        if sender.amount >= transaction.amount {
-            sender.amount -= transaction.amount;
+        let (gas_limit, gas_price, gas_prepayment) = self.gas_prepayment(transaction)?;
+        let total = transaction.amount.checked_add(gas_prepayment).ok_or_else(|| {
+            format!(
+                "Account {} tries to call some contract with the amount {} and the prepaid gas \
+                 fee {}, which overflows",
+                transaction.originator, transaction.amount, gas_prepayment,
+            )
+        })?;
+        if sender.amount >= total {
+            sender.amount -= total;
             set(state_update, &account_id_to_bytes(COL_ACCOUNT, &transaction.originator), sender);
+            let mut async_call = AsyncCall::new(
+                transaction.method_name.clone(),
+                transaction.args.clone(),
+                transaction.amount,
+                mana - 1,
+                accounting_info,
+            );
+            async_call.prepaid_gas_fee = gas_prepayment;
+            async_call.gas_limit = Some(gas_limit);
+            async_call.gas_price = gas_price;
             let receipt = ReceiptTransaction::new(
                 transaction.originator.clone(),
                 transaction.contract_id.clone(),
                 create_nonce_with_nonce(&hash, 0),
-                ReceiptBody::NewCall(AsyncCall::new(
-                    transaction.method_name.clone(),
-                    transaction.args.clone(),
-                    transaction.amount,
-                    mana - 1,
-                    accounting_info,
-                ))
+                ReceiptBody::NewCall(async_call),
             );
             Ok(vec![receipt])
         } else {
             Err(
                 format!(
-                    "Account {} tries to call some contract with the amount {}, but has staked {} and only has {}",
+                    "Account {} tries to call some contract with the amount {} and the prepaid gas fee {}, but has staked {} and only has {}",
                     transaction.originator,
                     transaction.amount,
+                    gas_prepayment,
                     sender.staked,
                     sender.amount
                 )
@@ -217,6 +375,81 @@ impl Runtime {
         }
     }
 
+    /// Calls the migration method of the freshly deployed code. The method runs without mana,
+    /// so it can only touch the contract's own state. The signer of the deployment pays for the
+    /// gas like for a function call: the fee of the maximum gas at the minimum gas price is
+    /// prepaid and the unused part is refunded. On error the transaction is rolled back together
+    /// with the code change.
+    fn migrate(
+        self,
+        state_update: &mut TrieUpdate,
+        contract_id: &AccountId,
+        code: &[u8],
+        method_name: &[u8],
+        nonce: &CryptoHash,
+        sender: &mut Account,
+        accounting_info: &AccountingInfo,
+        block_index: BlockIndex,
+    ) -> Result<(), String> {
+        let gas_limit = self.gas_limit(None);
+        let gas_price = self.fees.gas_price_num;
+        let prepaid_gas_fee = gas_fee(&self.fees, gas_price, gas_limit)
+            .ok_or_else(|| format!("migration gas fee of contract {} overflows", contract_id))?;
+        if sender.amount < prepaid_gas_fee {
+            return Err(format!(
+                "sender {} does not have enough balance {} to prepay the migration gas fee {}",
+                contract_id, sender.amount, prepaid_gas_fee
+            ));
+        }
+        sender.amount -= prepaid_gas_fee;
+        let (balance, gas_used, storage_usage) = {
+            let mut runtime_ext = RuntimeExt::new(
+                state_update,
+                contract_id,
+                accounting_info,
+                nonce,
+                sender.storage_usage,
+                None,
+                gas_price,
+            );
+            let wasm_res = executor::execute(
+                code,
+                method_name,
+                &[],
+                &[],
+                &mut runtime_ext,
+                &self.wasm_config,
+                &RuntimeContext::new(
+                    sender.amount,
+                    0,
+                    contract_id,
+                    contract_id,
+                    0,
+                    gas_limit,
+                    block_index,
+                    nonce.as_ref().to_vec(),
+                ),
+            ).map_err(|e| format!("wasm migration preparation failed with error: {:?}", e))?;
+            wasm_res.return_data
+                .map_err(|e| format!("wasm migration execution failed with error: {:?}", e))?;
+            // Receipts of the migration are not sent anywhere, so the deployment fails instead of
+            // silently dropping them.
+            if !runtime_ext.receipts.is_empty() || !runtime_ext.callbacks.is_empty() {
+                return Err(format!(
+                    "migration method of contract {} must not create promises",
+                    contract_id
+                ));
+            }
+            (wasm_res.balance, wasm_res.gas_used, runtime_ext.storage_usage())
+        };
+        let unused_gas_fee = gas_fee(&self.fees, gas_price, gas_used)
+            .map_or(0, |fee| prepaid_gas_fee.saturating_sub(fee));
+        sender.amount = balance + unused_gas_fee;
+        sender.storage_usage = storage_usage;
+        set(state_update, &account_id_to_bytes(COL_ACCOUNT, contract_id), sender);
+        Ok(())
+    }
+
     /// node receives signed_transaction, processes it
     /// and generates the receipt to send to receiver
     fn apply_signed_transaction(
@@ -234,25 +467,46 @@ impl Runtime {
             get(state_update, &account_id_to_bytes(COL_ACCOUNT, &sender_account_id));
         match sender {
             Some(mut sender) => {
-                if transaction.body.get_nonce() <= sender.nonce {
+                let access_keys = get_access_keys(state_update, &sender_account_id);
+                let signer = verify_transaction_signature(
+                    &transaction,
+                    &sender.public_keys,
+                    &access_keys,
+                    sender.multisig_threshold,
+                )?;
+                let nonce = get_key_nonce(state_update, &sender_account_id, signer.public_key());
+                if transaction.body.get_nonce() <= nonce {
                     return Err(format!(
-                        "Transaction nonce {} must be larger than sender nonce {}",
+                        "Transaction nonce {} must be larger than nonce {} of key {}",
                         transaction.body.get_nonce(),
-                        sender.nonce,
-                    ));
-                }
-                if !verify_transaction_signature(&transaction, &sender.public_keys) {
-                    return Err(format!(
-                        "transaction not signed with a public key of originator {:?}",
-                        transaction.body.get_originator()
+                        nonce,
+                        signer.public_key(),
                     ));
                 }
-                sender.nonce = transaction.body.get_nonce();
                 set(
                     state_update,
-                    &account_id_to_bytes(COL_ACCOUNT, &sender_account_id),
-                    &sender
+                    &public_key_to_bytes(COL_KEY_NONCE, &sender_account_id, signer.public_key()),
+                    &transaction.body.get_nonce(),
                 );
+                if let TransactionSigner::AccessKey(public_key) = signer {
+                    if let TransactionBody::FunctionCall(ref t) = transaction.body {
+                        // The fee and the prepaid gas are paid from the allowance as well, so the
+                        // key can not spend the balance of the account on them.
+                        let (_, _, gas_prepayment) = self.gas_prepayment(t)?;
+                        let cost = transaction_fee(&self.fees, &transaction.body)?
+                            .checked_add(gas_prepayment)
+                            .and_then(|cost| cost.checked_add(t.amount))
+                            .ok_or_else(|| {
+                                format!("Cost of the call of key {} overflows", public_key)
+                            })?;
+                        system::spend_allowance(
+                            state_update,
+                            &sender_account_id,
+                            &public_key,
+                            cost,
+                        )?;
+                    }
+                }
                 let contract_id = transaction.body.get_contract_id();
                 if let Some(ref contract_id) = contract_id {
                     if !is_valid_account_id(&contract_id) {
@@ -267,7 +521,19 @@ impl Runtime {
                     &contract_id,
                     mana,
                 ).ok_or_else(|| format!("sender {} does not have enough mana {}", sender_account_id, mana))?;
-                match transaction.body {
+                let prev_amount = sender.amount;
+                let prev_storage_usage = sender.storage_usage
+                    + entries_storage_usage(state_update, &sender_account_id, &sender);
+                let fee = transaction_fee(&self.fees, &transaction.body)?;
+                if sender.amount < fee {
+                    return Err(format!(
+                        "sender {} does not have enough balance {} to pay the fee {}",
+                        sender_account_id, sender.amount, fee
+                    ));
+                }
+                sender.amount -= fee;
+                set(state_update, &account_id_to_bytes(COL_ACCOUNT, &sender_account_id), &sender);
+                let receipts = match transaction.body {
                     TransactionBody::SendMoney(ref t) => {
                         system::send_money(
                             state_update,
@@ -302,7 +568,20 @@ impl Runtime {
                             &t.contract_id,
                             &t.wasm_byte_array,
                             &mut sender,
-                        )
+                            &self.wasm_config,
+                        ).and_then(|receipts| match t.migration_method_name {
+                            Some(ref method_name) => self.migrate(
+                                state_update,
+                                &t.contract_id,
+                                &t.wasm_byte_array,
+                                method_name,
+                                &transaction.get_hash(),
+                                &mut sender,
+                                &accounting_info,
+                                block_index,
+                            ).map(|_| receipts),
+                            None => Ok(receipts),
+                        })
                     },
                     TransactionBody::CreateAccount(ref t) => {
                         system::create_account(
@@ -334,7 +613,46 @@ impl Runtime {
                             &mut sender
                         )
                     }
-                }
+                    TransactionBody::Unstake(ref t) => {
+                        system::unstake(
+                            state_update,
+                            &t,
+                            &sender_account_id,
+                            &mut sender,
+                            authority_proposals,
+                        )
+                    }
+                    TransactionBody::SetMultisigPolicy(ref t) => {
+                        system::set_multisig_policy(
+                            state_update,
+                            t,
+                            &mut sender,
+                        )
+                    }
+                    TransactionBody::DeleteAccount(ref t) => {
+                        let receipts = system::delete_account(
+                            state_update,
+                            t,
+                            transaction.get_hash(),
+                            &mut sender,
+                            accounting_info,
+                            self.num_shards,
+                        )?;
+                        // The account is gone, there is no storage left to pay for.
+                        return Ok(receipts);
+                    }
+                }?;
+                let storage_usage = sender.storage_usage
+                    + entries_storage_usage(state_update, &sender_account_id, &sender);
+                check_storage_rent(
+                    &self.storage_rent,
+                    &sender_account_id,
+                    prev_amount,
+                    prev_storage_usage,
+                    storage_usage,
+                    &sender,
+                )?;
+                Ok(receipts)
             }
             _ => Err(format!("sender {} does not exist", sender_account_id))
         }
@@ -382,7 +700,8 @@ impl Runtime {
             ReturnData::Promise(PromiseId::Receipt(id)) => {
                 let receipt = runtime_ext.receipts.get_mut(&id).expect("receipt must exist");
                 match receipt.body {
-                    ReceiptBody::NewCall(ref mut call) => {
+                    ReceiptBody::NewCall(ref mut call)
+                    | ReceiptBody::ScheduledCall(ScheduledCall { ref mut call, .. }) => {
                         if call.callback.is_some() {
                             return Err("receipt already has callback".to_string());
                         } else {
@@ -420,18 +739,26 @@ impl Runtime {
         mana_accounting: &mut ManaAccounting,
         block_index: BlockIndex,
         logs: &mut Vec<LogEntry>,
+        events: &mut Vec<ContractEvent>,
     ) -> Result<Vec<ReceiptTransaction>, String> {
         let code: Vec<u8> = get(state_update, &account_id_to_bytes(COL_CODE, receiver_id))
             .ok_or_else(|| format!("cannot find contract code for account {}", receiver_id.clone()))?;
         mana_accounting.gas_used = 0;
         mana_accounting.mana_refund = async_call.mana;
+        mana_accounting.gas_fee_refund = async_call.prepaid_gas_fee;
         mana_accounting.accounting_info = async_call.accounting_info.clone();
+        let mut unused_gas_fee = async_call.prepaid_gas_fee;
+        // Contracts can not change the other entries of the account.
+        let entries_usage = entries_storage_usage(state_update, receiver_id, receiver);
         let result = {
             let mut runtime_ext = RuntimeExt::new(
                 state_update,
                 receiver_id,
                 &async_call.accounting_info,
                 nonce,
+                receiver.storage_usage,
+                async_call.gas_limit,
+                async_call.gas_price,
             );
             let mut wasm_res = executor::execute(
                 &code,
@@ -439,23 +766,29 @@ impl Runtime {
                 &async_call.args,
                 &[],
                 &mut runtime_ext,
-                &wasm::types::Config::default(),
+                &self.wasm_config,
                 &RuntimeContext::new(
                     receiver.amount,
                     async_call.amount,
                     sender_id,
                     receiver_id,
                     async_call.mana,
+                    self.gas_limit(async_call.gas_limit),
                     block_index,
                     nonce.as_ref().to_vec(),
                 ),
             ).map_err(|e| format!("wasm async call preparation failed with error: {:?}", e))?;
             mana_accounting.gas_used = wasm_res.gas_used;
             mana_accounting.mana_refund = wasm_res.mana_left;
+            unused_gas_fee = gas_fee(&self.fees, async_call.gas_price, wasm_res.gas_used)
+                .map_or(0, |fee| async_call.prepaid_gas_fee.saturating_sub(fee));
+            mana_accounting.gas_fee_refund = unused_gas_fee;
             logs.append(&mut wasm_res.logs);
+            events.append(&mut wasm_res.events);
             let balance = wasm_res.balance;
             let return_data = wasm_res.return_data
                 .map_err(|e| format!("wasm async call execution failed with error: {:?}", e))?;
+            mana_accounting.gas_fee_refund = runtime_ext.share_gas_fee(&self.fees, unused_gas_fee);
             Self::return_data_to_receipts(
                 &mut runtime_ext,
                 return_data,
@@ -463,10 +796,26 @@ impl Runtime {
                 sender_id,
                 receiver_id,
             ).and_then(|receipts| {
+                let (prev_amount, prev_storage_usage) = (receiver.amount, receiver.storage_usage);
                 receiver.amount = balance;
+                receiver.storage_usage = runtime_ext.storage_usage();
+                check_storage_rent(
+                    &self.storage_rent,
+                    receiver_id,
+                    prev_amount,
+                    entries_usage + prev_storage_usage,
+                    entries_usage + receiver.storage_usage,
+                    receiver,
+                )?;
                 Ok(receipts)
             })
         };
+        if result.is_err() {
+            // Receipts of the failed call are dropped, so the mana and the gas fee reserved for
+            // them are returned.
+            mana_accounting.mana_refund = async_call.mana;
+            mana_accounting.gas_fee_refund = unused_gas_fee;
+        }
         set(
             state_update,
             &account_id_to_bytes(COL_ACCOUNT, &receiver_id),
@@ -484,8 +833,10 @@ impl Runtime {
         nonce: &CryptoHash,
         receiver: &mut Account,
         mana_accounting: &mut ManaAccounting,
+        callback_info: &mut Option<CallbackInfo>,
         block_index: BlockIndex,
         logs: &mut Vec<String>,
+        events: &mut Vec<ContractEvent>,
     ) -> Result<Vec<ReceiptTransaction>, String> {
         let mut needs_removal = false;
         let mut callback: Option<Callback> = 
@@ -494,6 +845,9 @@ impl Runtime {
             .ok_or_else(|| format!("account {} does not have contract code", receiver_id.clone()))?;
         mana_accounting.gas_used = 0;
         mana_accounting.mana_refund = 0;
+        let mut unused_gas_fee = 0;
+        // Contracts can not change the other entries of the account.
+        let entries_usage = entries_storage_usage(state_update, receiver_id, receiver);
         let receipts = match callback {
             Some(ref mut callback) => {
                 callback.results[callback_res.info.result_index] = callback_res.result.clone();
@@ -505,10 +859,17 @@ impl Runtime {
                         receiver_id,
                         &callback.accounting_info,
                         nonce,
+                        receiver.storage_usage,
+                        callback.gas_limit,
+                        callback.gas_price,
                     );
 
                     mana_accounting.accounting_info = callback.accounting_info.clone();
                     mana_accounting.mana_refund = callback.mana;
+                    unused_gas_fee = callback.prepaid_gas_fee;
+                    mana_accounting.gas_fee_refund = unused_gas_fee;
+                    // The next callback in the chain is notified if this one fails.
+                    *callback_info = callback.callback.clone();
                     needs_removal = true;
                     executor::execute(
                         &code,
@@ -516,13 +877,14 @@ impl Runtime {
                         &callback.args,
                         &callback.results,
                         &mut runtime_ext,
-                        &wasm::types::Config::default(),
+                        &self.wasm_config,
                         &RuntimeContext::new(
                             receiver.amount,
                             0,
                             sender_id,
                             receiver_id,
                             callback.mana,
+                            self.gas_limit(callback.gas_limit),
                             block_index,
                             nonce.as_ref().to_vec(),
                         ),
@@ -531,11 +893,17 @@ impl Runtime {
                     .and_then(|mut res| {
                         mana_accounting.gas_used = res.gas_used;
                         mana_accounting.mana_refund = res.mana_left;
+                        unused_gas_fee = gas_fee(&self.fees, callback.gas_price, res.gas_used)
+                            .map_or(0, |fee| callback.prepaid_gas_fee.saturating_sub(fee));
+                        mana_accounting.gas_fee_refund = unused_gas_fee;
                         logs.append(&mut res.logs);
+                        events.append(&mut res.events);
                         let balance = res.balance;
                         res.return_data
                             .map_err(|e| format!("wasm callback execution failed with error: {:?}", e))
-                            .and_then(|data|
+                            .and_then(|data| {
+                                mana_accounting.gas_fee_refund =
+                                    runtime_ext.share_gas_fee(&self.fees, unused_gas_fee);
                                 Self::return_data_to_receipts(
                                     &mut runtime_ext,
                                     data,
@@ -543,9 +911,20 @@ impl Runtime {
                                     sender_id,
                                     receiver_id,
                                 )
-                            )
+                            })
                             .and_then(|receipts| {
+                                let prev_amount = receiver.amount;
+                                let prev_storage_usage = receiver.storage_usage;
                                 receiver.amount = balance;
+                                receiver.storage_usage = runtime_ext.storage_usage();
+                                check_storage_rent(
+                                    &self.storage_rent,
+                                    receiver_id,
+                                    prev_amount,
+                                    entries_usage + prev_storage_usage,
+                                    entries_usage + receiver.storage_usage,
+                                    receiver,
+                                )?;
                                 Ok(receipts)
                             })
                     })
@@ -560,6 +939,10 @@ impl Runtime {
         };
         if needs_removal {
             if receipts.is_err() {
+                // Receipts of the failed callback are dropped, so the mana and the gas fee reserved
+                // for them are returned.
+                mana_accounting.mana_refund = callback.as_ref().map_or(0, |c| c.mana);
+                mana_accounting.gas_fee_refund = unused_gas_fee;
                 // On error, we rollback previous changes and then commit the deletion
                 state_update.rollback();
                 state_update.remove(&callback_id_to_bytes(&callback_res.info.id));
@@ -591,23 +974,30 @@ impl Runtime {
         receipt: &ReceiptTransaction,
         new_receipts: &mut Vec<ReceiptTransaction>,
         block_index: BlockIndex,
-        logs: &mut Vec<String>,
+        result: &mut TransactionResult,
     ) -> Result<(), String> {
         let receiver: Option<Account> = 
             get(state_update, &account_id_to_bytes(COL_ACCOUNT, &receipt.receiver));
         let mut amount = 0;
         let mut callback_info = None;
         let mut receiver_exists = true;
+        // Events are only recorded if the receipt is applied successfully.
+        let mut events = vec![];
         let mut mana_accounting = ManaAccounting::default();
-        let result = match receiver {
+        if let ReceiptBody::NewCall(async_call) = &receipt.body {
+            // The reserved mana and the prepaid gas fee are returned in full, unless the call is
+            // executed.
+            mana_accounting.mana_refund = async_call.mana;
+            mana_accounting.gas_fee_refund = async_call.prepaid_gas_fee;
+            mana_accounting.accounting_info = async_call.accounting_info.clone();
+        }
+        let res = match receiver {
             Some(mut receiver) => {
                 match &receipt.body {
                     ReceiptBody::NewCall(async_call) => {
                         amount = async_call.amount;
                         if async_call.method_name.is_empty() {
                             if amount > 0 {
-                                mana_accounting.mana_refund = async_call.mana;
-                                mana_accounting.accounting_info = async_call.accounting_info.clone();
                                 system::deposit(
                                     state_update,
                                     async_call.amount,
@@ -631,7 +1021,8 @@ impl Runtime {
                                 &mut receiver,
                                 &mut mana_accounting,
                                 block_index,
-                                logs,
+                                &mut result.logs,
+                                &mut events,
                             )
                         }
                     },
@@ -644,8 +1035,10 @@ impl Runtime {
                             &receipt.nonce,
                             &mut receiver,
                             &mut mana_accounting,
+                            &mut callback_info,
                             block_index,
-                            logs,
+                            &mut result.logs,
+                            &mut events,
                         )
                     }
                     ReceiptBody::Refund(amount) => {
@@ -657,6 +1050,29 @@ impl Runtime {
                         );
                         Ok(vec![])
                     },
+                    ReceiptBody::ScheduledCall(scheduled_call) => {
+                        amount = scheduled_call.call.amount;
+                        callback_info = scheduled_call.call.callback.clone();
+                        if scheduled_call.block_index <= block_index {
+                            Err(format!(
+                                "cannot schedule a call at block {} while processing block {}",
+                                scheduled_call.block_index,
+                                block_index,
+                            ))
+                        } else {
+                            // Stored as a new call, that is executed when the block is processed.
+                            let scheduled_receipt = ReceiptTransaction::new(
+                                receipt.originator.clone(),
+                                receipt.receiver.clone(),
+                                receipt.nonce,
+                                ReceiptBody::NewCall(scheduled_call.call.clone()),
+                            );
+                            let mut key = scheduled_receipts_prefix(scheduled_call.block_index);
+                            key.extend_from_slice(receipt.nonce.as_ref());
+                            set(state_update, &key, &scheduled_receipt);
+                            Ok(vec![])
+                        }
+                    }
                     ReceiptBody::ManaAccounting(mana_accounting) => {
                         let key = get_tx_stake_key(
                             &mana_accounting.accounting_info.originator,
@@ -676,6 +1092,14 @@ impl Runtime {
                             // TODO(#445): Figure out what to do when the TxStake doesn't exist during mana accounting
                             panic!("TX stake doesn't exist when mana accounting arrived");
                         }
+                        if mana_accounting.gas_fee_refund > 0 {
+                            receiver.amount += mana_accounting.gas_fee_refund;
+                            set(
+                                state_update,
+                                &account_id_to_bytes(COL_ACCOUNT, &receipt.receiver),
+                                &receiver,
+                            );
+                        }
                         Ok(vec![])
                     }
                 }
@@ -683,25 +1107,31 @@ impl Runtime {
             _ => {
                 receiver_exists = false;
                 let err = Err(format!("receiver {} does not exist", receipt.receiver));
-                if let ReceiptBody::NewCall(call) = &receipt.body {
-                    amount = call.amount;
-                    if call.method_name == SYSTEM_METHOD_CREATE_ACCOUNT {
-                        system_create_account(
-                            state_update,
-                            &call,
-                            &receipt.receiver,
-                        )
-                    } else {
+                match &receipt.body {
+                    ReceiptBody::NewCall(call) => {
+                        amount = call.amount;
+                        if call.method_name == SYSTEM_METHOD_CREATE_ACCOUNT {
+                            system_create_account(
+                                state_update,
+                                &call,
+                                &receipt.receiver,
+                            )
+                        } else {
+                            err
+                        }
+                    }
+                    ReceiptBody::ScheduledCall(scheduled_call) => {
+                        amount = scheduled_call.call.amount;
                         err
                     }
-                } else {
-                    err
+                    _ => err,
                 }
             }
         };
-        let res = match result {
+        let res = match res {
             Ok(mut receipts) => {
                 new_receipts.append(&mut receipts);
+                result.events.append(&mut events);
                 Ok(())
             }
             Err(s) => {
@@ -734,7 +1164,14 @@ impl Runtime {
                 Err(s)
             }
         };
-        if mana_accounting.mana_refund > 0 || mana_accounting.gas_used > 0 {
+        if let ReceiptBody::NewCall(async_call) = &receipt.body {
+            result.fee = async_call.prepaid_gas_fee - mana_accounting.gas_fee_refund;
+        }
+        result.gas_used = mana_accounting.gas_used;
+        if mana_accounting.mana_refund > 0
+            || mana_accounting.gas_used > 0
+            || mana_accounting.gas_fee_refund > 0
+        {
             let new_receipt = ReceiptTransaction::new(
                 receipt.receiver.clone(),
                 mana_accounting.accounting_info.originator.clone(),
@@ -763,12 +1200,20 @@ impl Runtime {
     fn process_transaction(
         runtime: Self,
         state_update: &mut TrieUpdate,
+        shard_id: ShardId,
         block_index: BlockIndex,
         transaction: &SignedTransaction,
         new_receipts: &mut HashMap<ShardId, Vec<ReceiptTransaction>>,
         authority_proposals: &mut Vec<AuthorityStake>,
     ) -> TransactionResult {
         let mut result = TransactionResult::default();
+        let originator = transaction.body.get_originator();
+        if account_to_shard_id(&originator, runtime.num_shards) != shard_id {
+            result.status = TransactionStatus::Failed;
+            result.logs.push("transaction sent to the wrong shard".to_string());
+            Self::print_log(&result.logs);
+            return result;
+        }
         match runtime.apply_signed_transaction(
             state_update,
             block_index,
@@ -778,7 +1223,7 @@ impl Runtime {
             Ok(receipts) => {
                 for receipt in receipts {
                     result.receipts.push(receipt.nonce);
-                    let shard_id = receipt.shard_id();
+                    let shard_id = receipt.shard_id(runtime.num_shards);
                     if new_receipts.contains_key(&shard_id) {
                         new_receipts
                         .entry(shard_id)
@@ -789,6 +1234,8 @@ impl Runtime {
                 }
                 state_update.commit();
                 result.status = TransactionStatus::Completed;
+                result.fee = transaction_fee(&runtime.fees, &transaction.body)
+                    .expect("The fee was charged by the transaction");
             }
             Err(s) => {
                 state_update.rollback();
@@ -809,18 +1256,18 @@ impl Runtime {
         new_receipts: &mut HashMap<ShardId, Vec<ReceiptTransaction>>,
     ) -> TransactionResult {
         let mut result = TransactionResult::default();
-        if account_to_shard_id(&receipt.receiver) == shard_id {
+        if account_to_shard_id(&receipt.receiver, runtime.num_shards) == shard_id {
             let mut tmp_new_receipts = vec![];
             let apply_result = runtime.apply_receipt(
                 state_update, 
                 receipt,
                 &mut tmp_new_receipts,
                 block_index,
-                &mut result.logs
+                &mut result,
             );
             for receipt in tmp_new_receipts {
                 result.receipts.push(receipt.nonce);
-                let shard_id = receipt.shard_id();
+                let shard_id = receipt.shard_id(runtime.num_shards);
                 if new_receipts.contains_key(&shard_id) {
                     new_receipts
                     .entry(shard_id)
@@ -849,7 +1296,26 @@ impl Runtime {
         result
     }
 
-    /// apply receipts from previous block and transactions from this block
+    /// Removes the receipts scheduled at the given block from the state and returns them.
+    fn take_scheduled_receipts(
+        state_update: &mut TrieUpdate,
+        block_index: BlockIndex,
+    ) -> Vec<ReceiptTransaction> {
+        let keys = get_keys_with_prefix(state_update, &scheduled_receipts_prefix(block_index));
+        let receipts = keys
+            .iter()
+            .filter_map(|key| {
+                let receipt = get(state_update, key);
+                state_update.remove(key);
+                receipt
+            })
+            .collect();
+        state_update.commit();
+        receipts
+    }
+
+    /// apply scheduled receipts of this block, receipts from previous block and transactions
+    /// from this block
     pub fn apply(
         self,
         mut state_update: TrieUpdate,
@@ -862,6 +1328,24 @@ impl Runtime {
         let shard_id = apply_state.shard_id;
         let block_index = apply_state.block_index;
         let mut tx_result = vec![];
+        for withdrawal in apply_state.withdrawals.iter() {
+            if account_to_shard_id(&withdrawal.account_id, self.num_shards) == shard_id {
+                system::withdraw_stake(&mut state_update, withdrawal);
+            }
+        }
+        state_update.commit();
+        let mut scheduled_receipts = vec![];
+        for receipt in Self::take_scheduled_receipts(&mut state_update, block_index) {
+            scheduled_receipts.push(receipt.nonce);
+            tx_result.push(Self::process_receipt(
+                self,
+                &mut state_update,
+                shard_id,
+                block_index,
+                &receipt,
+                &mut new_receipts,
+            ));
+        }
         for receipt in prev_receipts.iter().flat_map(|b| &b.receipts) {
             tx_result.push(Self::process_receipt(
                 self,
@@ -876,6 +1360,7 @@ impl Runtime {
             tx_result.push(Self::process_transaction(
                 self,
                 &mut state_update,
+                shard_id,
                 block_index,
                 transaction,
                 &mut new_receipts,
@@ -889,6 +1374,7 @@ impl Runtime {
             authority_proposals,
             shard_id,
             new_receipts,
+            scheduled_receipts,
             tx_result,
         }
     }
@@ -909,9 +1395,10 @@ impl Runtime {
                     public_keys: vec![PublicKey::from(&public_key.0)],
                     bls_public_key: BlsPublicKey::empty(),
                     amount: *balance,
-                    nonce: 0,
                     staked: 0,
                     code_hash: hash(wasm_binary),
+                    storage_usage: wasm_binary.len() as u64,
+                    multisig_threshold: 0,
                 },
             );
             // Default code
@@ -934,12 +1421,14 @@ impl Runtime {
             );
             // TODO(#345): Add system TX stake
         });
-        for (account_id, _pk, amount) in initial_authorities {
+        for (account_id, public_key, amount) in initial_authorities {
             let account_id_bytes = account_id_to_bytes(COL_ACCOUNT, account_id);
             let mut account: Account = get(
                 &mut state_update,
                 &account_id_bytes,
             ).expect("account must exist");
+            account.bls_public_key =
+                BlsPublicKey::from_base58(&public_key.0).expect("invalid authority key");
             account.staked = *amount;
             set(
                 &mut state_update,
@@ -1118,6 +1607,133 @@ mod tests {
         assert_ne!(root, apply_results[1].root);
     }
 
+    #[test]
+    fn test_transaction_fees() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        chain_spec.fees = FeeConfig {
+            send_money: 1,
+            function_call: 2,
+            per_byte: 1,
+            gas_price_num: 1,
+            gas_price_denum: 1_000_000,
+            ..Default::default()
+        };
+        let prepaid = gas_fee(&chain_spec.fees, 1, chain_spec.max_gas_limit).unwrap();
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (root, apply_results) = alice.send_money(root, &bob_account(), 10);
+        assert_eq!(apply_results[0].tx_result[0].fee, 1);
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        assert_eq!(viewer.view_account(&mut state_update, &alice_account()).unwrap().amount, 89);
+
+        let (root, apply_results) =
+            alice.call_function(root, &bob_account(), "run_test", vec![], 0);
+        // 2 for the call and 1 per byte of the method name.
+        assert_eq!(apply_results[0].tx_result[0].fee, 10);
+        let gas_fee = apply_results[1].tx_result[0].fee;
+        assert!(gas_fee < prepaid);
+        // The unused gas is refunded by the mana accounting receipt.
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let amount = viewer.view_account(&mut state_update, &alice_account()).unwrap().amount;
+        assert_eq!(amount, 89 - 10 - gas_fee);
+    }
+
+    #[test]
+    fn test_function_call_gas_limit() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        chain_spec.fees =
+            FeeConfig { gas_price_num: 1, gas_price_denum: 1_000_000, ..Default::default() };
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (root, apply_results) = alice.call_function_with_gas_limit(
+            root,
+            &bob_account(),
+            "run_test",
+            vec![],
+            0,
+            Some(1),
+        );
+        // The call runs out of gas and is charged for at most its gas limit.
+        let tx_result = &apply_results[1].tx_result[0];
+        assert_eq!(tx_result.status, TransactionStatus::Failed);
+        assert!(tx_result.gas_used <= 1);
+        let failed_call_fee = tx_result.fee;
+        assert_eq!(failed_call_fee, gas_fee(&chain_spec.fees, 1, tx_result.gas_used).unwrap());
+
+        let (root, apply_results) = alice.call_function_with_gas_limit(
+            root,
+            &bob_account(),
+            "run_test",
+            vec![],
+            0,
+            Some(Gas::max_value()),
+        );
+        // The gas limit is capped by the chain-wide maximum, so Alice can afford the prepayment.
+        let tx_result = &apply_results[1].tx_result[0];
+        assert_eq!(tx_result.status, TransactionStatus::Completed);
+        assert!(tx_result.gas_used > 1);
+        assert!(tx_result.gas_used <= chain_spec.max_gas_limit);
+        assert_eq!(tx_result.fee, gas_fee(&chain_spec.fees, 1, tx_result.gas_used).unwrap());
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let amount = viewer.view_account(&mut state_update, &alice_account()).unwrap().amount;
+        assert_eq!(amount, 100 - failed_call_fee - tx_result.fee);
+    }
+
+    #[test]
+    fn test_function_call_gas_price() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        chain_spec.fees =
+            FeeConfig { gas_price_num: 2, gas_price_denum: 1_000_000, ..Default::default() };
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let bob = bob_account();
+        let (root, apply_results) =
+            alice.call_function_with_gas(root, &bob, "run_test", vec![], 0, None, Some(1));
+        // The offered price is lower than the minimum gas price.
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+
+        let (root, apply_results) =
+            alice.call_function_with_gas(root, &bob, "run_test", vec![], 0, None, Some(5));
+        let tx_result = &apply_results[1].tx_result[0];
+        assert_eq!(tx_result.status, TransactionStatus::Completed);
+        assert_eq!(tx_result.fee, gas_fee(&chain_spec.fees, 5, tx_result.gas_used).unwrap());
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let amount = viewer.view_account(&mut state_update, &alice_account()).unwrap().amount;
+        assert_eq!(amount, 100 - tx_result.fee);
+    }
+
+    #[test]
+    fn test_function_call_gas_price_limits() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        chain_spec.max_gas_price = 10;
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let bob = bob_account();
+        let (root, apply_results) =
+            alice.call_function_with_gas(root, &bob, "run_test", vec![], 0, None, Some(11));
+        // The offered price is higher than the maximum gas price.
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+
+        chain_spec.max_gas_price = Balance::max_value();
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (new_root, apply_results) = alice.call_function_with_gas(
+            root,
+            &bob,
+            "run_test",
+            vec![],
+            0,
+            None,
+            Some(Balance::max_value()),
+        );
+        // The prepaid gas fee overflows instead of wrapping around to a small amount.
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+    }
+
     #[test]
     fn test_async_call_with_logs() {
         let (runtime, trie, root) = get_runtime_and_trie();
@@ -1169,13 +1785,13 @@ mod tests {
         let block_index = 1;
         let mut state_update = TrieUpdate::new(trie.clone(), root);
         let mut new_receipts = vec![];
-        let mut logs = vec![];
+        let mut result = TransactionResult::default();
         runtime.apply_receipt(
             &mut state_update,
             &receipt,
             &mut new_receipts,
             block_index,
-            &mut logs,
+            &mut result,
         ).unwrap();
         assert_eq!(new_receipts.len(), 2);
 
@@ -1235,6 +1851,195 @@ mod tests {
         assert!(callback.is_none());
     }
 
+    #[test]
+    fn test_failed_async_call_refunds() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let accounting_info = AccountingInfo {
+            originator: alice_account(),
+            contract_id: None,
+        };
+        let mut async_call = AsyncCall::new(
+            b"a_function_that_does_not_exist".to_vec(),
+            vec![],
+            5,
+            3,
+            accounting_info.clone(),
+        );
+        let callback_info = CallbackInfo::new([0; 32].to_vec(), 0, alice_account());
+        async_call.callback = Some(callback_info.clone());
+        let receipt = ReceiptTransaction::new(
+            alice_account(),
+            bob_account(),
+            hash(&[1, 2, 3]),
+            ReceiptBody::NewCall(async_call),
+        );
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let mut new_receipts = vec![];
+        let mut result = TransactionResult::default();
+        assert!(runtime.apply_receipt(
+            &mut state_update,
+            &receipt,
+            &mut new_receipts,
+            1,
+            &mut result,
+        ).is_err());
+        assert_eq!(new_receipts.len(), 3);
+        // The attached tokens go back to the sender.
+        assert_eq!(new_receipts[0].receiver, alice_account());
+        assert_eq!(new_receipts[0].body, ReceiptBody::Refund(5));
+        // The callback is notified about the failure.
+        assert_eq!(new_receipts[1].receiver, alice_account());
+        assert_eq!(
+            new_receipts[1].body,
+            ReceiptBody::Callback(CallbackResult::new(callback_info, None)),
+        );
+        // The reserved mana is returned to the originator.
+        assert_eq!(new_receipts[2].receiver, alice_account());
+        if let ReceiptBody::ManaAccounting(ref mana_accounting) = new_receipts[2].body {
+            assert_eq!(mana_accounting.mana_refund, 3);
+            assert_eq!(mana_accounting.accounting_info, accounting_info);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn test_failed_callback_notifies_next_callback() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let accounting_info = AccountingInfo {
+            originator: alice_account(),
+            contract_id: None,
+        };
+        // `joined` waits for two results, as with `promise_and`, and the second one is the
+        // result of `failing`, as with `promise_then` on a callback.
+        let joined_id = [1; 32].to_vec();
+        let mut joined = Callback::new(
+            b"sum_with_multiple_results".to_vec(),
+            vec![],
+            0,
+            accounting_info.clone(),
+        );
+        joined.results = vec![Some(encode_int(1).to_vec()), None];
+        joined.result_counter = 1;
+        let failing_id = [2; 32].to_vec();
+        let mut failing = Callback::new(
+            b"a_function_that_does_not_exist".to_vec(),
+            vec![],
+            2,
+            accounting_info.clone(),
+        );
+        failing.results.resize(1, None);
+        failing.callback = Some(CallbackInfo::new(joined_id.clone(), 1, alice_account()));
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        set(&mut state_update, &callback_id_to_bytes(&joined_id), &joined);
+        set(&mut state_update, &callback_id_to_bytes(&failing_id), &failing);
+        state_update.commit();
+
+        let receipt = ReceiptTransaction::new(
+            alice_account(),
+            bob_account(),
+            hash(&[1, 2, 3]),
+            ReceiptBody::Callback(CallbackResult::new(
+                CallbackInfo::new(failing_id.clone(), 0, bob_account()),
+                Some(vec![]),
+            )),
+        );
+        let mut new_receipts = vec![];
+        let mut result = TransactionResult::default();
+        assert!(runtime.apply_receipt(
+            &mut state_update,
+            &receipt,
+            &mut new_receipts,
+            1,
+            &mut result,
+        ).is_err());
+        let failing: Option<Callback> = get(&state_update, &callback_id_to_bytes(&failing_id));
+        assert!(failing.is_none());
+        assert_eq!(new_receipts.len(), 2);
+        let failure = new_receipts[0].clone();
+        assert_eq!(
+            failure.body,
+            ReceiptBody::Callback(CallbackResult::new(
+                CallbackInfo::new(joined_id.clone(), 1, alice_account()),
+                None,
+            )),
+        );
+        if let ReceiptBody::ManaAccounting(ref mana_accounting) = new_receipts[1].body {
+            assert_eq!(mana_accounting.mana_refund, 2);
+        } else {
+            assert!(false);
+        }
+
+        // The failure completes the joined callback, which is executed and removed.
+        let mut new_receipts = vec![];
+        let mut result = TransactionResult::default();
+        runtime.apply_receipt(
+            &mut state_update,
+            &failure,
+            &mut new_receipts,
+            1,
+            &mut result,
+        ).unwrap();
+        let joined: Option<Callback> = get(&state_update, &callback_id_to_bytes(&joined_id));
+        assert!(joined.is_none());
+    }
+
+    #[test]
+    fn test_scheduled_receipt() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let scheduled_deposit = |nonce: u8, block_index: BlockIndex| {
+            let call = AsyncCall::new(vec![], vec![], 10, 0, AccountingInfo {
+                originator: alice_account(),
+                contract_id: None,
+            });
+            ReceiptTransaction::new(
+                alice_account(),
+                bob_account(),
+                hash(&[nonce]),
+                ReceiptBody::ScheduledCall(ScheduledCall::new(block_index, call)),
+            )
+        };
+        let apply_block = |root: MerkleHash, block_index, receipts| {
+            let apply_state = ApplyState {
+                root,
+                shard_id: 0,
+                parent_block_hash: CryptoHash::default(),
+                block_index,
+                withdrawals: vec![],
+            };
+            let state_update = TrieUpdate::new(trie.clone(), root);
+            let result =
+                runtime.apply(state_update, &apply_state, &[to_receipt_block(receipts)], &[]);
+            trie.apply_changes(result.db_changes.clone()).unwrap();
+            result
+        };
+        let bob_amount = |root| {
+            let mut state_update = TrieUpdate::new(trie.clone(), root);
+            TrieViewer {}.view_account(&mut state_update, &bob_account()).unwrap().amount
+        };
+        let initial_amount = bob_amount(root);
+
+        let result = apply_block(root, 1, vec![scheduled_deposit(1, 3)]);
+        assert_eq!(result.tx_result[0].status, TransactionStatus::Completed);
+        assert!(result.new_receipts.is_empty());
+        assert_eq!(bob_amount(result.root), initial_amount);
+        let result = apply_block(result.root, 2, vec![]);
+        assert_eq!(bob_amount(result.root), initial_amount);
+        let result = apply_block(result.root, 3, vec![]);
+        assert_eq!(bob_amount(result.root), initial_amount + 10);
+        assert_eq!(result.scheduled_receipts, vec![hash(&[1])]);
+        assert_eq!(result.tx_result[0].status, TransactionStatus::Completed);
+        let state_update = TrieUpdate::new(trie.clone(), result.root);
+        assert!(get_keys_with_prefix(&state_update, &scheduled_receipts_prefix(3)).is_empty());
+
+        // Calls cannot be scheduled at the current or past blocks, the amount is refunded.
+        let result = apply_block(result.root, 4, vec![scheduled_deposit(2, 4)]);
+        assert_eq!(result.tx_result[0].status, TransactionStatus::Failed);
+        let refund = &result.new_receipts[&0][0];
+        assert_eq!(refund.receiver, alice_account());
+        assert_eq!(refund.body, ReceiptBody::Refund(10));
+    }
+
     #[test]
     fn test_nonce_update_when_deploying_contract() {
         let (runtime, trie, root) = get_runtime_and_trie();
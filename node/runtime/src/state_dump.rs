@@ -0,0 +1,190 @@
+//! Export of the state under a given root into a file and import of it into another trie, e.g. to
+//! copy a shard state between environments or to start a new chain from an existing state.
+//! Accounts, contract code and contract storage are decoded, so the dump can be inspected and
+//! edited, the other entries are kept as the raw key and value bytes.
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use primitives::hash::bs58_format;
+use primitives::traits::{Decode, Encode};
+use primitives::types::{AccountId, MerkleHash};
+use storage::{DBChanges, Trie};
+
+use super::{account_id_to_bytes, Account, COL_ACCOUNT, COL_CODE};
+
+/// Version of the dump format, increased with every incompatible change of the records or of
+/// the encoding of the stored structs.
+pub const STATE_DUMP_VERSION: u32 = 1;
+
+/// Separates the account id from the contract storage key, see `RuntimeExt::new`.
+const DATA_SEPARATOR: u8 = b',';
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StateRecord {
+    Account { account_id: AccountId, account: Account },
+    Code { account_id: AccountId, code: Vec<u8> },
+    /// Entry of the contract storage of the account.
+    Data { account_id: AccountId, key: Vec<u8>, value: Vec<u8> },
+    /// Any other entry, e.g. callbacks, access keys or transaction stakes.
+    Raw { key: Vec<u8>, value: Vec<u8> },
+}
+
+impl StateRecord {
+    fn from_raw(key: Vec<u8>, value: Vec<u8>) -> StateRecord {
+        let account_id = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).ok();
+        let decoded = match key.split_first() {
+            Some((col, rest)) if *col == COL_ACCOUNT[0] => {
+                match rest.iter().position(|&b| b == DATA_SEPARATOR) {
+                    Some(pos) => account_id(&rest[..pos]).map(|account_id| StateRecord::Data {
+                        account_id,
+                        key: rest[pos + 1..].to_vec(),
+                        value: value.clone(),
+                    }),
+                    None => match (account_id(rest), Decode::decode(&value)) {
+                        (Some(account_id), Ok(account)) => {
+                            Some(StateRecord::Account { account_id, account })
+                        }
+                        _ => None,
+                    },
+                }
+            }
+            Some((col, rest)) if *col == COL_CODE[0] => {
+                match (account_id(rest), Decode::decode(&value)) {
+                    (Some(account_id), Ok(code)) => Some(StateRecord::Code { account_id, code }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        decoded.unwrap_or_else(|| StateRecord::Raw { key, value })
+    }
+
+    fn to_raw(&self) -> Result<(Vec<u8>, Vec<u8>), String> {
+        let encode_err = |e| format!("Failed to encode the record: {}", e);
+        Ok(match self {
+            StateRecord::Account { account_id, account } => (
+                account_id_to_bytes(COL_ACCOUNT, account_id),
+                account.encode().map_err(encode_err)?,
+            ),
+            StateRecord::Code { account_id, code } => {
+                (account_id_to_bytes(COL_CODE, account_id), code.encode().map_err(encode_err)?)
+            }
+            StateRecord::Data { account_id, key, value } => {
+                let mut storage_key = account_id_to_bytes(COL_ACCOUNT, account_id);
+                storage_key.push(DATA_SEPARATOR);
+                storage_key.extend_from_slice(key);
+                (storage_key, value.clone())
+            }
+            StateRecord::Raw { key, value } => (key.clone(), value.clone()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StateDump {
+    pub version: u32,
+    /// Root of the exported state, the import checks that it rebuilds the same root.
+    #[serde(with = "bs58_format")]
+    pub root: MerkleHash,
+    pub records: Vec<StateRecord>,
+}
+
+/// Reads all the entries of the state under the root.
+pub fn export_state(trie: &Arc<Trie>, root: &MerkleHash) -> Result<StateDump, String> {
+    let mut records = vec![];
+    for item in trie.iter(root)? {
+        let (key, value) = item?;
+        records.push(StateRecord::from_raw(key, value.to_vec()));
+    }
+    Ok(StateDump { version: STATE_DUMP_VERSION, root: *root, records })
+}
+
+/// Builds the state of the dump from scratch. Returns the root and the changes that store the
+/// state, the root is checked against the root of the dump.
+pub fn import_state(
+    trie: &Arc<Trie>,
+    dump: &StateDump,
+) -> Result<(MerkleHash, DBChanges), String> {
+    if dump.version != STATE_DUMP_VERSION {
+        return Err(format!(
+            "State dump version {} is not supported, expected {}",
+            dump.version, STATE_DUMP_VERSION
+        ));
+    }
+    let entries = dump
+        .records
+        .iter()
+        .map(|record| record.to_raw().map(|(key, value)| (key, Some(value))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (db_changes, root) = trie.update(&Trie::empty_root(), entries.into_iter());
+    if root != dump.root {
+        return Err(format!("Imported state has root {}, expected {}", root, dump.root));
+    }
+    Ok((root, db_changes))
+}
+
+pub fn write_state_dump(path: &Path, dump: &StateDump) -> Result<(), String> {
+    let data = serde_json::to_vec(dump).map_err(|e| format!("Failed to serialize: {}", e))?;
+    fs::write(path, data).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+pub fn read_state_dump(path: &Path) -> Result<StateDump, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    serde_json::from_slice(&data).map_err(|e| format!("Failed to deserialize {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::hash::hash;
+    use crate::test_utils::{generate_test_chain_spec, get_runtime_and_trie_from_chain_spec};
+    use storage::test_utils::create_trie;
+
+    fn genesis_dump() -> StateDump {
+        let (chain_spec, _, _) = generate_test_chain_spec();
+        let (_, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        export_state(&trie, &root).unwrap()
+    }
+
+    #[test]
+    fn test_export_decodes_records() {
+        let dump = genesis_dump();
+        assert_eq!(dump.version, STATE_DUMP_VERSION);
+        let alice = dump.records.iter().find_map(|record| match record {
+            StateRecord::Account { account_id, account } if account_id == "alice.near" => {
+                Some(account)
+            }
+            _ => None,
+        });
+        assert_eq!(alice.unwrap().amount, 100);
+        assert!(dump.records.iter().any(|record| match record {
+            StateRecord::Code { account_id, code } => account_id == "bob.near" && !code.is_empty(),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_import_rebuilds_root() {
+        let dump = genesis_dump();
+        let path = std::env::temp_dir().join("runtime_test_state_dump.json");
+        write_state_dump(&path, &dump).unwrap();
+        let dump = read_state_dump(&path).unwrap();
+
+        let trie = create_trie();
+        let (root, db_changes) = import_state(&trie, &dump).unwrap();
+        assert_eq!(root, dump.root);
+        trie.apply_changes(db_changes).unwrap();
+        assert_eq!(export_state(&trie, &root).unwrap(), dump);
+    }
+
+    #[test]
+    fn test_import_checks_root() {
+        let mut dump = genesis_dump();
+        dump.root = hash(b"other");
+        assert!(import_state(&create_trie(), &dump).is_err());
+        let mut dump = genesis_dump();
+        dump.version = STATE_DUMP_VERSION + 1;
+        assert!(import_state(&create_trie(), &dump).is_err());
+    }
+}
@@ -3,14 +3,19 @@ use std::str;
 use std::time::Instant;
 
 use primitives::hash::CryptoHash;
+use primitives::traits::Decode;
+use primitives::transaction::AccessKey;
+use primitives::trie_proof::TrieProof;
 use primitives::utils::is_valid_account_id;
-use primitives::types::{AccountId, Balance, AccountingInfo};
+use primitives::types::{AccountId, Balance, AccountingInfo, MerkleHash};
 use storage::{TrieUpdate};
+use configs::chain_spec::DEFAULT_MAX_GAS_LIMIT;
 use wasm::executor;
 use wasm::types::{ReturnData, RuntimeContext};
 
 use super::{
-    Account, account_id_to_bytes, get, RuntimeExt, COL_ACCOUNT, COL_CODE,
+    Account, account_id_to_bytes, get, get_access_keys, get_key_nonce, get_max_key_nonce,
+    RuntimeExt, COL_ACCOUNT, COL_CODE,
 };
 use primitives::signature::PublicKey;
 
@@ -19,17 +24,37 @@ pub struct ViewStateResult {
     pub values: HashMap<Vec<u8>, Vec<u8>>
 }
 
+/// Contract state together with proofs of every value against `root`.
+#[derive(Serialize, Deserialize)]
+pub struct ViewStateWithProofResult {
+    pub root: MerkleHash,
+    pub values: HashMap<Vec<u8>, (Vec<u8>, TrieProof)>,
+}
+
 pub struct TrieViewer {}
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct AccountViewCallResult {
     pub account: AccountId,
+    /// The largest nonce used by the keys of the account, see `TrieViewer::get_nonce_for_key`
+    /// for the nonce of a specific key.
     pub nonce: u64,
     pub amount: Balance,
     pub stake: u64,
     pub code_hash: CryptoHash,
 }
 
+/// Account together with the proof of its raw trie value against `root`.
+/// If the account does not exist, `value` is `None` and the proof shows the key is absent.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct AccountViewWithProofResult {
+    pub root: MerkleHash,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    pub account: Option<AccountViewCallResult>,
+    pub proof: TrieProof,
+}
+
 impl TrieViewer {
 
     pub fn view_account(
@@ -45,7 +70,7 @@ impl TrieViewer {
             Some(account) => {
                 Ok(AccountViewCallResult {
                     account: account_id.clone(),
-                    nonce: account.nonce,
+                    nonce: get_max_key_nonce(state_update, account_id),
                     amount: account.amount,
                     stake: account.staked,
                     code_hash: account.code_hash
@@ -55,6 +80,33 @@ impl TrieViewer {
         }
     }
 
+    pub fn view_account_with_proof(
+        &self,
+        state_update: &TrieUpdate,
+        account_id: &AccountId,
+    ) -> Result<AccountViewWithProofResult, String> {
+        if !is_valid_account_id(account_id) {
+            return Err(format!("Account ID '{}' is not valid", account_id));
+        }
+        let key = account_id_to_bytes(COL_ACCOUNT, account_id);
+        let (value, proof) = state_update.get_with_proof(&key)?;
+        let account = match value {
+            Some(ref data) => {
+                let account: Account = Decode::decode(data)
+                    .map_err(|_| format!("Failed to decode account {}", account_id))?;
+                Some(AccountViewCallResult {
+                    account: account_id.clone(),
+                    nonce: get_max_key_nonce(state_update, account_id),
+                    amount: account.amount,
+                    stake: account.staked,
+                    code_hash: account.code_hash,
+                })
+            }
+            None => None,
+        };
+        Ok(AccountViewWithProofResult { root: state_update.get_root(), key, value, account, proof })
+    }
+
     pub fn get_public_keys_for_account(
         &self,
         state_update: &mut TrieUpdate,
@@ -69,6 +121,48 @@ impl TrieViewer {
         }
     }
 
+    /// Number of public keys that must sign the transactions of the account, see
+    /// `Account::multisig_threshold`.
+    pub fn get_multisig_threshold_for_account(
+        &self,
+        state_update: &mut TrieUpdate,
+        account_id: &AccountId,
+    ) -> Result<u32, String> {
+        if !is_valid_account_id(account_id) {
+            return Err(format!("Account ID '{}' is not valid", account_id));
+        }
+        match get::<Account>(state_update, &account_id_to_bytes(COL_ACCOUNT, account_id)) {
+            Some(account) => Ok(account.multisig_threshold),
+            _ => Err(format!("account {} does not exist while viewing", account_id)),
+        }
+    }
+
+    /// Nonce of the last transaction signed with the given key of the account.
+    pub fn get_nonce_for_key(
+        &self,
+        state_update: &TrieUpdate,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+    ) -> Result<u64, String> {
+        if !is_valid_account_id(account_id) {
+            return Err(format!("Account ID '{}' is not valid", account_id));
+        }
+        Ok(get_key_nonce(state_update, account_id, public_key))
+    }
+
+    /// Function-call-only keys of the account with their permissions. The keys returned by
+    /// `get_public_keys_for_account` have full access.
+    pub fn get_access_keys_for_account(
+        &self,
+        state_update: &mut TrieUpdate,
+        account_id: &AccountId,
+    ) -> Result<Vec<(PublicKey, AccessKey)>, String> {
+        if !is_valid_account_id(account_id) {
+            return Err(format!("Account ID '{}' is not valid", account_id));
+        }
+        Ok(get_access_keys(state_update, account_id))
+    }
+
     pub fn view_state(
         &self,
         state_update: &TrieUpdate,
@@ -90,6 +184,36 @@ impl TrieViewer {
         })
     }
 
+    pub fn view_state_with_proof(
+        &self,
+        state_update: &TrieUpdate,
+        account_id: &AccountId
+    ) -> Result<ViewStateWithProofResult, String> {
+        if !is_valid_account_id(account_id) {
+            return Err(format!("Account ID '{}' is not valid", account_id));
+        }
+        let mut values = HashMap::default();
+        let mut prefix = account_id_to_bytes(COL_ACCOUNT, account_id);
+        prefix.append(&mut b",".to_vec());
+        let mut error = None;
+        state_update.for_keys_with_prefix(&prefix, |key| {
+            match state_update.get_with_proof(key) {
+                Ok((Some(value), proof)) => {
+                    values.insert(key.to_vec(), (value, proof));
+                }
+                Ok((None, _)) => {}
+                Err(e) => error = Some(e),
+            }
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        Ok(ViewStateWithProofResult {
+            root: state_update.get_root(),
+            values,
+        })
+    }
+
     pub fn call_function(
         &self,
         mut state_update: TrieUpdate,
@@ -116,6 +240,9 @@ impl TrieViewer {
                         contract_id: None,
                     },
                     &empty_hash,
+                    account.storage_usage,
+                    None,
+                    0,
                 );
                 executor::execute(
                     &code,
@@ -130,6 +257,7 @@ impl TrieViewer {
                         contract_id,
                         contract_id,
                         0,
+                        DEFAULT_MAX_GAS_LIMIT,
                         block_index,
                         root.as_ref().into(),
                     ),
@@ -173,6 +301,7 @@ impl TrieViewer {
 
 #[cfg(test)]
 mod tests {
+    use primitives::trie_proof::verify_trie_proof;
     use primitives::types::AccountId;
     use std::collections::HashMap;
     use crate::test_utils::*;
@@ -238,6 +367,21 @@ mod tests {
         assert_eq!(view_call_result.unwrap(), encode_int(3).to_vec());
     }
 
+    #[test]
+    fn test_view_account_with_proof() {
+        let (viewer, state_update) = get_test_trie_viewer();
+        let root = state_update.get_root();
+        let result = viewer.view_account_with_proof(&state_update, &alice_account()).unwrap();
+        assert_eq!(result.root, root);
+        assert_eq!(result.account.unwrap().amount, 100);
+        let value = result.value.unwrap();
+        assert!(verify_trie_proof(&root, &result.key, Some(&value), &result.proof));
+
+        let result = viewer.view_account_with_proof(&state_update, &"eve.near".to_string()).unwrap();
+        assert!(result.account.is_none());
+        assert!(verify_trie_proof(&root, &result.key, None, &result.proof));
+    }
+
     #[test]
     fn test_view_state() {
         let (viewer, state_update) = get_test_trie_viewer();
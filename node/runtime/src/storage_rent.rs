@@ -0,0 +1,54 @@
+//! Accounts pay for the data they keep in the state by locking a part of their balance. The
+//! storage usage of the account is the size of every entry it owns in the state: the account
+//! record, the access keys and the nonces of its keys, the code and the keys and values of its
+//! contract storage. The code and the contract storage are tracked in `Account::storage_usage`,
+//! the other entries are few and are counted when the usage is checked.
+use configs::chain_spec::StorageRentConfig;
+use primitives::traits::Encode;
+use primitives::types::{AccountId, Balance};
+use storage::TrieUpdate;
+
+use crate::{
+    Account, COL_ACCESS_KEY, COL_ACCOUNT, COL_KEY_NONCE, account_id_to_bytes, get_keys_with_prefix,
+    public_key_prefix,
+};
+
+/// Bytes taken by the entries of the account that are not tracked in `Account::storage_usage`,
+/// i.e. the account record, the access keys and the nonces of the keys.
+pub fn entries_storage_usage(
+    state_update: &TrieUpdate,
+    account_id: &AccountId,
+    account: &Account,
+) -> u64 {
+    let record_len = account.encode().map_or(0, |data| data.len());
+    let mut usage = (account_id_to_bytes(COL_ACCOUNT, account_id).len() + record_len) as u64;
+    for col in [COL_ACCESS_KEY, COL_KEY_NONCE].iter() {
+        for key in get_keys_with_prefix(state_update, &public_key_prefix(col, account_id)) {
+            let value_len = state_update.get(&key).map_or(0, |value| value.len());
+            usage += (key.len() + value_len) as u64;
+        }
+    }
+    usage
+}
+
+/// Checks that the balance of the account still covers its storage usage after a change from
+/// the given previous balance and storage usage. Changes that neither lower the balance nor
+/// increase the storage are always allowed, so that an account that fell short can be topped up.
+pub fn check_storage_rent(
+    config: &StorageRentConfig,
+    account_id: &AccountId,
+    prev_amount: Balance,
+    prev_storage_usage: u64,
+    storage_usage: u64,
+    account: &Account,
+) -> Result<(), String> {
+    let locked_balance = config.locked_balance(storage_usage);
+    let worsened = account.amount < prev_amount || storage_usage > prev_storage_usage;
+    if worsened && account.amount < locked_balance {
+        return Err(format!(
+            "Account {} has {}, but needs {} to cover {} bytes of storage",
+            account_id, account.amount, locked_balance, storage_usage
+        ));
+    }
+    Ok(())
+}
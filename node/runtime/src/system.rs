@@ -1,16 +1,23 @@
 use storage::TrieUpdate;
-use primitives::types::{AccountId, AccountingInfo, AuthorityStake};
+use primitives::types::{AccountId, AccountingInfo, AuthorityStake, Balance, ShardId};
 use primitives::traits::Decode;
 use primitives::hash::{hash, CryptoHash};
 use primitives::signature::PublicKey;
-use primitives::utils::is_valid_account_id;
+use primitives::utils::{account_to_shard_id, is_valid_account_id};
 use primitives::transaction::{
     AsyncCall, ReceiptTransaction, SendMoneyTransaction,
     ReceiptBody, StakeTransaction, CreateAccountTransaction,
-    SwapKeyTransaction, AddKeyTransaction, DeleteKeyTransaction,
+    SwapKeyTransaction, AddKeyTransaction, DeleteKeyTransaction, UnstakeTransaction, AccessKey,
+    DeleteAccountTransaction, SetMultisigPolicyTransaction,
+};
+use super::{
+    COL_ACCOUNT, COL_ACCESS_KEY, COL_CODE, COL_PROPOSED_STAKE, COL_TX_STAKE_SEPARATOR, get, set,
+    account_id_to_bytes, public_key_prefix, public_key_to_bytes, get_keys_with_prefix, Account,
+    create_nonce_with_nonce,
 };
-use super::{COL_ACCOUNT, COL_CODE, set, account_id_to_bytes, Account, create_nonce_with_nonce};
 use crate::{TxTotalStake, get_tx_stake_key};
+use wasm::cache::compile_cached_module;
+use wasm::types::{Config, Error as WasmError};
 
 /// const does not allow function call, so have to resort to this
 pub fn system_account() -> AccountId { "system".to_string() }
@@ -57,6 +64,31 @@ pub fn send_money(
     }
 }
 
+/// Stake of the last proposal of the account. It differs from `staked` only while unstaked
+/// tokens wait for `withdraw_stake`, since `staked` has to cover the stake still in use.
+fn get_proposed_stake(
+    state_update: &TrieUpdate,
+    account_id: &AccountId,
+    account: &Account,
+) -> Balance {
+    get(state_update, &account_id_to_bytes(COL_PROPOSED_STAKE, account_id))
+        .unwrap_or(account.staked)
+}
+
+fn set_proposed_stake(
+    state_update: &mut TrieUpdate,
+    account_id: &AccountId,
+    account: &Account,
+    proposed_stake: Balance,
+) {
+    let key = account_id_to_bytes(COL_PROPOSED_STAKE, account_id);
+    if proposed_stake == account.staked {
+        state_update.remove(&key);
+    } else {
+        set(state_update, &key, &proposed_stake);
+    }
+}
+
 pub fn staking(
     state_update: &mut TrieUpdate,
     body: &StakeTransaction,
@@ -65,14 +97,19 @@ pub fn staking(
     authority_proposals: &mut Vec<AuthorityStake>,
 ) -> Result<Vec<ReceiptTransaction>, String> {
     if sender.amount >= body.amount && !sender.bls_public_key.is_empty() {
+        // Proposals carry the total stake, so staking again tops up the last proposed stake,
+        // keeping the pending unstakes.
+        let proposed_stake =
+            get_proposed_stake(state_update, sender_account_id, sender) + body.amount;
         authority_proposals.push(AuthorityStake {
             account_id: sender_account_id.clone(),
             public_key: sender.bls_public_key.clone(),
-            amount: body.amount,
+            amount: proposed_stake,
         });
         sender.amount -= body.amount;
         sender.staked += body.amount;
         set(state_update, &account_id_to_bytes(COL_ACCOUNT, sender_account_id), &sender);
+        set_proposed_stake(state_update, sender_account_id, sender, proposed_stake);
         Ok(vec![])
     } else if sender.amount < body.amount {
         let err_msg = format!(
@@ -84,7 +121,64 @@ pub fn staking(
         );
         Err(err_msg)
     } else {
-        Err(format!("Account {} already staked", body.originator))
+        Err(format!("Account {} does not have an authority key to stake with", body.originator))
+    }
+}
+
+/// Proposes to lower the stake of the sender by the given amount. The tokens stay locked in
+/// `staked` until the authority bookkeeping confirms that the stake is no longer used, see
+/// `withdraw_stake`. Unstakes that are still pending add up.
+pub fn unstake(
+    state_update: &mut TrieUpdate,
+    body: &UnstakeTransaction,
+    sender_account_id: &AccountId,
+    sender: &mut Account,
+    authority_proposals: &mut Vec<AuthorityStake>,
+) -> Result<Vec<ReceiptTransaction>, String> {
+    if body.amount == 0 {
+        return Err("Unstaking 0 tokens".to_string());
+    }
+    if sender.bls_public_key.is_empty() {
+        return Err(format!("Account {} does not have an authority key to unstake with", body.originator));
+    }
+    let proposed_stake = get_proposed_stake(state_update, sender_account_id, sender);
+    if proposed_stake >= body.amount {
+        authority_proposals.push(AuthorityStake {
+            account_id: sender_account_id.clone(),
+            public_key: sender.bls_public_key.clone(),
+            amount: proposed_stake - body.amount,
+        });
+        set_proposed_stake(state_update, sender_account_id, sender, proposed_stake - body.amount);
+        Ok(vec![])
+    } else {
+        Err(
+            format!(
+                "Account {} tries to unstake {}, but has only {} staked without pending unstakes",
+                body.originator,
+                body.amount,
+                proposed_stake,
+            )
+        )
+    }
+}
+
+/// Returns the tokens above `withdrawal.amount` from `staked` back to `amount`, once the
+/// authority has left the set with its previous stake. Tokens of a later proposal stay locked.
+pub fn withdraw_stake(
+    state_update: &mut TrieUpdate,
+    withdrawal: &AuthorityStake,
+) {
+    let account_id_bytes = account_id_to_bytes(COL_ACCOUNT, &withdrawal.account_id);
+    let account: Option<Account> = get(state_update, &account_id_bytes);
+    if let Some(mut account) = account {
+        let proposed_stake = get_proposed_stake(state_update, &withdrawal.account_id, &account);
+        let locked = std::cmp::max(withdrawal.amount, proposed_stake);
+        if account.staked > locked {
+            account.amount += account.staked - locked;
+            account.staked = locked;
+            set(state_update, &account_id_bytes, &account);
+            set_proposed_stake(state_update, &withdrawal.account_id, &account, proposed_stake);
+        }
     }
 }
 
@@ -151,14 +245,21 @@ pub fn deploy(
     sender_id: &AccountId,
     code: &[u8],
     sender: &mut Account,
+    wasm_config: &Config,
 ) -> Result<Vec<ReceiptTransaction>, String> {
     // Signature should be already checked at this point
+    // Reject invalid code before storing it. This also warms the compiled module cache,
+    // so the first call to the contract doesn't need to compile it.
+    compile_cached_module(code, wasm_config).map_err(|e| match e {
+        WasmError::Prepare(e) => format!("Invalid contract code: {:?}", e),
+        e => format!("Failed to compile contract code: {:?}", e),
+    })?;
+    let code_key = account_id_to_bytes(COL_CODE, &sender_id);
+    let old_code_len = get::<Vec<u8>>(state_update, &code_key).map_or(0, |code| code.len());
+    sender.storage_usage =
+        sender.storage_usage.saturating_sub(old_code_len as u64) + code.len() as u64;
     sender.code_hash = hash(code);
-    set(
-        state_update,
-        &account_id_to_bytes(COL_CODE, &sender_id),
-        &code,
-    );
+    set(state_update, &code_key, &code);
     set(
         state_update,
         &account_id_to_bytes(COL_ACCOUNT, &sender_id),
@@ -194,17 +295,26 @@ pub fn add_key(
     account: &mut Account
 ) -> Result<Vec<ReceiptTransaction>, String> {
     let new_key = PublicKey::new(&body.new_key)?;
-    let num_keys = account.public_keys.len();
-    account.public_keys.retain(|&x| x != new_key);
-    if account.public_keys.len() < num_keys {
+    let access_key_key = public_key_to_bytes(COL_ACCESS_KEY, &body.originator, &new_key);
+    if account.public_keys.contains(&new_key) || state_update.get(&access_key_key).is_some() {
         return Err("Cannot add key that already exists".to_string());
     }
-    account.public_keys.push(new_key);
-    set(
-        state_update,
-        &account_id_to_bytes(COL_ACCOUNT, &body.originator),
-        &account
-    );
+    match &body.access_key {
+        Some(access_key) => {
+            if !is_valid_account_id(&access_key.contract_id) {
+                return Err("Invalid contract_id of the access key".to_string());
+            }
+            set(state_update, &access_key_key, access_key);
+        }
+        None => {
+            account.public_keys.push(new_key);
+            set(
+                state_update,
+                &account_id_to_bytes(COL_ACCOUNT, &body.originator),
+                &account
+            );
+        }
+    }
     Ok(vec![])
 }
 
@@ -214,6 +324,11 @@ pub fn delete_key(
     account: &mut Account,
 ) -> Result<Vec<ReceiptTransaction>, String> {
     let cur_key = PublicKey::new(&body.cur_key)?;
+    let access_key_key = public_key_to_bytes(COL_ACCESS_KEY, &body.originator, &cur_key);
+    if state_update.get(&access_key_key).is_some() {
+        state_update.remove(&access_key_key);
+        return Ok(vec![]);
+    }
     let num_keys = account.public_keys.len();
     account.public_keys.retain(|&x| x != cur_key);
     if account.public_keys.len() == num_keys {
@@ -224,6 +339,12 @@ pub fn delete_key(
     if account.public_keys.is_empty() {
         return Err("Account must have at least one public key".to_string());
     }
+    if account.public_keys.len() < account.multisig_threshold as usize {
+        return Err(format!(
+            "Account {} must keep at least {} public keys for its multisig threshold",
+            body.originator, account.multisig_threshold
+        ));
+    }
     set(
         state_update,
         &account_id_to_bytes(COL_ACCOUNT, &body.originator),
@@ -232,7 +353,125 @@ pub fn delete_key(
     Ok(vec![])
 }
 
+pub fn set_multisig_policy(
+    state_update: &mut TrieUpdate,
+    body: &SetMultisigPolicyTransaction,
+    account: &mut Account,
+) -> Result<Vec<ReceiptTransaction>, String> {
+    if account.public_keys.len() < body.threshold as usize {
+        return Err(format!(
+            "Account {} has {} public keys, fewer than the multisig threshold {}",
+            body.originator,
+            account.public_keys.len(),
+            body.threshold
+        ));
+    }
+    account.multisig_threshold = body.threshold;
+    set(
+        state_update,
+        &account_id_to_bytes(COL_ACCOUNT, &body.originator),
+        &account
+    );
+    Ok(vec![])
+}
+
+/// Subtracts the cost of a function call, i.e. the attached amount, the fee and the prepaid gas,
+/// from the allowance of the access key that signed it.
+pub fn spend_allowance(
+    state_update: &mut TrieUpdate,
+    account_id: &AccountId,
+    public_key: &PublicKey,
+    amount: Balance,
+) -> Result<(), String> {
+    let key = public_key_to_bytes(COL_ACCESS_KEY, account_id, public_key);
+    let mut access_key: AccessKey = get(state_update, &key)
+        .ok_or_else(|| format!("Account {} does not have access key {}", account_id, public_key))?;
+    if let Some(allowance) = access_key.allowance {
+        if allowance < amount {
+            return Err(format!("Access key allowance {} is lower than {}", allowance, amount));
+        }
+        access_key.allowance = Some(allowance - amount);
+        set(state_update, &key, &access_key);
+    }
+    Ok(())
+}
 
+/// Removes the account together with its code, contract storage, keys and transaction stakes,
+/// but keeps the nonces of its keys. Accounts that still have stake cannot be deleted. The
+/// remaining balance is deposited to the beneficiary, which must exist in the same shard, since
+/// a failed deposit could not be refunded to the deleted account.
+pub fn delete_account(
+    state_update: &mut TrieUpdate,
+    body: &DeleteAccountTransaction,
+    hash: CryptoHash,
+    account: &mut Account,
+    accounting_info: AccountingInfo,
+    num_shards: ShardId,
+) -> Result<Vec<ReceiptTransaction>, String> {
+    if account.staked > 0 {
+        return Err(format!(
+            "Account {} cannot be deleted while it has {} staked",
+            body.originator, account.staked
+        ));
+    }
+    if !is_valid_account_id(&body.beneficiary_id) {
+        return Err(format!("Beneficiary {} does not match requirements", body.beneficiary_id));
+    }
+    if body.beneficiary_id == body.originator {
+        return Err("Account cannot be its own beneficiary".to_string());
+    }
+    if account_to_shard_id(&body.beneficiary_id, num_shards)
+        != account_to_shard_id(&body.originator, num_shards)
+    {
+        return Err(format!(
+            "Beneficiary {} is not in the shard of account {}",
+            body.beneficiary_id, body.originator
+        ));
+    }
+    if get::<Account>(state_update, &account_id_to_bytes(COL_ACCOUNT, &body.beneficiary_id))
+        .is_none()
+    {
+        return Err(format!("Beneficiary {} does not exist", body.beneficiary_id));
+    }
+    let tx_stake_key = get_tx_stake_key(&body.originator, &None);
+    let mut contract_tx_stakes = tx_stake_key.clone();
+    contract_tx_stakes.extend_from_slice(COL_TX_STAKE_SEPARATOR);
+    let mut contract_storage = account_id_to_bytes(COL_ACCOUNT, &body.originator);
+    contract_storage.push(b',');
+    // Nonces of the keys are kept, so that the transactions signed before the deletion cannot be
+    // replayed if the account is created again with the same keys.
+    let prefixes = [
+        contract_storage,
+        contract_tx_stakes,
+        public_key_prefix(COL_ACCESS_KEY, &body.originator),
+    ];
+    for prefix in prefixes.iter() {
+        for key in get_keys_with_prefix(state_update, prefix) {
+            state_update.remove(&key);
+        }
+    }
+    state_update.remove(&tx_stake_key);
+    state_update.remove(&account_id_to_bytes(COL_PROPOSED_STAKE, &body.originator));
+    state_update.remove(&account_id_to_bytes(COL_CODE, &body.originator));
+    state_update.remove(&account_id_to_bytes(COL_ACCOUNT, &body.originator));
+    if account.amount == 0 {
+        return Ok(vec![]);
+    }
+    let receipt = ReceiptTransaction::new(
+        body.originator.clone(),
+        body.beneficiary_id.clone(),
+        create_nonce_with_nonce(&hash, 0),
+        ReceiptBody::NewCall(AsyncCall::new(
+            // Empty method name is used for deposit
+            vec![],
+            vec![],
+            account.amount,
+            0,
+            accounting_info,
+        ))
+    );
+    Ok(vec![receipt])
+}
 
 pub fn system_create_account(
     state_update: &mut TrieUpdate,
@@ -272,10 +511,14 @@ mod tests {
     use crate::test_utils::*;
     use primitives::hash::hash;
     use primitives::signature::get_key_pair;
+    use primitives::test_utils::get_key_pair_from_seed;
+    use configs::chain_spec::{FeeConfig, StorageRentConfig};
     use primitives::traits::Encode;
     use primitives::transaction::{TransactionBody, TransactionStatus};
     use crate::state_viewer::{AccountViewCallResult, TrieViewer};
-    use crate::get;
+    use crate::{get, get_max_key_nonce, COL_KEY_NONCE};
+    use crate::storage_rent::entries_storage_usage;
+    use crate::ApplyState;
 
     #[test]
     fn test_upload_contract() {
@@ -302,9 +545,10 @@ mod tests {
 
     #[test]
     fn test_redeploy_contract() {
-        let test_binary = b"test_binary";
+        let test_binary = include_bytes!("../../../core/wasm/runtest/res/wasm_with_mem.wasm");
         let (runtime, trie, root) = get_runtime_and_trie();
         let (mut bob, root) = User::new(runtime, &bob_account(), trie.clone(), root);
+        let (root, _) = bob.deploy_contract(root, &bob_account(), &contract_with_size(100));
         let (new_root, mut apply_results) = bob.deploy_contract(
             root, &bob_account(), test_binary
         );
@@ -320,6 +564,92 @@ mod tests {
         assert_eq!(code, test_binary.to_vec())
     }
 
+    #[test]
+    fn test_deploy_invalid_contract() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut bob, root) = User::new(runtime, &bob_account(), trie.clone(), root);
+        let (new_root, apply_results) = bob.deploy_contract(root, &bob_account(), b"test_binary");
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(
+            apply_results[0].tx_result[0].logs,
+            vec!["Runtime error: Invalid contract code: Deserialization".to_string()]
+        );
+        assert_eq!(new_root, root);
+    }
+
+    #[test]
+    fn test_deploy_contract_with_migration() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let wasm_binary = include_bytes!("../../../core/wasm/runtest/res/wasm_with_mem.wasm");
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (root, _) = alice.create_account(root, &eve_account(), 10);
+        let (mut eve, root) = User::new(runtime, &eve_account(), trie.clone(), root);
+        let get_code = |root: CryptoHash| -> Option<Vec<u8>> {
+            let mut state_update = TrieUpdate::new(trie.clone(), root);
+            get(&mut state_update, &account_id_to_bytes(COL_CODE, &eve_account()))
+        };
+
+        // A failing migration reverts the deployment.
+        let (new_root, apply_results) = eve.deploy_contract_with_migration(
+            root, &eve_account(), wasm_binary, Some("missing_method")
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        assert_eq!(get_code(new_root), None);
+
+        // So does a migration that creates promises, since they would be dropped.
+        let (new_root, apply_results) = eve.deploy_contract_with_migration(
+            root, &eve_account(), wasm_binary, Some("create_promises_and_join")
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(apply_results[0].new_receipts.len(), 0);
+        assert_eq!(get_code(new_root), None);
+
+        let (new_root, apply_results) = eve.deploy_contract_with_migration(
+            root, &eve_account(), wasm_binary, Some("run_test")
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        assert_eq!(apply_results[0].new_receipts.len(), 0);
+        assert_eq!(get_code(new_root), Some(wasm_binary.to_vec()));
+
+        // The old code stays in place if the new one cannot be deployed.
+        let (root, apply_results) = eve.deploy_contract_with_migration(
+            new_root, &eve_account(), b"test_binary", Some("run_test")
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(root, new_root);
+        assert_eq!(get_code(root), Some(wasm_binary.to_vec()));
+    }
+
+    #[test]
+    fn test_migration_gas_is_prepaid_by_signer() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        // The maximum gas of the migration costs 104.
+        chain_spec.fees =
+            FeeConfig { gas_price_num: 1, gas_price_denum: 100_000, ..Default::default() };
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let wasm_binary = include_bytes!("../../../core/wasm/runtest/res/wasm_with_mem.wasm");
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (root, _) = alice.create_account(root, &eve_account(), 10);
+        let (mut eve, root) = User::new(runtime, &eve_account(), trie.clone(), root);
+        let (new_root, apply_results) = eve.deploy_contract_with_migration(
+            root, &eve_account(), wasm_binary, Some("run_test")
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+
+        let (root, _) = alice.send_money(root, &eve_account(), 100);
+        let (root, apply_results) = eve.deploy_contract_with_migration(
+            root, &eve_account(), wasm_binary, Some("run_test")
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let amount = viewer.view_account(&mut state_update, &eve_account()).unwrap().amount;
+        // The unused part of the prepaid gas is refunded.
+        assert!(amount > 110 - 104);
+    }
+
     #[test]
     fn test_send_money() {
         let (runtime, trie, root) = get_runtime_and_trie();
@@ -618,6 +948,147 @@ mod tests {
         assert_eq!(account.public_keys.len(), 2);
     }
 
+    #[test]
+    fn test_access_key() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (pub_key, secret_key) = get_key_pair();
+        let access_key = AccessKey {
+            contract_id: bob_account(),
+            method_names: vec![b"run_test".to_vec()],
+            allowance: Some(10),
+        };
+        let (root, _) = alice.add_access_key(root, pub_key, access_key.clone());
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        assert_eq!(
+            viewer.get_access_keys_for_account(&mut state_update, &alice_account()).unwrap(),
+            vec![(pub_key, access_key)]
+        );
+        let public_keys =
+            viewer.get_public_keys_for_account(&mut state_update, &alice_account()).unwrap();
+        assert!(!public_keys.contains(&pub_key));
+
+        let (full_key, full_secret_key) = alice.use_key(pub_key, secret_key);
+        let (new_root, apply_results) = alice.send_money(root, &bob_account(), 1);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        let (new_root, apply_results) =
+            alice.call_function(root, "carol.near", "run_test", vec![], 0);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        let (new_root, apply_results) = alice.call_function(root, "bob.near", "stop", vec![], 0);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        let (root, apply_results) = alice.call_function(root, "bob.near", "run_test", vec![], 4);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let access_keys =
+            viewer.get_access_keys_for_account(&mut state_update, &alice_account()).unwrap();
+        assert_eq!(access_keys[0].1.allowance, Some(6));
+        // Allowance is exhausted.
+        let (new_root, apply_results) =
+            alice.call_function(root, "bob.near", "run_test", vec![], 7);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        // Access key cannot delete keys.
+        let (new_root, _) = alice.delete_key(root, full_key);
+        assert_eq!(new_root, root);
+
+        alice.use_key(full_key, full_secret_key);
+        let (root, _) = alice.delete_key(root, pub_key);
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        assert!(viewer
+            .get_access_keys_for_account(&mut state_update, &alice_account())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_access_key_pays_fees_and_gas() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        chain_spec.fees = FeeConfig {
+            function_call: 1,
+            gas_price_num: 1,
+            gas_price_denum: 1_000_000,
+            ..Default::default()
+        };
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (pub_key, secret_key) = get_key_pair();
+        let access_key =
+            AccessKey { contract_id: bob_account(), method_names: vec![], allowance: Some(5) };
+        let (root, _) = alice.add_access_key(root, pub_key, access_key);
+        alice.use_key(pub_key, secret_key);
+
+        // The prepaid gas of the maximum gas limit exceeds the allowance.
+        let (new_root, apply_results) =
+            alice.call_function(root, "bob.near", "run_test", vec![], 0);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        // So does a high gas price with a low gas limit.
+        let (new_root, apply_results) = alice.call_function_with_gas(
+            root,
+            "bob.near",
+            "run_test",
+            vec![],
+            0,
+            Some(1_000),
+            Some(1_000_000_000),
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+
+        // 1 for the call and 1 for the prepaid gas.
+        let (root, apply_results) = alice.call_function_with_gas(
+            root,
+            "bob.near",
+            "run_test",
+            vec![],
+            0,
+            Some(1_000_000),
+            None,
+        );
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let access_keys =
+            viewer.get_access_keys_for_account(&mut state_update, &alice_account()).unwrap();
+        assert_eq!(access_keys[0].1.allowance, Some(3));
+    }
+
+    #[test]
+    fn test_nonces_per_key() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        // Each user signs with its own key of the same account.
+        let (mut alice1, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (mut alice2, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (root, apply_results) = alice1.send_money(root, &bob_account(), 1);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let (root, apply_results) = alice2.send_money(root, &bob_account(), 1);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let (root, apply_results) = alice2.send_money(root, &bob_account(), 1);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let alice = alice_account();
+        assert_eq!(viewer.get_nonce_for_key(&state_update, &alice, &alice1.pub_key), Ok(1));
+        assert_eq!(viewer.get_nonce_for_key(&state_update, &alice, &alice2.pub_key), Ok(2));
+        assert_eq!(viewer.view_account(&mut state_update, &alice).unwrap().nonce, 2);
+
+        // Nonce of the key that signed the transaction must increase.
+        let tx_body = TransactionBody::SendMoney(SendMoneyTransaction {
+            nonce: 2,
+            originator: alice_account(),
+            receiver: bob_account(),
+            amount: 1,
+        });
+        let (new_root, apply_results) = alice2.send_tx(root, tx_body);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+    }
+
     #[test]
     fn test_delete_key() {
         let (runtime, trie, root) = get_runtime_and_trie();
@@ -631,6 +1102,44 @@ mod tests {
         assert_eq!(account.public_keys.len(), 1);
     }
 
+    #[test]
+    fn test_multisig_account() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (pub_key, secret_key) = get_key_pair();
+        let (root, _) = alice.add_key(root, pub_key);
+        let get_account = |root| {
+            let mut state_update = TrieUpdate::new(trie.clone(), root);
+            get::<Account>(&mut state_update, &account_id_to_bytes(COL_ACCOUNT, &alice_account()))
+                .unwrap()
+        };
+
+        // Alice has 3 keys, so the threshold cannot be higher.
+        let (new_root, _) = alice.set_multisig_policy(root, 4);
+        assert_eq!(new_root, root);
+        let (root, apply_results) = alice.set_multisig_policy(root, 2);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        assert_eq!(get_account(root).multisig_threshold, 2);
+
+        let (new_root, apply_results) = alice.send_money(root, &bob_account(), 10);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+
+        alice.set_cosigners(vec![secret_key]);
+        let (root, apply_results) = alice.send_money(root, &bob_account(), 10);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        assert_eq!(get_account(root).amount, 90);
+
+        // Deleting a key is fine as long as enough keys are left for the threshold.
+        let (root, _) = alice.delete_key(root, pub_key);
+        assert_eq!(get_account(root).public_keys.len(), 2);
+        let (_, genesis_secret_key) = get_key_pair_from_seed(&alice_account());
+        alice.set_cosigners(vec![genesis_secret_key]);
+        let (new_root, apply_results) = alice.delete_key(root, alice.pub_key);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+    }
+
     #[test]
     fn test_delete_key_not_owned() {
         let (runtime, trie, root) = get_runtime_and_trie();
@@ -669,4 +1178,220 @@ mod tests {
         assert_eq!(account.public_keys.len(), 1);
     }
 
+    #[test]
+    fn test_delete_account() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (mut bob, root) = User::new(runtime, &bob_account(), trie.clone(), root);
+        let (root, _) = alice.send_money(root, &bob_account(), 10);
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        let mut storage_key = account_id_to_bytes(COL_ACCOUNT, &bob_account());
+        storage_key.extend_from_slice(b",key");
+        state_update.set(&storage_key, &storage::DBValue::from_slice(b"value"));
+        let (root, db_changes) = state_update.finalize();
+        trie.apply_changes(db_changes).unwrap();
+
+        let (root, apply_results) = bob.delete_account(root, &alice_account());
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        assert!(viewer.view_account(&mut state_update, &bob_account()).is_err());
+        assert_eq!(viewer.view_account(&mut state_update, &alice_account()).unwrap().amount, 100);
+        for key in [
+            storage_key,
+            account_id_to_bytes(COL_CODE, &bob_account()),
+            get_tx_stake_key(&bob_account(), &None),
+        ].iter() {
+            assert!(state_update.get(key).is_none());
+        }
+        // Nonces stay to prevent replays of the old transactions.
+        assert_eq!(get_max_key_nonce(&state_update, &bob_account()), 1);
+    }
+
+    #[test]
+    fn test_delete_account_with_stake() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (new_root, apply_results) = alice.delete_account(root, &bob_account());
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+    }
+
+    #[test]
+    fn test_delete_account_without_beneficiary() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (mut bob, root) = User::new(runtime, &bob_account(), trie.clone(), root);
+        let (root, _) = alice.send_money(root, &bob_account(), 10);
+        let (new_root, apply_results) = bob.delete_account(root, "carol.near");
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        let viewer = TrieViewer {};
+        let mut state_update = TrieUpdate::new(trie.clone(), root);
+        assert_eq!(viewer.view_account(&mut state_update, &bob_account()).unwrap().amount, 10);
+    }
+
+    #[test]
+    fn test_storage_rent() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime.clone(), &alice_account(), trie.clone(), root);
+        let (mut bob, root) = User::new(runtime, &bob_account(), trie.clone(), root);
+        // Locks 2 coins with the default config.
+        let code = contract_with_size(200_000);
+        let (new_root, apply_results) = bob.deploy_contract(root, &bob_account(), &code);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+
+        let (root, _) = alice.send_money(root, &bob_account(), 10);
+        let (root, apply_results) = bob.deploy_contract(root, &bob_account(), &code);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let state_update = TrieUpdate::new(trie.clone(), root);
+        let account: Account =
+            get(&state_update, &account_id_to_bytes(COL_ACCOUNT, &bob_account())).unwrap();
+        assert_eq!(account.storage_usage, code.len() as u64);
+
+        let (new_root, apply_results) = bob.send_money(root, &alice_account(), 9);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+        let (_, apply_results) = bob.send_money(root, &alice_account(), 8);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+    }
+
+    #[test]
+    fn test_storage_rent_from_chain_spec() {
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        // Every byte locks a coin, so the account record alone takes more than the balance.
+        chain_spec.storage_rent =
+            StorageRentConfig { balance_per_byte_num: 1, balance_per_byte_denum: 1 };
+        let (runtime, trie, root) = get_runtime_and_trie_from_chain_spec(&chain_spec);
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (new_root, apply_results) = alice.send_money(root, &bob_account(), 1);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert_eq!(new_root, root);
+    }
+
+    #[test]
+    fn test_storage_usage_of_keys() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let usage = |root| {
+            let state_update = TrieUpdate::new(trie.clone(), root);
+            let account: Account =
+                get(&state_update, &account_id_to_bytes(COL_ACCOUNT, &alice_account())).unwrap();
+            entries_storage_usage(&state_update, &alice_account(), &account)
+        };
+        let prev_usage = usage(root);
+        let (pub_key, _) = get_key_pair();
+        let access_key = AccessKey {
+            contract_id: bob_account(),
+            method_names: vec![b"run_test".to_vec()],
+            allowance: Some(10),
+        };
+        let (root, apply_results) = alice.add_access_key(root, pub_key, access_key.clone());
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        // The new access key and the nonce of the key that signed the transaction.
+        let access_key_usage = public_key_to_bytes(COL_ACCESS_KEY, &alice_account(), &pub_key)
+            .len()
+            + access_key.encode().unwrap().len();
+        let nonce_usage = public_key_to_bytes(COL_KEY_NONCE, &alice_account(), &alice.pub_key)
+            .len()
+            + 1u64.encode().unwrap().len();
+        assert_eq!(usage(root), prev_usage + (access_key_usage + nonce_usage) as u64);
+    }
+
+    #[test]
+    fn test_unstake() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (new_root, apply_results) = alice.unstake(root, 20);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        let proposals = &apply_results[0].authority_proposals;
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[0].account_id, alice_account());
+        assert_eq!(proposals[0].amount, 30);
+        // Tokens stay locked until the authority leaves the set.
+        let mut state_update = TrieUpdate::new(trie.clone(), new_root);
+        let account = get::<Account>(
+            &mut state_update,
+            &account_id_to_bytes(COL_ACCOUNT, &alice_account()),
+        ).unwrap();
+        assert_eq!((account.amount, account.staked), (100, 50));
+
+        let apply_state = ApplyState {
+            root: new_root,
+            shard_id: 0,
+            parent_block_hash: CryptoHash::default(),
+            block_index: 1,
+            withdrawals: proposals.clone(),
+        };
+        let state_update = TrieUpdate::new(trie.clone(), new_root);
+        let apply_result = runtime.apply(state_update, &apply_state, &[], &[]);
+        trie.apply_changes(apply_result.db_changes).unwrap();
+        let mut state_update = TrieUpdate::new(trie.clone(), apply_result.root);
+        let account = get::<Account>(
+            &mut state_update,
+            &account_id_to_bytes(COL_ACCOUNT, &alice_account()),
+        ).unwrap();
+        assert_eq!((account.amount, account.staked), (120, 30));
+    }
+
+    #[test]
+    fn test_unstake_more_than_staked() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (_, apply_results) = alice.unstake(root, 51);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+        assert!(apply_results[0].authority_proposals.is_empty());
+    }
+
+    #[test]
+    fn test_stake_proposes_total_amount() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (_, apply_results) = alice.stake(root, 10);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Completed);
+        assert_eq!(apply_results[0].authority_proposals[0].amount, 60);
+    }
+
+    #[test]
+    fn test_unstakes_add_up() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (root, apply_results) = alice.unstake(root, 20);
+        assert_eq!(apply_results[0].authority_proposals[0].amount, 30);
+        let (root, apply_results) = alice.unstake(root, 10);
+        assert_eq!(apply_results[0].authority_proposals[0].amount, 20);
+        let (_, apply_results) = alice.unstake(root, 21);
+        assert_eq!(apply_results[0].tx_result[0].status, TransactionStatus::Failed);
+    }
+
+    #[test]
+    fn test_stake_keeps_pending_unstake() {
+        let (runtime, trie, root) = get_runtime_and_trie();
+        let (mut alice, root) = User::new(runtime, &alice_account(), trie.clone(), root);
+        let (root, _) = alice.unstake(root, 20);
+        let (root, apply_results) = alice.stake(root, 10);
+        let proposals = &apply_results[0].authority_proposals;
+        assert_eq!(proposals[0].amount, 40);
+
+        // The withdrawal of the unstake only releases the tokens above the last proposal.
+        let apply_state = ApplyState {
+            root,
+            shard_id: 0,
+            parent_block_hash: CryptoHash::default(),
+            block_index: 1,
+            withdrawals: vec![AuthorityStake { amount: 30, ..proposals[0].clone() }],
+        };
+        let state_update = TrieUpdate::new(trie.clone(), root);
+        let apply_result = runtime.apply(state_update, &apply_state, &[], &[]);
+        trie.apply_changes(apply_result.db_changes).unwrap();
+        let state_update = TrieUpdate::new(trie.clone(), apply_result.root);
+        let account = get::<Account>(
+            &state_update,
+            &account_id_to_bytes(COL_ACCOUNT, &alice_account()),
+        ).unwrap();
+        assert_eq!((account.amount, account.staked), (110, 40));
+        let proposed_stake_key = account_id_to_bytes(COL_PROPOSED_STAKE, &alice_account());
+        assert!(state_update.get(&proposed_stake_key).is_none());
+    }
 }
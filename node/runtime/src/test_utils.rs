@@ -3,7 +3,7 @@ use std::sync::Arc;
 use byteorder::{ByteOrder, LittleEndian};
 
 use primitives::aggregate_signature::BlsSecretKey;
-use primitives::types::{MerkleHash, GroupSignature, AccountingInfo, AccountId};
+use primitives::types::{MerkleHash, GroupSignature, AccountingInfo, AccountId, Balance, Gas};
 use primitives::signature::{get_key_pair, PublicKey, SecretKey, sign};
 use primitives::signer::InMemorySigner;
 use primitives::hash::{hash, CryptoHash};
@@ -14,11 +14,14 @@ use primitives::transaction::{
     SignedTransaction, ReceiptTransaction, TransactionBody,
     SendMoneyTransaction, DeployContractTransaction, FunctionCallTransaction,
     CreateAccountTransaction, ReceiptBody, Callback, AsyncCall, CallbackInfo,
-    CallbackResult, AddKeyTransaction, DeleteKeyTransaction
+    CallbackResult, AddKeyTransaction, DeleteKeyTransaction, StakeTransaction,
+    UnstakeTransaction, AccessKey, DeleteAccountTransaction, SetMultisigPolicyTransaction,
 };
 use primitives::chain::{SignedShardBlockHeader, ShardBlockHeader, ReceiptBlock};
 
 use configs::ChainSpec;
+use configs::chain_spec::{DEFAULT_MAX_GAS_LIMIT, DEFAULT_MAX_GAS_PRICE, StorageRentConfig};
+use wasm::types::Config;
 use crate::state_viewer::TrieViewer;
 
 use super::{
@@ -67,12 +70,25 @@ pub fn generate_test_chain_spec() -> (ChainSpec, InMemorySigner, SecretKey) {
         beacon_chain_epoch_length: 2,
         beacon_chain_num_seats_per_slot: 10,
         boot_nodes: vec![],
+        fees: Default::default(),
+        num_shards: 1,
+        max_gas_limit: DEFAULT_MAX_GAS_LIMIT,
+        max_gas_price: DEFAULT_MAX_GAS_PRICE,
+        storage_rent: StorageRentConfig::default(),
+        genesis_state: None,
     }, signer, secret_key)
 }
 
 pub fn get_runtime_and_trie_from_chain_spec(chain_spec: &ChainSpec) -> (Runtime, Arc<Trie>, MerkleHash) {
     let trie = create_trie();
-    let runtime = Runtime {};
+    let runtime = Runtime {
+        fees: chain_spec.fees,
+        num_shards: chain_spec.num_shards,
+        max_gas_limit: chain_spec.max_gas_limit,
+        max_gas_price: chain_spec.max_gas_price,
+        storage_rent: chain_spec.storage_rent,
+        wasm_config: Config::default(),
+    };
     let trie_update = TrieUpdate::new(trie.clone(), MerkleHash::default());
     let (genesis_root, db_changes) = runtime.apply_genesis_state(
         trie_update,
@@ -102,6 +118,23 @@ pub fn encode_int(val: i32) -> [u8; 4] {
     tmp
 }
 
+/// Valid empty wasm module padded with a custom section to roughly `size` bytes.
+pub fn contract_with_size(size: usize) -> Vec<u8> {
+    let mut code = b"\0asm\x01\0\0\0".to_vec();
+    let mut payload = vec![3u8];
+    payload.extend_from_slice(b"pad");
+    payload.resize(size.saturating_sub(code.len() + 6).max(payload.len()), 0);
+    // Custom section with the payload length encoded as a 5-byte LEB128.
+    code.push(0);
+    let len = payload.len() as u32;
+    for i in 0..5 {
+        let byte = ((len >> (7 * i)) & 0x7f) as u8;
+        code.push(if i < 4 { byte | 0x80 } else { byte });
+    }
+    code.extend(payload);
+    code
+}
+
 pub fn to_receipt_block(receipts: Vec<ReceiptTransaction>) -> ReceiptBlock {
     let header = SignedShardBlockHeader {
         body: ShardBlockHeader {
@@ -146,6 +179,7 @@ impl Runtime {
                 shard_id: cur_apply_state.shard_id,
                 block_index: cur_apply_state.block_index,
                 parent_block_hash: cur_apply_state.parent_block_hash,
+                withdrawals: vec![],
             };
             receipts = vec![to_receipt_block(apply_result.new_receipts.drain().flat_map(|(_, v)| v).collect())];
             txs = vec![];
@@ -168,7 +202,9 @@ pub struct User {
     nonce: u64,
     trie: Arc<Trie>,
     pub pub_key: PublicKey,
-    secret_key: SecretKey
+    secret_key: SecretKey,
+    /// Keys that add their signatures to the transactions of a multisig account.
+    cosigners: Vec<SecretKey>,
 }
 
 impl User {
@@ -199,10 +235,22 @@ impl User {
             nonce: 1,
             trie,
             pub_key,
-            secret_key
+            secret_key,
+            cosigners: vec![],
         }, new_root)
     }
 
+    /// Signs the following transactions with the given key. Returns the previously used key.
+    pub fn use_key(&mut self, pub_key: PublicKey, secret_key: SecretKey) -> (PublicKey, SecretKey) {
+        let pub_key = std::mem::replace(&mut self.pub_key, pub_key);
+        (pub_key, std::mem::replace(&mut self.secret_key, secret_key))
+    }
+
+    /// Adds signatures of the given keys to the following transactions.
+    pub fn set_cosigners(&mut self, cosigners: Vec<SecretKey>) {
+        self.cosigners = cosigners;
+    }
+
     pub fn send_tx(
         &mut self,
         root: CryptoHash,
@@ -210,12 +258,15 @@ impl User {
     ) -> (MerkleHash, Vec<ApplyResult>) {
         let hash = tx_body.get_hash();
         let signature = sign(hash.as_ref(), &self.secret_key);
-        let transaction = SignedTransaction::new(signature, tx_body);
+        let mut transaction = SignedTransaction::new(signature, tx_body);
+        transaction.additional_signatures =
+            self.cosigners.iter().map(|secret_key| sign(hash.as_ref(), secret_key)).collect();
         let apply_state = ApplyState {
             root,
             shard_id: 0,
             parent_block_hash: CryptoHash::default(),
-            block_index: 0
+            block_index: 0,
+            withdrawals: vec![],
         };
         let apply_results = self.runtime.apply_all_vec(
             self.trie.clone(), apply_state, vec![], vec![transaction]
@@ -274,11 +325,22 @@ impl User {
         root: MerkleHash,
         contract_id: &str,
         wasm_binary: &[u8]
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        self.deploy_contract_with_migration(root, contract_id, wasm_binary, None)
+    }
+
+    pub fn deploy_contract_with_migration(
+        &mut self,
+        root: MerkleHash,
+        contract_id: &str,
+        wasm_binary: &[u8],
+        migration_method_name: Option<&str>,
     ) -> (MerkleHash, Vec<ApplyResult>) {
         let tx_body = TransactionBody::DeployContract(DeployContractTransaction {
             nonce: self.nonce,
             contract_id: contract_id.to_string(),
             wasm_byte_array: wasm_binary.to_vec(),
+            migration_method_name: migration_method_name.map(|name| name.as_bytes().to_vec()),
         });
         self.nonce += 1;
         self.send_tx(root, tx_body)
@@ -291,6 +353,31 @@ impl User {
         method_name: &str,
         args: Vec<u8>,
         amount: u64
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        self.call_function_with_gas_limit(root, contract_id, method_name, args, amount, None)
+    }
+
+    pub fn call_function_with_gas_limit(
+        &mut self,
+        root: MerkleHash,
+        contract_id: &str,
+        method_name: &str,
+        args: Vec<u8>,
+        amount: u64,
+        gas_limit: Option<Gas>,
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        self.call_function_with_gas(root, contract_id, method_name, args, amount, gas_limit, None)
+    }
+
+    pub fn call_function_with_gas(
+        &mut self,
+        root: MerkleHash,
+        contract_id: &str,
+        method_name: &str,
+        args: Vec<u8>,
+        amount: u64,
+        gas_limit: Option<Gas>,
+        gas_price: Option<Balance>,
     ) -> (MerkleHash, Vec<ApplyResult>) {
         let tx_body = TransactionBody::FunctionCall(FunctionCallTransaction {
                 nonce: self.nonce,
@@ -299,6 +386,8 @@ impl User {
                 method_name: method_name.as_bytes().to_vec(),
                 args,
                 amount,
+                gas_limit,
+                gas_price,
         });
         self.nonce += 1;
         self.send_tx(root, tx_body)
@@ -312,7 +401,24 @@ impl User {
         let tx_body = TransactionBody::AddKey(AddKeyTransaction {
             nonce: self.nonce,
             originator: self.account_id.clone(),
-            new_key: key.0[..].to_vec()
+            new_key: key.0[..].to_vec(),
+            access_key: None,
+        });
+        self.nonce += 1;
+        self.send_tx(root, tx_body)
+    }
+
+    pub fn add_access_key(
+        &mut self,
+        root: MerkleHash,
+        key: PublicKey,
+        access_key: AccessKey,
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        let tx_body = TransactionBody::AddKey(AddKeyTransaction {
+            nonce: self.nonce,
+            originator: self.account_id.clone(),
+            new_key: key.0[..].to_vec(),
+            access_key: Some(access_key),
         });
         self.nonce += 1;
         self.send_tx(root, tx_body)
@@ -332,6 +438,62 @@ impl User {
         self.send_tx(root, tx_body)
     }
 
+    pub fn delete_account(
+        &mut self,
+        root: MerkleHash,
+        beneficiary_id: &str,
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        let tx_body = TransactionBody::DeleteAccount(DeleteAccountTransaction {
+            nonce: self.nonce,
+            originator: self.account_id.clone(),
+            beneficiary_id: beneficiary_id.to_string(),
+        });
+        self.nonce += 1;
+        self.send_tx(root, tx_body)
+    }
+
+    pub fn set_multisig_policy(
+        &mut self,
+        root: MerkleHash,
+        threshold: u32,
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        let tx_body = TransactionBody::SetMultisigPolicy(SetMultisigPolicyTransaction {
+            nonce: self.nonce,
+            originator: self.account_id.clone(),
+            threshold,
+        });
+        self.nonce += 1;
+        self.send_tx(root, tx_body)
+    }
+
+    pub fn stake(
+        &mut self,
+        root: MerkleHash,
+        amount: u64,
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        let tx_body = TransactionBody::Stake(StakeTransaction {
+            nonce: self.nonce,
+            originator: self.account_id.clone(),
+            amount,
+        });
+        self.nonce += 1;
+        self.send_tx(root, tx_body)
+    }
+
+    pub fn unstake(
+        &mut self,
+        root: MerkleHash,
+        amount: u64,
+    ) -> (MerkleHash, Vec<ApplyResult>) {
+        let tx_body = TransactionBody::Unstake(UnstakeTransaction {
+            nonce: self.nonce,
+            originator: self.account_id.clone(),
+            amount,
+        });
+        self.nonce += 1;
+        self.send_tx(root, tx_body)
+    }
+
     fn send_receipt(
         &mut self,
         root: MerkleHash,
@@ -341,7 +503,8 @@ impl User {
             root,
             shard_id: 0,
             parent_block_hash: CryptoHash::default(),
-            block_index: 0
+            block_index: 0,
+            withdrawals: vec![],
         };
         let apply_results = self.runtime.apply_all_vec(
             self.trie.clone(), apply_state, vec![to_receipt_block(vec![receipt])], vec![]
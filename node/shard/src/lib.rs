@@ -10,7 +10,9 @@ extern crate storage;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
+use chain::BlockInsertResult;
 use configs::chain_spec::ChainSpec;
+use node_runtime::state_dump::{import_state, read_state_dump};
 use node_runtime::state_viewer::TrieViewer;
 use node_runtime::{ApplyState, Runtime};
 use primitives::block_traits::{SignedBlock, SignedHeader};
@@ -18,11 +20,12 @@ use primitives::chain::{ReceiptBlock, SignedShardBlock, SignedShardBlockHeader};
 use primitives::hash::CryptoHash;
 use primitives::merkle::{merklize, MerklePath};
 use primitives::transaction::{
-    FinalTransactionResult, FinalTransactionStatus, ReceiptTransaction, SignedTransaction,
-    TransactionLogs, TransactionResult, TransactionStatus, TransactionAddress
+    ContractEvent, FinalTransactionResult, FinalTransactionStatus, ReceiptTransaction,
+    SignedTransaction, TransactionLogs, TransactionResult, TransactionStatus, TransactionAddress
 };
-use primitives::types::{AuthorityStake, BlockId, BlockIndex, MerkleHash, ShardId};
-use storage::ShardChainStorage;
+use primitives::types::{AccountId, AuthorityStake, BlockId, BlockIndex, MerkleHash, ShardId};
+use primitives::utils::account_to_shard_id;
+use storage::{GenericStorage, ShardChainStorage};
 use storage::{Trie, TrieUpdate};
 use mempool::Pool;
 
@@ -35,15 +38,27 @@ pub struct SignedTransactionInfo {
     pub result: TransactionResult,
 }
 
+/// Contract event together with the block and the transaction or receipt that emitted it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ContractEventInfo {
+    pub block_index: BlockIndex,
+    pub hash: CryptoHash,
+    pub event: ContractEvent,
+}
+
+/// State changes, authority proposals, hashes of the executed scheduled receipts, transaction
+/// results and new receipts of a block.
 type ShardBlockExtraInfo = (
     storage::DBChanges,
     Vec<AuthorityStake>,
+    Vec<CryptoHash>,
     Vec<TransactionResult>,
     HashMap<ShardId, ReceiptBlock>,
 );
 
 #[allow(unused)]
 pub struct ShardClient {
+    pub shard_id: ShardId,
     pub chain: Arc<chain::BlockChain<SignedShardBlockHeader, SignedShardBlock, ShardChainStorage>>,
     pub receipts: RwLock<HashMap<BlockIndex, HashMap<ShardId, ReceiptBlock>>>,
     pub trie: Arc<Trie>,
@@ -54,23 +69,72 @@ pub struct ShardClient {
 }
 
 impl ShardClient {
-    pub fn new(chain_spec: &ChainSpec, storage: Arc<RwLock<ShardChainStorage>>) -> Self {
+    /// Creates the client of the given shard, the genesis state of the shard only contains the
+    /// accounts that belong to it.
+    pub fn new(
+        chain_spec: &ChainSpec,
+        shard_id: ShardId,
+        storage: Arc<RwLock<ShardChainStorage>>,
+    ) -> Self {
         let trie = Arc::new(Trie::new(storage.clone()));
-        let runtime = Runtime {};
-        let state_update = TrieUpdate::new(trie.clone(), MerkleHash::default());
-        let (genesis_root, db_changes) = runtime.apply_genesis_state(
-            state_update,
-            &chain_spec.accounts,
-            &chain_spec.genesis_wasm,
-            &chain_spec.initial_authorities,
-        );
-        trie.apply_changes(db_changes).expect("Failed to commit genesis state");
-        let genesis = SignedShardBlock::genesis(genesis_root);
+        let runtime = Runtime {
+            fees: chain_spec.fees,
+            num_shards: chain_spec.num_shards,
+            max_gas_limit: chain_spec.max_gas_limit,
+            max_gas_price: chain_spec.max_gas_price,
+            storage_rent: chain_spec.storage_rent,
+            wasm_config: Default::default(),
+        };
+        let (genesis_root, db_changes) = match chain_spec.genesis_state {
+            Some(ref path) => {
+                assert_eq!(
+                    chain_spec.num_shards, 1,
+                    "Genesis state is only supported for one shard"
+                );
+                let dump = read_state_dump(path).expect("Failed to read the genesis state");
+                import_state(&trie, &dump).expect("Failed to import the genesis state")
+            }
+            None => {
+                let state_update = TrieUpdate::new(trie.clone(), MerkleHash::default());
+                let in_shard = |account_id: &AccountId| {
+                    account_to_shard_id(account_id, chain_spec.num_shards) == shard_id
+                };
+                let accounts: Vec<_> =
+                    chain_spec.accounts.iter().filter(|a| in_shard(&a.0)).cloned().collect();
+                let initial_authorities: Vec<_> = chain_spec
+                    .initial_authorities
+                    .iter()
+                    .filter(|a| in_shard(&a.0))
+                    .cloned()
+                    .collect();
+                runtime.apply_genesis_state(
+                    state_update,
+                    &accounts,
+                    &chain_spec.genesis_wasm,
+                    &initial_authorities,
+                )
+            }
+        };
+        let genesis = SignedShardBlock::genesis(shard_id, genesis_root);
+        // The genesis state is committed together with the first launch on the storage, the
+        // reference counts of its nodes would be incremented on every launch otherwise.
+        let genesis_stored = storage
+            .write()
+            .expect(POISONED_LOCK_ERR)
+            .blockchain_storage_mut()
+            .block(&genesis.block_hash())
+            .expect("Failed to read the genesis block")
+            .is_some();
+        if !genesis_stored {
+            trie.apply_block_changes(db_changes, 0, &genesis_root)
+                .expect("Failed to commit genesis state");
+        }
 
         let chain = Arc::new(chain::BlockChain::new(genesis, storage.clone()));
         let trie_viewer = TrieViewer {};
         let pool = Pool::new(storage.clone(), trie.clone());
         Self { 
+            shard_id,
             chain,
             receipts: RwLock::new(HashMap::new()),
             trie,
@@ -95,20 +159,97 @@ impl ShardClient {
         &self,
         block: &SignedShardBlock,
         db_transaction: storage::DBChanges,
+        scheduled_receipts: &[CryptoHash],
         tx_result: Vec<TransactionResult>,
         new_receipts: HashMap<ShardId, ReceiptBlock>,
     ) {
-        self.trie.apply_changes(db_transaction).ok();
-        self.chain.insert_block(block.clone());
+        let state_root = block.merkle_root_state();
+        self.trie.apply_block_changes(db_transaction, block.index(), &state_root).ok();
+        let result = self.chain.insert_block(block.clone());
+        self.update_pool(result);
         self.storage
             .write()
             .expect(POISONED_LOCK_ERR)
-            .extend_transaction_results_addresses(block, tx_result)
+            .extend_transaction_results_addresses(block, scheduled_receipts, tx_result)
             .unwrap();
         let index = block.index();
         self.receipts.write().expect(POISONED_LOCK_ERR).insert(index, new_receipts);
     }
 
+    /// Makes the given known block the best one, e.g. when the beacon chain switches to another
+    /// branch. The state does not need to be reverted, since every block has its own state root.
+    pub fn set_head(&self, hash: &CryptoHash) {
+        match self.chain.set_best_block(hash) {
+            Ok(result) => self.update_pool(result),
+            Err(e) => error!("Failed to switch the shard chain head: {}", e),
+        }
+    }
+
+    /// Releases the states of the blocks that are more than `retention` blocks behind the best
+    /// block, never touching the state of the last final block. The states of the blocks that did
+    /// not make it to the best chain are released together with the best chain block of the same
+    /// index. Returns the number of removed trie nodes.
+    pub fn prune_state(&self, retention: BlockIndex) -> usize {
+        let prune_below = std::cmp::min(
+            self.chain.best_index().saturating_sub(retention),
+            self.chain.finalized_index(),
+        );
+        let storage = self.storage.read().expect(POISONED_LOCK_ERR);
+        let mut index = match storage.pruned_state_index() {
+            Ok(index) => index,
+            Err(e) => {
+                error!("Failed to read the pruned state index: {}", e);
+                return 0;
+            }
+        };
+        drop(storage);
+        let mut removed = 0;
+        while index < prune_below {
+            let block = match self.chain.get_block(&BlockId::Number(index)) {
+                Some(block) => block,
+                None => break,
+            };
+            match self.trie.prune_states(index, Some(block.merkle_root_state())) {
+                Ok(count) => removed += count,
+                Err(e) => {
+                    error!("Failed to prune the state of block {}: {}", index, e);
+                    break;
+                }
+            }
+            index += 1;
+        }
+        if removed > 0 {
+            debug!(
+                target: "client",
+                "Pruned {} state nodes of shard {} below block {}",
+                removed,
+                self.shard_id,
+                index
+            );
+        }
+        removed
+    }
+
+    /// Keeps the pool in sync with the best chain. Transactions and receipts of the blocks that
+    /// left the best chain go back to the pool, so that they can be included in the new branch.
+    fn update_pool(&self, result: BlockInsertResult) {
+        let (reverted, applied) = match result {
+            BlockInsertResult::Extended => (vec![], vec![self.chain.best_hash()]),
+            BlockInsertResult::Reorg { reverted, applied } => (reverted, applied),
+            _ => return,
+        };
+        for hash in reverted {
+            if let Some(block) = self.chain.get_block(&BlockId::Hash(hash)) {
+                self.pool.revert_block(&block);
+            }
+        }
+        for hash in applied {
+            if let Some(block) = self.chain.get_block(&BlockId::Hash(hash)) {
+                self.pool.import_block(&block);
+            }
+        }
+    }
+
     fn compute_receipt_blocks(
         shard_ids: Vec<ShardId>,
         receipts: Vec<Vec<ReceiptTransaction>>,
@@ -130,6 +271,7 @@ impl ShardClient {
         last_block_hash: CryptoHash,
         prev_receipts: Vec<ReceiptBlock>,
         transactions: Vec<SignedTransaction>,
+        withdrawals: Vec<AuthorityStake>,
     ) -> (SignedShardBlock, ShardBlockExtraInfo) {
         let last_block = self
             .chain
@@ -140,6 +282,7 @@ impl ShardClient {
             parent_block_hash: last_block_hash,
             block_index: last_block.body.header.index + 1,
             shard_id: last_block.body.header.shard_id,
+            withdrawals,
         };
         let state_update = TrieUpdate::new(self.trie.clone(), apply_state.root);
         let apply_result =
@@ -165,24 +308,28 @@ impl ShardClient {
         let shard_block_extra = (
             apply_result.db_changes,
             apply_result.authority_proposals,
+            apply_result.scheduled_receipts,
             apply_result.tx_result,
             receipt_map,
         );
         (shard_block, shard_block_extra)
     }
 
-    pub fn apply_block(&self, block: SignedShardBlock) -> bool {
+    pub fn apply_block(&self, block: SignedShardBlock, withdrawals: Vec<AuthorityStake>) -> bool {
+        let _pruning = self.trie.pause_pruning();
         let state_merkle_root = block.body.header.merkle_root_state;
         let receipt_merkle_root = block.body.header.receipt_merkle_root;
-        let (shard_block, (db_changes, _, tx_result, receipt_map)) = self.prepare_new_block(
-            block.body.header.parent_hash,
-            block.body.receipts,
-            block.body.transactions,
-        );
+        let (shard_block, (db_changes, _, scheduled, tx_result, receipt_map)) =
+            self.prepare_new_block(
+                block.body.header.parent_hash,
+                block.body.receipts,
+                block.body.transactions,
+                withdrawals,
+            );
         if shard_block.body.header.merkle_root_state == state_merkle_root
             && shard_block.body.header.receipt_merkle_root == receipt_merkle_root
         {
-            self.insert_block(&shard_block, db_changes, tx_result, receipt_map);
+            self.insert_block(&shard_block, db_changes, &scheduled, tx_result, receipt_map);
             true
         } else {
             error!("Received Invalid block. It's a scam");
@@ -230,46 +377,44 @@ impl ShardClient {
         })
     }
 
-    fn collect_transaction_final_result(
+    pub fn get_transaction_final_result(&self, hash: &CryptoHash) -> FinalTransactionResult {
+        get_transaction_final_result(hash, |hash| self.get_transaction_result(hash))
+    }
+
+    /// Events emitted by the given contract in the blocks of the best chain with indices from
+    /// `from_index` to `to_index` inclusive, optionally filtered by topic.
+    pub fn get_contract_events(
         &self,
-        transaction_result: &TransactionResult,
-        logs: &mut Vec<TransactionLogs>,
-    ) -> FinalTransactionStatus {
-        match transaction_result.status {
-            TransactionStatus::Unknown => FinalTransactionStatus::Unknown,
-            TransactionStatus::Failed => FinalTransactionStatus::Failed,
-            TransactionStatus::Completed => {
-                for r in transaction_result.receipts.iter() {
-                    let receipt_result = self.get_transaction_result(&r);
-                    logs.push(TransactionLogs {
-                        hash: *r,
-                        lines: receipt_result.logs.clone(),
-                        receipts: receipt_result.receipts.clone(),
-                    });
-                    match self.collect_transaction_final_result(&receipt_result, logs) {
-                        FinalTransactionStatus::Failed => return FinalTransactionStatus::Failed,
-                        FinalTransactionStatus::Completed => {}
-                        _ => return FinalTransactionStatus::Started,
-                    };
-                }
-                FinalTransactionStatus::Completed
+        contract_id: &AccountId,
+        topic: Option<&[u8]>,
+        from_index: BlockIndex,
+        to_index: BlockIndex,
+    ) -> Result<Vec<ContractEventInfo>, String> {
+        let mut result = vec![];
+        for index in from_index..=std::cmp::min(to_index, self.chain.best_index()) {
+            let block_hash = match self.chain.get_header(&BlockId::Number(index)) {
+                Some(header) => header.block_hash(),
+                None => continue,
+            };
+            let mut guard = self.storage.write().expect(POISONED_LOCK_ERR);
+            let events = guard
+                .contract_events(&block_hash)
+                .map_err(|e| format!("failed to read the events of block {}: {}", index, e))?;
+            if let Some(events) = events {
+                result.extend(
+                    events
+                        .iter()
+                        .filter(|e| &e.event.contract_id == contract_id)
+                        .filter(|e| topic.map_or(true, |t| e.event.topic.as_slice() == t))
+                        .map(|e| ContractEventInfo {
+                            block_index: index,
+                            hash: e.hash,
+                            event: e.event.clone(),
+                        }),
+                );
             }
         }
-    }
-
-    pub fn get_transaction_final_result(&self, hash: &CryptoHash) -> FinalTransactionResult {
-        let transaction_result = self.get_transaction_result(hash);
-        let mut result = FinalTransactionResult {
-            status: FinalTransactionStatus::Unknown,
-            logs: vec![TransactionLogs {
-                hash: *hash,
-                lines: transaction_result.logs.clone(),
-                receipts: transaction_result.receipts.clone(),
-            }],
-        };
-        result.status =
-            self.collect_transaction_final_result(&transaction_result, &mut result.logs);
-        result
+        Ok(result)
     }
 
     pub fn get_receipt_block(
@@ -286,8 +431,59 @@ impl ShardClient {
     }
 }
 
+fn collect_transaction_final_result<F>(
+    transaction_result: &TransactionResult,
+    logs: &mut Vec<TransactionLogs>,
+    get_result: &F,
+) -> FinalTransactionStatus
+where
+    F: Fn(&CryptoHash) -> TransactionResult,
+{
+    match transaction_result.status {
+        TransactionStatus::Unknown => FinalTransactionStatus::Unknown,
+        TransactionStatus::Failed => FinalTransactionStatus::Failed,
+        TransactionStatus::Completed => {
+            for r in transaction_result.receipts.iter() {
+                let receipt_result = get_result(r);
+                logs.push(TransactionLogs {
+                    hash: *r,
+                    lines: receipt_result.logs.clone(),
+                    receipts: receipt_result.receipts.clone(),
+                });
+                match collect_transaction_final_result(&receipt_result, logs, get_result) {
+                    FinalTransactionStatus::Failed => return FinalTransactionStatus::Failed,
+                    FinalTransactionStatus::Completed => {}
+                    _ => return FinalTransactionStatus::Started,
+                };
+            }
+            FinalTransactionStatus::Completed
+        }
+    }
+}
+
+/// Result of the transaction and of all the receipts it caused. The results are looked up with
+/// `get_result`, since the receipts can be executed by other shards.
+pub fn get_transaction_final_result<F>(hash: &CryptoHash, get_result: F) -> FinalTransactionResult
+where
+    F: Fn(&CryptoHash) -> TransactionResult,
+{
+    let transaction_result = get_result(hash);
+    let mut result = FinalTransactionResult {
+        status: FinalTransactionStatus::Unknown,
+        logs: vec![TransactionLogs {
+            hash: *hash,
+            lines: transaction_result.logs.clone(),
+            receipts: transaction_result.receipts.clone(),
+        }],
+    };
+    result.status =
+        collect_transaction_final_result(&transaction_result, &mut result.logs, &get_result);
+    result
+}
+
 #[cfg(test)]
 mod tests {
+    use node_runtime::state_dump::{export_state, write_state_dump};
     use node_runtime::test_utils::generate_test_chain_spec;
     use primitives::signature::{sign, SecretKey};
     use primitives::transaction::{
@@ -295,14 +491,14 @@ mod tests {
         FinalTransactionStatus, TransactionAddress
     };
     use primitives::types::Balance;
-    use storage::test_utils::create_beacon_shard_storages;
+    use storage::test_utils::{create_beacon_shard_storages, create_memory_storage};
 
     use super::*;
 
     fn get_test_client() -> (ShardClient, SecretKey) {
         let (chain_spec, _, secret_key) = generate_test_chain_spec();
         let shard_storage = create_beacon_shard_storages().1;
-        let shard_client = ShardClient::new(&chain_spec, shard_storage);
+        let shard_client = ShardClient::new(&chain_spec, 0, shard_storage);
         (shard_client, secret_key)
     }
 
@@ -334,9 +530,9 @@ mod tests {
     fn test_transaction_failed() {
         let (client, secret_key) = get_test_client();
         let tx = send_money_tx("xyz.near", "bob.near", 100, secret_key);
-        let (block, (db_changes, _, tx_status, receipts)) =
-            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx.clone()]);
-        client.insert_block(&block, db_changes, tx_status, receipts);
+        let (block, (db_changes, _, scheduled, tx_status, receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx.clone()], vec![]);
+        client.insert_block(&block, db_changes, &scheduled, tx_status, receipts);
 
         let result = client.get_transaction_result(&tx.get_hash());
         assert_eq!(result.status, TransactionStatus::Failed);
@@ -346,9 +542,9 @@ mod tests {
     fn test_get_transaction_status_complete() {
         let (client, secret_key) = get_test_client();
         let tx = send_money_tx("alice.near", "bob.near", 10, secret_key);
-        let (block, (db_changes, _, tx_status, new_receipts)) =
-            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx.clone()]);
-        client.insert_block(&block, db_changes, tx_status, new_receipts);
+        let (block, (db_changes, _, scheduled, tx_status, new_receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx.clone()], vec![]);
+        client.insert_block(&block, db_changes, &scheduled, tx_status, new_receipts);
 
         let result = client.get_transaction_result(&tx.get_hash());
         assert_eq!(result.status, TransactionStatus::Completed);
@@ -362,9 +558,9 @@ mod tests {
         assert_eq!(final_result.logs[0].receipts.len(), 1);
 
         let receipt_block = client.get_receipt_block(block.index(), block.shard_id()).unwrap();
-        let (block2, (db_changes2, _, tx_status2, receipts)) =
-            client.prepare_new_block(block.hash, vec![receipt_block], vec![]);
-        client.insert_block(&block2, db_changes2, tx_status2, receipts);
+        let (block2, (db_changes2, _, scheduled, tx_status2, receipts)) =
+            client.prepare_new_block(block.hash, vec![receipt_block], vec![], vec![]);
+        client.insert_block(&block2, db_changes2, &scheduled, tx_status2, receipts);
 
         let result2 = client.get_transaction_result(&result.receipts[0]);
         assert_eq!(result2.status, TransactionStatus::Completed);
@@ -391,11 +587,177 @@ mod tests {
             CryptoHash::default(),
         );
         let db_changes = HashMap::default();
-        client.insert_block(&block, db_changes, vec![TransactionResult::default()], HashMap::new());
+        client.insert_block(
+            &block,
+            db_changes,
+            &[],
+            vec![TransactionResult::default()],
+            HashMap::new(),
+        );
         let address = client.get_transaction_address(&hash);
         let expected = TransactionAddress { block_hash: block.hash, index: 0 };
         assert_eq!(address, Some(expected.clone()));
     }
 
+    #[test]
+    fn test_get_contract_events() {
+        let (client, secret_key) = get_test_client();
+        let tx = send_money_tx("alice.near", "bob.near", 10, secret_key);
+        let (block, (db_changes, _, scheduled, mut tx_results, receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx.clone()], vec![]);
+        let event = |contract_id: &str, topic: &str| ContractEvent {
+            contract_id: contract_id.to_string(),
+            topic: topic.as_bytes().to_vec(),
+            data: vec![1, 2, 3],
+        };
+        tx_results[0].events = vec![
+            event("alice.near", "transfer"),
+            event("alice.near", "mint"),
+            event("bob.near", "transfer"),
+        ];
+        client.insert_block(&block, db_changes, &scheduled, tx_results, receipts);
+
+        let alice = "alice.near".to_string();
+        let events = client.get_contract_events(&alice, None, 0, 10).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].block_index, block.index());
+        assert_eq!(events[0].hash, tx.get_hash());
+        let transfer = Some("transfer".as_bytes());
+        let events = client.get_contract_events(&alice, transfer, 0, 10).unwrap();
+        assert_eq!(events, vec![ContractEventInfo {
+            block_index: block.index(),
+            hash: tx.get_hash(),
+            event: event("alice.near", "transfer"),
+        }]);
+        assert!(client.get_contract_events(&alice, None, 0, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_prune_state() {
+        let (client, secret_key) = get_test_client();
+        let genesis_root = client.chain.best_block().merkle_root_state();
+        let tx = send_money_tx("alice.near", "bob.near", 10, secret_key);
+        let (block, (db_changes, _, scheduled, tx_results, receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx], vec![]);
+        client.insert_block(&block, db_changes, &scheduled, tx_results, receipts);
+        // Nothing is pruned before the block is final.
+        assert_eq!(client.prune_state(0), 0);
+
+        client.chain.set_finalized_index(block.index());
+        assert!(client.prune_state(0) > 0);
+        assert!(client.trie.get_state_nodes(&[genesis_root]).is_empty());
+        let mut state_update = client.get_state_update();
+        let bob = "bob.near".to_string();
+        assert!(client.trie_viewer.view_account(&mut state_update, &bob).is_ok());
+        // The state of the final block is kept.
+        assert_eq!(client.prune_state(0), 0);
+    }
+
+    #[test]
+    fn test_prune_state_of_forks() {
+        let (client, secret_key) = get_test_client();
+        let tx = send_money_tx("alice.near", "bob.near", 10, secret_key.clone());
+        let (block, (db_changes, _, scheduled, tx_results, receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx], vec![]);
+        client.insert_block(&block, db_changes, &scheduled, tx_results, receipts);
+        let tx = send_money_tx("alice.near", "bob.near", 20, secret_key);
+        let (fork, (db_changes, _, scheduled, tx_results, receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx], vec![]);
+        client.insert_block(&fork, db_changes, &scheduled, tx_results, receipts);
+        assert_eq!(client.chain.best_hash(), block.block_hash());
+        let (next, (db_changes, _, scheduled, tx_results, receipts)) =
+            client.prepare_new_block(block.block_hash(), vec![], vec![], vec![]);
+        client.insert_block(&next, db_changes, &scheduled, tx_results, receipts);
+
+        client.chain.set_finalized_index(next.index());
+        assert!(client.prune_state(0) > 0);
+        // The state of the block that did not make it to the best chain is released as well.
+        assert!(client.trie.get_state_nodes(&[fork.merkle_root_state()]).is_empty());
+        let mut state_update = client.get_state_update();
+        let bob = "bob.near".to_string();
+        assert!(client.trie_viewer.view_account(&mut state_update, &bob).is_ok());
+    }
+
+    #[test]
+    fn test_genesis_from_state_dump() {
+        let (client, secret_key) = get_test_client();
+        let tx = send_money_tx("alice.near", "bob.near", 10, secret_key);
+        let (block, (db_changes, _, scheduled, tx_results, receipts)) =
+            client.prepare_new_block(client.genesis_hash(), vec![], vec![tx], vec![]);
+        client.insert_block(&block, db_changes, &scheduled, tx_results, receipts);
+        let dump = export_state(&client.trie, &block.merkle_root_state()).unwrap();
+        let path = std::env::temp_dir().join("shard_test_genesis_from_state_dump.json");
+        write_state_dump(&path, &dump).unwrap();
+
+        let (mut chain_spec, _, _) = generate_test_chain_spec();
+        chain_spec.genesis_state = Some(path);
+        let new_client = ShardClient::new(&chain_spec, 0, create_beacon_shard_storages().1);
+        let genesis = new_client.chain.best_block();
+        assert_eq!(genesis.index(), 0);
+        assert_eq!(genesis.merkle_root_state(), block.merkle_root_state());
+        let mut state_update = new_client.get_state_update();
+        let alice = "alice.near".to_string();
+        let account = new_client.trie_viewer.view_account(&mut state_update, &alice).unwrap();
+        assert_eq!(account.amount, 90);
+    }
+
+    #[test]
+    fn test_cross_shard_send_money() {
+        let (mut chain_spec, _, secret_key) = generate_test_chain_spec();
+        chain_spec.num_shards = 2;
+        let clients: Vec<_> = create_memory_storage(2)
+            .1
+            .into_iter()
+            .enumerate()
+            .map(|(shard_id, storage)| ShardClient::new(&chain_spec, shard_id as ShardId, storage))
+            .collect();
+        let alice_shard = account_to_shard_id(&"alice.near".to_string(), 2);
+        let bob_shard = account_to_shard_id(&"bob.near".to_string(), 2);
+        assert_ne!(alice_shard, bob_shard);
+        let alice_client = &clients[alice_shard as usize];
+        let bob_client = &clients[bob_shard as usize];
+        let mut state_update = bob_client.get_state_update();
+        let alice = "alice.near".to_string();
+        assert!(bob_client.trie_viewer.view_account(&mut state_update, &alice).is_err());
+
+        let tx = send_money_tx("alice.near", "bob.near", 10, secret_key);
+        // The transaction is rejected by the shard that does not have the originator.
+        let genesis_hash = bob_client.genesis_hash();
+        let (_, (_, _, _, tx_results, _)) =
+            bob_client.prepare_new_block(genesis_hash, vec![], vec![tx.clone()], vec![]);
+        assert_eq!(tx_results[0].status, TransactionStatus::Failed);
+
+        let (block, (db_changes, _, scheduled, tx_results, receipts)) =
+            alice_client.prepare_new_block(
+                alice_client.genesis_hash(),
+                vec![],
+                vec![tx.clone()],
+                vec![],
+            );
+        alice_client.insert_block(&block, db_changes, &scheduled, tx_results, receipts);
+        assert!(alice_client.get_receipt_block(block.index(), alice_shard).is_none());
+        let receipt_block = alice_client.get_receipt_block(block.index(), bob_shard).unwrap();
+        let (block2, (db_changes2, _, scheduled, tx_results2, receipts2)) =
+            bob_client.prepare_new_block(
+                bob_client.genesis_hash(),
+                vec![receipt_block],
+                vec![],
+                vec![],
+            );
+        bob_client.insert_block(&block2, db_changes2, &scheduled, tx_results2, receipts2);
+
+        let final_result = get_transaction_final_result(&tx.get_hash(), |hash| {
+            clients
+                .iter()
+                .map(|client| client.get_transaction_result(hash))
+                .find(|result| result.status != TransactionStatus::Unknown)
+                .unwrap_or_default()
+        });
+        assert_eq!(final_result.status, FinalTransactionStatus::Completed);
+        let mut state_update = bob_client.get_state_update();
+        let bob = bob_client.trie_viewer.view_account(&mut state_update, &"bob.near".to_string());
+        assert_eq!(bob.unwrap().amount, 10);
+    }
+
     // TODO(472): Add extensive testing for ShardBlockChain.
 }
@@ -6,7 +6,7 @@ use futures::future;
 use futures::sync::mpsc::{channel, Sender};
 
 use client::Client;
-use configs::{get_testnet_configs, ClientConfig, NetworkConfig, RPCConfig};
+use configs::{get_testnet_configs, ClientConfig, NetworkConfig, RPCConfig, StorageBackend};
 use consensus::adapters::transaction_to_payload;
 use primitives::chain::ChainPayload;
 use primitives::transaction::SignedTransaction;
@@ -24,9 +24,21 @@ pub fn start_from_configs(client_cfg: ClientConfig, network_cfg: NetworkConfig,
         let (transactions_tx, transactions_rx) = channel(1024);
         let (receipts_tx, receipts_rx) = channel(1024);
         spawn_rpc_server_task(transactions_tx, &rpc_cfg, client.clone());
+        // A read-only database can't store blocks, the node only answers the queries.
+        if client_cfg.storage_backend == StorageBackend::ReadOnly {
+            return Ok(());
+        }
 
         let (consensus_control_tx, consensus_control_rx) = channel(1024);
 
+        if !client_cfg.archive {
+            coroutines::state_pruner::spawn_state_pruner(
+                client.clone(),
+                client_cfg.state_retention,
+                coroutines::state_pruner::PRUNING_PERIOD,
+            );
+        }
+
         // Create a task that consumes the consensuses
         // and produces the beacon chain blocks.
         let (beacon_block_consensus_body_tx, beacon_block_consensus_body_rx) = channel(1024);
@@ -281,6 +281,16 @@ declare function _near_log(msg_ptr: usize): void;
         amount: u64,
     ) -> u32;
 
+    // Same as `promise_create`, but the call is executed at the block with the given index.
+    fn promise_schedule(
+        account_id: *const u8,
+        method_name: *const u8,
+        arguments: *const u8,
+        mana: u32,
+        amount: u64,
+        block_index: u64,
+    ) -> u32;
+
     fn promise_then(
         promise_index: u32,
         method_name: *const u8,
@@ -305,4 +315,7 @@ declare function _near_log(msg_ptr: usize): void;
     fn random32() -> u32;
 
     fn block_index() -> u64;
+
+    // Emits a typed event with the given topic and data, stored with the transaction result.
+    fn emit_event(topic: *const u8, data: *const u8);
 */
\ No newline at end of file
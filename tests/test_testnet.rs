@@ -10,6 +10,7 @@ use configs::ClientConfig;
 use configs::NetworkConfig;
 use configs::RPCConfig;
 use primitives::network::PeerInfo;
+use storage::StorageBackend;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
@@ -31,6 +32,9 @@ fn test_node_ready(
             "./node/configs/res/testnet_chain.json",
         ))),
         log_level: log::LevelFilter::Off,
+        archive: false,
+        state_retention: 100,
+        storage_backend: StorageBackend::Memory,
     };
 
     let network_cfg = NetworkConfig {
@@ -40,6 +44,7 @@ fn test_node_ready(
         reconnect_delay: Duration::from_millis(50),
         gossip_interval: Duration::from_millis(50),
         gossip_sample_size: 10,
+        state_sync: false,
     };
 
     let rpc_cfg = RPCConfig { rpc_port };